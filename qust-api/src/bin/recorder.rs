@@ -0,0 +1,8 @@
+use qust_api::prelude::*;
+use qust::prelude::logging_service;
+
+fn main() {
+    let config = RecorderConfig::from_args().unwrap();
+    logging_service(config.tracing_config.path.clone(), config.contracts.clone());
+    Recorder::new(config).run();
+}
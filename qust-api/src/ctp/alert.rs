@@ -0,0 +1,65 @@
+use qust::prelude::*;
+use serde::{ Deserialize, Serialize };
+use serde_json::{ json, Value };
+use std::time::Duration;
+use anyhow::{ anyhow, Result };
+
+/// Sinks are called on the thread of the `AlertBus`, a request gets a runtime of its own.
+fn post_json(url: &str, body: &Value) -> Result<()> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async {
+        let res = reqwest::Client::new()
+            .post(url)
+            .json(body)
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await?;
+        if !res.status().is_success() {
+            return Err(anyhow!("{} answered {}", url, res.status()));
+        }
+        Ok(())
+    })
+}
+
+/// Posts the `Alert` as json.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookSink {
+    pub url: String,
+}
+
+#[typetag::serde]
+impl AlertSink for WebhookSink {
+    fn send(&self, alert: &Alert) -> Result<()> {
+        post_json(&self.url, &serde_json::to_value(alert)?)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum ImFormat {
+    DingTalk,
+    WeCom,
+    Feishu,
+    Slack,
+}
+
+/// A text message to the incoming webhook of an IM robot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImSink {
+    pub url: String,
+    pub format: ImFormat,
+}
+
+#[typetag::serde]
+impl AlertSink for ImSink {
+    fn send(&self, alert: &Alert) -> Result<()> {
+        let text = alert.to_string();
+        let body = match self.format {
+            ImFormat::DingTalk | ImFormat::WeCom => json!({ "msgtype": "text", "text": { "content": text } }),
+            ImFormat::Feishu => json!({ "msg_type": "text", "content": { "text": text } }),
+            ImFormat::Slack => json!({ "text": text }),
+        };
+        post_json(&self.url, &body)
+    }
+}
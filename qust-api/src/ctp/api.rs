@@ -21,8 +21,9 @@ impl ApiConvert<DataRecv> for (sstr, DepthMarketDataField) {
         let depth_data = self.1;
         let tick_data = TickData {
             t: {
-                let c = format!("{} {}.{}", depth_data.TradingDay.to_str_0(), depth_data.UpdateTime.to_str_0(), depth_data.UpdateMillisec);
-                dt::parse_from_str(&c, "%Y%m%d %H:%M:%S%.f").expect(&c)
+                let c = format!("{}.{}", depth_data.UpdateTime.to_str_0(), depth_data.UpdateMillisec);
+                let time = tt::parse_from_str(&c, "%H:%M:%S%.f").expect(&c);
+                feed_time(time, chrono::Local::now().naive_local())
             },
             c     : depth_data.LastPrice as f32,
             v     : depth_data.Volume as f32,
@@ -36,11 +37,21 @@ impl ApiConvert<DataRecv> for (sstr, DepthMarketDataField) {
     }
 }
 
+/// Ticks are cleaned here, where the limit prices of the day are still known.
 impl ApiConvert<DataRecv> for (&CtpQueryRes, DepthMarketDataField) {
     fn api_convert(self) -> Option<DataRecv> {
         let istm = self.1.InstrumentID;
-        let contract =  *self.0.contract_ticker_map.get(&istm)?;
-        (contract, self.1).api_convert()
+        let contract =  *self.0.contract_ticker_map.read().unwrap().get(&istm)?;
+        let limits = (self.1.LowerLimitPrice as f32, self.1.UpperLimitPrice as f32);
+        let limits = (limits.0 > 0. && limits.1 < 1e10).then_some(limits);
+        match (contract, self.1).api_convert()? {
+            DataRecv::TickData(c, tick_data) => {
+                TICK_WATCH.on_tick(c);
+                let tick_data = self.0.clean_tick(c, tick_data, limits)?;
+                Some(DataRecv::TickData(c, tick_data))
+            }
+            other => Some(other),
+        }
     }
 }
 
@@ -143,6 +154,39 @@ impl ApiConvert<DataRecv> for OrderField {
     }
 }
 
+impl ApiConvert<LiveFill> for OnRspTradeField {
+    fn api_convert(self) -> Option<LiveFill> {
+        let id = gb18030_cstr_to_str_i8(&self.InvestUnitID).to_string();
+        let contract = gb18030_cstr_to_str_i8(&self.InstrumentID).to_string();
+        let num = self.Volume as f32;
+        let price = self.Price as f32;
+        let is_open = self.OffsetFlag as u8 as char == '0';
+        let action = match (self.Direction as u8 as char, is_open) {
+            ('0', true) => OrderAction::LoOpen(num, price),
+            ('0', false) => OrderAction::LoClose(num, price),
+            ('1', true) => OrderAction::ShOpen(num, price),
+            ('1', false) => OrderAction::ShClose(num, price),
+            _ => return None,
+        };
+        LiveFill {
+            time: {
+                let c = format!("{} {}", self.TradeDate.to_str_0(), self.TradeTime.to_str_0());
+                dt::parse_from_str(&c, "%Y%m%d %H:%M:%S").ok()?
+            },
+            stra: id.get(..ORDER_RET_ID_LEN)?.to_string(),
+            ticker: contract.as_str().extract_ticker()?.0,
+            contract,
+            action,
+        }.pip(Some)
+    }
+}
+
+impl ApiConvert<DataRecv> for OnRspTradeField {
+    fn api_convert(self) -> Option<DataRecv> {
+        ApiConvert::<LiveFill>::api_convert(self).map(DataRecv::Fill)
+    }
+}
+
 impl ApiConvert<DataRecv> for Vec<OrderField> {
     fn api_convert(self) -> Option<DataRecv> {
         let mut res = vec![];
@@ -189,46 +233,77 @@ impl<T: ApiConvert<DataRecv>> ApiConvert<DataRecv> for (&CtpQueryRes, T) {
 pub struct CtpQueryRes {
     pub trading_account: RwLock<TradingAccountField>,
     pub instrument_info: RwLock<hm<IstmId, InstrumentField>>,
-    pub contract_data_receive_map: hm<DataRecvId, NotifyDataRecv>, 
-    pub contract_ticker_map: hm<IstmId, &'static str>,
+    /// the last record of an instrument query came in
+    pub instrument_queried: RwLock<bool>,
+    pub contract_data_receive_map: RwLock<hm<DataRecvId, NotifyDataRecv>>,
+    pub contract_ticker_map: RwLock<hm<IstmId, &'static str>>,
+    pub tick_cleaner: RwLock<Option<TickCleanerSet>>,
+    /// net hold of every contract of the account, from the last position query
+    pub positions: RwLock<hm<String, f32>>,
+    positions_pending: Mutex<hm<String, f32>>,
 }
 
 impl CtpQueryRes {
+    /// The set only takes a write lock when the cleaning is switched, each contract is
+    /// cleaned under a lock of its own.
+    fn clean_tick(&self, contract: sstr, tick_data: TickData, limits: Option<(f32, f32)>) -> Option<TickData> {
+        let cleaner_guard = self.tick_cleaner.read().unwrap();
+        let Some(cleaner) = cleaner_guard.as_ref() else {
+            return Some(tick_data);
+        };
+        let (res, reports) = cleaner.clean(contract, tick_data, limits);
+        for quality in reports {
+            loge!("ctp", "tick quality {}: {:?}", contract, quality);
+        }
+        res
+    }
+
+    /// Adds up the records of a position query, `positions` is replaced on the last one.
+    pub fn update_position(&self, position: Option<InvestorPositionField>, is_last: bool) {
+        let mut pending = self.positions_pending.lock().unwrap();
+        if let Some(p) = position {
+            let sign = match p.PosiDirection {
+                50 => 1.,
+                51 => -1.,
+                _ => 0.,
+            };
+            *pending.entry(p.InstrumentID.to_str_0().to_string()).or_default() += sign * p.Position as f32;
+        }
+        if is_last {
+            *self.positions.write().unwrap() = std::mem::take(&mut *pending);
+        }
+    }
 
-    pub fn send_data_recv<T>(&self, data: T)
+    /// Dispatches to the strategies the data is for. Waits while one of them is full,
+    /// see `DataRecvPipe::send`.
+    pub async fn send_data_recv<T>(&self, data: T)
     where
          for<'l> (&'l Self, T): ApiConvert<DataRecv>,
     {
+        let mut stamp = HopStamp::new(Hop::Spi);
         let Some(data_recv) = (self, data).api_convert() else {
             return;
         };
-        match &data_recv {
-            DataRecv::TickData(c, _) => {
-                for (k, data_recv_on) in self.contract_data_receive_map.iter() {
-                    if *c == k.tick_data_id {
-                        data_recv_on.push(data_recv.clone());
-                        data_recv_on.notify_all();
-                    }
+        stamp.mark(Hop::Convert);
+        // taken out of the lock, strategies can be attached while one of them is full
+        let data_recv_to = self.contract_data_receive_map
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(k, _)| match &data_recv {
+                DataRecv::TickData(c, _) => *c == k.tick_data_id,
+                DataRecv::OrderRecv(order_recv) => {
+                    order_recv.id.len() >= ORDER_RET_ID_LEN
+                        && order_recv.id[..ORDER_RET_ID_LEN] == k.order_return_id
                 }
-            }
-            DataRecv::OrderRecv(order_recv) => {
-                for (k, data_recv_on) in self.contract_data_receive_map.iter() {
-                    if order_recv.id.len() < ORDER_RET_ID_LEN {
-                        return;
-                    }
-                    if order_recv.id[..ORDER_RET_ID_LEN] == k.order_return_id {
-                        data_recv_on.push(data_recv.clone());
-                        data_recv_on.notify_all();
-                    }
-                }
-            }
-            DataRecv::OrderRecvHis(_) => {
-                for (_, data_recv_on) in self.contract_data_receive_map.iter() {
-                    data_recv_on.push(data_recv.clone());
-                    data_recv_on.notify_all();
-                }
-            }
+                DataRecv::OrderRecvHis(_) => true,
+                DataRecv::Fill(fill) => fill.stra == k.order_return_id,
+            })
+            .map(|(_, data_recv_on)| data_recv_on.clone())
+            .collect_vec();
+        for data_recv_on in data_recv_to {
+            data_recv_on.send(data_recv.clone(), stamp.clone()).await;
         }
 
     }
-}
\ No newline at end of file
+}
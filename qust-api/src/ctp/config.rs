@@ -60,22 +60,56 @@ pub struct Config {
    pub algo: Option<Box<dyn Algo>>,
    #[serde(default)]
    pub tracing_config: TracingConfig,
+   #[serde(default)]
+   pub tick_clean: Option<TickCleanConfig>,
+   #[serde(default)]
+   pub scheduler: SchedulerConfig,
+   #[serde(default)]
+   pub alert: AlertConfig,
 }
 
+/// The parts of `Config` the live runners read.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LiveConfig {
+    pub scheduler: SchedulerConfig,
+    pub alert: AlertConfig,
+}
+
+impl From<&Config> for LiveConfig {
+    fn from(config: &Config) -> Self {
+        Self { scheduler: config.scheduler.clone(), alert: config.alert.clone() }
+    }
+}
 
 pub fn get_config() -> Result<Config> {
+    get_config_from("config.toml")
+}
+
+/// Reads the toml file given as the first argument, or `default_path`.
+pub fn get_config_from<T: serde::de::DeserializeOwned>(default_path: &str) -> Result<T> {
     use std::env;
     let args = env::args().collect::<Vec<_>>();
     let config_path = if args.len() > 1 {
         &args[1]
     } else {
-        "config.toml"
+        default_path
     };
     let to_parsed_string = std::fs::read_to_string(config_path)?;
     let config = toml::from_str(&to_parsed_string)?;
     Ok(config)
 }
 
+/// Reads a `NamedStraSpec` from a `.json` or `.toml` file.
+pub fn get_stra_spec(path: &str) -> Result<NamedStraSpec> {
+    let text = std::fs::read_to_string(path)?;
+    match std::path::Path::new(path).extension().and_then(|x| x.to_str()) {
+        Some("json") => NamedStraSpec::from_json(&text),
+        Some("toml") => Ok(toml::from_str(&text)?),
+        other => Err(anyhow::anyhow!("unknown spec format {:?} of {}", other, path)),
+    }
+}
+
 pub trait ConfigParse {
     type Output;
     fn config_parse(&self) -> Self::Output;
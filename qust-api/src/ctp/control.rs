@@ -0,0 +1,363 @@
+use qust::prelude::*;
+use axum::{
+    extract::{ ws::{ Message, WebSocket, WebSocketUpgrade }, Path, Query, State },
+    http::StatusCode,
+    response::Response,
+    routing::{ delete, get, post, put },
+    Json, Router,
+};
+use serde::{ Deserialize, Serialize };
+use std::net::SocketAddr;
+use std::sync::{ Arc, RwLock };
+use std::thread;
+use tokio::sync::broadcast::error::RecvError;
+use anyhow::{ anyhow, Result };
+use super::config::{ get_stra_spec, Config, LiveConfig };
+use super::ctp_wrapper::{ live_scheduler, run_ctp_with_scheduler, Ctp, CtpApi };
+
+/// The `Ctp` running now, `run_ctp` builds a new one on every session.
+static CURRENT_CTP: RwLock<Option<Arc<Ctp>>> = RwLock::new(None);
+
+pub(super) fn set_current_ctp(ctp: &Arc<Ctp>) {
+    *CURRENT_CTP.write().unwrap() = Some(ctp.clone());
+}
+
+type Reply<T> = Result<Json<T>, (StatusCode, String)>;
+
+fn not_found(id: &str) -> (StatusCode, String) {
+    (StatusCode::NOT_FOUND, format!("no strategy {}", id))
+}
+
+fn bad_request(e: anyhow::Error) -> (StatusCode, String) {
+    (StatusCode::BAD_REQUEST, e.to_string())
+}
+
+/// The running `CtpApi`, strategies are attached to it.
+fn current_service() -> Result<CtpApi, (StatusCode, String)> {
+    CURRENT_CTP
+        .read()
+        .unwrap()
+        .clone()
+        .map(|ctp| CtpApi { ctp })
+        .ok_or_else(|| (StatusCode::SERVICE_UNAVAILABLE, "ctp is not running".into()))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FlattenQuery {
+    pub contract: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MultiplierBody {
+    pub multiplier: f32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DetachQuery {
+    #[serde(default)]
+    pub force: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SpecFileBody {
+    pub path: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KillState {
+    pub on: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReloadReport {
+    pub algo_set: Vec<String>,
+    pub algo_skipped: Vec<String>,
+    pub tick_clean: bool,
+}
+
+/// HTTP/JSON control of a running `StraApi`, for localhost only.
+///
+/// | route | |
+/// |---|---|
+/// | `GET /strategies` | hold, target and pnl of every strategy |
+/// | `POST /strategies/:id/pause`, `/resume` | |
+/// | `POST /strategies/:id/flatten?contract=` | all contracts without `contract` |
+/// | `POST /contracts/:contract/flatten` | every strategy trading it |
+/// | `PUT /strategies/:id/algo` | a typetag `Algo`, e.g. `{"Algo":"AlgoTarget"}` |
+/// | `PUT /strategies/:id/multiplier` | `{"multiplier":2.0}` |
+/// | `GET`, `POST`, `DELETE /kill` | the kill switch |
+/// | `POST /config/reload` | `algo` and `tick_clean` of the config file |
+/// | `GET /specs` | specs attached by the `StraManager` |
+/// | `POST /specs` | a `NamedStraSpec` to attach |
+/// | `POST /specs/file` | `{"path":"stra.toml"}`, a json or toml `NamedStraSpec` |
+/// | `DELETE /specs/:name?force=` | refused while it holds without `force` |
+/// | `GET /latency` | |
+/// | `GET /events` | websocket of `LiveEvent` |
+pub struct ControlServer {
+    stra_api: Arc<StraApi>,
+    manager: Option<Arc<StraManager>>,
+    config_path: String,
+}
+
+impl ControlServer {
+    pub fn new(stra_api: Arc<StraApi>, config_path: &str) -> Self {
+        Self { stra_api, manager: None, config_path: config_path.into() }
+    }
+
+    /// Serves the `/specs` routes with `manager`.
+    pub fn with_manager(mut self, manager: Arc<StraManager>) -> Self {
+        self.manager = Some(manager);
+        self
+    }
+
+    fn controls(&self) -> Vec<Arc<StraControl>> {
+        self.stra_api.controls()
+    }
+
+    fn find(&self, id: &str) -> Result<Arc<StraControl>, (StatusCode, String)> {
+        self.controls()
+            .into_iter()
+            .find(|x| x.id == id)
+            .ok_or_else(|| not_found(id))
+    }
+
+    fn manager(&self) -> Result<&Arc<StraManager>, (StatusCode, String)> {
+        self.manager
+            .as_ref()
+            .ok_or_else(|| (StatusCode::NOT_FOUND, "no strategy manager".into()))
+    }
+
+    pub fn router(self) -> Router {
+        Router::new()
+            .route("/strategies", get(strategies))
+            .route("/strategies/:id/pause", post(pause))
+            .route("/strategies/:id/resume", post(resume))
+            .route("/strategies/:id/flatten", post(flatten))
+            .route("/strategies/:id/algo", put(set_algo))
+            .route("/strategies/:id/multiplier", put(set_multiplier))
+            .route("/contracts/:contract/flatten", post(flatten_contract))
+            .route("/kill", get(kill_get).post(kill_on).delete(kill_off))
+            .route("/config/reload", post(reload_config))
+            .route("/specs", get(specs).post(attach_spec))
+            .route("/specs/file", post(attach_spec_file))
+            .route("/specs/:name", delete(detach_spec))
+            .route("/latency", get(latency))
+            .route("/events", get(events))
+            .with_state(Arc::new(self))
+    }
+
+    /// Serves on a thread of its own, the live loop blocks the threads it runs on.
+    pub fn spawn(self, addr: SocketAddr) -> Result<thread::JoinHandle<()>> {
+        if !addr.ip().is_loopback() {
+            return Err(anyhow!("control server only listens on localhost, got {}", addr));
+        }
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()?;
+        let listener = runtime.block_on(tokio::net::TcpListener::bind(addr))?;
+        loge!("ctp", "control server on {}", addr);
+        let router = self.router();
+        thread::spawn(move || {
+            if let Err(e) = runtime.block_on(async move { axum::serve(listener, router).await }) {
+                loge!(level: Error, "ctp", "control server stopped: {}", e);
+            }
+        })
+        .pip(Ok)
+    }
+}
+
+type AppState = State<Arc<ControlServer>>;
+
+async fn strategies(State(server): AppState) -> Json<Vec<StraStatus>> {
+    server.controls().iter().map(|x| x.status()).collect::<Vec<_>>().pip(Json)
+}
+
+async fn pause(State(server): AppState, Path(id): Path<String>) -> Reply<StraStatus> {
+    let control = server.find(&id)?;
+    control.pause();
+    Ok(Json(control.status()))
+}
+
+async fn resume(State(server): AppState, Path(id): Path<String>) -> Reply<StraStatus> {
+    let control = server.find(&id)?;
+    control.resume();
+    Ok(Json(control.status()))
+}
+
+async fn flatten(
+    State(server): AppState,
+    Path(id): Path<String>,
+    Query(query): Query<FlattenQuery>,
+) -> Reply<StraStatus> {
+    let control = server.find(&id)?;
+    if !control.flatten(query.contract.as_deref()) {
+        return Err(not_found(&format!("{} on {:?}", id, query.contract)));
+    }
+    Ok(Json(control.status()))
+}
+
+async fn flatten_contract(State(server): AppState, Path(contract): Path<String>) -> Reply<Vec<StraStatus>> {
+    let res = server.controls()
+        .iter()
+        .filter(|x| x.flatten(Some(&contract)))
+        .map(|x| x.status())
+        .collect::<Vec<_>>();
+    if res.is_empty() {
+        return Err(not_found(&format!("on {}", contract)));
+    }
+    Ok(Json(res))
+}
+
+async fn set_algo(State(server): AppState, Path(id): Path<String>, Json(algo): Json<AlgoBox>) -> Reply<StraStatus> {
+    let control = server.find(&id)?;
+    control.set_algo(algo).map_err(bad_request)?;
+    Ok(Json(control.status()))
+}
+
+async fn set_multiplier(
+    State(server): AppState,
+    Path(id): Path<String>,
+    Json(body): Json<MultiplierBody>,
+) -> Reply<StraStatus> {
+    let control = server.find(&id)?;
+    control.set_multiplier(body.multiplier).map_err(bad_request)?;
+    Ok(Json(control.status()))
+}
+
+async fn kill_get() -> Json<KillState> {
+    Json(KillState { on: is_killed() })
+}
+
+async fn kill_on() -> Json<KillState> {
+    kill_switch(true);
+    kill_get().await
+}
+
+async fn kill_off() -> Json<KillState> {
+    kill_switch(false);
+    kill_get().await
+}
+
+async fn reload_config(State(server): AppState) -> Reply<ReloadReport> {
+    let config = std::fs::read_to_string(&server.config_path)
+        .map_err(anyhow::Error::from)
+        .and_then(|x| toml::from_str::<Config>(&x).map_err(anyhow::Error::from))
+        .map_err(bad_request)?;
+    let mut report = ReloadReport::default();
+    if let Some(algo) = config.algo {
+        for control in server.controls().iter() {
+            match control.set_algo(algo.clone()) {
+                Ok(()) => report.algo_set.push(control.id.clone()),
+                Err(_) => report.algo_skipped.push(control.id.clone()),
+            }
+        }
+    }
+    if let (Some(tick_clean), Some(ctp)) = (config.tick_clean, CURRENT_CTP.read().unwrap().clone()) {
+        CtpApi { ctp }.set_tick_clean(tick_clean);
+        report.tick_clean = true;
+    }
+    loge!("ctp", "config reloaded from {}: {:?}", server.config_path, report);
+    Ok(Json(report))
+}
+
+async fn specs(State(server): AppState) -> Reply<Vec<AttachedSpec>> {
+    Ok(Json(server.manager()?.specs()))
+}
+
+async fn attach_spec(State(server): AppState, Json(spec): Json<NamedStraSpec>) -> Reply<Vec<String>> {
+    let service = current_service()?;
+    server.manager()?
+        .attach(spec, &service)
+        .map(Json)
+        .map_err(bad_request)
+}
+
+async fn attach_spec_file(State(server): AppState, Json(body): Json<SpecFileBody>) -> Reply<Vec<String>> {
+    let spec = get_stra_spec(&body.path).map_err(bad_request)?;
+    let service = current_service()?;
+    server.manager()?
+        .attach(spec, &service)
+        .map(Json)
+        .map_err(bad_request)
+}
+
+async fn detach_spec(
+    State(server): AppState,
+    Path(name): Path<String>,
+    Query(query): Query<DetachQuery>,
+) -> Reply<AttachedSpec> {
+    let service = current_service()?;
+    server.manager()?
+        .detach(&name, query.force, &service)
+        .map(Json)
+        .map_err(bad_request)
+}
+
+async fn latency() -> Json<Vec<LatencyStats>> {
+    Json(LATENCY.stats())
+}
+
+async fn events(ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(send_events)
+}
+
+async fn send_events(mut socket: WebSocket) {
+    let mut rx = EVENTS.subscribe();
+    loop {
+        let event = match rx.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(n)) => {
+                loge!("ctp", "event stream skipped {} events", n);
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        let Ok(text) = serde_json::to_string(&event) else {
+            continue;
+        };
+        if socket.send(Message::Text(text.into())).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// `run_ctp` with `server` listening on `addr`, the positions of the account are checked
+/// against the strategies on `PreClose` and `EndOfDay`.
+pub async fn run_ctp_with_control(
+    running_api: RunningApi<Arc<StraApi>, CtpApi>,
+    config: LiveConfig,
+    server: ControlServer,
+    addr: SocketAddr,
+) {
+    if let Err(e) = server.spawn(addr) {
+        loge!(level: Error, "ctp", "control server not started: {}", e);
+    }
+    let stra_api = running_api.stra_api.clone();
+    let scheduler = live_scheduler(&running_api, config)
+        .with_task(SessionPhase::PreClose, "position check", position_check(stra_api.clone()))
+        .with_task(SessionPhase::EndOfDay, "position check", position_check(stra_api));
+    run_ctp_with_scheduler(running_api, scheduler).await
+}
+
+/// A session task raising `PositionMismatch` alerts for the positions the account holds
+/// other than the strategies of `stra_api`, e.g. on `PreClose` and `EndOfDay`. Nothing is
+/// checked when the positions can not be queried, e.g. after the service logged out on `Stop`.
+pub fn position_check(stra_api: Arc<StraApi>) -> impl FnMut(&SessionEvent) + Send + 'static {
+    move |event| {
+        let Some(ctp) = CURRENT_CTP.read().unwrap().clone() else {
+            return;
+        };
+        let res = ctp.req_update_positions();
+        if res != 0 {
+            loge!("ctp", "positions not checked on {:?}, query returned {}", event.phase, res);
+            return;
+        }
+        // the query answers on the callback thread
+        std::thread::sleep(std::time::Duration::from_secs(3));
+        let positions = ctp.positions();
+        position_mismatches(&positions, &stra_api.controls())
+            .into_iter()
+            .for_each(raise);
+    }
+}
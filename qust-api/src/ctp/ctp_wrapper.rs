@@ -5,7 +5,7 @@ use ctp_futures::md_api::MdApi;
 use ctp_futures::trader_api::TraderApi;
 use ctp_futures::{ md_api, trader_api as td_api, CThostFtdcQryOrderField};
 use futures::{StreamExt, executor::block_on};
-use super::config::CtpAccountConfig;
+use super::config::{ CtpAccountConfig, LiveConfig };
 use super::utiles::*;
 use super::api::{ApiConvert, CtpOrderAction, CtpQueryRes, OrderSendWithAcco};
 use super::type_bridge::*;
@@ -78,6 +78,10 @@ impl Ctp {
         Ok(res.into())
     }
 
+    fn alert_source(&self, front: &str) -> String {
+        format!("{} {}", front, self.ca.account)
+    }
+
     fn md_accu(&self) -> i32 {
         let a = &mut self.md_rid.lock().unwrap().0;
         *a += 1;
@@ -120,12 +124,23 @@ impl Ctp {
         self.td.lock().unwrap().req_qry_instrument(&mut req, self.td_accu())
     }
 
+    fn req_qry_instrument_all(&self) -> i32 {
+        *self.query_res.instrument_queried.write().unwrap() = false;
+        let mut req = QryInstrumentField::default();
+        self.td.lock().unwrap().req_qry_instrument(&mut req, self.td_accu())
+    }
+
     fn update_qry_instrument(&self, data: InstrumentField) {
         self.query_res.instrument_info.write().unwrap().insert(data.InstrumentID, data);
     }
     
 
-    fn req_update_positions(&self) -> i32 {
+    /// Net positions of the account from the last position query.
+    pub(super) fn positions(&self) -> hm<String, f32> {
+        self.query_res.positions.read().unwrap().clone()
+    }
+
+    pub(super) fn req_update_positions(&self) -> i32 {
         let mut req = QryInvestorPositionField::default();
         set_cstr_from_str_truncate_i8(&mut req.BrokerID, self.ca.broker_id.as_str());
         set_cstr_from_str_truncate_i8(&mut req.InvestorID, self.ca.account.as_str());
@@ -200,7 +215,7 @@ impl Ctp {
         self.td.lock().unwrap().req_user_login(&mut req, self.td_accu())
     }
 
-    fn settlement_info_confirm(&self) -> i32 {
+    pub(super) fn settlement_info_confirm(&self) -> i32 {
         let mut req = SettlementInfoConfirmField::default();
         set_cstr_from_str_truncate_i8(&mut req.BrokerID, &self.ca.broker_id);
         set_cstr_from_str_truncate_i8(&mut req.InvestorID, &self.ca.account);
@@ -253,6 +268,8 @@ impl Ctp {
         let contracts = self
             .query_res
             .contract_ticker_map
+            .read()
+            .unwrap()
             .keys()
             .map(|x| {
                 x.to_str_0()
@@ -265,6 +282,8 @@ impl Ctp {
          let contracts = self
             .query_res
             .contract_ticker_map
+            .read()
+            .unwrap()
             .keys()
             .map(|x| {
                 x.to_str_0()
@@ -292,6 +311,7 @@ impl Ctp {
                 OnFrontDisconnected(p) => {
                     loge!("ctp", "md disconnected");
                     if *self.need_reconnect_md.lock().unwrap() {
+                        raise(Alert::new(AlertLevel::Warn, AlertKind::Disconnected, self.alert_source("md"), "md front disconnected"));
                         loge!("ctp", "try to reconnect md");
                         while self.login_md() != 0 {
                             loge!("stra", "try to reconnect md...");
@@ -299,6 +319,7 @@ impl Ctp {
                             continue;
                         }
                         self.subsecribe_market_data_all();
+                        raise(Alert::new(AlertLevel::Warn, AlertKind::Reconnected, self.alert_source("md"), "md logged in again"));
                     } else {
                         loge!("ctp", "md disconnect intentiolly");
                         self.md.lock().unwrap().release();
@@ -312,6 +333,12 @@ impl Ctp {
                     if error_id != 0 {
                         loge!(level: Error, "ctp", "md login wrong");
                         println!("ctp md login wrong: {}", error_msg.to_str_0());
+                        raise(Alert::new(
+                            AlertLevel::Critical,
+                            AlertKind::LoginFailed,
+                            self.alert_source("md"),
+                            format!("md login failed {}: {}", error_id, error_msg.to_str_0()),
+                        ));
                     } else {
                         loge!("ctp", "md login success");
                         println!("ctp: md login success");
@@ -329,7 +356,7 @@ impl Ctp {
                 }
                 OnRtnDepthMarketData(ref md) => {
                     let market_data: DepthMarketDataField = md.p_depth_market_data.unwrap();
-                    self.query_res.send_data_recv(market_data);
+                    self.query_res.send_data_recv(market_data).await;
                 }
                 OnRspUnSubMarketData(ref p) => {
                     loge!("ctp", "unsubmarketdata res: {}", p.p_rsp_info.unwrap().ErrorMsg.to_str_0());
@@ -371,11 +398,13 @@ impl Ctp {
                 OnFrontDisconnected(p) => {
                     if *self.need_reconnect_td.lock().unwrap() {
                         loge!("ctp", "td disconnected, try again..");
+                        raise(Alert::new(AlertLevel::Warn, AlertKind::Disconnected, self.alert_source("td"), "td front disconnected"));
                         while self.login_td() != 0 {
                             loge!("ctp", "try to reconnect td...");
                             sleep2(1);
                             continue;
                         }
+                        raise(Alert::new(AlertLevel::Warn, AlertKind::Reconnected, self.alert_source("td"), "td logged in again"));
                     } else {
                         loge!("ctp", "td disconnect intentiolly");
                         self.td.lock().unwrap().release();
@@ -390,6 +419,15 @@ impl Ctp {
                     } else {
                         loge!(level: Error, "ctp", "authenticate error id: {:?} error msg: {}, program exit.",
                              error_id, error_msg.to_str_0());
+                        raise(Alert::new(
+                            AlertLevel::Critical,
+                            AlertKind::LoginFailed,
+                            self.alert_source("td"),
+                            format!("authenticate failed {}: {}, program exit", error_id, error_msg.to_str_0()),
+                        ));
+                        if !flush_alerts(dura::from_secs(10)) {
+                            loge!(level: Error, "ctp", "alerts not all sent before the exit");
+                        }
                         std::process::exit(-1);
                     }
                 }
@@ -403,6 +441,12 @@ impl Ctp {
                     } else {
                         loge!(level: Error, "ctp", "td login failed: {error_id}");
                         println!("ctp td login wrong: {}", error_msg.to_str_0());
+                        raise(Alert::new(
+                            AlertLevel::Critical,
+                            AlertKind::LoginFailed,
+                            self.alert_source("td"),
+                            format!("td login failed {}: {}", error_id, error_msg.to_str_0()),
+                        ));
                     }
                 }
                 OnRspUserLogout(ref p) => {
@@ -427,9 +471,7 @@ impl Ctp {
                     }
                 }
                 OnRspQryInvestorPosition(ref p) => {
-                    if let Some(p) = p.p_investor_position {
-                        // self.query_res.update_position(p);
-                    }
+                    self.query_res.update_position(p.p_investor_position, p.b_is_last);
                     if p.b_is_last {
                         sleep2(1);
                     }
@@ -440,14 +482,12 @@ impl Ctp {
                         self.order_his.lock().unwrap().push(p_order);
                     }
                     if p.b_is_last {
-                        let zz = &mut self.order_his.lock().unwrap();
-                        let mut kk = vec![];
-                        kk.append(zz);
-                        self.query_res.send_data_recv(kk);
+                        let kk = std::mem::take(&mut *self.order_his.lock().unwrap());
+                        self.query_res.send_data_recv(kk).await;
                     }
                 }
                 OnRspOrderInsert(ref p) => {
-                    self.query_res.send_data_recv(p.clone());
+                    self.query_res.send_data_recv(p.clone()).await;
                     let g: RspInfoField = p.p_rsp_info.unwrap();
                     if g.ErrorID != 0 {
                         println!(
@@ -456,23 +496,35 @@ impl Ctp {
                             p.p_input_order.unwrap(),
                             p.p_input_order.unwrap().see_string(),
                         );
+                        raise(Alert::new(
+                            AlertLevel::Warn,
+                            AlertKind::OrderRejected,
+                            p.p_input_order.unwrap().InstrumentID.to_str_0().to_string(),
+                            format!("insert error {}: {}", g.ErrorID, g.ErrorMsg.to_str_0()),
+                        ));
                         sleep2(1);
                     }
                 }
                 OnRtnOrder(ref p) => {
                     let p_order: OrderField = p.p_order.unwrap();
-                    self.query_res.send_data_recv(p_order);
+                    self.query_res.send_data_recv(p_order).await;
                 }
                 OnRspQryInstrument(ref p) => {
-                    if p.b_is_last {
-                        let res = p.p_instrument.unwrap();
+                    if let Some(res) = p.p_instrument {
                         self.update_qry_instrument(res);
                     }
+                    if p.b_is_last {
+                        *self.query_res.instrument_queried.write().unwrap() = true;
+                    }
                 }
                 OnRtnTrade(ref p) => {
                     println!("{:?}", p);
                     if let Some(p) = p.p_trade {
                         println!("{}", p.see_string());
+                        if let Some(live_fill) = ApiConvert::<LiveFill>::api_convert(p) {
+                            loge!("ctp", "{}", live_fill.to_log());
+                        }
+                        self.query_res.send_data_recv(p).await;
                     }
                 }
                 OnRtnInstrumentStatus(ref p) => {
@@ -488,41 +540,36 @@ impl Ctp {
         }
     }
 
+    /// Sends every order of the strategy in the order it was made, the hops it passed
+    /// since the callback that caused it are logged with the result of `req_order`.
     pub fn start_spy_on_data_send(&self, trade_api: TradeApi) -> Option<()> {
         let contract = trade_api.contract;
+        let stra = trade_api.stra_id;
         let instrumentid = contract.into_istm_id();
-        let ticker = self.query_res.contract_ticker_map.get(&instrumentid)?.extract_ticker()?.0;
+        let ticker = self.query_res.contract_ticker_map.read().unwrap().get(&instrumentid)?.extract_ticker()?.0;
         loge!("spy", "ctp start holder nitification: {}", contract);
-        loop {
-            let (mut guard, is_started) = trade_api
-                .data_send
-                .wait_or_exit_vec(&format!("ctp stop holder notification: {}", contract));
-            if !is_started { 
-                break; 
-            }
-            let mut order_send = guard.clone();
-            guard.clear();
-            drop(guard);
-            let Some(order_send) = order_send.pop_back() else {
-                continue;
-            };
+        let info = format!("ctp stop holder notification: {}", contract);
+        trade_api.data_send.serve(&info, |Stamped { data: order_send, mut stamp }| {
             if order_send.id.is_empty() {
                 loge!(ticker, "windows wrong: somehow be notified when start tradeapi");
-                continue;
+                return;
             }
             loge!(ticker, "ctp get a order_action_price notify: {:?}", order_send);
             let Some(mut order) = OrderSendWithAcco {
                 contract: &instrumentid,
                 invester_id: &self.ca.account,
-                order_input: order_send.clone(),
+                order_input: order_send,
                 broker_id: self.ca.broker_id.as_str(),
                 account: self.ca.account.as_str(),
             }.api_convert() else { 
-                continue;
+                return;
             };
             let req_order_res = self.req_order(&mut order);
+            stamp.mark(Hop::Req);
             loge!(ticker, "ctp req a order, res: {req_order_res} -- {:?}", order);
-        }
+            loge!(ticker, "latency {}", stamp);
+            LATENCY.record(&stra, contract, &stamp);
+        });
         Some(())
     }
 
@@ -541,20 +588,52 @@ impl CtpApi {
         let (contract_data_receive_map, contract_ticker_map) = trade_api_vec
             .into_iter()
             .fold((hm::new(), hm::new()), |mut accu, trade_api| {
+                TICK_WATCH.watch(trade_api.contract);
                 let istm_id = trade_api.contract.into_istm_id();
                 accu.0.insert(trade_api.data_recv_id.clone(), trade_api.data_recv.clone());
                 accu.1.insert(istm_id, trade_api.contract);
                 accu
             });
         let query_res = CtpQueryRes {
-            contract_data_receive_map,
-            contract_ticker_map,
+            contract_data_receive_map: RwLock::new(contract_data_receive_map),
+            contract_ticker_map: RwLock::new(contract_ticker_map),
             ..Default::default()
         };
         let ctp = Ctp::new("./data", &account, query_res).pip(Arc::new);
         CtpApi { ctp }
     }
 
+    /// Clean market data ticks before they reach the strategies.
+    pub fn set_tick_clean(&self, config: TickCleanConfig) {
+        *self.ctp.query_res.tick_cleaner.write().unwrap() = Some(TickCleanerSet::new(config));
+    }
+
+    /// Every futures contract of the front, from an instrument query. Empty when the query
+    /// is not answered within `wait_secs`.
+    pub fn query_contracts(&self, wait_secs: u64) -> Vec<String> {
+        if self.ctp.req_qry_instrument_all() != 0 {
+            return vec![];
+        }
+        for _ in 0..wait_secs {
+            sleep2(1);
+            if *self.ctp.query_res.instrument_queried.read().unwrap() {
+                return self.ctp.query_res.instrument_info
+                    .read()
+                    .unwrap()
+                    .values()
+                    .filter(|x| x.ProductClass == THOST_FTDC_PC_Futures as i8)
+                    .map(|x| x.InstrumentID.to_str_0().to_string())
+                    .sorted()
+                    .collect_vec();
+            }
+        }
+        vec![]
+    }
+
+    pub fn tick_clean_config(&self) -> Option<TickCleanConfig> {
+        self.ctp.query_res.tick_cleaner.read().unwrap().as_ref().map(|x| x.config.clone())
+    }
+
     pub fn init_service(&self) {
         *self.ctp.need_reconnect_md.lock().unwrap() = true;
         *self.ctp.need_reconnect_td.lock().unwrap() = true;
@@ -619,50 +698,150 @@ impl ServiceApi for CtpApi {
         self.logout();
         Ok(())
     }
+
+    fn attach_trade_api(&self, trade_api: Vec<TradeApi>) -> Result<()> {
+        let query_res = &self.ctp.query_res;
+        let mut contract_new = vec![];
+        {
+            let mut data_recv_map = query_res.contract_data_receive_map.write().unwrap();
+            let mut contract_ticker_map = query_res.contract_ticker_map.write().unwrap();
+            for x in trade_api.iter() {
+                data_recv_map.insert(x.data_recv_id.clone(), x.data_recv.clone());
+                if contract_ticker_map.insert(x.contract.into_istm_id(), x.contract).is_none() {
+                    TICK_WATCH.watch(x.contract);
+                    contract_new.push(x.contract.to_string());
+                }
+            }
+        }
+        // pipes not started yet are served by the next `start`
+        let running = trade_api
+            .into_iter()
+            .filter(|x| x.data_send.is_started())
+            .collect_vec();
+        if !running.is_empty() {
+            self.start_spy_on_data_send(running);
+            if !contract_new.is_empty() {
+                self.ctp.subscribe_market_data(contract_new);
+            }
+        }
+        Ok(())
+    }
+
+    fn detach_trade_api(&self, trade_api: Vec<TradeApi>) -> Result<()> {
+        let query_res = &self.ctp.query_res;
+        let contract_gone = {
+            let mut data_recv_map = query_res.contract_data_receive_map.write().unwrap();
+            for x in trade_api.iter() {
+                data_recv_map.remove(&x.data_recv_id);
+            }
+            trade_api
+                .iter()
+                .map(|x| x.contract)
+                .unique()
+                .filter(|&c| !data_recv_map.keys().any(|k| k.tick_data_id == c))
+                .collect_vec()
+        };
+        {
+            let mut contract_ticker_map = query_res.contract_ticker_map.write().unwrap();
+            for c in contract_gone.iter() {
+                TICK_WATCH.unwatch(c);
+                contract_ticker_map.remove(&c.into_istm_id());
+            }
+        }
+        if !contract_gone.is_empty() && trade_api.iter().any(|x| x.data_send.is_started()) {
+            self.ctp.un_subscribe_market_data(contract_gone.iter().map(|x| x.to_string()).collect_vec());
+        }
+        Ok(())
+    }
 }
 
 
-pub async fn run_ctp<T: ServiceApi>(running_api: RunningApi<T, CtpApi>) {
-    use super::time_manager::*;
+/// `run_ctp_with_scheduler` on the sessions of the tickers traded, as `config.scheduler` sets them.
+pub async fn run_ctp<T: ServiceApi>(running_api: RunningApi<T, CtpApi>, config: LiveConfig) {
+    let scheduler = live_scheduler(&running_api, config);
+    run_ctp_with_scheduler(running_api, scheduler).await
+}
+
+/// Spawns the `AlertBus` of `config` and makes the scheduler of the tickers traded.
+pub fn live_scheduler<T>(running_api: &RunningApi<T, CtpApi>, config: LiveConfig) -> SessionScheduler {
+    AlertBus::new(config.alert).spawn();
+    let tickers = running_api.trade_api.iter().map(|x| x.ticker).collect_vec();
+    SessionScheduler::new(tickers, config.scheduler)
+}
 
+/// Logs in on `Start` of every session and out on `Stop`, a failed start is tried again on
+/// `Auction` and `Open`. The settlement is confirmed again on `Auction`, the tick watch is
+/// reset on `Start` and the latency is logged on `EndOfDay`, the other tasks of `scheduler`
+/// run as they are.
+pub async fn run_ctp_with_scheduler<T: ServiceApi>(running_api: RunningApi<T, CtpApi>, scheduler: SessionScheduler) {
     let mut running_api = running_api;
     running_api.init().unwrap();
-    let mut time_manager = TimeManager::default();
-    let sleep_n = 100;
-    for _ in 0..10000 {
-        match time_manager.get_state() {
-            RunningAction::StartToRun(target_state) => {
-                loge!("ctp", "Start running");
-                if let Ok(()) = running_api.start() {
-                    time_manager.last_running_state = target_state;
-                }
-                sleep2(sleep_n)
+    super::control::set_current_ctp(&running_api.service_api.ctp);
+    spawn_latency_log(std::time::Duration::from_secs(60));
+    TICK_WATCH.set_calendar(scheduler.config.calendar.clone());
+    spawn_stale_check(60, std::time::Duration::from_secs(30));
+    let mut scheduler = scheduler
+        .with_task(SessionPhase::Start, "tick watch", |_| TICK_WATCH.reset())
+        .with_task(SessionPhase::EndOfDay, "latency", |_| LATENCY.log());
+    let mut is_running = false;
+    if scheduler.should_run(chrono::Local::now().naive_local()) {
+        is_running = start_session(&mut running_api, &mut scheduler);
+    }
+    loop {
+        let Some(event) = scheduler.wait_next() else {
+            loge!(level: Warn, "ctp", "no session for the tickers traded");
+            sleep2(3600);
+            running_api.sync_trade_api();
+            scheduler.set_tickers(running_api.trade_api.iter().map(|x| x.ticker).collect_vec());
+            continue;
+        };
+        match event.phase {
+            SessionPhase::Start | SessionPhase::Auction | SessionPhase::Open if !is_running => {
+                is_running = start_session(&mut running_api, &mut scheduler);
             }
-            RunningAction::StopToRun(target_state) => {
+            SessionPhase::Auction => {
+                running_api.service_api.ctp.settlement_info_confirm();
+            }
+            SessionPhase::Stop if is_running => {
                 loge!("ctp", "Stop running");
-                running_api.stop().unwrap();
+                running_api.sync_trade_api();
+                if let Err(e) = running_api.stop() {
+                    loge!(level: Error, "ctp", "stop failed: {}", e);
+                }
+                let tick_clean_config = running_api.service_api.tick_clean_config();
                 running_api.service_api = CtpApi::new(
                     running_api.service_api.ctp.ca.clone(), 
                     running_api.trade_api.clone()
                 );
-                time_manager.last_running_state = target_state;
-                sleep2(sleep_n)
-            }
-            RunningAction::Sleep(time_sleep, msg) => {
-                loge!("ctp", "{}", msg);
-                sleep2(time_sleep);
-                // sleep2(sleep_n);
-            }
-            RunningAction::Impossible => {
-                loge!("ctp", "an impossible time service action");
-                sleep2(sleep_n);
+                if let Some(config) = tick_clean_config {
+                    running_api.service_api.set_tick_clean(config);
+                }
+                super::control::set_current_ctp(&running_api.service_api.ctp);
+                is_running = false;
             }
+            _ => {}
+        }
+    }
+}
+
+fn start_session<T: ServiceApi>(running_api: &mut RunningApi<T, CtpApi>, scheduler: &mut SessionScheduler) -> bool {
+    loge!("ctp", "Start running");
+    running_api.sync_trade_api();
+    scheduler.set_tickers(running_api.trade_api.iter().map(|x| x.ticker).collect_vec());
+    match running_api.start() {
+        Ok(()) => true,
+        Err(e) => {
+            loge!(level: Error, "ctp", "start failed: {}", e);
+            raise(Alert::new(AlertLevel::Critical, AlertKind::LoginFailed, "ctp", format!("start failed: {}", e)));
+            false
         }
-        // self.save_dil();
     }
 }
 
-pub fn running_api_ctp(stra_api: StraApi, account: CtpAccountConfig) -> RunningApi<StraApi, CtpApi> {
+/// `stra_api` is kept behind an `Arc`, share it with a `StraManager` to attach strategies
+/// while it runs.
+pub fn running_api_ctp(stra_api: impl Into<Arc<StraApi>>, account: CtpAccountConfig) -> RunningApi<Arc<StraApi>, CtpApi> {
+    let stra_api = stra_api.into();
     let trade_api_vec = stra_api.get_trade_api_vec();
     let ctp_api = CtpApi::new(account, trade_api_vec.clone());
     RunningApi {
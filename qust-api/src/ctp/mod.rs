@@ -3,11 +3,16 @@
 pub(super) mod api;
 pub(super) mod type_bridge;
 pub(super) mod utiles;
-pub(super) mod time_manager;
 pub mod ctp_wrapper;
 pub mod config;
+pub mod recorder;
+pub mod control;
+pub mod alert;
 
 pub mod prelude {
     pub use super::ctp_wrapper::*;
     pub use super::config::*;
+    pub use super::recorder::*;
+    pub use super::control::*;
+    pub use super::alert::*;
 }
\ No newline at end of file
@@ -0,0 +1,352 @@
+use qust::prelude::*;
+use qust::std_prelude::*;
+use qust_io::prelude::GenDi;
+use serde::{ Serialize, Deserialize };
+use std::sync::mpsc::{ channel, Receiver, RecvTimeoutError, Sender };
+use std::time::{ Duration, Instant };
+use super::config::{ get_config_from, CtpAccountConfig, TracingConfig };
+use super::ctp_wrapper::CtpApi;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecorderConfig {
+    pub ctp_account_config: CtpAccountConfig,
+    /// contracts like `rb2501`, one per ticker since the store is keyed by ticker
+    pub contracts: Vec<String>,
+    /// record every futures contract of the front, subscribed with `subsecribe_market_data_all`.
+    /// Ticks are stored by contract then, a ticker has several contracts trading. `contracts`
+    /// are recorded alone when the instrument query is not answered.
+    #[serde(default)]
+    pub all_contracts: bool,
+    /// root of the `GenDi` store
+    pub data_path: String,
+    #[serde(default = "default_batch_size")]
+    pub batch_size: usize,
+    /// seconds between two flushes of a partial batch
+    #[serde(default = "default_flush_secs")]
+    pub flush_secs: u64,
+    #[serde(default = "default_tick_clean")]
+    pub tick_clean: TickCleanConfig,
+    #[serde(default)]
+    pub tracing_config: TracingConfig,
+    #[serde(default)]
+    pub scheduler: SchedulerConfig,
+    #[serde(default)]
+    pub alert: AlertConfig,
+}
+
+fn default_batch_size() -> usize {
+    2000
+}
+
+fn default_flush_secs() -> u64 {
+    30
+}
+
+fn default_tick_clean() -> TickCleanConfig {
+    TickCleanConfig { volume: VolumeMode::Cumulative, ..Default::default() }
+}
+
+impl RecorderConfig {
+    pub fn from_args() -> anyhow::Result<Self> {
+        get_config_from("recorder.toml")
+    }
+}
+
+enum RecorderMsg {
+    Tick(sstr, TickData),
+    /// a session closed, true for the close that ends the trading day
+    SessionClose(bool),
+}
+
+/// Cleans ticks per contract and appends them to the tick store in batches,
+/// a batch never spans two trading days, the night session is in the batch of the next one.
+struct TickWriter {
+    gen_di: GenDi,
+    batch_size: usize,
+    by_contract: bool,
+    cleaners: hm<sstr, TickCleaner>,
+    batches: hm<sstr, (da, PriceTick)>,
+    tick_clean: TickCleanConfig,
+    calendar: TradingCalendar,
+}
+
+impl TickWriter {
+    fn push(&mut self, contract: sstr, tick_data: TickData) {
+        let Some((ticker, _)) = contract.extract_ticker() else {
+            loge!("ctp", "recorder can not map contract {} to a ticker", contract);
+            return;
+        };
+        let tick_clean = &self.tick_clean;
+        let calendar = &self.calendar;
+        let Some(tick_data) = self.cleaners
+            .entry(contract)
+            .or_insert_with(|| {
+                let mut cleaner = TickCleaner::new(ticker, tick_clean.clone());
+                cleaner.calendar = calendar.clone();
+                cleaner
+            })
+            .clean(tick_data)
+        else {
+            return;
+        };
+        let date = self.calendar.trading_day(tick_data.t);
+        if let Some((batch_date, _)) = self.batches.get(contract) {
+            if *batch_date != date {
+                self.flush_one(contract);
+            }
+        }
+        let batch = self.batches
+            .entry(contract)
+            .or_insert_with(|| (date, PriceTick::with_capacity(self.batch_size)));
+        batch.1.update(&tick_data);
+        if batch.1.t.len() >= self.batch_size {
+            self.flush_one(contract);
+        }
+    }
+
+    fn flush_one(&mut self, contract: sstr) {
+        let Some((date, price_tick)) = self.batches.remove(contract) else {
+            return;
+        };
+        if price_tick.t.is_empty() {
+            return;
+        }
+        let key = if self.by_contract {
+            contract.to_string()
+        } else {
+            contract.extract_ticker().unwrap().0.to_string()
+        };
+        let appended = self.gen_di.tick_store().append_segment(&key, date, &price_tick);
+        match appended {
+            Ok(n) => loge!("ctp", "recorder {} {}: {} ticks appended", contract, date, n),
+            Err(e) => {
+                loge!(level: Error, "ctp", "recorder {} {}: append failed, {}", contract, date, e);
+                self.batches.insert(contract, (date, price_tick));
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        let contracts = self.batches.keys().cloned().collect_vec();
+        contracts.into_iter().for_each(|x| self.flush_one(x));
+    }
+
+    /// Flush everything and log the quality of the trading days that are over, the segments
+    /// of the day are compacted at its end.
+    fn roll_over(&mut self, end_of_day: bool) {
+        self.flush();
+        if end_of_day {
+            match self.gen_di.tick_store().compact_all() {
+                Ok(n) => loge!("ctp", "recorder: {} days of ticks compacted", n),
+                Err(e) => loge!(level: Error, "ctp", "recorder: compaction failed, {}", e),
+            }
+        }
+        for (contract, cleaner) in self.cleaners.iter_mut() {
+            let reports = if end_of_day { cleaner.finish() } else { std::mem::take(&mut cleaner.reports) };
+            for quality in reports {
+                loge!("ctp", "recorder tick quality {}: {:?}", contract, quality);
+            }
+        }
+    }
+}
+
+pub struct Recorder {
+    pub config: RecorderConfig,
+}
+
+impl Recorder {
+    pub fn new(config: RecorderConfig) -> Self {
+        Self { config }
+    }
+
+    /// The contracts share one pipe and one reader, ticks are never coalesced.
+    fn trade_api_vec(contracts: &[String], data_recv: &NotifyDataRecv) -> Vec<TradeApi> {
+        contracts
+            .iter()
+            .filter_map(|contract| {
+                let contract: sstr = Box::leak(contract.clone().into_boxed_str());
+                let ticker = contract.extract_ticker()?.0;
+                TradeApi {
+                    contract,
+                    ticker,
+                    stra_id: "recorder".to_string(),
+                    data_recv_id: DataRecvId {
+                        tick_data_id: contract.to_string(),
+                        order_return_id: format!("{:0>width$}", "", width = ORDER_RET_ID_LEN),
+                    },
+                    data_send: Default::default(),
+                    data_recv: data_recv.clone(),
+                }
+                .pip(Some)
+            })
+            .collect_vec()
+    }
+
+    fn spawn_reader(data_recv: &NotifyDataRecv, sender: &Sender<RecorderMsg>) {
+        data_recv.start();
+        let data_recv = data_recv.clone();
+        let sender = sender.clone();
+        thread::spawn(move || {
+            data_recv.serve("recorder stop", |data| {
+                for x in data {
+                    if let DataRecv::TickData(c, tick_data) = x.data {
+                        if sender.send(RecorderMsg::Tick(c, tick_data)).is_err() {
+                            data_recv.stop();
+                            return;
+                        }
+                    }
+                }
+            });
+        });
+    }
+
+    /// Attaches every futures contract of the front and subscribes them all.
+    fn record_all(&self, ctp_api: &CtpApi, data_recv: &NotifyDataRecv) {
+        let contracts = ctp_api.query_contracts(30);
+        if contracts.is_empty() {
+            loge!(level: Error, "ctp", "recorder: no answer to the instrument query, recording {:?}", self.config.contracts);
+            return;
+        }
+        let contracts_new = contracts
+            .into_iter()
+            .filter(|x| !self.config.contracts.contains(x))
+            .collect_vec();
+        let trade_api_vec = Self::trade_api_vec(&contracts_new, data_recv);
+        let n = trade_api_vec.len() + self.config.contracts.len();
+        let _ = ctp_api.attach_trade_api(trade_api_vec);
+        ctp_api.ctp.subsecribe_market_data_all();
+        loge!("ctp", "recorder records all {} contracts", n);
+    }
+
+    fn start_session(&self, sender: &Sender<RecorderMsg>) -> Option<(CtpApi, NotifyDataRecv)> {
+        loge!("ctp", "recorder start, {} contracts", self.config.contracts.len());
+        let pipe_config = PipeConfig { capacity: 1 << 16, tick_flow: TickFlow::Queue };
+        let data_recv: NotifyDataRecv = Arc::new(DataRecvPipe::with_config(pipe_config));
+        let trade_api_vec = Self::trade_api_vec(&self.config.contracts, &data_recv);
+        let ctp_api = CtpApi::new(self.config.ctp_account_config.clone(), trade_api_vec);
+        Self::spawn_reader(&data_recv, sender);
+        match ctp_api.start(vec![]) {
+            Ok(()) => {
+                if self.config.all_contracts {
+                    self.record_all(&ctp_api, &data_recv);
+                }
+                Some((ctp_api, data_recv))
+            }
+            Err(e) => {
+                loge!(level: Error, "ctp", "recorder start failed: {}", e);
+                raise(Alert::new(AlertLevel::Critical, AlertKind::LoginFailed, "recorder", format!("start failed: {}", e)));
+                data_recv.stop();
+                None
+            }
+        }
+    }
+
+    fn spawn_writer(&self, receiver: Receiver<RecorderMsg>) -> thread::JoinHandle<()> {
+        let mut writer = TickWriter {
+            gen_di: GenDi(Box::leak(self.config.data_path.clone().into_boxed_str())),
+            batch_size: self.config.batch_size.max(1),
+            by_contract: self.config.all_contracts,
+            cleaners: hm::new(),
+            batches: hm::new(),
+            tick_clean: self.config.tick_clean.clone(),
+            calendar: self.config.scheduler.calendar.clone(),
+        };
+        let flush_every = Duration::from_secs(self.config.flush_secs.max(1));
+        thread::spawn(move || {
+            let mut last_flush = Instant::now();
+            loop {
+                match receiver.recv_timeout(flush_every) {
+                    Ok(RecorderMsg::Tick(c, tick_data)) => writer.push(c, tick_data),
+                    Ok(RecorderMsg::SessionClose(end_of_day)) => writer.roll_over(end_of_day),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => {
+                        writer.roll_over(true);
+                        break;
+                    }
+                }
+                if last_flush.elapsed() >= flush_every {
+                    writer.flush();
+                    last_flush = Instant::now();
+                }
+            }
+        })
+    }
+
+    /// Runs from `Start` to `Stop` of every session and sleeps outside of them. Every stop
+    /// flushes the batches and drops the ctp connection, the next session starts with a
+    /// fresh one. Disconnects inside a session are handled by the md reconnect of
+    /// `Ctp::start_md`.
+    pub fn run(&self) {
+        AlertBus::new(self.config.alert.clone()).spawn();
+        let (sender, receiver) = channel();
+        let writer = self.spawn_writer(receiver);
+        let tickers = match self.config.all_contracts {
+            true => tickers_all.clone(),
+            false => self.config.contracts.iter().filter_map(|x| Some(x.extract_ticker()?.0)).collect_vec(),
+        };
+        let mut scheduler = SessionScheduler::new(tickers, self.config.scheduler.clone());
+        let mut running: Option<(CtpApi, NotifyDataRecv)> = None;
+        if scheduler.should_run(chrono::Local::now().naive_local()) {
+            running = self.start_session(&sender);
+        }
+        loop {
+            let Some(event) = scheduler.wait_next() else {
+                loge!(level: Error, "ctp", "recorder: no session for {:?}", self.config.contracts);
+                break;
+            };
+            match event.phase {
+                SessionPhase::Start | SessionPhase::Auction | SessionPhase::Open if running.is_none() => {
+                    running = self.start_session(&sender);
+                }
+                SessionPhase::Stop => {
+                    loge!("ctp", "recorder session close");
+                    if let Some((ctp_api, data_recv)) = running.take() {
+                        let _ = ctp_api.stop(vec![]);
+                        data_recv.stop();
+                    }
+                    let end_of_day = event.part == SessionPart::Day;
+                    let _ = sender.send(RecorderMsg::SessionClose(end_of_day));
+                }
+                _ => {}
+            }
+            if writer.is_finished() {
+                loge!(level: Error, "ctp", "recorder writer exited");
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn night_and_day_session_go_to_one_trading_day() {
+        let mut writer = TickWriter {
+            gen_di: GenDi("recorder_test"),
+            batch_size: 1000,
+            by_contract: false,
+            cleaners: hm::new(),
+            batches: hm::new(),
+            tick_clean: default_tick_clean(),
+            calendar: TradingCalendar::default(),
+        };
+        let now = |x: &str| dt::parse_from_str(x, "%Y-%m-%d %H:%M:%S%.f").unwrap();
+        // times of day as sent by the feed, dated by the clock they arrived at
+        [
+            ("21:00:00.500", "2024-01-05 21:00:00.600", 10.),
+            ("02:29:59.500", "2024-01-06 02:29:59.600", 20.),
+            ("09:00:00.500", "2024-01-08 09:00:00.600", 30.),
+            ("14:59:59.500", "2024-01-08 14:59:59.600", 40.),
+        ]
+        .into_iter()
+        .for_each(|(time, arrived, v)| {
+            let t = feed_time(tt::parse_from_str(time, "%H:%M:%S%.f").unwrap(), now(arrived));
+            writer.push("au2406", TickData { t, c: 500., v, ..Default::default() });
+        });
+        let (date, price_tick) = &writer.batches["au2406"];
+        assert_eq!(*date, da::from_ymd_opt(2024, 1, 8).unwrap());
+        assert_eq!(price_tick.v, vec![10., 10., 10., 10.]);
+    }
+}
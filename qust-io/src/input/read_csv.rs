@@ -1,18 +1,437 @@
 #![allow(async_fn_in_trait)]
+use std::fs::File;
+use std::io::Read;
 use csv::StringRecord;
+use polars::prelude::{ DataFrame, DataType, ParquetReader, PolarsError, SerReader, TimeUnit };
 use qust::prelude::{dt, Di, KlineData, PriceOri, TickData, Tri};
+use crate::transform::pl_util::PlFrom;
 
+/* #region Options */
+/// A column picked by its position or by its header name.
+#[derive(Debug, Clone)]
+pub enum ColKey {
+    Index(usize),
+    Name(String),
+}
 
-trait ReadRecord {
-    type Output;
-    fn read_record(&self, record: &StringRecord) -> Self::Output;
+impl From<usize> for ColKey {
+    fn from(value: usize) -> Self {
+        ColKey::Index(value)
+    }
+}
+
+impl From<&str> for ColKey {
+    fn from(value: &str) -> Self {
+        ColKey::Name(value.to_string())
+    }
+}
+
+impl From<String> for ColKey {
+    fn from(value: String) -> Self {
+        ColKey::Name(value)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum TimeFormat {
+    /// chrono format, a format with `%z` or `%:z` is read as zone aware time
+    Fmt(String),
+    UnixS,
+    UnixMs,
+}
+
+impl Default for TimeFormat {
+    fn default() -> Self {
+        TimeFormat::Fmt("%Y-%m-%dT%H:%M:%S%.f".into())
+    }
+}
+
+impl TimeFormat {
+    /// Zone aware and unix times are shifted to `utc_offset` seconds (UTC when `None`),
+    /// naive times are taken as they are.
+    pub fn parse(&self, s: &str, utc_offset: Option<i32>) -> Option<dt> {
+        let shift = |t: dt| t + chrono::Duration::seconds(utc_offset.unwrap_or(0) as i64);
+        match self {
+            // a naive parse reads `%z` and drops it, so zone aware formats never take it
+            TimeFormat::Fmt(f) if f.contains("%z") || f.contains("%:z") => {
+                chrono::DateTime::parse_from_str(s, f).ok().map(|x| shift(x.naive_utc()))
+            }
+            TimeFormat::Fmt(f) => dt::parse_from_str(s, f).ok(),
+            TimeFormat::UnixS => chrono::DateTime::from_timestamp(s.parse().ok()?, 0).map(|x| shift(x.naive_utc())),
+            TimeFormat::UnixMs => chrono::DateTime::from_timestamp_millis(s.parse().ok()?).map(|x| shift(x.naive_utc())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BadRowPolicy {
+    /// drop the row
+    Skip,
+    /// take unparsable values from the last good row, rows with a bad time are dropped
+    Fill,
+    /// stop at the first bad row
+    Fail,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReadOptions {
+    pub has_header: bool,
+    pub delimiter: u8,
+    pub time_format: TimeFormat,
+    pub utc_offset: Option<i32>,
+    pub bad_row: BadRowPolicy,
+    /// at most this many bad rows are kept in the report, all of them are counted
+    pub max_report: usize,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        Self {
+            has_header: true,
+            delimiter: b',',
+            time_format: TimeFormat::default(),
+            utc_offset: None,
+            bad_row: BadRowPolicy::Skip,
+            max_report: 1000,
+        }
+    }
+}
+/* #endregion */
+
+/* #region Report */
+#[derive(Debug, Clone)]
+pub struct BadRow {
+    /// 1 based, counting the header
+    pub line: usize,
+    pub column: String,
+    pub value: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReadReport {
+    pub rows: usize,
+    pub read: usize,
+    pub skipped: usize,
+    pub filled: usize,
+    pub bad: Vec<BadRow>,
+}
+
+impl ReadReport {
+    pub fn is_clean(&self) -> bool {
+        self.skipped == 0 && self.filled == 0
+    }
 }
 
+#[derive(Debug)]
+pub enum ReadError {
+    Io(std::io::Error),
+    Http(reqwest::Error),
+    Polars(PolarsError),
+    MissingColumn(String),
+    BadRow(BadRow),
+}
+
+impl std::fmt::Display for ReadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReadError::Io(e) => write!(f, "io error: {e}"),
+            ReadError::Http(e) => write!(f, "http error: {e}"),
+            ReadError::Polars(e) => write!(f, "parquet error: {e}"),
+            ReadError::MissingColumn(c) => write!(f, "missing column: {c}"),
+            ReadError::BadRow(x) => write!(f, "bad row at line {}, column {}: {:?}, {}", x.line, x.column, x.value, x.reason),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+impl From<std::io::Error> for ReadError {
+    fn from(value: std::io::Error) -> Self {
+        ReadError::Io(value)
+    }
+}
+
+impl From<reqwest::Error> for ReadError {
+    fn from(value: reqwest::Error) -> Self {
+        ReadError::Http(value)
+    }
+}
+
+impl From<PolarsError> for ReadError {
+    fn from(value: PolarsError) -> Self {
+        ReadError::Polars(value)
+    }
+}
+/* #endregion */
+
+/* #region Row parser */
+/// Turns raw rows into `(time, values)`, the first key is the time column.
+struct RowParser<'a> {
+    names: Vec<&'static str>,
+    keys: Vec<ColKey>,
+    idx: Vec<usize>,
+    options: &'a ReadOptions,
+    prev: Option<Vec<f32>>,
+    report: ReadReport,
+}
+
+impl<'a> RowParser<'a> {
+    fn new(keys: Vec<(&'static str, ColKey)>, options: &'a ReadOptions) -> Self {
+        let (names, keys): (Vec<_>, Vec<_>) = keys.into_iter().unzip();
+        Self { names, keys, idx: vec![], options, prev: None, report: ReadReport::default() }
+    }
+
+    fn resolve(&mut self, headers: Option<&StringRecord>) -> Result<(), ReadError> {
+        self.idx = self.keys
+            .iter()
+            .map(|key| match key {
+                ColKey::Index(i) => Ok(*i),
+                ColKey::Name(name) => headers
+                    .and_then(|h| h.iter().position(|x| x.trim() == name.as_str()))
+                    .ok_or_else(|| ReadError::MissingColumn(name.clone())),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(())
+    }
+
+    fn bad(&mut self, line: usize, i: usize, value: &str, reason: &str) -> Result<(), ReadError> {
+        let bad_row = BadRow {
+            line,
+            column: self.names[i].to_string(),
+            value: value.to_string(),
+            reason: reason.to_string(),
+        };
+        if self.options.bad_row == BadRowPolicy::Fail {
+            return Err(ReadError::BadRow(bad_row));
+        }
+        if self.report.bad.len() < self.options.max_report {
+            self.report.bad.push(bad_row);
+        }
+        Ok(())
+    }
+
+    fn accept(
+        &mut self,
+        line: usize,
+        t: Result<dt, (String, &'static str)>,
+        values: Vec<Result<f32, (String, &'static str)>>,
+    ) -> Result<Option<(dt, Vec<f32>)>, ReadError> {
+        self.report.rows += 1;
+        let t = match t {
+            Ok(t) => t,
+            Err((value, reason)) => {
+                self.bad(line, 0, &value, reason)?;
+                self.report.skipped += 1;
+                return Ok(None);
+            }
+        };
+        let mut res = Vec::with_capacity(values.len());
+        let mut is_bad = false;
+        for (i, value) in values.into_iter().enumerate() {
+            match value {
+                Ok(v) => res.push(v),
+                Err((value, reason)) => {
+                    self.bad(line, i + 1, &value, reason)?;
+                    is_bad = true;
+                    res.push(f32::NAN);
+                }
+            }
+        }
+        if is_bad {
+            match (self.options.bad_row, &self.prev) {
+                (BadRowPolicy::Fill, Some(prev)) => {
+                    res.iter_mut().zip(prev.iter()).for_each(|(x, p)| if x.is_nan() { *x = *p });
+                    self.report.filled += 1;
+                }
+                _ => {
+                    self.report.skipped += 1;
+                    return Ok(None);
+                }
+            }
+        }
+        self.prev = Some(res.clone());
+        self.report.read += 1;
+        Ok(Some((t, res)))
+    }
+
+    fn accept_record(&mut self, line: usize, record: &StringRecord) -> Result<Option<(dt, Vec<f32>)>, ReadError> {
+        let get = |i: usize| record.get(i).map(|x| x.trim());
+        let t = match get(self.idx[0]) {
+            Some(s) => self.options.time_format
+                .parse(s, self.options.utc_offset)
+                .ok_or((s.to_string(), "failed to parse time")),
+            None => Err((String::new(), "missing field")),
+        };
+        let values = self.idx[1..]
+            .iter()
+            .map(|i| match get(*i) {
+                Some(s) => s.parse::<f32>().map_err(|_| (s.to_string(), "failed to parse number")),
+                None => Err((String::new(), "missing field")),
+            })
+            .collect();
+        self.accept(line, t, values)
+    }
+}
+
+/// Splits a byte stream into csv records, only complete lines are parsed so a chunk
+/// boundary never cuts a row. Quoted fields must not contain line breaks.
+struct CsvFeed<'a, F> {
+    parser: RowParser<'a>,
+    line: usize,
+    rest: Vec<u8>,
+    f: F,
+}
+
+impl<'a, F: FnMut(dt, &[f32])> CsvFeed<'a, F> {
+    fn new(parser: RowParser<'a>, f: F) -> Self {
+        Self { parser, line: 0, rest: vec![], f }
+    }
+
+    fn push(&mut self, chunk: &[u8]) -> Result<(), ReadError> {
+        self.rest.extend_from_slice(chunk);
+        if let Some(end) = self.rest.iter().rposition(|x| *x == b'\n') {
+            let block = self.rest.drain(..=end).collect::<Vec<u8>>();
+            self.parse_block(&block)?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<ReadReport, ReadError> {
+        let block = std::mem::take(&mut self.rest);
+        self.parse_block(&block)?;
+        Ok(self.parser.report)
+    }
+
+    fn parse_block(&mut self, block: &[u8]) -> Result<(), ReadError> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .delimiter(self.parser.options.delimiter)
+            .from_reader(block);
+        for record in reader.records() {
+            self.line += 1;
+            let record = match record {
+                Ok(record) => record,
+                Err(e) => {
+                    self.parser.report.rows += 1;
+                    self.parser.report.skipped += 1;
+                    self.parser.bad(self.line, 0, "", &e.to_string())?;
+                    continue;
+                }
+            };
+            if self.parser.idx.is_empty() {
+                if self.parser.options.has_header {
+                    self.parser.resolve(Some(&record))?;
+                    continue;
+                }
+                self.parser.resolve(None)?;
+            }
+            if let Some((t, values)) = self.parser.accept_record(self.line, &record)? {
+                (self.f)(t, &values);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_remote(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+/// Streams rows of a local csv, a remote csv or a local parquet file into `f`.
+async fn read_rows<F: FnMut(dt, &[f32])>(
+    path: &str,
+    keys: Vec<(&'static str, ColKey)>,
+    options: &ReadOptions,
+    f: F,
+) -> Result<ReadReport, ReadError> {
+    let parser = RowParser::new(keys, options);
+    if path.ends_with(".parquet") && !is_remote(path) {
+        return read_parquet_rows(path, parser, f);
+    }
+    let mut feed = CsvFeed::new(parser, f);
+    if is_remote(path) {
+        let mut response = reqwest::get(path).await?.error_for_status()?;
+        while let Some(chunk) = response.chunk().await? {
+            feed.push(&chunk)?;
+        }
+    } else {
+        let mut file = File::open(path)?;
+        let mut buf = vec![0u8; 1 << 20];
+        loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            feed.push(&buf[..n])?;
+        }
+    }
+    feed.finish()
+}
+
+/// Parquet columns are looked up by name, index keys refer to the column order.
+fn read_parquet_rows<F: FnMut(dt, &[f32])>(
+    path: &str,
+    mut parser: RowParser,
+    mut f: F,
+) -> Result<ReadReport, ReadError> {
+    let df = ParquetReader::new(File::open(path)?).finish()?;
+    let headers = StringRecord::from(df.get_column_names_str());
+    parser.resolve(Some(&headers))?;
+    let columns = df.get_columns();
+    let t_col = &columns[parser.idx[0]];
+    let t_vec: Vec<Option<dt>> = match t_col.dtype() {
+        DataType::Datetime(_, _) => t_col
+            .cast(&DataType::Datetime(TimeUnit::Milliseconds, None))?
+            .datetime()?
+            .to_vec()
+            .into_iter()
+            .map(|x| x.map(dt::pl_from))
+            .collect(),
+        _ => t_col
+            .cast(&DataType::String)?
+            .str()?
+            .into_iter()
+            .map(|x| x.and_then(|s| parser.options.time_format.parse(s, parser.options.utc_offset)))
+            .collect(),
+    };
+    let value_cols = parser.idx[1..]
+        .iter()
+        .map(|i| columns[*i].cast(&DataType::Float32))
+        .collect::<Result<Vec<_>, _>>()?;
+    let value_vecs = value_cols
+        .iter()
+        .map(|x| Ok(x.f32()?.into_iter().collect::<Vec<_>>()))
+        .collect::<Result<Vec<_>, PolarsError>>()?;
+    for (row, t) in t_vec.into_iter().enumerate() {
+        let t = t.ok_or((String::new(), "null or unparsable time"));
+        let values = value_vecs
+            .iter()
+            .map(|x| x[row].ok_or((String::new(), "null value")))
+            .collect();
+        if let Some((t, values)) = parser.accept(row + 2, t, values)? {
+            f(t, &values);
+        }
+    }
+    Ok(parser.report)
+}
+
+pub fn read_parquet_df(path: &str) -> Result<DataFrame, ReadError> {
+    Ok(ParquetReader::new(File::open(path)?).finish()?)
+}
+/* #endregion */
+
+/* #region Readers */
 pub trait ReadCsv {
     type Output;
     async fn read_csv(&self, path: &str) -> Self::Output;
 }
 
+/// Kline reader, every column is a header name or a position, e.g.
+/// ```ignore
+/// DiReader { t: "datetime", o: "open", h: "high", l: "low", c: "close", v: "volume", options: Default::default() }
+/// ```
 pub struct DiReader<T> {
     pub t: T,
     pub o: T,
@@ -20,75 +439,36 @@ pub struct DiReader<T> {
     pub l: T,
     pub c: T,
     pub v: T,
-    pub t_format: Option<&'static str>,
-    pub has_header: bool,
-}
-
-struct DiReaderRecord {
-    t: usize,
-    o: usize,
-    h: usize,
-    l: usize,
-    c: usize,
-    v: usize,
-    t_format: &'static str,
-}
-
-
-impl ReadRecord for DiReaderRecord {
-    type Output = KlineData;
-    fn read_record(&self, record: &StringRecord) -> Self::Output {
-        KlineData {
-            t: {
-                let t = record[self.t].trim();
-                dt::parse_from_str(t, self.t_format)
-                    .unwrap_or_else(|_| panic!("failed to parse time, provided: {}, format: {}", t, self.t_format))
-            },
-            o: record[self.o].trim().parse().unwrap(),
-            h: record[self.h].trim().parse().unwrap(),
-            l: record[self.l].trim().parse().unwrap(),
-            c: record[self.c].trim().parse().unwrap(),
-            v: record[self.v].trim().parse().unwrap(),
-            ki: Default::default(),
-        }
-    }
+    pub options: ReadOptions,
 }
 
-
-impl ReadCsv for DiReader<usize> {
-    type Output = PriceOri;
+impl<T: Clone + Into<ColKey>> ReadCsv for DiReader<T> {
+    type Output = Result<(PriceOri, ReadReport), ReadError>;
     async fn read_csv(&self, path: &str) -> Self::Output {
-        let di_reader_record = DiReaderRecord {
-            t: self.t,
-            o: self.o,
-            h: self.h,
-            l: self.l,
-            c: self.c,
-            v: self.v,
-            t_format: self.t_format.unwrap_or("%Y-%m-%dT%H:%M:%S%.f"),
-        };
-        let skip_n = if self.has_header { 1 } else { 0 };
+        let keys = vec![
+            ("t", self.t.clone().into()),
+            ("o", self.o.clone().into()),
+            ("h", self.h.clone().into()),
+            ("l", self.l.clone().into()),
+            ("c", self.c.clone().into()),
+            ("v", self.v.clone().into()),
+        ];
         let mut price_ori = PriceOri::with_capacity(100_000);
-        if path.contains("https") {
-            let response = reqwest::get(path).await.unwrap().text().await.unwrap();
-            let mut reader = csv::ReaderBuilder::new()
-                .has_headers(self.has_header)
-                .from_reader(response.as_bytes());
-            for record in reader.records().skip(skip_n) {
-                let record = record.unwrap();
-                let kline_data = di_reader_record.read_record(&record);
-                price_ori.update(&kline_data);
-            }
-        } else {
-            let mut reader = csv::Reader::from_path(path).unwrap();
-            for record in reader.records().skip(skip_n) {
-                let record = record.unwrap();
-                let kline_data = di_reader_record.read_record(&record);
-                price_ori.update(&kline_data);
-            }
-        }
+        let report = read_rows(path, keys, &self.options, |t, x| {
+            let kline_data = KlineData {
+                t,
+                o: x[0],
+                h: x[1],
+                l: x[2],
+                c: x[3],
+                v: x[4],
+                ki: Default::default(),
+            };
+            price_ori.update(&kline_data);
+        })
+        .await?;
         price_ori.shrink_to_fit();
-        price_ori
+        Ok((price_ori, report))
     }
 }
 
@@ -100,79 +480,40 @@ pub struct TickReader<T> {
     pub bid1: T,
     pub ask1_v: T,
     pub bid1_v: T,
-    pub t_format: Option<&'static str>,
-    pub has_header: bool,
-}
-
-struct TickReaderRecord {
-    t: usize,
-    c: usize,
-    v: usize,
-    ask1: usize,
-    bid1: usize,
-    ask1_v: usize,
-    bid1_v: usize,
-    t_format: &'static str,
-}
-
-impl ReadRecord for TickReaderRecord {
-    type Output = TickData;
-    fn read_record(&self, record: &StringRecord) -> Self::Output {
-        TickData {
-            t: {
-                let t = record[self.t].trim();
-                dt::parse_from_str(t, self.t_format)
-                    .unwrap_or_else(|_| panic!("failed to parse time, provided: {}, format: {}", t, self.t_format))
-            },
-            c: record[self.c].trim().parse().unwrap(),
-            v: record[self.v].trim().parse().unwrap(),
-            ask1: record[self.ask1].trim().parse().unwrap(),
-            bid1: record[self.bid1].trim().parse().unwrap(),
-            ask1_v: record[self.ask1_v].trim().parse().unwrap(),
-            bid1_v: record[self.bid1_v].trim().parse().unwrap(),
-            ct: 1,
-        }
-
-    }
+    pub options: ReadOptions,
 }
 
-
-impl ReadCsv for TickReader<usize> {
-    type Output = Vec<TickData>;
+impl<T: Clone + Into<ColKey>> ReadCsv for TickReader<T> {
+    type Output = Result<(Vec<TickData>, ReadReport), ReadError>;
     async fn read_csv(&self, path: &str) -> Self::Output {
-        let tick_reader_record = TickReaderRecord {
-            t: self.t,
-            c: self.c,
-            v: self.v,
-            ask1: self.ask1,
-            bid1: self.bid1,
-            ask1_v: self.ask1_v,
-            bid1_v: self.bid1_v,
-            t_format: self.t_format.unwrap_or("%Y-%m-%dT%H:%M:%S%.f"),
-        };
-        let mut res = Vec::with_capacity(100000);
-        if path.contains("https") {
-            let response = reqwest::get(path).await.unwrap().text().await.unwrap();
-            let mut reader = csv::ReaderBuilder::new()
-                .has_headers(self.has_header)
-                .from_reader(response.as_bytes());
-            for record in reader.records() {
-                let record = record.unwrap();
-                let tick_data = tick_reader_record.read_record(&record);
-                res.push(tick_data);
-            }
-        } else {
-            let mut reader = csv::Reader::from_path(path).unwrap();
-            for record in reader.records() {
-                let record = record.unwrap();
-                let tick_data = tick_reader_record.read_record(&record);
-                res.push(tick_data);
-            }
-        }
+        let keys = vec![
+            ("t", self.t.clone().into()),
+            ("c", self.c.clone().into()),
+            ("v", self.v.clone().into()),
+            ("ask1", self.ask1.clone().into()),
+            ("bid1", self.bid1.clone().into()),
+            ("ask1_v", self.ask1_v.clone().into()),
+            ("bid1_v", self.bid1_v.clone().into()),
+        ];
+        let mut res = Vec::with_capacity(100_000);
+        let report = read_rows(path, keys, &self.options, |t, x| {
+            res.push(TickData {
+                t,
+                c: x[0],
+                v: x[1],
+                ask1: x[2],
+                bid1: x[3],
+                ask1_v: x[4],
+                bid1_v: x[5],
+                ct: 1,
+            });
+        })
+        .await?;
         res.shrink_to_fit();
-        res
+        Ok((res, report))
     }
 }
+/* #endregion */
 
 const remote_kline_url: &str = "https://raw.githubusercontent.com/baiguoname/qust/refs/heads/main/examples/git_test/kline_data.csv";
 const remote_tick_url: &str = "https://raw.githubusercontent.com/baiguoname/qust/refs/heads/main/examples/git_test/tick_data.csv";
@@ -185,10 +526,14 @@ pub async fn read_remote_kline_data() -> Di {
         l: 3,
         c: 4,
         v: 5,
-        t_format: None,
-        has_header: true,
+        options: ReadOptions::default(),
     };
-    di_reader.read_csv(remote_kline_url).await.to_di(qust::prelude::aler, qust::prelude::rl5m.tri_box())
+    di_reader
+        .read_csv(remote_kline_url)
+        .await
+        .unwrap()
+        .0
+        .to_di(qust::prelude::aler, qust::prelude::rl5m.tri_box())
 }
 
 pub async fn read_remote_tick_data() -> Vec<TickData> {
@@ -200,10 +545,27 @@ pub async fn read_remote_tick_data() -> Vec<TickData> {
         bid1: 4,
         ask1_v: 5,
         bid1_v: 6,
-        t_format: None,
-        has_header:true,
+        options: ReadOptions::default(),
     };
     tick_reader
         .read_csv(remote_tick_url)
         .await
+        .unwrap()
+        .0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zone_aware_time_is_shifted_to_utc_offset() {
+        let f = TimeFormat::Fmt("%Y-%m-%d %H:%M:%S%:z".into());
+        let s = "2024-03-01 09:30:00+08:00";
+        let t = |h, m| chrono::NaiveDate::from_ymd_opt(2024, 3, 1).unwrap().and_hms_opt(h, m, 0).unwrap();
+        assert_eq!(f.parse(s, None), Some(t(1, 30)));
+        assert_eq!(f.parse(s, Some(8 * 3600)), Some(t(9, 30)));
+        let naive = TimeFormat::Fmt("%Y-%m-%d %H:%M:%S".into());
+        assert_eq!(naive.parse("2024-03-01 09:30:00", None), Some(t(9, 30)));
+    }
 }
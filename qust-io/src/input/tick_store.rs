@@ -0,0 +1,469 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::{ Path, PathBuf };
+use polars::prelude::*;
+use qust::prelude::{ dt, da, PriceTick, Ticker };
+use qust_ds::prelude::*;
+use serde::{ Deserialize, Serialize };
+use crate::transform::pl_util::PlInto;
+
+pub const TICK_COLS: [&str; 8] = ["t", "c", "v", "ct", "bid1", "ask1", "bid1_v", "ask1_v"];
+const MANIFEST_NAME: &str = "manifest.json";
+
+/* #region Manifest */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TickPartition {
+    pub date: da,
+    pub rows: usize,
+    pub start: dt,
+    pub end: dt,
+}
+
+/// ticker -> compacted partitions sorted by date, kept next to the data so a reader
+/// never has to open files that are out of range. A recorder of all contracts keys them
+/// by contract instead. Segments of a day not compacted yet are not in it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TickManifest {
+    pub tickers: BTreeMap<String, Vec<TickPartition>>,
+}
+
+impl TickManifest {
+    pub fn partitions(&self, ticker: Ticker) -> &[TickPartition] {
+        self.partitions_of(&ticker.to_string())
+    }
+
+    pub fn partitions_of(&self, key: &str) -> &[TickPartition] {
+        self.tickers
+            .get(key)
+            .map(|x| x.as_slice())
+            .unwrap_or_default()
+    }
+
+    pub fn dates(&self, ticker: Ticker) -> Vec<da> {
+        self.partitions(ticker).map(|x| x.date)
+    }
+
+    fn upsert(&mut self, key: &str, partition: TickPartition) {
+        let partitions = self.tickers.entry(key.to_string()).or_default();
+        match partitions.binary_search_by(|x| x.date.cmp(&partition.date)) {
+            Ok(i) => partitions[i] = partition,
+            Err(i) => partitions.insert(i, partition),
+        }
+    }
+}
+/* #endregion */
+
+/* #region TickStore */
+/// Ticks partitioned as `{root}/{key}/{date}.parquet`, one file per trading day. The key is
+/// the ticker, or the contract for a recorder of all contracts, the `*_of` methods take
+/// the key and the others the ticker.
+/// A live recorder writes each flush as a segment `{root}/{key}/{date}/{n}.parquet` and
+/// `compact_to` merges the segments of a day into its partition once the day is over,
+/// readers see the segments as well. Partitions are only ever appended to: ticks at or
+/// before the last stored tick of a day are dropped on ingestion.
+pub struct TickStore {
+    pub root: PathBuf,
+}
+
+impl TickStore {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self { root: root.as_ref().to_path_buf() }
+    }
+
+    pub fn partition_path(&self, ticker: Ticker, date: da) -> PathBuf {
+        self.partition_path_of(&ticker.to_string(), date)
+    }
+
+    pub fn partition_path_of(&self, key: &str, date: da) -> PathBuf {
+        self.root.join(key).join(format!("{date}.parquet"))
+    }
+
+    fn segment_dir(&self, key: &str, date: da) -> PathBuf {
+        self.root.join(key).join(date.to_string())
+    }
+
+    /// Segments of a day not compacted yet, in the order they were written.
+    fn segments(&self, key: &str, date: da) -> Vec<PathBuf> {
+        let mut res = std::fs::read_dir(self.segment_dir(key, date))
+            .map(|x| x.filter_map(|x| x.ok().map(|x| x.path())).collect_vec())
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|x| Some((segment_index(&x)?, x)))
+            .collect_vec();
+        res.sort_by_key(|x| x.0);
+        res.into_iter().map(|x| x.1).collect_vec()
+    }
+
+    /// Days of `key` with segments not compacted yet.
+    pub fn pending_dates(&self, key: &str) -> Vec<da> {
+        let mut res = std::fs::read_dir(self.root.join(key))
+            .map(|x| x.filter_map(|x| x.ok()).collect_vec())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|x| x.file_type().is_ok_and(|x| x.is_dir()))
+            .filter_map(|x| x.file_name().to_str()?.parse::<da>().ok())
+            .collect_vec();
+        res.sort();
+        res
+    }
+
+    /// (date, in the manifest) of every day of `key`, compacted or not.
+    fn days(&self, key: &str) -> Vec<(da, bool)> {
+        let mut res = self.manifest().partitions_of(key).map(|x| (x.date, true));
+        res.extend(self.pending_dates(key).into_iter().map(|x| (x, false)));
+        res.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+        res.dedup_by_key(|x| x.0);
+        res
+    }
+
+    pub fn dates(&self, ticker: Ticker) -> Vec<da> {
+        self.dates_of(&ticker.to_string())
+    }
+
+    /// Days of `key` in the manifest or with segments.
+    pub fn dates_of(&self, key: &str) -> Vec<da> {
+        self.days(key).map(|x| x.0)
+    }
+
+    pub fn manifest(&self) -> TickManifest {
+        std::fs::read_to_string(self.root.join(MANIFEST_NAME))
+            .ok()
+            .and_then(|x| serde_json::from_str(&x).ok())
+            .unwrap_or_default()
+    }
+
+    fn save_manifest(&self, manifest: &TickManifest) -> PolarsResult<()> {
+        let path = self.root.join(MANIFEST_NAME);
+        let path_tmp = path.with_extension("json.tmp");
+        std::fs::write(&path_tmp, serde_json::to_string(manifest).unwrap())?;
+        std::fs::rename(path_tmp, path)?;
+        Ok(())
+    }
+
+    /// Append one day of ticks, returns the number of rows actually written.
+    pub fn append(&self, ticker: Ticker, date: da, price: &PriceTick) -> PolarsResult<usize> {
+        self.append_to(&ticker.to_string(), date, price)
+    }
+
+    /// `append` under any key, the ticks go into the partition of the day at once.
+    pub fn append_to(&self, key: &str, date: da, price: &PriceTick) -> PolarsResult<usize> {
+        let res = self.append_segment(key, date, price)?;
+        self.compact_to(key, date)?;
+        Ok(res)
+    }
+
+    /// Append the ticks as a new segment of the day, nothing written before is read or
+    /// rewritten but the last segment, for its last time. Returns the number of rows written.
+    pub fn append_segment(&self, key: &str, date: da, price: &PriceTick) -> PolarsResult<usize> {
+        let segments = self.segments(key, date);
+        let last = match segments.last() {
+            Some(path) => read_times(path)?.last().copied(),
+            None => self.manifest()
+                .partitions_of(key)
+                .iter()
+                .find(|x| x.date == date)
+                .map(|x| x.end),
+        };
+        let start = match last {
+            Some(t) => price.t.partition_point(|x| *x <= t),
+            None => 0,
+        };
+        if start >= price.t.len() {
+            return Ok(0);
+        }
+        let mut df = price_tick_to_df(price, start..price.t.len())?;
+        let n = segments.last().and_then(|x| segment_index(x)).map_or(0, |x| x + 1);
+        let path = self.segment_dir(key, date).join(format!("{n}.parquet"));
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        write_parquet(&mut df, &path)?;
+        Ok(price.t.len() - start)
+    }
+
+    /// Merge the segments of a day into its partition and record it in the manifest,
+    /// returns the number of rows merged.
+    pub fn compact_to(&self, key: &str, date: da) -> PolarsResult<usize> {
+        let segments = self.segments(key, date);
+        if segments.is_empty() {
+            return Ok(0);
+        }
+        let path = self.partition_path_of(key, date);
+        let mut df = match path.exists() {
+            true => Some(ParquetReader::new(File::open(&path)?).finish()?),
+            false => None,
+        };
+        // the end is taken from the partition itself, so segments a stopped compaction
+        // already merged are not merged twice
+        let mut end = match &df {
+            Some(df) => df_times(df)?.last().copied(),
+            None => None,
+        };
+        let mut merged = 0;
+        for segment in segments.iter() {
+            let df_segment = ParquetReader::new(File::open(segment)?).finish()?;
+            let t = df_times(&df_segment)?;
+            let start = end.map_or(0, |e| t.partition_point(|x| *x <= e));
+            if start >= t.len() {
+                continue;
+            }
+            let df_segment = df_segment.slice(start as i64, t.len() - start);
+            merged += df_segment.height();
+            end = t.last().copied();
+            match df.as_mut() {
+                Some(df) => { df.vstack_mut(&df_segment)?; }
+                None => df = Some(df_segment),
+            }
+        }
+        if let Some(mut df) = df {
+            if merged > 0 {
+                write_parquet(&mut df, &path)?;
+            }
+            let t = df_times(&df)?;
+            if let (Some(start), Some(end)) = (t.first(), t.last()) {
+                let mut manifest = self.manifest();
+                manifest.upsert(key, TickPartition { date, rows: df.height(), start: *start, end: *end });
+                self.save_manifest(&manifest)?;
+            }
+        }
+        std::fs::remove_dir_all(self.segment_dir(key, date))?;
+        Ok(merged)
+    }
+
+    /// `compact_to` every day with segments of every key, returns the number of days.
+    pub fn compact_all(&self) -> PolarsResult<usize> {
+        let keys = std::fs::read_dir(&self.root)
+            .map(|x| x.filter_map(|x| x.ok()).collect_vec())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|x| x.file_type().is_ok_and(|x| x.is_dir()))
+            .filter_map(|x| x.file_name().to_str().map(|x| x.to_string()))
+            .collect_vec();
+        let mut res = 0;
+        for key in keys.iter() {
+            for date in self.pending_dates(key) {
+                self.compact_to(key, date)?;
+                res += 1;
+            }
+        }
+        Ok(res)
+    }
+
+    fn scan_files(&self, files: Vec<PathBuf>) -> PolarsResult<Option<LazyFrame>> {
+        if files.is_empty() {
+            return Ok(None);
+        }
+        let lfs = files
+            .iter()
+            .map(|x| LazyFrame::scan_parquet(x, ScanArgsParquet::default()))
+            .collect::<PolarsResult<Vec<_>>>()?;
+        Ok(Some(concat(lfs, UnionArgs::default())?))
+    }
+
+    /// The partition of a day when in the manifest, then its segments.
+    fn day_files(&self, key: &str, date: da, compacted: bool) -> Vec<PathBuf> {
+        let mut res = if compacted { vec![self.partition_path_of(key, date)] } else { vec![] };
+        res.extend(self.segments(key, date));
+        res
+    }
+
+    /// Lazy scan over the days that match `range`.
+    pub fn scan_dates<T: Fromt<da> + PartialOrd>(
+        &self,
+        ticker: Ticker,
+        range: &ForCompare<T>,
+    ) -> PolarsResult<Option<LazyFrame>> {
+        self.scan_dates_of(&ticker.to_string(), range)
+    }
+
+    pub fn scan_dates_of<T: Fromt<da> + PartialOrd>(
+        &self,
+        key: &str,
+        range: &ForCompare<T>,
+    ) -> PolarsResult<Option<LazyFrame>> {
+        let files = self.days(key)
+            .into_iter()
+            .filter(|x| range.compare_time(&x.0))
+            .flat_map(|(date, compacted)| self.day_files(key, date, compacted))
+            .collect_vec();
+        self.scan_files(files)
+    }
+
+    /// Lazy scan filtered on tick time, partitions out of range are never opened and the
+    /// time filter is pushed down to the parquet row groups.
+    pub fn scan(&self, ticker: Ticker, range: &ForCompare<dt>) -> PolarsResult<Option<LazyFrame>> {
+        self.scan_of(&ticker.to_string(), range)
+    }
+
+    pub fn scan_of(&self, key: &str, range: &ForCompare<dt>) -> PolarsResult<Option<LazyFrame>> {
+        let partitions = self.manifest().partitions_of(key).to_vec();
+        let files = self.days(key)
+            .into_iter()
+            .flat_map(|(date, compacted)| {
+                let in_range = partitions
+                    .iter()
+                    .find(|x| x.date == date)
+                    .is_none_or(|x| overlaps(range, x.start, x.end));
+                self.day_files(key, date, compacted && in_range)
+            })
+            .collect_vec();
+        Ok(self.scan_files(files)?.map(|x| x.filter(range_expr(range))))
+    }
+
+    /// Ticks in `range` with only `cols` read, e.g. `&["t", "c", "v"]`.
+    pub fn get_df(&self, ticker: Ticker, range: &ForCompare<dt>, cols: &[&str]) -> PolarsResult<DataFrame> {
+        match self.scan(ticker, range)? {
+            Some(lf) => lf.select(cols.map(|x| col(*x))).collect(),
+            None => Ok(DataFrame::empty()),
+        }
+    }
+
+    /// Same selection of days as `GenDi::get_tick`.
+    pub fn get_tick<T: Fromt<da> + PartialOrd>(&self, ticker: Ticker, range: ForCompare<T>) -> Option<PriceTick> {
+        self.get_tick_of(&ticker.to_string(), range)
+    }
+
+    /// The ticks of a contract of a recorder of all contracts, or of any other key.
+    pub fn get_tick_of<T: Fromt<da> + PartialOrd>(&self, key: &str, range: ForCompare<T>) -> Option<PriceTick> {
+        let df = self.scan_dates_of(key, &range).ok()??.collect().ok()?;
+        df_to_price_tick(&df).ok()
+    }
+
+    pub fn get_tick_range(&self, ticker: Ticker, range: &ForCompare<dt>) -> Option<PriceTick> {
+        let df = self.scan(ticker, range).ok()??.collect().ok()?;
+        df_to_price_tick(&df).ok()
+    }
+}
+
+/// `n` of a segment named `{n}.parquet`.
+fn segment_index(path: &Path) -> Option<usize> {
+    path.file_name()?.to_str()?.strip_suffix(".parquet")?.parse().ok()
+}
+
+fn write_parquet(df: &mut DataFrame, path: &Path) -> PolarsResult<()> {
+    let path_tmp = path.with_extension("parquet.tmp");
+    ParquetWriter::new(File::create(&path_tmp)?).finish(df)?;
+    std::fs::rename(path_tmp, path)?;
+    Ok(())
+}
+
+fn df_times(df: &DataFrame) -> PolarsResult<Vec<dt>> {
+    Ok(df.column("t")?.clone().pl_into())
+}
+
+fn read_times(path: &Path) -> PolarsResult<Vec<dt>> {
+    df_times(&ParquetReader::new(File::open(path)?).finish()?)
+}
+
+fn overlaps(range: &ForCompare<dt>, start: dt, end: dt) -> bool {
+    match range {
+        ForCompare::After(x) => end >= *x,
+        ForCompare::Before(x) => start < *x,
+        ForCompare::Between(x) => end >= x.start && start < x.end,
+        ForCompare::List(x) => x.iter().any(|i| overlaps(i, start, end)),
+    }
+}
+
+fn lit_dt(t: &dt) -> Expr {
+    lit(t.and_utc().timestamp_millis()).cast(DataType::Datetime(TimeUnit::Milliseconds, None))
+}
+
+fn range_expr(range: &ForCompare<dt>) -> Expr {
+    match range {
+        ForCompare::After(x) => col("t").gt_eq(lit_dt(x)),
+        ForCompare::Before(x) => col("t").lt(lit_dt(x)),
+        ForCompare::Between(x) => col("t").gt_eq(lit_dt(&x.start)).and(col("t").lt(lit_dt(&x.end))),
+        ForCompare::List(x) => x
+            .iter()
+            .map(|i| range_expr(i))
+            .reduce(|a, b| a.or(b))
+            .unwrap_or(lit(false)),
+    }
+}
+/* #endregion */
+
+/* #region Conversion */
+pub fn price_tick_to_df(price: &PriceTick, range: std::ops::Range<usize>) -> PolarsResult<DataFrame> {
+    let t = price.t[range.clone()].map(|x| x.and_utc().timestamp_millis());
+    let mut df = df!(
+        "t" => t,
+        "c" => &price.c[range.clone()],
+        "v" => &price.v[range.clone()],
+        "ct" => &price.ct[range.clone()],
+        "bid1" => &price.bid1[range.clone()],
+        "ask1" => &price.ask1[range.clone()],
+        "bid1_v" => &price.bid1_v[range.clone()],
+        "ask1_v" => &price.ask1_v[range],
+    )?;
+    df.apply("t", |x| x.cast(&DataType::Datetime(TimeUnit::Milliseconds, None)).unwrap())?;
+    Ok(df)
+}
+
+fn f32_col(df: &DataFrame, name: &str) -> PolarsResult<v32> {
+    Ok(df.column(name)?.f32()?.into_iter().map(|x| x.unwrap_or(f32::NAN)).collect_vec())
+}
+
+/// Columns missing from a projected frame are left empty.
+pub fn df_to_price_tick(df: &DataFrame) -> PolarsResult<PriceTick> {
+    let names = df.get_column_names_str();
+    let get = |name: &str| -> PolarsResult<v32> {
+        if names.contains(&name) { f32_col(df, name) } else { Ok(vec![]) }
+    };
+    let t = if names.contains(&"t") { df.column("t")?.clone().pl_into() } else { vec![] };
+    let ct = if names.contains(&"ct") {
+        df.column("ct")?.i32()?.into_iter().map(|x| x.unwrap_or_default()).collect_vec()
+    } else {
+        vec![]
+    };
+    Ok(PriceTick {
+        t,
+        c: get("c")?,
+        v: get("v")?,
+        ct,
+        bid1: get("bid1")?,
+        ask1: get("ask1")?,
+        bid1_v: get("bid1_v")?,
+        ask1_v: get("ask1_v")?,
+    })
+}
+/* #endregion */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ticks(secs: std::ops::Range<i64>) -> PriceTick {
+        let t0 = da::from_ymd_opt(2024, 1, 2).unwrap().and_hms_opt(9, 0, 0).unwrap();
+        let mut res = PriceTick::with_capacity(0);
+        for i in secs {
+            res.t.push(t0 + chrono::Duration::seconds(i));
+            res.c.push(i as f32);
+            res.v.push(1.);
+            res.ct.push(0);
+            res.bid1.push(i as f32);
+            res.ask1.push(i as f32);
+            res.bid1_v.push(1.);
+            res.ask1_v.push(1.);
+        }
+        res
+    }
+
+    #[test]
+    fn segments_are_read_before_and_after_compaction() {
+        let root = std::env::temp_dir().join(format!("qust_tick_store_{}", std::process::id()));
+        let store = TickStore::new(&root);
+        let date = da::from_ymd_opt(2024, 1, 2).unwrap();
+        assert_eq!(store.append_segment("au2406", date, &ticks(0..3)).unwrap(), 3);
+        // the first two overlap the segment before
+        assert_eq!(store.append_segment("au2406", date, &ticks(1..5)).unwrap(), 2);
+        assert_eq!(store.pending_dates("au2406"), vec![date]);
+        assert!(store.manifest().partitions_of("au2406").is_empty());
+        let range = ForCompare::Between(date..date + chrono::Duration::days(1));
+        assert_eq!(store.get_tick_of("au2406", range.clone()).unwrap().c, vec![0., 1., 2., 3., 4.]);
+        assert_eq!(store.compact_to("au2406", date).unwrap(), 5);
+        assert!(store.pending_dates("au2406").is_empty());
+        assert_eq!(store.manifest().partitions_of("au2406")[0].rows, 5);
+        assert_eq!(store.append_segment("au2406", date, &ticks(3..6)).unwrap(), 1);
+        assert_eq!(store.dates_of("au2406"), vec![date]);
+        assert_eq!(store.get_tick_of("au2406", range).unwrap().c, vec![0., 1., 2., 3., 4., 5.]);
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,4 +1,5 @@
 use qust::{
+    loge,
     prelude::{ori, Event},
     trade::prelude::*,
 };
@@ -6,6 +7,7 @@ use chrono::Duration;
 use qust_ds::prelude::*;
 use itertools::Itertools;
 use std::{path::{Path, PathBuf},  thread};
+use super::tick_store::TickStore;
 
 pub struct GenDi(pub &'static str);
 
@@ -18,31 +20,67 @@ pub fn otimes<T: Clone, N: Clone>(x: &[T], y: &[N]) -> Vec<(T, N)> {
 }
 
 impl GenDi {
+    pub fn tick_store(&self) -> TickStore {
+        TickStore::new(self.0.to_owned() + "/Rtick_pq")
+    }
+
+    /// Read the days of `range` from the parquet tick store, and the days it does not have
+    /// from the `Rtick` files, so a ticker migrated in part keeps its older days. Ticks a
+    /// recorder of all contracts stored by contract are read with `TickStore::get_tick_of`.
     pub fn get_tick<T: Fromt<da> + PartialOrd>(
         &self,
         ticker: Ticker,
         range: ForCompare<T>,
     ) -> Option<PriceTick> {
+        let store = self.tick_store();
+        let saved = store.dates(ticker);
         let p_str = self.0.to_owned() + "/Rtick/" + &ticker.to_string();
-        let mut file_vec = p_str.get_file_vec().ok()?;
-        file_vec.sort();
-        let price_vec = file_vec
-            .iter()
-            .filter(|x| range.compare_time(&x.to_da()))
+        let file_vec = p_str.get_file_vec();
+        if file_vec.is_err() && saved.is_empty() {
+            return None;
+        }
+        //(date, Rtick file), None for a day of the store
+        let mut days = file_vec
+            .unwrap_or_default()
+            .into_iter()
+            .map(|x| (x.to_da(), Some(x)))
+            .chain(saved.into_iter().map(|x| (x, None)))
+            .filter(|x| range.compare_time(&x.0))
             .collect_vec();
-        let es_len = 25_000 * price_vec.len();
-        let mut res =
-            price_vec
-                .into_iter()
-                .fold(PriceTick::with_capacity(es_len), |mut accu, item| {
-                    let mut price_tick = rof::<PriceTick>(item, &p_str);
-                    accu.cat(&mut price_tick);
-                    accu
-                });
+        days.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.is_some().cmp(&b.1.is_some())));
+        days.dedup_by_key(|x| x.0);
+        let mut res = PriceTick::with_capacity(25_000 * days.len());
+        for (from_store, run) in &days.iter().chunk_by(|x| x.1.is_none()) {
+            let run = run.collect_vec();
+            if from_store {
+                let dates = run[0].0..run[run.len() - 1].0 + Duration::days(1);
+                let mut price_tick = store.get_tick(ticker, ForCompare::Between(dates))?;
+                res.cat(&mut price_tick);
+            } else {
+                for (_, file) in run {
+                    let mut price_tick = rof::<PriceTick>(file.as_ref().unwrap(), &p_str);
+                    res.cat(&mut price_tick);
+                }
+            }
+        }
         res.shrink_to_fit();
         res.into()
     }
 
+    /// Ticks of `range` run through a `TickCleaner`, with one quality report per trading day.
+    pub fn get_tick_clean<T: Fromt<da> + PartialOrd>(
+        &self,
+        ticker: Ticker,
+        range: ForCompare<T>,
+        config: TickCleanConfig,
+    ) -> Option<(PriceTick, Vec<TickQuality>)> {
+        let price_tick = self.get_tick(ticker, range)?;
+        let mut cleaner = TickCleaner::new(ticker, config);
+        cleaner.calendar = self.trading_calendar();
+        let res = cleaner.clean_price_tick(&price_tick);
+        Some((res, cleaner.finish()))
+    }
+
     pub fn getl_tick<T: Fromt<da> + PartialOrd + Clone>(
         &self,
         ticker: &[Ticker],
@@ -58,6 +96,28 @@ impl GenDi {
         res
     }
 
+    pub fn tick_mmap_path(&self, ticker: Ticker) -> PathBuf {
+        PathBuf::from(self.0).join("Rtick_mm").join(format!("{ticker}.qcol"))
+    }
+
+    /// Write the ticks of `range` into one column file that `open_tick_mmap` maps without copying.
+    pub fn build_tick_mmap<T: Fromt<da> + PartialOrd>(
+        &self,
+        ticker: Ticker,
+        range: ForCompare<T>,
+    ) -> std::io::Result<()> {
+        let price_tick = self
+            .get_tick(ticker, range)
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, ticker.to_string()))?;
+        let path = self.tick_mmap_path(ticker);
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        MmapPriceTick::save(&price_tick, path)
+    }
+
+    pub fn open_tick_mmap(&self, ticker: Ticker) -> Option<MmapPriceTick> {
+        MmapPriceTick::open(self.tick_mmap_path(ticker)).ok()
+    }
+
     pub fn get_tick_data_vec<T: Fromt<da> + PartialOrd>(
         &self,
         ticker: Ticker,
@@ -100,44 +160,113 @@ impl GenDi {
             if !path_dir.is_dir() {
                 std::fs::create_dir(path_dir).unwrap();
             }
-            di.pcon.sof(&di.pcon.ticker.to_string(), &path_str)
+            di.owned_pcon().sof(&di.pcon.ticker.to_string(), &path_str)
         });
     }
 
     pub fn sof_tick_data(&self, price: &PriceTick, ticker: Ticker, date: da) {
         let save_path = self.0.to_owned() + "/Rtick/" + &ticker.to_string();
         price.sof(&date.to_string(), &save_path);
+        if let Err(e) = self.tick_store().append(ticker, date, price) {
+            loge!(level: Error, ticker, "tick store append failed: {} {}", date, e);
+        }
     }
 
-    pub fn update_dil(&self, dil: &mut Dil) {
-        dil.dil.iter_mut().for_each(|x| {
-            let max_time = x.pcon.price.t.last().unwrap().date();
-            let tick_data = self
-                .get_tick(x.pcon.ticker, (max_time + Duration::days(1)).after())
-                .unwrap();
-            let mut price_data = tick_data.to_price_ori(x.pcon.inter.clone(), x.pcon.ticker);
-            x.pcon.price.cat(&mut price_data);
-        });
+    /// Copy the `Rtick` files of `ticker` into the parquet tick store, days already there are skipped.
+    pub fn migrate_to_tick_store(&self, ticker: Ticker) -> usize {
+        let store = self.tick_store();
+        let saved = store.dates(ticker);
+        let p_str = self.0.to_owned() + "/Rtick/" + &ticker.to_string();
+        let mut file_vec = p_str.get_file_vec().unwrap_or_default();
+        file_vec.sort();
+        file_vec
+            .iter()
+            .filter(|x| !saved.contains(&x.to_da()))
+            .filter(|x| {
+                let price_tick = rof::<PriceTick>(x, &p_str);
+                store.append(ticker, x.to_da(), &price_tick).is_ok()
+            })
+            .count()
+    }
+
+    /// Holidays from `{root}/calendar.json` (a list of dates), weekdays only when missing.
+    pub fn trading_calendar(&self) -> TradingCalendar {
+        std::fs::read_to_string(self.0.to_owned() + "/calendar.json")
+            .ok()
+            .and_then(|x| serde_json::from_str::<Vec<da>>(&x).ok())
+            .map(TradingCalendar::new)
+            .unwrap_or_default()
     }
 
-    pub fn update_dil_file(&self, name: &str, path: &str) {
+    /// Bring every `Di` up to the last saved tick. Each `Di` resumes after its last finished
+    /// bar, so a bar left open by the previous update is rebuilt from its first tick. Only
+    /// the `Di`s that got new bars lose their cached results.
+    pub fn update_dil(&self, dil: &mut Dil) -> DilUpdateReport {
+        let calendar = self.trading_calendar();
+        let mut tick_cache: hm<(Ticker, da), Option<PriceTick>> = hm::new();
+        let items = dil.dil
+            .iter_mut()
+            .map(|di| {
+                let ident = di.pcon.ident();
+                let Some(&last_time) = di.base_t().last() else {
+                    return DiUpdate::new(ident, None, DiUpdateStatus::Empty);
+                };
+                // one day back, the night session can be saved under the next trading day
+                let from = last_time.date() - Duration::days(1);
+                let price_tick = tick_cache
+                    .entry((di.pcon.ticker, from))
+                    .or_insert_with(|| self.get_tick(di.pcon.ticker, from.after()))
+                    .as_ref();
+                let Some(price_tick) = price_tick.filter(|x| x.t.last().is_some_and(|t| *t > last_time)) else {
+                    return DiUpdate::new(ident, Some(last_time), DiUpdateStatus::NoNewData);
+                };
+                let tick_dates = price_tick.t
+                    .iter()
+                    .filter(|t| **t > last_time)
+                    .map(|t| t.date())
+                    .dedup()
+                    .collect_vec();
+                let last_tick_date = *tick_dates.last().unwrap();
+                let missing_days = calendar.missing_days(
+                    last_time.date() + Duration::days(1),
+                    last_tick_date,
+                    &tick_dates,
+                );
+                let bars_added = di.update_from_tick(price_tick);
+                let mut res = DiUpdate::new(ident, Some(last_time), DiUpdateStatus::Updated);
+                res.bars_added = bars_added;
+                res.end = di.base_t().last().cloned();
+                res.missing_days = missing_days;
+                res
+            })
+            .collect_vec();
+        DilUpdateReport { items }
+    }
+
+    pub fn update_dil_file(&self, name: &str, path: &str) -> DilUpdateReport {
         let mut dil = rof::<Dil>(name, path);
-        self.update_dil(&mut dil);
-        dil.sof(name, path);
+        let res = self.update_dil(&mut dil);
+        if res.is_changed() {
+            dil.sof(name, path);
+        }
+        res
     }
 
     pub fn update_from_mongodb(&self) {
         todo!();
     }
 
+    /// Dates saved in the tick store and in the legacy `Rtick` files, together.
     pub fn get_ticks_saved_date(&self, ticker: Ticker) -> Vec<da> {
         let mut res = (self.0.to_owned() + "/Rtick/" + &ticker.to_string())
             .get_file_vec()
-            .unwrap()
+            .unwrap_or_default()
             .iter()
             .map(|x| x.to_da())
             .collect_vec();
+        res.extend(self.tick_store().dates(ticker));
         res.sort();
+        res.dedup();
         res
     }
 
@@ -235,6 +364,56 @@ pub trait ToIdentVec {
     fn to_ident_vec(&self, path: &str) -> Vec<(TriBox, Ticker)>;
 }
 
+#[derive(Debug, Clone)]
+pub enum DiUpdateStatus {
+    Updated,
+    NoNewData,
+    /// a `Di` without bars has nothing to resume from
+    Empty,
+}
+
+#[derive(Debug, Clone)]
+pub struct DiUpdate {
+    pub ident: PconIdent,
+    pub status: DiUpdateStatus,
+    /// last bar before the update
+    pub start: Option<dt>,
+    /// last bar after the update
+    pub end: Option<dt>,
+    pub bars_added: usize,
+    /// trading days after `start` with no ticks
+    pub missing_days: Vec<da>,
+}
+
+impl DiUpdate {
+    fn new(ident: PconIdent, start: Option<dt>, status: DiUpdateStatus) -> Self {
+        Self { ident, status, start, end: start, bars_added: 0, missing_days: vec![] }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DilUpdateReport {
+    pub items: Vec<DiUpdate>,
+}
+
+impl DilUpdateReport {
+    pub fn is_changed(&self) -> bool {
+        self.items.iter().any(|x| x.bars_added > 0)
+    }
+
+    pub fn changed(&self) -> Vec<&PconIdent> {
+        self.items.iter().filter(|x| x.bars_added > 0).map(|x| &x.ident).collect_vec()
+    }
+
+    pub fn gaps(&self) -> Vec<(&PconIdent, &[da])> {
+        self.items
+            .iter()
+            .filter(|x| !x.missing_days.is_empty())
+            .map(|x| (&x.ident, x.missing_days.as_slice()))
+            .collect_vec()
+    }
+}
+
 impl ToIdentVec for TriBox {
     fn to_ident_vec(&self, path: &str) -> Vec<(TriBox, Ticker)> {
         format!("{}/{:?}", path, self)
@@ -3,6 +3,7 @@
 pub mod input {
     pub mod ticks;
     pub mod read_csv;
+    pub mod tick_store;
 }
 
 pub mod output {
@@ -12,24 +13,34 @@ pub mod output {
     pub mod array;
     pub mod color;
     pub mod show;
+    pub mod overfit;
+    pub mod tearsheet;
+    pub mod report;
 }
 
 pub mod transform {
     pub mod pl_util;
+    pub mod sweep;
+    pub mod factor;
 }
 
 pub mod prelude {
     pub use crate::{
-        input::{ ticks::*, read_csv::* },
+        input::{ ticks::*, read_csv::*, tick_store::* },
         output::{
             excel::{IntoDf, ToIndex, ToValue, ToValueString, ToCsv, ConcatDf, EvcxrDisplay},
             plot::*,
             profile::*,
             array::*,
             show::*,
+            overfit::*,
+            tearsheet::*,
+            report::*,
         },
         transform::{
             pl_util::*,
+            sweep::*,
+            factor::*,
         }
     };
 
@@ -212,7 +212,8 @@ impl IntoDf for PriceArc {
 impl IntoDf for Di {
     type Index = String;
     type Value = Vec<f32>;
-    fn to_df(self) -> Df<Self::Index, Self::Value> {
+    fn to_df(mut self) -> Df<Self::Index, Self::Value> {
+        self.own_price();
         self.pcon.price.to_df()
     }
 }
@@ -259,7 +260,8 @@ impl IntoDf for Aee<PriceOri> {
 impl IntoDf for Aee<Di> {
     type Index = String;
     type Value = Vec<f32>;
-    fn to_df(self) -> Df<Self::Index, Self::Value> {
+    fn to_df(mut self) -> Df<Self::Index, Self::Value> {
+        self.0.own_price();
         self.0.pcon.price.pip(Aee).to_df()
     }
 }
@@ -378,6 +380,33 @@ impl IntoDf for StatsRes {
     }
 }
 
+impl IntoDf for TradeList {
+    type Index = String;
+    type Value = Vec<String>;
+    fn to_df(self) -> Df<Self::Index, Self::Value> {
+        let t = &self.trades;
+        Df {
+            index: t.map(|x| x.entry_time.debug_string()),
+            value: vec![
+                t.map(|_| self.ticker.debug_string()),
+                t.map(|x| x.exit_time.debug_string()),
+                t.map(|x| x.entry_price.to_string()),
+                t.map(|x| x.exit_price.to_string()),
+                t.map(|x| x.dire.debug_string()),
+                t.map(|x| x.size.to_string()),
+                t.map(|x| x.pnl.to_string()),
+                t.map(|x| x.cost.to_string()),
+                t.map(|x| x.bars.to_string()),
+                t.map(|x| x.mae.to_string()),
+                t.map(|x| x.mfe.to_string()),
+            ],
+            column: vec![
+                "ticker", "exit_time", "entry_price", "exit_price", "dire", "size", "pnl", "cost",
+                "bars", "mae", "mfe"].map(|x| String::from(*x)),
+        }
+    }
+}
+
 pub trait ConcatDf {
     type Output;
     fn concat_df(self) -> Self::Output;
@@ -487,7 +516,8 @@ impl IntoDf for Aee<Aee<PriceOri>> {
 impl IntoDf for Aee<Aee<Di>> {
     type Index = String;
     type Value = Vec<String>;
-    fn to_df(self) -> Df<Self::Index, Self::Value> {
+    fn to_df(mut self) -> Df<Self::Index, Self::Value> {
+        self.0.0.own_price();
         self.0.0.pcon.price.pip(Aee).pip(Aee).to_df()
     }
 }
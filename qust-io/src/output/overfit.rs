@@ -0,0 +1,327 @@
+use std::cmp::Ordering;
+use qust::prelude::*;
+use super::profile::{ max_drawdown, Sr };
+
+/* #region Normal distribution */
+fn erf(x: f64) -> f64 {
+    //Abramowitz and Stegun 7.1.26
+    let sign = x.signum();
+    let x = x.abs();
+    let t = 1. / (1. + 0.327_591_1 * x);
+    let y = ((((1.061_405_429 * t - 1.453_152_027) * t + 1.421_413_741) * t - 0.284_496_736) * t
+        + 0.254_829_592) * t;
+    sign * (1. - y * (-x * x).exp())
+}
+
+pub fn norm_cdf(x: f64) -> f64 {
+    0.5 * (1. + erf(x / std::f64::consts::SQRT_2))
+}
+
+pub fn norm_ppf(p: f64) -> f64 {
+    //Acklam's rational approximation
+    const A: [f64; 6] = [
+        -3.969_683_028_665_376e1, 2.209_460_984_245_205e2, -2.759_285_104_469_687e2,
+        1.383_577_518_672_690e2, -3.066_479_806_614_716e1, 2.506_628_277_459_239,
+    ];
+    const B: [f64; 5] = [
+        -5.447_609_879_822_406e1, 1.615_858_368_580_409e2, -1.556_989_798_598_866e2,
+        6.680_131_188_771_972e1, -1.328_068_155_288_572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784_894_002_430_293e-3, -3.223_964_580_411_365e-1, -2.400_758_277_161_838,
+        -2.549_732_539_343_734, 4.374_664_141_464_968, 2.938_163_982_698_783,
+    ];
+    const D: [f64; 4] = [
+        7.784_695_709_041_462e-3, 3.224_671_290_700_398e-1, 2.445_134_137_142_996,
+        3.754_408_661_907_416,
+    ];
+    let p_low = 0.02425;
+    let tail = |q: f64| {
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.)
+    };
+    if p <= 0. {
+        f64::NEG_INFINITY
+    } else if p >= 1. {
+        f64::INFINITY
+    } else if p < p_low {
+        tail((-2. * p.ln()).sqrt())
+    } else if p <= 1. - p_low {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.)
+    } else {
+        -tail((-2. * (1. - p).ln()).sqrt())
+    }
+}
+
+///(mean, std, skew, kurtosis), kurtosis is 3 for normal data
+fn moments(data: &[f32]) -> (f64, f64, f64, f64) {
+    let n = data.len() as f64;
+    let mean = data.iter().map(|x| *x as f64).sum::<f64>() / n;
+    let m = |k: i32| data.iter().map(|x| (*x as f64 - mean).powi(k)).sum::<f64>() / n;
+    let (m2, m3, m4) = (m(2), m(3), m(4));
+    (mean, m2.sqrt(), m3 / m2.powf(1.5), m4 / m2.powi(2))
+}
+
+fn sr_period(data: &[f32]) -> f64 {
+    let (mean, std, _, _) = moments(data);
+    if std == 0. { 0. } else { mean / std }
+}
+
+fn cmp_f32(a: &f32, b: &f32) -> Ordering {
+    a.partial_cmp(b).unwrap_or(Ordering::Equal)
+}
+/* #endregion */
+
+/* #region Deflated Sharpe ratio */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DsrRes {
+    pub sr: f32,
+    pub sr0: f32,
+    pub dsr: f32,
+    pub n_trials: usize,
+}
+
+/// `data` is the pnl of the chosen strategy, `sr_trials` the per-period sharpe ratios
+/// of every configuration tried. Both sharpe ratios are not annualized.
+pub fn deflated_sr(data: &[f32], sr_trials: &[f32]) -> DsrRes {
+    let euler_gamma = 0.577_215_664_9;
+    let (mean, std, skew, kurt) = moments(data);
+    let sr = if std == 0. { 0. } else { mean / std };
+    let n = sr_trials.len();
+    let sr0 = if n > 1 {
+        let (_, sr_std, _, _) = moments(sr_trials);
+        let n = n as f64;
+        sr_std * ((1. - euler_gamma) * norm_ppf(1. - 1. / n)
+            + euler_gamma * norm_ppf(1. - 1. / (n * std::f64::consts::E)))
+    } else {
+        0.
+    };
+    let t = data.len() as f64;
+    let denom = (1. - skew * sr + (kurt - 1.) / 4. * sr * sr).max(f64::EPSILON);
+    let z = (sr - sr0) * (t - 1.).sqrt() / denom.sqrt();
+    DsrRes {
+        sr: sr as f32,
+        sr0: sr0 as f32,
+        dsr: norm_cdf(z) as f32,
+        n_trials: n,
+    }
+}
+
+pub trait DeflatedSr {
+    fn deflated_sr(&self) -> DsrRes;
+}
+
+impl DeflatedSr for [PnlRes<da>] {
+    fn deflated_sr(&self) -> DsrRes {
+        if self.is_empty() {
+            return DsrRes { sr: f32::NAN, sr0: f32::NAN, dsr: f32::NAN, n_trials: 0 };
+        }
+        let sr_trials = self.map(|x| sr_period(&x.1[0]) as f32);
+        let i_best = sr_trials
+            .iter()
+            .enumerate()
+            .max_by(|a, b| cmp_f32(a.1, b.1))
+            .unwrap()
+            .0;
+        deflated_sr(&self[i_best].1[0], &sr_trials)
+    }
+}
+
+impl<T> DeflatedSr for [InfoPnlRes<T, da>] {
+    fn deflated_sr(&self) -> DsrRes {
+        self.map(|x| x.1.clone()).deflated_sr()
+    }
+}
+/* #endregion */
+
+/* #region Probability of backtest overfitting */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PboRes {
+    pub pbo: f32,
+    pub logits: v32,
+    pub is_sr: v32,
+    pub oos_sr: v32,
+}
+
+fn align_pnl(data: &[PnlRes<da>]) -> vv32 {
+    let dates: vda = data.map(|x| x.0.clone()).union_vecs();
+    data.map(|x| {
+        let pnl_map: hm<da, f32> = x.0.iter().cloned().zip(x.1[0].iter().cloned()).collect();
+        dates.map(|t| pnl_map.get(t).cloned().unwrap_or(0.))
+    })
+}
+
+pub trait Pbo {
+    /// Combinatorially symmetric cross validation, `n_split` is rounded down to an even number.
+    /// `pbo` is NaN without a block of data for each split.
+    fn pbo(&self, n_split: usize) -> PboRes;
+}
+
+impl Pbo for [PnlRes<da>] {
+    fn pbo(&self, n_split: usize) -> PboRes {
+        let mat = align_pnl(self);
+        let n = mat.len();
+        let t = mat.first().map(|x| x.len()).unwrap_or(0);
+        let n_split = (n_split - n_split % 2).max(2);
+        if n == 0 || t < n_split {
+            return PboRes { pbo: f32::NAN, logits: vec![], is_sr: vec![], oos_sr: vec![] };
+        }
+        let block = t / n_split;
+        let blocks = (0..n_split)
+            .map(|i| i * block..if i == n_split - 1 { t } else { (i + 1) * block })
+            .collect_vec();
+        let select = |x: &v32, idx: &[usize]| -> v32 {
+            idx.iter().flat_map(|i| x[blocks[*i].clone()].to_vec()).collect_vec()
+        };
+        let mut logits = vec![];
+        let mut is_sr = vec![];
+        let mut oos_sr = vec![];
+        for is_idx in (0..n_split).combinations(n_split / 2) {
+            let oos_idx = (0..n_split).filter(|x| !is_idx.contains(x)).collect_vec();
+            let sr_is = mat.map(|x| select(x, &is_idx).sr());
+            let sr_oos = mat.map(|x| select(x, &oos_idx).sr());
+            let i_best = sr_is
+                .iter()
+                .enumerate()
+                .max_by(|a, b| cmp_f32(a.1, b.1))
+                .unwrap()
+                .0;
+            let rank = sr_oos.iter().filter(|x| cmp_f32(x, &sr_oos[i_best]).is_lt()).count() + 1;
+            let omega = rank as f32 / (n + 1) as f32;
+            logits.push((omega / (1. - omega)).ln());
+            is_sr.push(sr_is[i_best]);
+            oos_sr.push(sr_oos[i_best]);
+        }
+        let pbo = logits.iter().filter(|x| **x <= 0.).count() as f32 / logits.len() as f32;
+        PboRes { pbo, logits, is_sr, oos_sr }
+    }
+}
+
+impl<T> Pbo for [InfoPnlRes<T, da>] {
+    fn pbo(&self, n_split: usize) -> PboRes {
+        self.map(|x| x.1.clone()).pbo(n_split)
+    }
+}
+/* #endregion */
+
+/* #region Block bootstrap */
+struct SplitMix(u64);
+
+impl SplitMix {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn gen_range(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BootstrapCi {
+    pub point: f32,
+    pub lower: f32,
+    pub upper: f32,
+}
+
+/// Circular moving block bootstrap, blocks keep the autocorrelation of daily pnl.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockBootstrap {
+    pub block_len: usize,
+    pub n_sample: usize,
+    pub alpha: f32,
+    pub seed: u64,
+}
+
+impl Default for BlockBootstrap {
+    fn default() -> Self {
+        Self { block_len: 20, n_sample: 1000, alpha: 0.05, seed: 0 }
+    }
+}
+
+impl BlockBootstrap {
+    pub fn samples(&self, data: &[f32]) -> vv32 {
+        let t = data.len();
+        let block_len = self.block_len.clamp(1, t.max(1));
+        let mut rng = SplitMix(self.seed);
+        (0..self.n_sample)
+            .map(|_| {
+                let mut res = Vec::with_capacity(t);
+                while res.len() < t {
+                    let start = rng.gen_range(t);
+                    (0..block_len.min(t - res.len())).for_each(|j| res.push(data[(start + j) % t]));
+                }
+                res
+            })
+            .collect_vec()
+    }
+
+    pub fn ci<F: Fn(&[f32]) -> f32>(&self, data: &[f32], f: F) -> BootstrapCi {
+        let stats = self.samples(data).map(|x| f(x));
+        BootstrapCi {
+            point: f(data),
+            lower: stats.quantile(self.alpha / 2.),
+            upper: stats.quantile(1. - self.alpha / 2.),
+        }
+    }
+
+    pub fn sr_ci(&self, pnl: &PnlRes<da>) -> BootstrapCi {
+        self.ci(&pnl.1[0], |x| x.sr())
+    }
+
+    pub fn mdd_ci(&self, pnl: &PnlRes<da>) -> BootstrapCi {
+        self.ci(&pnl.1[0], max_drawdown)
+    }
+}
+/* #endregion */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pnl_res(pnl: v32) -> PnlRes<da> {
+        let start = da::from_ymd_opt(2024, 1, 1).unwrap();
+        let dates = (0..pnl.len()).map(|i| start + chrono::Duration::days(i as i64)).collect_vec();
+        PnlRes(dates, vec![pnl])
+    }
+
+    #[test]
+    fn norm_known_values() {
+        assert!((norm_cdf(0.) - 0.5).abs() < 1e-7);
+        assert!((norm_cdf(1.959_964) - 0.975).abs() < 1e-6);
+        assert!((norm_ppf(0.975) - 1.959_964).abs() < 1e-6);
+        assert!((norm_ppf(0.01) + 2.326_348).abs() < 1e-6);
+    }
+
+    #[test]
+    fn dsr_of_zero_mean_pnl_is_half() {
+        let res = deflated_sr(&[1., -1., 1., -1.], &[0.]);
+        assert_eq!(res.sr, 0.);
+        assert_eq!(res.sr0, 0.);
+        assert!((res.dsr - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn pbo_of_dominant_strategy_is_zero() {
+        let base = (0..40).map(|i| if i % 2 == 0 { 1. } else { -0.5 }).collect_vec();
+        let better = base.iter().map(|x| x + 0.5).collect_vec();
+        let res = [pnl_res(base), pnl_res(better)].pbo(4);
+        assert_eq!(res.logits.len(), 6);
+        assert_eq!(res.pbo, 0.);
+    }
+
+    #[test]
+    fn empty_input_is_nan() {
+        assert!(max_drawdown(&[]).is_nan());
+        assert!(<[PnlRes<da>]>::pbo(&[], 4).pbo.is_nan());
+        assert!([pnl_res(vec![1.])].pbo(4).pbo.is_nan());
+        assert_eq!(max_drawdown(&[1., -2., 1.]), 2.);
+    }
+}
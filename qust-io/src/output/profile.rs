@@ -173,7 +173,11 @@ pub fn ret_annu(pnl: &PnlRes<da>) -> f32 {
     pnl_sum / k
 }
 
-fn max_drawdown(x: &[f32]) -> f32 {
+/// NaN for empty data.
+pub fn max_drawdown(x: &[f32]) -> f32 {
+    if x.is_empty() {
+        return f32::NAN;
+    }
     let pnl_cum = x.cumsum();
     let t =  pnl_cum.iter()
         .scan(pnl_cum[0], |accu, x| {
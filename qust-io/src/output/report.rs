@@ -0,0 +1,74 @@
+use std::path::Path;
+use qust::prelude::*;
+
+/// A self contained html page of tables followed by plotly charts, one chart per key of `data`.
+#[derive(Debug, Clone, Default)]
+pub struct HtmlReport {
+    pub sections: Vec<(String, String)>,
+    pub plots: Vec<String>,
+    pub data: serde_json::Value,
+}
+
+impl HtmlReport {
+    pub fn section(mut self, title: &str, html: String) -> Self {
+        self.sections.push((title.to_string(), html));
+        self
+    }
+
+    /// `data[name]` is the list of traces of the chart `name`, charts are drawn in the order of `names`.
+    pub fn plots(mut self, names: &[&str], data: serde_json::Value) -> Self {
+        self.plots = names.map(|x| x.to_string());
+        self.data = data;
+        self
+    }
+
+    pub fn to_html(&self) -> String {
+        let sections = self.sections
+            .iter()
+            .map(|(title, html)| format!("<h3>{title}</h3>\n{html}\n"))
+            .join("");
+        let divs = self.plots
+            .iter()
+            .map(|x| format!("<div id=\"{x}\" class=\"plot\"></div>\n"))
+            .join("");
+        let names = self.plots.iter().map(|x| format!("\"{x}\"")).join(", ");
+        format!(
+            r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<script src="https://cdn.plot.ly/plotly-2.35.2.min.js"></script>
+<style>
+body {{ font-family: Consolas, monospace; background: #282828; color: #ddd; }}
+table {{ border-collapse: collapse; margin: 10px 0; }}
+td, th {{ border: 1px solid #555; padding: 2px 8px; text-align: right; }}
+.plot {{ height: 360px; }}
+</style>
+</head>
+<body>
+{sections}{divs}<script>
+const data = {};
+const layout = {{ paper_bgcolor: "rgba(0,0,0,0)", plot_bgcolor: "rgba(0,0,0,0)", font: {{ color: "#ddd" }} }};
+for (const k of [{names}]) {{
+    Plotly.newPlot(k, data[k], Object.assign({{ title: k }}, layout));
+}}
+</script>
+</body>
+</html>"#,
+            self.data,
+        )
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        std::fs::write(path, self.to_html())
+    }
+}
+
+/// Two column table of name and value.
+pub fn html_table_kv(rows: &[(&str, String)]) -> String {
+    rows
+        .iter()
+        .map(|(k, v)| format!("<tr><td>{k}</td><td>{v}</td></tr>"))
+        .join("")
+        .pip(|x| format!("<table>{x}</table>"))
+}
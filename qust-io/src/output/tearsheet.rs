@@ -0,0 +1,301 @@
+use std::path::Path;
+use chrono::Datelike;
+use qust::prelude::*;
+use serde_json::json;
+use super::report::{html_table_kv, HtmlReport};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TearsheetConfig {
+    pub capital: f32,
+    ///annual risk free rate, 0.02 for 2%
+    pub rf: f32,
+    pub top_n: usize,
+}
+
+impl TearsheetConfig {
+    pub fn new(capital: f32) -> Self {
+        Self { capital, rf: 0., top_n: 5 }
+    }
+}
+
+/* #region Drawdown */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Drawdown {
+    pub start: da,
+    pub trough: da,
+    pub recovery: Option<da>,
+    pub depth: f32,
+    pub depth_pct: f32,
+    pub days: usize,
+}
+
+fn drawdown_table(pnl: &PnlRes<da>, capital: f32) -> Vec<Drawdown> {
+    let equity = pnl.1[0].cumsum();
+    let mut res = vec![];
+    let mut peak = (0usize, 0f32);
+    let mut trough: Option<(usize, f32)> = None;
+    for (i, v) in equity.iter().enumerate() {
+        if *v >= peak.1 {
+            if let Some((i_trough, v_trough)) = trough.take() {
+                res.push((peak.0, i_trough, Some(i), peak.1 - v_trough));
+            }
+            peak = (i, *v);
+        } else {
+            match trough {
+                Some((_, v_trough)) if v_trough <= *v => {}
+                _ => trough = Some((i, *v)),
+            }
+        }
+    }
+    if let Some((i_trough, v_trough)) = trough {
+        res.push((peak.0, i_trough, None, peak.1 - v_trough));
+    }
+    let l = pnl.0.len();
+    res.sort_by(|a, b| b.3.total_cmp(&a.3));
+    res
+        .into_iter()
+        .map(|(i_start, i_trough, i_recovery, depth)| Drawdown {
+            start: pnl.0[i_start],
+            trough: pnl.0[i_trough],
+            recovery: i_recovery.map(|i| pnl.0[i]),
+            depth,
+            depth_pct: 100. * depth / capital,
+            days: i_recovery.unwrap_or(l - 1) - i_start,
+        })
+        .collect_vec()
+}
+/* #endregion */
+
+/* #region Returns matrix */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReturnsMatrix {
+    pub years: Vec<i32>,
+    ///percent return, years x 12, NaN where there is no data
+    pub monthly: vv32,
+    pub yearly: v32,
+}
+
+fn returns_matrix(pnl: &PnlRes<da>, capital: f32) -> ReturnsMatrix {
+    let years = pnl.0.iter().map(|x| x.year()).unique().collect_vec();
+    let mut monthly = vec![vec![f32::NAN; 12]; years.len()];
+    let mut yearly = vec![0f32; years.len()];
+    for (t, v) in pnl.0.iter().zip(pnl.1[0].iter()) {
+        let i = years.iter().position(|x| *x == t.year()).unwrap();
+        let j = t.month0() as usize;
+        let r = 100. * v / capital;
+        monthly[i][j] = if monthly[i][j].is_nan() { r } else { monthly[i][j] + r };
+        yearly[i] += r;
+    }
+    ReturnsMatrix { years, monthly, yearly }
+}
+/* #endregion */
+
+/* #region Trade stats */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TradeStats {
+    pub trade_num: usize,
+    pub win_rate: f32,
+    pub payoff: f32,
+    pub avg_bars: f32,
+    ///minutes
+    pub avg_hold_time: f32,
+    pub avg_mae: f32,
+    pub avg_mfe: f32,
+}
+
+fn trade_stats(trades: &[Trade]) -> TradeStats {
+    let n = trades.len().max(1) as f32;
+    let wins = trades.iter().filter(|x| x.pnl > 0.).map(|x| x.pnl).collect_vec();
+    let losses = trades.iter().filter(|x| x.pnl <= 0.).map(|x| x.pnl).collect_vec();
+    let mean = |x: &[f32]| if x.is_empty() { 0. } else { x.iter().sum::<f32>() / x.len() as f32 };
+    TradeStats {
+        trade_num: trades.len(),
+        win_rate: wins.len() as f32 / n,
+        payoff: mean(&wins) / mean(&losses).abs(),
+        avg_bars: trades.iter().map(|x| x.bars as f32).sum::<f32>() / n,
+        avg_hold_time: trades
+            .iter()
+            .map(|x| (x.exit_time - x.entry_time).num_seconds() as f32 / 60.)
+            .sum::<f32>() / n,
+        avg_mae: trades.iter().map(|x| x.mae).sum::<f32>() / n,
+        avg_mfe: trades.iter().map(|x| x.mfe).sum::<f32>() / n,
+    }
+}
+/* #endregion */
+
+/* #region Tearsheet */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tearsheet {
+    pub config: TearsheetConfig,
+    pub date: vda,
+    pub equity: v32,
+    pub ret_annu: f32,
+    pub vol_annu: f32,
+    pub sr: f32,
+    pub sortino: f32,
+    pub mdd: f32,
+    pub calmar: f32,
+    ///fraction of bars holding a position
+    pub exposure: f32,
+    ///traded money over capital, per year
+    pub turnover: f32,
+    pub drawdowns: Vec<Drawdown>,
+    pub returns_matrix: ReturnsMatrix,
+    pub trade_stats: TradeStats,
+    pub trades: Vec<Trade>,
+}
+
+/// `trade_list` is the `TradeList` of the same backtest, e.g. `Di::trade_list`.
+pub trait ToTearsheet {
+    fn tearsheet(&self, trade_list: &TradeList, config: &TearsheetConfig) -> Tearsheet;
+}
+
+impl ToTearsheet for PnlRes<dt> {
+    fn tearsheet(&self, trade_list: &TradeList, config: &TearsheetConfig) -> Tearsheet {
+        if self.0.is_empty() {
+            return Tearsheet::empty(config);
+        }
+        let pnl_da = self.da();
+        let ret = pnl_da.1[0].map(|x| x / config.capital);
+        let rf_day = config.rf / 240.;
+        let mean = ret.agg(RollFunc::Mean);
+        let std = ret.agg(RollFunc::Std);
+        let std_down = (ret
+            .iter()
+            .map(|x| (x - rf_day).min(0.).powi(2))
+            .sum::<f32>() / ret.len() as f32)
+            .sqrt();
+        let ret_annu = 100. * mean * 240.;
+        let drawdowns = drawdown_table(&pnl_da, config.capital);
+        let mdd = drawdowns.first().map(|x| x.depth_pct).unwrap_or(0.);
+        let years = ((*pnl_da.0.last().unwrap() - pnl_da.0[0]).num_days() as f32 / 365.).max(1. / 365.);
+        let trades = trade_list.trades.clone();
+        Tearsheet {
+            config: config.clone(),
+            date: pnl_da.0.clone(),
+            equity: pnl_da.1[0].cumsum(),
+            ret_annu,
+            vol_annu: 100. * std * 240f32.sqrt(),
+            sr: 240f32.sqrt() * (mean - rf_day) / std,
+            sortino: 240f32.sqrt() * (mean - rf_day) / std_down,
+            mdd,
+            calmar: ret_annu / mdd,
+            exposure: self.1[2].iter().filter(|x| **x != 0.).count() as f32 / self.1[2].len() as f32,
+            turnover: self.1[3].iter().map(|x| x.abs()).sum::<f32>() / 2. / config.capital / years,
+            drawdowns: drawdowns.into_iter().take(config.top_n).collect_vec(),
+            returns_matrix: returns_matrix(&pnl_da, config.capital),
+            trade_stats: trade_stats(&trades),
+            trades,
+        }
+    }
+}
+
+impl ToTearsheet for InfoPnlRes<Stra, dt> {
+    fn tearsheet(&self, trade_list: &TradeList, config: &TearsheetConfig) -> Tearsheet {
+        self.1.tearsheet(trade_list, config)
+    }
+}
+/* #endregion */
+
+/* #region Html */
+impl Tearsheet {
+    /// Tearsheet of a backtest without any bar.
+    pub fn empty(config: &TearsheetConfig) -> Self {
+        Tearsheet {
+            config: config.clone(),
+            date: vec![],
+            equity: vec![],
+            ret_annu: 0.,
+            vol_annu: 0.,
+            sr: 0.,
+            sortino: 0.,
+            mdd: 0.,
+            calmar: 0.,
+            exposure: 0.,
+            turnover: 0.,
+            drawdowns: vec![],
+            returns_matrix: ReturnsMatrix { years: vec![], monthly: vec![], yearly: vec![] },
+            trade_stats: trade_stats(&[]),
+            trades: vec![],
+        }
+    }
+
+    fn summary_html(&self) -> String {
+        let t = &self.trade_stats;
+        [
+            ("capital", format!("{:.0}", self.config.capital)),
+            ("risk free", format!("{:.2}%", 100. * self.config.rf)),
+            ("annual return", format!("{:.2}%", self.ret_annu)),
+            ("annual vol", format!("{:.2}%", self.vol_annu)),
+            ("sharpe", format!("{:.2}", self.sr)),
+            ("sortino", format!("{:.2}", self.sortino)),
+            ("max drawdown", format!("{:.2}%", self.mdd)),
+            ("calmar", format!("{:.2}", self.calmar)),
+            ("exposure", format!("{:.2}%", 100. * self.exposure)),
+            ("turnover", format!("{:.2}", self.turnover)),
+            ("trades", format!("{}", t.trade_num)),
+            ("win rate", format!("{:.2}%", 100. * t.win_rate)),
+            ("payoff", format!("{:.2}", t.payoff)),
+            ("avg bars", format!("{:.1}", t.avg_bars)),
+            ("avg hold minutes", format!("{:.1}", t.avg_hold_time)),
+            ("avg mae", format!("{:.1}", t.avg_mae)),
+            ("avg mfe", format!("{:.1}", t.avg_mfe)),
+        ]
+            .pip(|x| html_table_kv(&x))
+    }
+
+    fn drawdown_html(&self) -> String {
+        let rows = self.drawdowns
+            .iter()
+            .map(|x| format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{:.2}%</td><td>{}</td></tr>",
+                x.start,
+                x.trough,
+                x.recovery.map_or_else(|| "-".to_string(), |x| x.to_string()),
+                x.depth_pct,
+                x.days,
+            ))
+            .join("");
+        format!(
+            "<table><tr><th>start</th><th>trough</th><th>recovery</th><th>depth</th><th>days</th></tr>{rows}</table>"
+        )
+    }
+
+    fn plot_data(&self) -> serde_json::Value {
+        let date = self.date.map(|x| x.to_string());
+        let underwater = self.equity
+            .cum_max()
+            .iter()
+            .zip(self.equity.iter())
+            .map(|(x, y)| 100. * (y - x.max(0.)) / self.config.capital)
+            .collect_vec();
+        let m = &self.returns_matrix;
+        let z = m.monthly.map(|x| x.map(|v| if v.is_nan() { None } else { Some(*v) }));
+        json!({
+            "equity": [{ "x": date, "y": self.equity, "type": "scatter", "name": "equity" }],
+            "underwater": [{ "x": date, "y": underwater, "type": "scatter", "fill": "tozeroy", "name": "drawdown %" }],
+            "monthly": [{
+                "z": z,
+                "x": (1..=12).collect_vec(),
+                "y": m.years,
+                "type": "heatmap",
+                "colorscale": "RdBu",
+                "zmid": 0,
+            }],
+            "yearly": [{ "x": m.years, "y": m.yearly, "type": "bar", "name": "yearly %" }],
+        })
+    }
+
+    pub fn to_html(&self) -> String {
+        HtmlReport::default()
+            .section("summary", self.summary_html())
+            .section("top drawdowns", self.drawdown_html())
+            .plots(&["equity", "underwater", "monthly", "yearly"], self.plot_data())
+            .to_html()
+    }
+
+    pub fn save_html<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        std::fs::write(path, self.to_html())
+    }
+}
+/* #endregion */
@@ -0,0 +1,624 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+use chrono::Datelike;
+use polars::prelude::*;
+use qust::prelude::{dt, *};
+use qust::idct::expr::Expr as FactorExpr;
+use serde_json::json;
+use super::pl_util::ForecastRes;
+use crate::output::report::{html_table_kv, HtmlReport};
+
+/// Periods the per bar IC is aggregated by.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum FactorPeriod {
+    Day,
+    Week,
+    Month,
+    Year,
+    All,
+}
+
+impl FactorPeriod {
+    fn key(&self, t: &dt) -> String {
+        match self {
+            FactorPeriod::Day => t.format("%Y-%m-%d").to_string(),
+            FactorPeriod::Week => {
+                let week = t.date().iso_week();
+                format!("{}-W{:02}", week.year(), week.week())
+            }
+            FactorPeriod::Month => t.format("%Y-%m").to_string(),
+            FactorPeriod::Year => t.format("%Y").to_string(),
+            FactorPeriod::All => "all".to_string(),
+        }
+    }
+}
+
+/* #region Stats */
+/// Summary of an IC series, nan values are skipped.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IcStats {
+    pub mean: f32,
+    pub std: f32,
+    pub icir: f32,
+    pub t_stat: f32,
+    ///share of the periods with a positive IC
+    pub pos_ratio: f32,
+    pub n: usize,
+}
+
+impl IcStats {
+    pub fn new(ic: &[f32]) -> Self {
+        let data = ic.iter().filter(|x| x.is_finite()).cloned().collect_vec();
+        let n = data.len();
+        let mean = data.iter().sum::<f32>() / n as f32;
+        let std = if n > 1 {
+            (data.iter().map(|x| (x - mean).powi(2)).sum::<f32>() / (n - 1) as f32).sqrt()
+        } else {
+            f32::NAN
+        };
+        Self {
+            mean,
+            std,
+            icir: mean / std,
+            t_stat: mean / std * (n as f32).sqrt(),
+            pos_ratio: data.iter().filter(|x| **x > 0.).count() as f32 / n as f32,
+            n,
+        }
+    }
+}
+
+/// Spearman correlation of the pairs where both values are finite, nan if there are
+/// fewer than `min_obs` of them.
+pub fn rank_corr(x: &[f32], y: &[f32], min_obs: usize) -> f32 {
+    let (mut a, mut b): (v32, v32) = x
+        .iter()
+        .zip(y.iter())
+        .filter(|(x, y)| x.is_finite() && y.is_finite())
+        .map(|(x, y)| (*x, *y))
+        .unzip();
+    if a.len() < min_obs.max(2) {
+        return f32::NAN;
+    }
+    rank_row(&mut a);
+    rank_row(&mut b);
+    pearson(&a, &b)
+}
+
+fn pearson(x: &[f32], y: &[f32]) -> f32 {
+    let n = x.len() as f32;
+    let (mx, my) = (x.iter().sum::<f32>() / n, y.iter().sum::<f32>() / n);
+    let (mut sxy, mut sxx, mut syy) = (0f32, 0f32, 0f32);
+    for (a, b) in x.iter().zip(y.iter()) {
+        sxy += (a - mx) * (b - my);
+        sxx += (a - mx).powi(2);
+        syy += (b - my).powi(2);
+    }
+    if sxx == 0. || syy == 0. {
+        return f32::NAN;
+    }
+    sxy / (sxx * syy).sqrt()
+}
+
+/// Return from each bar to `h` bars later on the bars of the `Di` itself, nan for the last `h`.
+pub fn forward_return(c: &[f32], h: usize) -> v32 {
+    (0..c.len())
+        .map(|i| match c.get(i + h) {
+            Some(x) if c[i] != 0. => x / c[i] - 1.,
+            _ => f32::NAN,
+        })
+        .collect_vec()
+}
+/* #endregion */
+
+/* #region FactorData */
+/// A factor panel with its forward returns, one return panel per horizon, all on the
+/// index of the factor.
+#[derive(Debug, Clone)]
+pub struct FactorData {
+    pub factor: Panel,
+    pub horizons: Vec<usize>,
+    pub fwd: Vec<Panel>,
+    ///fewest tickers for a cross-sectional IC
+    pub min_obs: usize,
+}
+
+impl FactorData {
+    /// Errors if `horizons` is empty or holds a zero horizon.
+    pub fn new(dil: &Dil, factor: Panel, horizons: &[usize]) -> PolarsResult<Self> {
+        if horizons.is_empty() || horizons.contains(&0) {
+            let msg = format!("factor horizons must be non empty and positive, got {:?}", horizons);
+            return Err(PolarsError::ComputeError(msg.into()));
+        }
+        let fwd = horizons.map(|h| {
+            dil.panel(|di| forward_return(&di.c(), *h).into(), PanelFill::Nan)
+                .reindex_like(&factor)
+        });
+        Ok(Self { factor, horizons: horizons.to_vec(), fwd, min_obs: 3 })
+    }
+
+    pub fn from_expr(dil: &Dil, expr: &FactorExpr, horizons: &[usize]) -> PolarsResult<Self> {
+        Self::new(dil, dil.panel_expr(expr, PanelFill::Nan), horizons)
+    }
+
+    pub fn fwd_of(&self, h: usize) -> &Panel {
+        let i = self.horizons
+            .iter()
+            .position(|x| *x == h)
+            .expect("horizon not in FactorData::horizons");
+        &self.fwd[i]
+    }
+
+    fn ic_of(&self, factor: &Panel, fwd: &Panel) -> v32 {
+        (0..factor.len())
+            .map(|i| rank_corr(&factor.row(i), &fwd.row(i), self.min_obs))
+            .collect_vec()
+    }
+
+    /// Cross-sectional rank IC of every bar.
+    pub fn ic(&self, h: usize) -> v32 {
+        self.ic_of(&self.factor, self.fwd_of(h))
+    }
+
+    /// IC stats of each period, sorted by period.
+    pub fn ic_by_period(&self, h: usize, period: FactorPeriod) -> Vec<(String, IcStats)> {
+        let ic = self.ic(h);
+        let mut grp: BTreeMap<String, v32> = Default::default();
+        self.factor.time
+            .iter()
+            .zip(ic.iter())
+            .for_each(|(t, x)| grp.entry(period.key(t)).or_default().push(*x));
+        grp.into_iter().map(|(k, v)| (k, IcStats::new(&v))).collect_vec()
+    }
+
+    /// IC stats of every horizon.
+    pub fn ic_decay(&self) -> Vec<(usize, IcStats)> {
+        self.horizons
+            .iter()
+            .map(|h| (*h, IcStats::new(&self.ic(*h))))
+            .collect_vec()
+    }
+
+    /// Mean forward return of each of `n` factor quantiles at every bar.
+    pub fn quantile_returns(&self, h: usize, n: usize) -> QuantileReturns {
+        let bucket = self.factor.quantile_bucket(n);
+        let fwd = self.fwd_of(h);
+        let mut returns = vec![vec![f32::NAN; self.factor.len()]; n];
+        for i in 0..self.factor.len() {
+            let mut sum = vec![(0f32, 0usize); n];
+            for (b, r) in bucket.values.iter().zip(fwd.values.iter()) {
+                if b[i].is_finite() && r[i].is_finite() {
+                    let q = &mut sum[b[i] as usize];
+                    q.0 += r[i];
+                    q.1 += 1;
+                }
+            }
+            sum.iter().enumerate().for_each(|(q, (s, k))| {
+                if *k > 0 {
+                    returns[q][i] = s / *k as f32;
+                }
+            });
+        }
+        QuantileReturns { time: self.factor.time.clone(), horizon: h, returns }
+    }
+
+    /// Rank correlation of the factor with itself `lag` bars before.
+    pub fn autocorr(&self, lag: usize) -> v32 {
+        (0..self.factor.len())
+            .map(|i| {
+                if i < lag {
+                    return f32::NAN;
+                }
+                rank_corr(&self.factor.row(i), &self.factor.row(i - lag), self.min_obs)
+            })
+            .collect_vec()
+    }
+
+    /// Share of the tickers in the top and the bottom quantile that were not there
+    /// `lag` bars before.
+    pub fn turnover(&self, n: usize, lag: usize) -> (v32, v32) {
+        let bucket = self.factor.quantile_bucket(n);
+        let members = |i: usize, q: f32| {
+            bucket.values
+                .iter()
+                .enumerate()
+                .filter(|(_, x)| x[i] == q)
+                .map(|(j, _)| j)
+                .collect::<HashSet<_>>()
+        };
+        let turnover_of = |q: f32| {
+            (0..bucket.len())
+                .map(|i| {
+                    if i < lag {
+                        return f32::NAN;
+                    }
+                    let (now, pre) = (members(i, q), members(i - lag, q));
+                    if now.is_empty() || pre.is_empty() {
+                        return f32::NAN;
+                    }
+                    now.difference(&pre).count() as f32 / now.len() as f32
+                })
+                .collect_vec()
+        };
+        (turnover_of(n as f32 - 1.), turnover_of(0.))
+    }
+
+    /// IC stats computed inside each `ToSection` sector, tickers without a sector are left out.
+    pub fn sector_ic(&self, h: usize) -> Vec<(String, usize, IcStats)> {
+        let fwd = self.fwd_of(h);
+        let mut grp: hm<Comdty, Vec<usize>> = hm::new();
+        self.factor.tickers
+            .iter()
+            .enumerate()
+            .for_each(|(j, ticker)| {
+                if let Some(section) = ticker.try_section() {
+                    grp.entry(section).or_default().push(j);
+                }
+            });
+        let select = |panel: &Panel, columns: &[usize]| Panel {
+            time: panel.time.clone(),
+            tickers: columns.iter().map(|j| panel.tickers[*j]).collect_vec(),
+            values: columns.iter().map(|j| panel.values[*j].clone()).collect_vec(),
+        };
+        let mut res = grp
+            .into_iter()
+            .map(|(section, columns)| {
+                let ic = self.ic_of(&select(&self.factor, &columns), &select(fwd, &columns));
+                (format!("{:?}", section), columns.len(), IcStats::new(&ic))
+            })
+            .collect_vec();
+        res.sort_by(|a, b| a.0.cmp(&b.0));
+        res
+    }
+
+    /// Errors if the config asks for no quantile or a horizon this `FactorData` does not hold.
+    pub fn report(&self, config: &FactorReportConfig) -> PolarsResult<FactorReport> {
+        if config.quantiles == 0 {
+            return Err(PolarsError::ComputeError("factor report needs at least one quantile".into()));
+        }
+        let h = match (config.horizon, self.horizons.first()) {
+            (Some(h), _) if self.horizons.contains(&h) => h,
+            (None, Some(h)) => *h,
+            _ => {
+                let msg = format!("horizon {:?} not in the factor horizons {:?}", config.horizon, self.horizons);
+                return Err(PolarsError::ComputeError(msg.into()));
+            }
+        };
+        let (turnover_top, turnover_bottom) = self.turnover(config.quantiles, config.lag);
+        Ok(FactorReport {
+            config: config.clone(),
+            horizon: h,
+            time: self.factor.time.clone(),
+            ic: self.ic(h),
+            ic_by_period: self.ic_by_period(h, config.period),
+            ic_decay: self.ic_decay(),
+            quantile: self.quantile_returns(h, config.quantiles),
+            autocorr: self.autocorr(config.lag),
+            turnover_top,
+            turnover_bottom,
+            sector_ic: self.sector_ic(h),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuantileReturns {
+    pub time: vdt,
+    pub horizon: usize,
+    ///`returns[q]` is the return series of quantile `q`, 0 is the lowest factor value
+    pub returns: Vec<v32>,
+}
+
+impl QuantileReturns {
+    /// Top minus bottom quantile, nan if there is no quantile.
+    pub fn spread(&self) -> v32 {
+        match (self.returns.last(), self.returns.first()) {
+            (Some(top), Some(bottom)) => top.iter().zip(bottom.iter()).map(|(a, b)| a - b).collect_vec(),
+            _ => vec![f32::NAN; self.time.len()],
+        }
+    }
+
+    /// Mean return per bar of each quantile, nan bars are skipped.
+    pub fn mean(&self) -> v32 {
+        self.returns.map(|x| {
+            let data = x.iter().filter(|v| v.is_finite()).collect_vec();
+            data.iter().cloned().sum::<f32>() / data.len() as f32
+        })
+    }
+
+    /// Cumulative sum of the returns divided by the horizon, an approximation of holding
+    /// each quantile with overlapping positions.
+    pub fn cum(&self) -> Vec<v32> {
+        self.returns
+            .iter()
+            .chain(std::iter::once(&self.spread()))
+            .map(|x| {
+                let mut s = 0.;
+                x.iter()
+                    .map(|v| {
+                        if v.is_finite() {
+                            s += v / self.horizon as f32;
+                        }
+                        s
+                    })
+                    .collect_vec()
+            })
+            .collect_vec()
+    }
+}
+/* #endregion */
+
+/* #region DataFrame */
+fn ic_stats_columns<'a, T: 'a>(stats: impl Iterator<Item = &'a (T, IcStats)> + Clone) -> Vec<Column> {
+    let field = |name: &str, f: fn(&IcStats) -> f32| {
+        Column::Series(Series::new(name.into(), stats.clone().map(|x| f(&x.1)).collect_vec()).into())
+    };
+    vec![
+        field("ic_mean", |x| x.mean),
+        field("ic_std", |x| x.std),
+        field("icir", |x| x.icir),
+        field("t_stat", |x| x.t_stat),
+        field("pos_ratio", |x| x.pos_ratio),
+        Column::Series(Series::new("n".into(), stats.map(|x| x.1.n as u32).collect_vec()).into()),
+    ]
+}
+
+impl FactorData {
+    pub fn ic_df(&self) -> PolarsResult<DataFrame> {
+        let mut columns = vec![Column::Series(Series::new("t".into(), self.factor.time.clone()).into())];
+        for h in self.horizons.iter() {
+            columns.push(Column::Series(Series::new(format!("ic_{}", h).into(), self.ic(*h)).into()));
+        }
+        DataFrame::new(columns)
+    }
+
+    pub fn ic_by_period_df(&self, h: usize, period: FactorPeriod) -> PolarsResult<DataFrame> {
+        let data = self.ic_by_period(h, period);
+        let mut columns = vec![
+            Column::Series(Series::new("period".into(), data.map(|x| x.0.clone())).into())
+        ];
+        columns.extend(ic_stats_columns(data.iter()));
+        DataFrame::new(columns)
+    }
+
+    pub fn ic_decay_df(&self) -> PolarsResult<DataFrame> {
+        let data = self.ic_decay();
+        let mut columns = vec![
+            Column::Series(Series::new("horizon".into(), data.map(|x| x.0 as u32)).into())
+        ];
+        columns.extend(ic_stats_columns(data.iter()));
+        DataFrame::new(columns)
+    }
+
+    pub fn stability_df(&self, n: usize, lag: usize) -> PolarsResult<DataFrame> {
+        let (top, bottom) = self.turnover(n, lag);
+        df!(
+            "t" => &self.factor.time,
+            "autocorr" => self.autocorr(lag),
+            "turnover_top" => top,
+            "turnover_bottom" => bottom,
+        )
+    }
+
+    pub fn sector_ic_df(&self, h: usize) -> PolarsResult<DataFrame> {
+        let data = self.sector_ic(h);
+        let stats = data.iter().map(|x| (x.0.clone(), x.2.clone())).collect_vec();
+        let mut columns = vec![
+            Column::Series(Series::new("sector".into(), data.map(|x| x.0.clone())).into()),
+            Column::Series(Series::new("tickers".into(), data.map(|x| x.1 as u32)).into()),
+        ];
+        columns.extend(ic_stats_columns(stats.iter()));
+        DataFrame::new(columns)
+    }
+}
+
+impl QuantileReturns {
+    /// One column per quantile plus the top minus bottom spread.
+    pub fn to_df(&self) -> PolarsResult<DataFrame> {
+        let mut columns = vec![Column::Series(Series::new("t".into(), self.time.clone()).into())];
+        for (q, x) in self.returns.iter().enumerate() {
+            columns.push(Column::Series(Series::new(format!("q{}", q).into(), x.clone()).into()));
+        }
+        columns.push(Column::Series(Series::new("spread".into(), self.spread()).into()));
+        DataFrame::new(columns)
+    }
+
+    pub fn mean_df(&self) -> PolarsResult<DataFrame> {
+        df!(
+            "quantile" => (0..self.returns.len() as u32).collect_vec(),
+            "mean" => self.mean(),
+        )
+    }
+}
+
+impl ForecastRes {
+    /// Rank IC of `predict` against `y` over the whole sample.
+    pub fn rank_ic(&self) -> f32 {
+        rank_corr(&self.predict, &self.y, 2)
+    }
+
+    /// Mean and count of `y` in each of `n` quantiles of `predict`.
+    pub fn quantile_y(&self, n: usize) -> PolarsResult<DataFrame> {
+        self.to_df()?
+            .lazy()
+            .with_column(
+                (col("predict").rank(RankOptions { method: RankMethod::Average, descending: false }, None).cast(DataType::Float64)
+                    * lit(n as f64)
+                    / col("predict").len().cast(DataType::Float64))
+                    .ceil()
+                    .cast(DataType::Int32)
+                    .alias("quantile"),
+            )
+            .group_by([col("quantile")])
+            .agg([
+                col("y").mean().alias("mean"),
+                col("y").len().alias("num"),
+            ])
+            .sort(["quantile"], Default::default())
+            .collect()
+    }
+}
+/* #endregion */
+
+/* #region Html */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FactorReportConfig {
+    ///horizon of the IC and quantile charts, the first of `FactorData::horizons` if None
+    pub horizon: Option<usize>,
+    pub quantiles: usize,
+    pub period: FactorPeriod,
+    ///lag of the autocorrelation and turnover
+    pub lag: usize,
+}
+
+impl Default for FactorReportConfig {
+    fn default() -> Self {
+        Self { horizon: None, quantiles: 5, period: FactorPeriod::Month, lag: 1 }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FactorReport {
+    pub config: FactorReportConfig,
+    pub horizon: usize,
+    pub time: vdt,
+    pub ic: v32,
+    pub ic_by_period: Vec<(String, IcStats)>,
+    pub ic_decay: Vec<(usize, IcStats)>,
+    pub quantile: QuantileReturns,
+    pub autocorr: v32,
+    pub turnover_top: v32,
+    pub turnover_bottom: v32,
+    pub sector_ic: Vec<(String, usize, IcStats)>,
+}
+
+fn nan_to_none(data: &[f32]) -> Vec<Option<f32>> {
+    data.map(|x| if x.is_finite() { Some(*x) } else { None })
+}
+
+impl FactorReport {
+    fn summary_html(&self) -> String {
+        let s = IcStats::new(&self.ic);
+        let mean = |x: &[f32]| IcStats::new(x).mean;
+        [
+            ("horizon", format!("{}", self.horizon)),
+            ("ic mean", format!("{:.4}", s.mean)),
+            ("ic std", format!("{:.4}", s.std)),
+            ("icir", format!("{:.3}", s.icir)),
+            ("t stat", format!("{:.2}", s.t_stat)),
+            ("ic > 0", format!("{:.2}%", 100. * s.pos_ratio)),
+            ("autocorr", format!("{:.3}", mean(&self.autocorr))),
+            ("turnover top", format!("{:.2}%", 100. * mean(&self.turnover_top))),
+            ("turnover bottom", format!("{:.2}%", 100. * mean(&self.turnover_bottom))),
+        ]
+            .pip(|x| html_table_kv(&x))
+    }
+
+    fn plot_data(&self) -> serde_json::Value {
+        let time = self.time.map(|x| x.to_string());
+        let mut cum_ic = 0.;
+        let cum_ic = self.ic
+            .iter()
+            .map(|x| {
+                if x.is_finite() {
+                    cum_ic += x;
+                }
+                cum_ic
+            })
+            .collect_vec();
+        let n = self.quantile.returns.len();
+        let quantile_cum = self.quantile
+            .cum()
+            .into_iter()
+            .enumerate()
+            .map(|(q, x)| {
+                let name = if q == n { "spread".to_string() } else { format!("q{}", q) };
+                json!({ "x": time, "y": x, "type": "scatter", "name": name })
+            })
+            .collect_vec();
+        json!({
+            "ic": [{ "x": time, "y": cum_ic, "type": "scatter", "name": "cumulative ic" }],
+            "period": [{
+                "x": self.ic_by_period.map(|x| x.0.clone()),
+                "y": self.ic_by_period.map(|x| x.1.mean),
+                "type": "bar",
+                "name": "ic mean",
+            }],
+            "decay": [{
+                "x": self.ic_decay.map(|x| x.0),
+                "y": self.ic_decay.map(|x| x.1.mean),
+                "type": "bar",
+                "name": "ic mean",
+            }],
+            "quantile": [{
+                "x": (0..n).collect_vec(),
+                "y": nan_to_none(&self.quantile.mean()),
+                "type": "bar",
+                "name": "mean return",
+            }],
+            "quantile_cum": quantile_cum,
+            "stability": [
+                { "x": time, "y": nan_to_none(&self.autocorr), "type": "scatter", "name": "autocorr" },
+                { "x": time, "y": nan_to_none(&self.turnover_top), "type": "scatter", "name": "turnover top" },
+                { "x": time, "y": nan_to_none(&self.turnover_bottom), "type": "scatter", "name": "turnover bottom" },
+            ],
+            "sector": [{
+                "x": self.sector_ic.map(|x| x.0.clone()),
+                "y": self.sector_ic.map(|x| x.2.mean),
+                "type": "bar",
+                "name": "ic mean",
+            }],
+        })
+    }
+
+    pub fn to_html(&self) -> String {
+        HtmlReport::default()
+            .section("factor summary", self.summary_html())
+            .plots(
+                &["ic", "period", "decay", "quantile", "quantile_cum", "stability", "sector"],
+                self.plot_data(),
+            )
+            .to_html()
+    }
+
+    pub fn save_html<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        std::fs::write(path, self.to_html())
+    }
+}
+/* #endregion */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty_panel() -> Panel {
+        Panel { time: vec![], tickers: vec![], values: vec![] }
+    }
+
+    #[test]
+    fn empty_or_zero_horizons_are_rejected() {
+        let dil = Dil { dil: vec![] };
+        assert!(FactorData::new(&dil, empty_panel(), &[]).is_err());
+        assert!(FactorData::new(&dil, empty_panel(), &[0, 5]).is_err());
+    }
+
+    #[test]
+    fn report_rejects_zero_quantiles_and_unknown_horizon() {
+        let data = FactorData { factor: empty_panel(), horizons: vec![], fwd: vec![], min_obs: 3 };
+        assert!(data.report(&FactorReportConfig::default()).is_err());
+        let data = FactorData { horizons: vec![1], fwd: vec![empty_panel()], ..data };
+        let config = FactorReportConfig { quantiles: 0, ..Default::default() };
+        assert!(data.report(&config).is_err());
+        let config = FactorReportConfig { horizon: Some(5), ..Default::default() };
+        assert!(data.report(&config).is_err());
+        assert!(data.report(&FactorReportConfig::default()).is_ok());
+    }
+
+    #[test]
+    fn spread_without_quantiles_is_nan() {
+        let t = dt::parse_from_str("2024-01-02 09:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let res = QuantileReturns { time: vec![t], horizon: 1, returns: vec![] };
+        assert!(res.spread()[0].is_nan());
+        let res = QuantileReturns { returns: vec![vec![0.01], vec![0.03]], ..res };
+        assert!((res.spread()[0] - 0.02).abs() < 1e-7);
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::path::{ Path, PathBuf };
+use polars::prelude::*;
+use qust::prelude::*;
+use crate::prelude::{ Stats, StatsRes };
+
+/* #region Grid */
+pub trait ToGrid {
+    type Output;
+    fn to_grid(&self) -> Vec<Self::Output>;
+}
+
+impl<A: Clone> ToGrid for (Vec<A>,) {
+    type Output = (A,);
+    fn to_grid(&self) -> Vec<Self::Output> {
+        self.0.map(|a| (a.clone(),))
+    }
+}
+
+impl<A: Clone, B: Clone> ToGrid for (Vec<A>, Vec<B>) {
+    type Output = (A, B);
+    fn to_grid(&self) -> Vec<Self::Output> {
+        self.0
+            .iter()
+            .cartesian_product(self.1.iter())
+            .map(|(a, b)| (a.clone(), b.clone()))
+            .collect_vec()
+    }
+}
+
+impl<A: Clone, B: Clone, C: Clone> ToGrid for (Vec<A>, Vec<B>, Vec<C>) {
+    type Output = (A, B, C);
+    fn to_grid(&self) -> Vec<Self::Output> {
+        (self.0.clone(), self.1.clone())
+            .to_grid()
+            .into_iter()
+            .cartesian_product(self.2.iter())
+            .map(|((a, b), c)| (a, b, c.clone()))
+            .collect_vec()
+    }
+}
+
+impl<A: Clone, B: Clone, C: Clone, D: Clone> ToGrid for (Vec<A>, Vec<B>, Vec<C>, Vec<D>) {
+    type Output = (A, B, C, D);
+    fn to_grid(&self) -> Vec<Self::Output> {
+        (self.0.clone(), self.1.clone(), self.2.clone())
+            .to_grid()
+            .into_iter()
+            .cartesian_product(self.3.iter())
+            .map(|((a, b, c), d)| (a, b, c, d.clone()))
+            .collect_vec()
+    }
+}
+/* #endregion */
+
+/* #region ParamSweep */
+/// Runs `gen_ptm` over every param of `grid` on a `Dil`, one batch at a time.
+/// Each finished batch is written to `path` as `pnl_{n}.parquet` and `stats_{n}.parquet`,
+/// params already present in the stats files are skipped, so an interrupted sweep
+/// picks up where it stopped.
+pub struct ParamSweep<T, F> {
+    pub grid: Vec<T>,
+    pub gen_ptm: F,
+    pub comm_slip: CommSlip,
+    pub path: PathBuf,
+    pub batch_size: usize,
+}
+
+impl<T, F> ParamSweep<T, F>
+where
+    T: Serialize,
+    F: Fn(&T) -> Ptm,
+{
+    pub fn new<P: AsRef<Path>>(grid: Vec<T>, gen_ptm: F, path: P) -> Self {
+        Self {
+            grid,
+            gen_ptm,
+            comm_slip: cs2,
+            path: path.as_ref().to_path_buf(),
+            batch_size: 20,
+        }
+    }
+
+    pub fn param_key(param: &T) -> PolarsResult<String> {
+        serde_json::to_string(param).map_err(|e| PolarsError::ComputeError(e.to_string().into()))
+    }
+
+    pub fn finished_keys(&self) -> PolarsResult<HashSet<String>> {
+        let df = self.load_stats()?;
+        if df.height() == 0 {
+            return Ok(HashSet::new());
+        }
+        let res = df
+            .column("param")?
+            .as_materialized_series()
+            .str()?
+            .into_no_null_iter()
+            .map(|x| x.to_string())
+            .collect();
+        Ok(res)
+    }
+
+    pub fn run(&self, dil: &Dil) -> PolarsResult<DataFrame> {
+        std::fs::create_dir_all(&self.path)?;
+        let finished = self.finished_keys()?;
+        let mut pending = Vec::with_capacity(self.grid.len());
+        for param in self.grid.iter() {
+            let key = Self::param_key(param)?;
+            if !finished.contains(&key) {
+                pending.push((key, param));
+            }
+        }
+        let batch_start = self.batch_files("stats_")?
+            .iter()
+            .filter_map(|x| batch_index(x, "stats_"))
+            .max()
+            .map(|x| x + 1)
+            .unwrap_or(0);
+        for (n, chunk) in pending.chunks(self.batch_size.max(1)).enumerate() {
+            let stral = chunk
+                .iter()
+                .enumerate()
+                .flat_map(|(i, (_, param))| {
+                    let ptm = (self.gen_ptm)(param);
+                    (&ptm, dil)
+                        .to_stral_bare()
+                        .0
+                        .into_iter()
+                        .map(move |stra| Stra { name: stra.name.set_id(i), ..stra })
+                })
+                .collect_vec()
+                .to_stral_bare();
+            let res = stral.dil(dil).calc(Aee(self.comm_slip.clone()));
+            let keys = chunk.map(|(k, _)| k.clone());
+            let (mut df_pnl, mut df_stats) = sweep_frames(&keys, &res)?;
+            let n = batch_start + n;
+            write_parquet_atomic(&mut df_pnl, &self.path.join(format!("pnl_{n}.parquet")))?;
+            write_parquet_atomic(&mut df_stats, &self.path.join(format!("stats_{n}.parquet")))?;
+        }
+        self.load_stats()
+    }
+
+    pub fn load_stats(&self) -> PolarsResult<DataFrame> {
+        self.load_batches("stats_")
+    }
+
+    pub fn load_pnl(&self) -> PolarsResult<DataFrame> {
+        self.load_batches("pnl_")
+    }
+
+    fn batch_files(&self, prefix: &str) -> PolarsResult<Vec<PathBuf>> {
+        if !self.path.exists() {
+            return Ok(vec![]);
+        }
+        let mut res = std::fs::read_dir(&self.path)?
+            .filter_map(|x| x.ok().map(|x| x.path()))
+            .filter(|x| batch_index(x, prefix).is_some())
+            .collect_vec();
+        res.sort_by_key(|x| batch_index(x, prefix));
+        Ok(res)
+    }
+
+    fn load_batches(&self, prefix: &str) -> PolarsResult<DataFrame> {
+        let mut res: Option<DataFrame> = None;
+        for path in self.batch_files(prefix)? {
+            let df = ParquetReader::new(File::open(path)?).finish()?;
+            match res.as_mut() {
+                Some(accu) => { accu.vstack_mut(&df)?; }
+                None => res = Some(df),
+            }
+        }
+        Ok(res.unwrap_or_else(DataFrame::empty))
+    }
+}
+
+fn sweep_frames(keys: &[String], res: &[InfoPnlRes<Stra, da>]) -> PolarsResult<(DataFrame, DataFrame)> {
+    let mut pnl_param = vec![];
+    let mut pnl_ticker = vec![];
+    let mut pnl_date = vec![];
+    let mut pnl_value = vec![];
+    let mut stats_param = vec![];
+    let mut stats_ticker = vec![];
+    let mut stats_vec: Vec<StatsRes> = vec![];
+    for InfoPnlRes(stra, pnl) in res.iter() {
+        let key = &keys[stra.name.id.unwrap()];
+        let ticker = stra.ident.ticker.debug_string();
+        pnl.0.iter().zip(pnl.1[0].iter()).for_each(|(t, v)| {
+            pnl_param.push(key.clone());
+            pnl_ticker.push(ticker.clone());
+            pnl_date.push(*t);
+            pnl_value.push(*v);
+        });
+        stats_param.push(key.clone());
+        stats_ticker.push(ticker);
+        stats_vec.push(pnl.stats());
+    }
+    let df_pnl = df!(
+        "param" => pnl_param,
+        "ticker" => pnl_ticker,
+        "date" => pnl_date,
+        "pnl" => pnl_value,
+    )?;
+    let df_stats = df!(
+        "param" => stats_param,
+        "ticker" => stats_ticker,
+        "ret" => stats_vec.map(|x| x.ret),
+        "sr" => stats_vec.map(|x| x.sr),
+        "cratio" => stats_vec.map(|x| x.cratio),
+        "profit" => stats_vec.map(|x| x.profit),
+        "comm" => stats_vec.map(|x| x.comm),
+        "slip" => stats_vec.map(|x| x.slip),
+        "to_day" => stats_vec.map(|x| x.to_day),
+        "to_sum" => stats_vec.map(|x| x.to_sum),
+        "hold" => stats_vec.map(|x| x.hold),
+        "std" => stats_vec.map(|x| x.std),
+        "mdd" => stats_vec.map(|x| x.mdd),
+    )?;
+    Ok((df_pnl, df_stats))
+}
+
+fn write_parquet_atomic(df: &mut DataFrame, path: &Path) -> PolarsResult<()> {
+    let path_tmp = path.with_extension("parquet.tmp");
+    ParquetWriter::new(File::create(&path_tmp)?).finish(df)?;
+    std::fs::rename(path_tmp, path)?;
+    Ok(())
+}
+
+/// `n` of a batch file named `{prefix}{n}.parquet`.
+fn batch_index(path: &Path, prefix: &str) -> Option<usize> {
+    path.file_name()?
+        .to_str()?
+        .strip_prefix(prefix)?
+        .strip_suffix(".parquet")?
+        .parse()
+        .ok()
+}
+/* #endregion */
@@ -114,5 +114,5 @@ async fn main() {
     let stra_api = vec![stra1, stra2].to_stra_api();
     let account = SimnowAccount("171808", "Tangjihede00").config_parse();//account , password
     let running_api = running_api_ctp(stra_api, account);
-    run_ctp(running_api).await;
+    run_ctp(running_api, LiveConfig::default()).await;
 }
@@ -3,7 +3,7 @@ use super::pms::*;
 use crate::idct::dcon::Convert;
 use crate::sig::livesig::LiveSig;
 use crate::std_prelude::*;
-use crate::trade::di::{Di, PriceArc};
+use crate::trade::di::{Di, KlineInfo, PriceArc};
 use qust_derive::ta_derive;
 use qust_derive::AsRef;
 use qust_ds::prelude::*;
@@ -11,6 +11,7 @@ use dyn_clone::{clone_trait_object, DynClone};
 use std::any::Any;
 use std::fmt::Debug;
 use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 
 /* #region Calc Type */
 pub trait Calc<R>: DynClone + Send + Sync + Debug + 'static {
@@ -47,57 +48,42 @@ impl<R: 'static> Eq for dyn Calc<R> {}
 
 impl Calc<PriceArc> for Convert {
     fn calc(&self, di: &Di) -> PriceArc {
-        if di
-            .data_save
-            .save_dcon
-            .read()
-            .unwrap()
-            .contains_key(&self.to_box())
-        {
-            di.data_save.save_dcon.read().unwrap()[&self.to_box()].clone()
-        } else {
-            let price_pre = self.get_pre(di);
-            let res = self.convert(price_pre, di);
-            di.data_save
-                .save_dcon
-                .write()
-                .unwrap()
-                .insert(self.to_box(), res.clone());
-            res
+        let key = CalcKey::new(CacheKind::Dcon, self.id());
+        if let Some(CacheValue::Dcon(res)) = di.data_save.get(&key) {
+            return res;
         }
+        let price_pre = self.get_pre(di);
+        let res = self.convert(price_pre, di);
+        let deps = match self {
+            Convert::PreNow(pre, _) => vec![CalcKey::new(CacheKind::Dcon, pre.id())],
+            _ => vec![],
+        };
+        di.data_save.insert(key, CacheValue::Dcon(res.clone()), deps);
+        res
     }
 }
 
 impl Calc<avv32> for Pms {
     fn calc(&self, di: &Di) -> avv32 {
-        if di
-            .data_save
-            .save_pms2d
-            .read()
-            .unwrap()
-            .contains_key(&self.to_box())
-        {
-            di.data_save.save_pms2d.read().unwrap()[&self.to_box()].clone()
-        } else {
-            di.dcon.write().unwrap().push(self.dcon.clone());
-            di.part.write().unwrap().push(self.part.clone());
-            self.fore.start(di);
-            let res = di
-                .last_part()
-                .calc_part(di, self.fore.clone())
-                .into_iter()
-                .map(Arc::new)
-                .collect_vec();
-            self.fore.end(di);
-            di.dcon.write().unwrap().pop();
-            di.part.write().unwrap().pop();
-            di.data_save
-                .save_pms2d
-                .write()
-                .unwrap()
-                .insert(self.to_box(), res.clone());
-            res
+        let key = CalcKey::new(CacheKind::Pms, self.id());
+        if let Some(CacheValue::Pms(res)) = di.data_save.get(&key) {
+            return res;
         }
+        di.dcon.write().unwrap().push(self.dcon.clone());
+        di.part.write().unwrap().push(self.part.clone());
+        self.fore.start(di);
+        let res = di
+            .last_part()
+            .calc_part(di, self.fore.clone())
+            .into_iter()
+            .map(Arc::new)
+            .collect_vec();
+        self.fore.end(di);
+        di.dcon.write().unwrap().pop();
+        di.part.write().unwrap().pop();
+        let deps = vec![CalcKey::new(CacheKind::Dcon, self.dcon.id())];
+        di.data_save.insert(key, CacheValue::Pms(res.clone()), deps);
+        res
     }
 }
 
@@ -113,25 +99,16 @@ where
     R: Send + Sync + 'static,
 {
     fn calc(&self, di: &Di) -> Arc<BoxAny> {
-        if di
-            .data_save
-            .save_livesig
-            .read()
-            .unwrap()
-            .contains_key(&self.to_box())
-        {
-            let data = di.data_save.save_livesig.read().unwrap()[&self.to_box()].clone();
+        let key = CalcKey::new(CacheKind::LiveSig, self.id());
+        if let Some(CacheValue::Any(data)) = di.data_save.get(&key) {
             self.update(di, data.downcast_ref::<RwLock<R>>().unwrap());
             data
         } else {
             let res = self.get_data(di);
             self.update(di, &res);
+            let bytes = self.state_bytes(di, &res);
             let data: Arc<BoxAny> = Arc::new(Box::new(res));
-            di.data_save
-                .save_livesig
-                .write()
-                .unwrap()
-                .insert(self.to_box(), data.clone());
+            di.data_save.insert_sized(key, CacheValue::Any(data.clone()), vec![], bytes);
             data
         }
     }
@@ -178,22 +155,13 @@ where
     R: Send + Sync + 'static,
 {
     fn calc(&self, di: &Di) -> ABoxAny {
-        if di
-            .data_save
-            .save_others
-            .read()
-            .unwrap()
-            .contains_key(&self.to_box())
-        {
-            di.data_save.save_others.read().unwrap()[&self.to_box()].clone()
+        let key = CalcKey::new(CacheKind::Others, self.id());
+        if let Some(CacheValue::Any(data)) = di.data_save.get(&key) {
+            data
         } else {
             let res = self.0.calc_save(di);
             let data: Arc<BoxAny> = Arc::new(Box::new(res));
-            di.data_save
-                .save_others
-                .write()
-                .unwrap()
-                .insert(self.to_box(), data.clone());
+            di.data_save.insert(key, CacheValue::Any(data.clone()), vec![]);
             data
         }
     }
@@ -202,55 +170,356 @@ where
 /* #region DataSave */
 pub type BoxAny = Box<dyn Any + Sync + Send>;
 pub type ABoxAny = Arc<BoxAny>;
-type Hmt<T> = hm<Box<dyn Calc<T>>, T>;
+
+/// Default memory budget of one `DataSave`, read when the `DataSave` is made.
+pub static DATA_SAVE_BUDGET: AtomicUsize = AtomicUsize::new(512 << 20);
+
+/// `LiveSig` states kept by one `DataSave` at most, the least recently used go first.
+pub const LIVESIG_CAP: usize = 150;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CacheKind {
+    Dcon,
+    Pms,
+    LiveSig,
+    Others,
+}
+
+impl CacheKind {
+    const ALL: [CacheKind; 4] = [CacheKind::Dcon, CacheKind::Pms, CacheKind::LiveSig, CacheKind::Others];
+}
+
+/// Keyed by the whole id of the `Calc`, so two `Calc`s never share an entry. The id is
+/// formatted once per `calc` and is shared by the key, the entry and the dependents.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CalcKey {
+    pub kind: CacheKind,
+    pub id: Arc<str>,
+}
+
+impl CalcKey {
+    pub fn new(kind: CacheKind, id: String) -> Self {
+        Self { kind, id: id.into() }
+    }
+}
+
+#[derive(Clone)]
+pub enum CacheValue {
+    Dcon(PriceArc),
+    Pms(avv32),
+    Any(ABoxAny),
+}
+
+impl CacheValue {
+    /// Estimated heap size, `Any` values are only sized for the common vector types.
+    pub fn bytes(&self) -> usize {
+        match self {
+            CacheValue::Dcon(x) => {
+                x.t.len() * std::mem::size_of::<dt>()
+                    + (x.o.len() + x.h.len() + x.l.len() + x.c.len() + x.v.len()) * 4
+                    + x.ki.len() * std::mem::size_of::<KlineInfo>()
+                    + x.immut_info.iter().flat_map(|x| x.iter()).map(|x| x.len() * 4).sum::<usize>()
+            }
+            CacheValue::Pms(x) => x.iter().map(|x| x.len() * 4).sum(),
+            CacheValue::Any(x) => {
+                if let Some(x) = x.downcast_ref::<v32>() {
+                    x.len() * 4
+                } else if let Some(x) = x.downcast_ref::<vv32>() {
+                    x.iter().map(|x| x.len() * 4).sum()
+                } else if let Some(x) = x.downcast_ref::<vuz>() {
+                    x.len() * std::mem::size_of::<usize>()
+                } else if let Some(x) = x.downcast_ref::<Vec<vuz>>() {
+                    x.iter().map(|x| x.len() * std::mem::size_of::<usize>()).sum()
+                } else {
+                    std::mem::size_of::<BoxAny>()
+                }
+            }
+        }
+    }
+}
+
+struct CacheEntry {
+    value: CacheValue,
+    bytes: usize,
+    last_used: AtomicU64,
+}
+
 #[derive(Default)]
+struct CacheStore {
+    entries: hm<CalcKey, CacheEntry>,
+    /// key -> keys computed from it
+    dependents: hm<CalcKey, Vec<CalcKey>>,
+    bytes: usize,
+}
+
+impl CacheStore {
+    fn remove(&mut self, key: &CalcKey) -> usize {
+        let mut n = 0;
+        let mut stack = vec![key.clone()];
+        while let Some(key) = stack.pop() {
+            if let Some(entry) = self.entries.remove(&key) {
+                self.bytes -= entry.bytes;
+                n += 1;
+            }
+            if let Some(dependents) = self.dependents.remove(&key) {
+                stack.extend(dependents);
+            }
+        }
+        n
+    }
+
+    fn retain<F: Fn(&CalcKey) -> bool>(&mut self, f: F) -> usize {
+        let keys = self.entries.keys().filter(|x| !f(x)).cloned().collect_vec();
+        keys.iter().map(|x| self.remove(x)).sum()
+    }
+
+    /// Drop the links to entries that are gone. A link from a gone entry is kept while the
+    /// entries computed from it are cached, they still go when it is invalidated.
+    fn prune_dependents(&mut self) {
+        let entries = &self.entries;
+        self.dependents.retain(|_, dependents| {
+            dependents.retain(|x| entries.contains_key(x));
+            !dependents.is_empty()
+        });
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub inserts: u64,
+    pub evictions: u64,
+    pub invalidations: u64,
+    pub entries: usize,
+    pub bytes: usize,
+}
+
+impl CacheStats {
+    pub fn hit_rate(&self) -> f32 {
+        self.hits as f32 / (self.hits + self.misses).max(1) as f32
+    }
+
+    pub fn merge(mut self, other: &CacheStats) -> Self {
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.inserts += other.inserts;
+        self.evictions += other.evictions;
+        self.invalidations += other.invalidations;
+        self.entries += other.entries;
+        self.bytes += other.bytes;
+        self
+    }
+}
+
+/// Cached `Calc` results of one `Di`. Entries know the entries they were computed from,
+/// removing one removes everything built on it. When the cached bytes pass `budget` the
+/// least recently used entries are evicted. `LiveSig` states are updated in place, so an
+/// insert only evicts them past `LIVESIG_CAP`, `clear_with_condition` evicts them as any
+/// other entry.
 pub struct DataSave {
-    pub save_dcon: RwLock<Hmt<PriceArc>>,
-    pub save_pms2d: RwLock<Hmt<avv32>>,
-    pub save_livesig: RwLock<Hmt<ABoxAny>>,
-    pub save_others: RwLock<Hmt<ABoxAny>>,
+    store: RwLock<CacheStore>,
+    clock: AtomicU64,
+    budget: AtomicUsize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    inserts: AtomicU64,
+    evictions: AtomicU64,
+    invalidations: AtomicU64,
     pub save_any: RwLock<hm<String, ABoxAny>>,
 }
 
+impl Default for DataSave {
+    fn default() -> Self {
+        Self {
+            store: Default::default(),
+            clock: Default::default(),
+            budget: AtomicUsize::new(DATA_SAVE_BUDGET.load(Ordering::Relaxed)),
+            hits: Default::default(),
+            misses: Default::default(),
+            inserts: Default::default(),
+            evictions: Default::default(),
+            invalidations: Default::default(),
+            save_any: Default::default(),
+        }
+    }
+}
+
 impl DataSave {
+    pub fn get(&self, key: &CalcKey) -> Option<CacheValue> {
+        let store = self.store.read().unwrap();
+        match store.entries.get(key) {
+            Some(entry) => {
+                entry.last_used.store(self.clock.fetch_add(1, Ordering::Relaxed), Ordering::Relaxed);
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(entry.value.clone())
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    pub fn insert(&self, key: CalcKey, value: CacheValue, deps: Vec<CalcKey>) {
+        let bytes = value.bytes();
+        self.insert_sized(key, value, deps, bytes);
+    }
+
+    /// `insert` with the size of the value given, for values `CacheValue::bytes` can not size.
+    pub fn insert_sized(&self, key: CalcKey, value: CacheValue, deps: Vec<CalcKey>, bytes: usize) {
+        let entry = CacheEntry {
+            value,
+            bytes,
+            last_used: AtomicU64::new(self.clock.fetch_add(1, Ordering::Relaxed)),
+        };
+        let mut store = self.store.write().unwrap();
+        if let Some(old) = store.entries.insert(key.clone(), entry) {
+            store.bytes -= old.bytes;
+        }
+        store.bytes += bytes;
+        for dep in deps {
+            let dependents = store.dependents.entry(dep).or_default();
+            if !dependents.contains(&key) {
+                dependents.push(key.clone());
+            }
+        }
+        self.inserts.fetch_add(1, Ordering::Relaxed);
+        self.evict(&mut store, Some(&key), false);
+    }
+
+    /// Evict down to the budget, `LiveSig` states too when `livesig`, and down to
+    /// `LIVESIG_CAP` states either way.
+    fn evict(&self, store: &mut CacheStore, keep: Option<&CalcKey>, livesig: bool) {
+        let budget = self.budget.load(Ordering::Relaxed);
+        let mut n_livesig = store.entries.keys().filter(|x| x.kind == CacheKind::LiveSig).count();
+        if store.bytes <= budget && n_livesig <= LIVESIG_CAP {
+            return;
+        }
+        let mut candidates = store.entries
+            .iter()
+            .filter(|(k, _)| Some(*k) != keep)
+            .map(|(k, v)| (v.last_used.load(Ordering::Relaxed), k.clone()))
+            .collect_vec();
+        candidates.sort_unstable_by_key(|x| x.0);
+        let mut evicted = false;
+        for (_, key) in candidates {
+            let is_livesig = key.kind == CacheKind::LiveSig;
+            let over_cap = is_livesig && n_livesig > LIVESIG_CAP;
+            let over_budget = store.bytes > budget && (livesig || !is_livesig);
+            if !over_cap && !over_budget {
+                continue;
+            }
+            if let Some(entry) = store.entries.remove(&key) {
+                store.bytes -= entry.bytes;
+                n_livesig -= is_livesig as usize;
+                evicted = true;
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        if evicted {
+            store.prune_dependents();
+        }
+    }
+
+    /// Drop `key` and every entry computed from it.
+    pub fn invalidate(&self, key: &CalcKey) {
+        let n = self.store.write().unwrap().remove(key);
+        self.invalidations.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    /// The price of the `Di` got new bars: every result depending on it is stale,
+    /// `LiveSig` states catch up on their next update and are kept.
+    pub fn on_append(&self) {
+        let n = self.store.write().unwrap().retain(|x| x.kind == CacheKind::LiveSig);
+        self.invalidations.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    pub fn budget(&self) -> usize {
+        self.budget.load(Ordering::Relaxed)
+    }
+
+    pub fn set_budget(&self, budget: usize) {
+        self.budget.store(budget, Ordering::Relaxed);
+        self.evict(&mut self.store.write().unwrap(), None, false);
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.store.read().unwrap().bytes
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let store = self.store.read().unwrap();
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            inserts: self.inserts.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            invalidations: self.invalidations.load(Ordering::Relaxed),
+            entries: store.entries.len(),
+            bytes: store.bytes,
+        }
+    }
+
+    /// Number of entries of each kind, in the order dcon, pms, livesig, others.
     pub fn len(&self) -> Vec<usize> {
-        vec![
-            self.save_dcon.read().unwrap().len(),
-            self.save_pms2d.read().unwrap().len(),
-            self.save_livesig.read().unwrap().len(),
-            self.save_others.read().unwrap().len(),
-        ]
+        let store = self.store.read().unwrap();
+        CacheKind::ALL
+            .map(|kind| store.entries.keys().filter(|x| x.kind == kind).count())
+            .to_vec()
     }
     pub fn len_sum(&self) -> usize {
-        self.len().iter().sum::<usize>()
+        self.store.read().unwrap().entries.len()
     }
     pub fn clear(&self) {
-        self.save_dcon.write().unwrap().clear();
-        self.save_pms2d.write().unwrap().clear();
-        self.save_livesig.write().unwrap().clear();
-        self.save_others.write().unwrap().clear();
+        let mut store = self.store.write().unwrap();
+        *store = CacheStore::default();
+    }
+
+    pub fn clear_kind(&self, kinds: &[CacheKind]) {
+        let n = self.store.write().unwrap().retain(|x| !kinds.contains(&x.kind));
+        self.invalidations.fetch_add(n as u64, Ordering::Relaxed);
     }
 
     pub fn print_keys(&self) {
-        self.save_dcon.read().unwrap().keys().print();
-        self.save_pms2d.read().unwrap().keys().print();
-        self.save_livesig.read().unwrap().keys().print();
-        self.save_others.read().unwrap().keys().print();
+        let store = self.store.read().unwrap();
+        for kind in CacheKind::ALL {
+            store.entries
+                .keys()
+                .filter(|k| k.kind == kind)
+                .map(|k| &k.id)
+                .collect_vec()
+                .print();
+        }
     }
 
+    /// Evict down to the budget, `LiveSig` states included, for loops that cache many
+    /// results per `Di` like a backtest of many `Ptm`s.
     pub fn clear_with_condition(&self) {
-        if self.save_dcon.read().unwrap().len() > 15 {
-            self.save_dcon.write().unwrap().clear();
-        }
-        if self.save_pms2d.read().unwrap().len() > 150 {
-            self.save_pms2d.write().unwrap().clear();
-        }
-        if self.save_livesig.read().unwrap().len() > 150 {
-            self.save_livesig.write().unwrap().clear();
-        }
-        if self.save_others.read().unwrap().len() > 15 {
-            self.save_others.write().unwrap().clear();
-        }
+        self.evict(&mut self.store.write().unwrap(), None, true);
     }
 }
 /* #endregion */
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(n: usize) -> CacheValue {
+        CacheValue::Any(Arc::new(Box::new(vec![0f32; n])))
+    }
+
+    #[test]
+    fn livesig_states_are_capped_and_evicted_in_batch_use() {
+        let data_save = DataSave::default();
+        for i in 0..LIVESIG_CAP + 10 {
+            data_save.insert_sized(CalcKey::new(CacheKind::LiveSig, i.to_string()), state(1), vec![], 100);
+        }
+        assert_eq!(data_save.len()[2], LIVESIG_CAP);
+        assert!(data_save.get(&CalcKey::new(CacheKind::LiveSig, "0".into())).is_none());
+        data_save.set_budget(1000);
+        assert_eq!(data_save.len()[2], LIVESIG_CAP);
+        data_save.clear_with_condition();
+        assert!(data_save.bytes() <= 1000);
+        assert_eq!(data_save.len()[2], 10);
+    }
+}
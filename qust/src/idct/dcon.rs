@@ -1,5 +1,5 @@
 use crate::std_prelude::*;
-use crate::trade::di::{Di, PriceArc, PriceOri, ToArc};
+use crate::trade::{di::{Di, PriceArc, PriceOri, ToArc}, mmap::Col};
 use crate::trade::inter::{KlineData, KlineState, Pri};
 use qust_derive::AsRef;
 use qust_ds::prelude::*;
@@ -44,7 +44,7 @@ impl Convert {
         match self {
             PreNow(pre, _now) => di.calc(&**pre),
             _ => {
-                let mut price_res = di.pcon.price.clone().to_arc();
+                let mut price_res = di.price_arc();
                 price_res.finished = None;
                 price_res
             }
@@ -75,10 +75,10 @@ impl Convert {
                         .collect();
                 PriceArc {
                     t: price.t.clone(),
-                    o: Arc::new(open_price),
-                    h: Arc::new(high_price),
-                    l: Arc::new(low_price),
-                    c: Arc::new(close_price.collect()),
+                    o: open_price.into(),
+                    h: high_price.into(),
+                    l: low_price.into(),
+                    c: close_price.collect_vec().into(),
                     v: price.v.clone(),
                     ki: price.ki.clone(),
                     finished: None,
@@ -146,10 +146,10 @@ impl Convert {
                 let numerator = price.l.min();
                 PriceArc {
                     t: price.t.clone(),
-                    o: price.o.map(|x| x / numerator).to_arc(),
-                    h: price.h.map(|x| x / numerator).to_arc(),
-                    l: price.l.map(|x| x / numerator).to_arc(),
-                    c: price.c.map(|x| x / numerator).to_arc(),
+                    o: price.o.map(|x| x / numerator).into(),
+                    h: price.h.map(|x| x / numerator).into(),
+                    l: price.l.map(|x| x / numerator).into(),
+                    c: price.c.map(|x| x / numerator).into(),
                     v: price.v.clone(),
                     ki: price.ki.clone(),
                     finished: None,
@@ -168,7 +168,7 @@ impl Convert {
                     }
                 }
                 res.push(*c_vec_ori.last().unwrap());
-                let res = Arc::new(res);
+                let res: Col<f32> = res.into();
                 PriceArc {
                     t: price.t.clone(),
                     o: res.clone(),
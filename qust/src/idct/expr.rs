@@ -0,0 +1,720 @@
+use crate::idct::calc::{BoxAny, CacheKind, CacheValue, CalcKey};
+use crate::idct::part::is_new_trading_day;
+use crate::idct::ta::{KlineType, Ta};
+use crate::sig::livesig::LiveSig;
+use crate::std_prelude::*;
+use crate::trade::di::{Di, Dil};
+use crate::trade::inter::KlineData;
+use qust_derive::*;
+use qust_ds::prelude::*;
+use std::collections::VecDeque;
+use std::ops::{Neg, Range};
+
+/* #region Expr */
+#[ta_derive]
+#[derive(Copy)]
+pub enum UnaryOp {
+    Neg,
+    Abs,
+    Log,
+    Sqrt,
+    Sign,
+}
+
+#[ta_derive]
+#[derive(Copy)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Max,
+    Min,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+#[ta_derive]
+#[derive(Copy)]
+pub enum WindowFunc {
+    Roll(RollFunc),
+    /// share of the window at or below the current value, in `(0, 1]`
+    Rank,
+}
+
+/// Window and expanding state is reset at the start of every group.
+#[ta_derive]
+#[derive(Copy, PartialEq, Eq, Hash)]
+pub enum Group {
+    /// trading day, the night session belongs to the next day
+    Day,
+    /// a change of the underlying contract of a continuous series
+    Contract,
+}
+
+/// A factor expression evaluated on the bars of a `Di`, e.g.
+/// `(col("close")? / col("close")?.shift(1) - 1.).mean().rolling(20).over(Group::Day)`.
+/// The DSL stays out of the prelude, its names clash with polars, import it from `qust::idct::expr`.
+#[ta_derive]
+pub enum Expr {
+    Col(KlineType),
+    /// output `i` of an existing indicator, computed on the whole series and never reset
+    Ta(Box<dyn Ta>, usize),
+    Lit(f32),
+    Unary(Box<Expr>, UnaryOp),
+    Binary(Box<Expr>, BinOp, Box<Expr>),
+    Rolling(Box<Expr>, WindowFunc, usize),
+    Expanding(Box<Expr>, WindowFunc),
+    /// positive is a lag, negative looks ahead and can not be streamed
+    Shift(Box<Expr>, isize),
+    Over(Box<Expr>, Group),
+}
+
+pub fn col(name: &str) -> anyhow::Result<Expr> {
+    let kline_type = match name {
+        "o" | "open" => KlineType::Open,
+        "h" | "high" => KlineType::High,
+        "l" | "low" => KlineType::Low,
+        "c" | "close" => KlineType::Close,
+        "v" | "volume" => KlineType::Volume,
+        _ => anyhow::bail!("unknown column {name}, expected one of open, high, low, close, volume"),
+    };
+    Ok(Expr::Col(kline_type))
+}
+
+pub fn lit(x: f32) -> Expr {
+    Expr::Lit(x)
+}
+
+impl From<f32> for Expr {
+    fn from(value: f32) -> Self {
+        Expr::Lit(value)
+    }
+}
+
+impl From<KlineType> for Expr {
+    fn from(value: KlineType) -> Self {
+        Expr::Col(value)
+    }
+}
+
+/// An aggregation waiting for its window, `col("c")?.mean().rolling(5)`.
+#[derive(Debug, Clone)]
+pub struct WindowExpr(pub Expr, pub WindowFunc);
+
+impl WindowExpr {
+    pub fn rolling(self, n: usize) -> Expr {
+        Expr::Rolling(Box::new(self.0), self.1, n.max(1))
+    }
+
+    pub fn expanding(self) -> Expr {
+        Expr::Expanding(Box::new(self.0), self.1)
+    }
+}
+
+impl Expr {
+    pub fn ta<T: Ta + Clone>(ta: &T, i: usize) -> Self {
+        Expr::Ta(Box::new(ta.clone()), i)
+    }
+
+    fn unary(self, op: UnaryOp) -> Self {
+        Expr::Unary(Box::new(self), op)
+    }
+
+    fn binary<T: Into<Expr>>(self, op: BinOp, other: T) -> Self {
+        Expr::Binary(Box::new(self), op, Box::new(other.into()))
+    }
+
+    fn window(self, f: RollFunc) -> WindowExpr {
+        WindowExpr(self, WindowFunc::Roll(f))
+    }
+
+    pub fn sum(self) -> WindowExpr {
+        self.window(RollFunc::Sum)
+    }
+    pub fn mean(self) -> WindowExpr {
+        self.window(RollFunc::Mean)
+    }
+    pub fn min(self) -> WindowExpr {
+        self.window(RollFunc::Min)
+    }
+    pub fn max(self) -> WindowExpr {
+        self.window(RollFunc::Max)
+    }
+    pub fn var(self) -> WindowExpr {
+        self.window(RollFunc::Var)
+    }
+    pub fn std(self) -> WindowExpr {
+        self.window(RollFunc::Std)
+    }
+    pub fn skew(self) -> WindowExpr {
+        self.window(RollFunc::Skewness)
+    }
+    pub fn momentum(self) -> WindowExpr {
+        self.window(RollFunc::Momentum)
+    }
+    pub fn rank(self) -> WindowExpr {
+        WindowExpr(self, WindowFunc::Rank)
+    }
+
+    pub fn shift(self, n: isize) -> Self {
+        Expr::Shift(Box::new(self), n)
+    }
+    pub fn diff(self, n: isize) -> Self {
+        self.clone() - self.shift(n)
+    }
+    pub fn pct_change(self, n: isize) -> Self {
+        self.clone() / self.shift(n) - 1.
+    }
+    pub fn over(self, group: Group) -> Self {
+        Expr::Over(Box::new(self), group)
+    }
+
+    pub fn abs(self) -> Self {
+        self.unary(UnaryOp::Abs)
+    }
+    pub fn log(self) -> Self {
+        self.unary(UnaryOp::Log)
+    }
+    pub fn sqrt(self) -> Self {
+        self.unary(UnaryOp::Sqrt)
+    }
+    pub fn sign(self) -> Self {
+        self.unary(UnaryOp::Sign)
+    }
+
+    pub fn max_with<T: Into<Expr>>(self, other: T) -> Self {
+        self.binary(BinOp::Max, other)
+    }
+    pub fn min_with<T: Into<Expr>>(self, other: T) -> Self {
+        self.binary(BinOp::Min, other)
+    }
+    pub fn gt<T: Into<Expr>>(self, other: T) -> Self {
+        self.binary(BinOp::Gt, other)
+    }
+    pub fn ge<T: Into<Expr>>(self, other: T) -> Self {
+        self.binary(BinOp::Ge, other)
+    }
+    pub fn lt<T: Into<Expr>>(self, other: T) -> Self {
+        self.binary(BinOp::Lt, other)
+    }
+    pub fn le<T: Into<Expr>>(self, other: T) -> Self {
+        self.binary(BinOp::Le, other)
+    }
+
+    pub fn alias(self, name: &str) -> NamedExpr {
+        NamedExpr(name.into(), self)
+    }
+
+    /// Whether the expression only looks back, which is what streaming needs.
+    pub fn is_causal(&self) -> bool {
+        match self {
+            Expr::Col(_) | Expr::Ta(..) | Expr::Lit(_) => true,
+            Expr::Unary(x, _) | Expr::Rolling(x, ..) | Expr::Expanding(x, _) | Expr::Over(x, _) => x.is_causal(),
+            Expr::Binary(x, _, y) => x.is_causal() && y.is_causal(),
+            Expr::Shift(x, n) => *n >= 0 && x.is_causal(),
+        }
+    }
+}
+
+macro_rules! impl_expr_ops {
+    ($($trait: ident, $func: ident, $op: expr);*) => {
+        $(
+            impl<T: Into<Expr>> std::ops::$trait<T> for Expr {
+                type Output = Expr;
+                fn $func(self, rhs: T) -> Expr {
+                    self.binary($op, rhs)
+                }
+            }
+
+            impl std::ops::$trait<Expr> for f32 {
+                type Output = Expr;
+                fn $func(self, rhs: Expr) -> Expr {
+                    Expr::Lit(self).binary($op, rhs)
+                }
+            }
+        )*
+    };
+}
+
+impl_expr_ops!(
+    Add, add, BinOp::Add;
+    Sub, sub, BinOp::Sub;
+    Mul, mul, BinOp::Mul;
+    Div, div, BinOp::Div
+);
+
+impl Neg for Expr {
+    type Output = Expr;
+    fn neg(self) -> Expr {
+        self.unary(UnaryOp::Neg)
+    }
+}
+
+#[ta_derive]
+pub struct NamedExpr(pub String, pub Expr);
+
+impl From<Expr> for NamedExpr {
+    fn from(value: Expr) -> Self {
+        NamedExpr(format!("{:?}", value), value)
+    }
+}
+/* #endregion */
+
+/* #region Kernels */
+impl UnaryOp {
+    fn apply(&self, x: f32) -> f32 {
+        match self {
+            UnaryOp::Neg => -x,
+            UnaryOp::Abs => x.abs(),
+            UnaryOp::Log => x.ln(),
+            UnaryOp::Sqrt => x.sqrt(),
+            UnaryOp::Sign => if x.is_nan() { f32::NAN } else if x > 0. { 1. } else if x < 0. { -1. } else { 0. },
+        }
+    }
+}
+
+impl BinOp {
+    fn apply(&self, x: f32, y: f32) -> f32 {
+        let b = |x: bool| if x { 1. } else { 0. };
+        match self {
+            BinOp::Add => x + y,
+            BinOp::Sub => x - y,
+            BinOp::Mul => x * y,
+            BinOp::Div => x / y,
+            BinOp::Max => x.max(y),
+            BinOp::Min => x.min(y),
+            BinOp::Gt => b(x > y),
+            BinOp::Ge => b(x >= y),
+            BinOp::Lt => b(x < y),
+            BinOp::Le => b(x <= y),
+        }
+    }
+}
+
+/// The window ends with the current value, a window holding a nan gives nan.
+fn window_value(window: &[f32], f: WindowFunc) -> f32 {
+    if window.iter().any(|x| x.is_nan()) {
+        return f32::NAN;
+    }
+    match f {
+        WindowFunc::Roll(f) => window.agg(f),
+        WindowFunc::Rank => {
+            let x = window[window.len() - 1];
+            window.iter().filter(|y| **y <= x).count() as f32 / window.len() as f32
+        }
+    }
+}
+
+/// Running state of an expanding window, the batch and the streaming evaluation both
+/// go through it so that they give the same numbers. Nan values are skipped.
+#[derive(Debug, Clone, Default)]
+pub struct Expanding {
+    n: usize,
+    sum: f64,
+    sum2: f64,
+    sum3: f64,
+    min: f32,
+    max: f32,
+    first: f32,
+    sorted: Vec<f32>,
+}
+
+impl Expanding {
+    pub fn push(&mut self, x: f32, f: WindowFunc) -> f32 {
+        if !x.is_nan() {
+            if self.n == 0 {
+                self.min = x;
+                self.max = x;
+                self.first = x;
+            }
+            self.n += 1;
+            let xf = x as f64;
+            self.sum += xf;
+            self.sum2 += xf * xf;
+            self.sum3 += xf * xf * xf;
+            self.min = self.min.min(x);
+            self.max = self.max.max(x);
+            if let WindowFunc::Rank = f {
+                let i = self.sorted.partition_point(|y| *y <= x);
+                self.sorted.insert(i, x);
+            }
+        }
+        self.value(x, f)
+    }
+
+    fn value(&self, x: f32, f: WindowFunc) -> f32 {
+        if self.n == 0 {
+            return f32::NAN;
+        }
+        let n = self.n as f64;
+        let mean = self.sum / n;
+        let var = || (self.sum2 - self.sum * mean) / (n - 1.);
+        let res = match f {
+            WindowFunc::Rank if x.is_nan() => f64::NAN,
+            WindowFunc::Rank => self.sorted.partition_point(|y| *y <= x) as f64 / n,
+            WindowFunc::Roll(RollFunc::Sum) => self.sum,
+            WindowFunc::Roll(RollFunc::Mean) => mean,
+            WindowFunc::Roll(RollFunc::Min) => self.min as f64,
+            WindowFunc::Roll(RollFunc::Max) => self.max as f64,
+            WindowFunc::Roll(RollFunc::Var) => var(),
+            WindowFunc::Roll(RollFunc::Std) => var().max(0.).sqrt(),
+            WindowFunc::Roll(RollFunc::Momentum) => x as f64 / self.first as f64 - 1.,
+            WindowFunc::Roll(RollFunc::Skewness) => {
+                if self.n < 2 {
+                    f64::NAN
+                } else {
+                    let cm2 = self.sum2 / n - mean * mean;
+                    let cm3 = self.sum3 / n - 3. * mean * self.sum2 / n + 2. * mean.powi(3);
+                    cm3 / cm2.powf(1.5)
+                }
+            }
+        };
+        res as f32
+    }
+}
+
+fn shift_slice(data: &[f32], n: isize) -> v32 {
+    let len = data.len();
+    let k = n.unsigned_abs().min(len);
+    let mut res = vec![f32::NAN; len];
+    if n >= 0 {
+        res[k..].copy_from_slice(&data[..len - k]);
+    } else {
+        res[..len - k].copy_from_slice(&data[k..]);
+    }
+    res
+}
+/* #endregion */
+
+/* #region Plan */
+#[derive(Debug, Clone)]
+pub struct PlanNode {
+    pub expr: Expr,
+    pub inputs: Vec<usize>,
+    /// the groups this node is evaluated in, from `over`
+    pub groups: Vec<Group>,
+    pub id: String,
+}
+
+/// Expressions compiled to nodes in evaluation order, a subexpression used several
+/// times is one node. The series of every node is cached in the `DataSave` of the `Di`,
+/// so plans sharing a subexpression compute it once.
+#[derive(Debug, Clone, AsRef)]
+pub struct ExprPlan {
+    pub nodes: Vec<PlanNode>,
+    pub outputs: Vec<(String, usize)>,
+}
+
+impl ExprPlan {
+    pub fn new<T: Into<NamedExpr>, I: IntoIterator<Item = T>>(exprs: I) -> Self {
+        let mut res = ExprPlan { nodes: vec![], outputs: vec![] };
+        let mut seen: hm<String, usize> = hm::new();
+        for NamedExpr(name, expr) in exprs.into_iter().map(|x| x.into()) {
+            let i = res.add(&expr, &[], &mut seen);
+            res.outputs.push((name, i));
+        }
+        res
+    }
+
+    fn add(&mut self, expr: &Expr, groups: &[Group], seen: &mut hm<String, usize>) -> usize {
+        let id = format!("{:?} over {:?}", expr, groups);
+        if let Some(i) = seen.get(&id) {
+            return *i;
+        }
+        let inputs = match expr {
+            Expr::Col(_) | Expr::Ta(..) | Expr::Lit(_) => vec![],
+            Expr::Unary(x, _) | Expr::Rolling(x, ..) | Expr::Expanding(x, _) | Expr::Shift(x, _) => {
+                vec![self.add(x, groups, seen)]
+            }
+            Expr::Binary(x, _, y) => vec![self.add(x, groups, seen), self.add(y, groups, seen)],
+            Expr::Over(x, group) => {
+                let mut groups_inner = groups.to_vec();
+                if !groups_inner.contains(group) {
+                    groups_inner.push(*group);
+                }
+                vec![self.add(x, &groups_inner, seen)]
+            }
+        };
+        self.nodes.push(PlanNode { expr: expr.clone(), inputs, groups: groups.to_vec(), id: id.clone() });
+        seen.insert(id, self.nodes.len() - 1);
+        self.nodes.len() - 1
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.outputs.iter().map(|x| x.0.as_str()).collect_vec()
+    }
+
+    pub fn is_causal(&self) -> bool {
+        self.nodes.iter().all(|x| !matches!(x.expr, Expr::Shift(_, n) if n < 0))
+    }
+
+    /// Every output on the whole `Di`.
+    pub fn eval(&self, di: &Di) -> Vec<av32> {
+        let dcon = di.last_dcon();
+        let dcon_key = CalcKey::new(CacheKind::Dcon, format!("{:?}", dcon));
+        let mut segments: hm<Vec<Group>, Vec<Range<usize>>> = hm::new();
+        let mut keys: Vec<CalcKey> = Vec::with_capacity(self.nodes.len());
+        let mut values: Vec<av32> = Vec::with_capacity(self.nodes.len());
+        for node in self.nodes.iter() {
+            let key = CalcKey::new(CacheKind::Others, format!("expr {:?} {}", dcon, node.id));
+            let cached = match di.data_save.get(&key) {
+                Some(CacheValue::Any(x)) => x.downcast_ref::<av32>().cloned(),
+                _ => None,
+            };
+            let res = match cached {
+                Some(res) => res,
+                None => {
+                    let segments = segments
+                        .entry(node.groups.clone())
+                        .or_insert_with(|| group_segments(di, &node.groups));
+                    let res = Arc::new(node.eval(di, &node.inputs.map(|i| &values[*i][..]), segments));
+                    let mut deps = node.inputs.map(|i| keys[*i].clone());
+                    deps.push(dcon_key.clone());
+                    di.data_save.insert(key.clone(), CacheValue::Any(Arc::new(Box::new(res.clone()))), deps);
+                    res
+                }
+            };
+            keys.push(key);
+            values.push(res);
+        }
+        self.outputs.map(|(_, i)| values[*i].clone())
+    }
+
+    pub fn eval_dil(&self, dil: &Dil) -> Vec<Vec<av32>> {
+        dil.dil.map(|di| self.eval(di))
+    }
+
+    /// A streaming evaluator starting before the first bar.
+    pub fn stream(&self) -> ExprStream {
+        assert!(self.is_causal(), "a plan looking ahead with a negative shift can not be streamed");
+        ExprStream::new(self.clone())
+    }
+
+    /// The streaming state kept in the `DataSave` of `di`, brought up to the last bar.
+    pub fn live(&self, di: &Di) -> Arc<BoxAny> {
+        di.calc(self)
+    }
+}
+
+impl PlanNode {
+    fn eval(&self, di: &Di, inputs: &[&[f32]], segments: &[Range<usize>]) -> v32 {
+        let len = di.len();
+        match &self.expr {
+            Expr::Col(KlineType::Volume) => di.v().to_vec(),
+            Expr::Col(k) => di.get_kline(k).to_vec(),
+            Expr::Ta(ta, i) => di.calc::<&Box<dyn Ta>, Box<dyn Ta>, avv32>(ta)[*i].to_vec(),
+            Expr::Lit(x) => vec![*x; len],
+            Expr::Unary(_, op) => inputs[0].map(|x| op.apply(*x)),
+            Expr::Binary(_, op, _) => izip!(inputs[0].iter(), inputs[1].iter())
+                .map(|(x, y)| op.apply(*x, *y))
+                .collect_vec(),
+            Expr::Over(..) => inputs[0].to_vec(),
+            Expr::Rolling(_, f, n) => {
+                let mut res = Vec::with_capacity(len);
+                for range in segments.iter() {
+                    let data = &inputs[0][range.clone()];
+                    for i in 0..data.len() {
+                        let start = (i + 1).saturating_sub(*n);
+                        res.push(window_value(&data[start..=i], *f));
+                    }
+                }
+                res
+            }
+            Expr::Expanding(_, f) => {
+                let mut res = Vec::with_capacity(len);
+                for range in segments.iter() {
+                    let mut state = Expanding::default();
+                    res.extend(inputs[0][range.clone()].iter().map(|x| state.push(*x, *f)));
+                }
+                res
+            }
+            Expr::Shift(_, n) => segments
+                .iter()
+                .flat_map(|x| shift_slice(&inputs[0][x.clone()], *n))
+                .collect_vec(),
+        }
+    }
+}
+
+fn group_segments(di: &Di, groups: &[Group]) -> Vec<Range<usize>> {
+    let len = di.len();
+    if groups.is_empty() || len == 0 {
+        return std::iter::once(0..len).collect_vec();
+    }
+    let t = di.t();
+    let ki = di.calc(di.last_dcon()).ki;
+    let mut starts = vec![0];
+    for i in 1..len {
+        let is_new = groups.iter().any(|group| match group {
+            Group::Day => is_new_trading_day(&t[i - 1], &t[i]),
+            Group::Contract => ki[i - 1].contract != ki[i].contract,
+        });
+        if is_new {
+            starts.push(i);
+        }
+    }
+    starts.push(len);
+    starts.windows(2).map(|x| x[0]..x[1]).collect_vec()
+}
+/* #endregion */
+
+/* #region Stream */
+#[derive(Debug, Clone)]
+enum NodeState {
+    None,
+    Window(VecDeque<f32>),
+    Expanding(Expanding),
+}
+
+/// Evaluates a plan bar by bar with the same numbers as `ExprPlan::eval`, every node
+/// keeps only the state it needs.
+#[derive(Debug, Clone)]
+pub struct ExprStream {
+    pub plan: ExprPlan,
+    states: Vec<NodeState>,
+    last: Option<(dt, i32)>,
+    /// values of the outputs, one per bar seen
+    pub values: Vec<v32>,
+}
+
+impl ExprStream {
+    pub fn new(plan: ExprPlan) -> Self {
+        let states = plan.nodes.map(|x| Self::init_state(&x.expr));
+        let values = vec![vec![]; plan.outputs.len()];
+        Self { plan, states, last: None, values }
+    }
+
+    fn init_state(expr: &Expr) -> NodeState {
+        match expr {
+            Expr::Rolling(..) => NodeState::Window(VecDeque::new()),
+            Expr::Shift(_, n) if *n > 0 => NodeState::Window(VecDeque::new()),
+            Expr::Expanding(..) => NodeState::Expanding(Expanding::default()),
+            _ => NodeState::None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.first().map(|x| x.len()).unwrap_or_default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Feed one bar, a plan with `Ta` nodes needs `update` instead.
+    pub fn push(&mut self, bar: &KlineData) -> Vec<f32> {
+        self.step(bar, None)
+    }
+
+    /// Feed the bars of `di` not seen yet, returns the number of bars fed.
+    pub fn update(&mut self, di: &Di) -> usize {
+        let start = self.len();
+        let price = di.calc(di.last_dcon());
+        let has_ta = self.plan.nodes.iter().any(|x| matches!(x.expr, Expr::Ta(..)));
+        let ta_values = if has_ta {
+            self.plan.nodes.map(|x| match &x.expr {
+                Expr::Ta(ta, i) => Some(di.calc::<&Box<dyn Ta>, Box<dyn Ta>, avv32>(ta)[*i].clone()),
+                _ => None,
+            })
+        } else {
+            vec![]
+        };
+        for i in start..price.t.len() {
+            let bar = KlineData {
+                t: price.t[i],
+                o: price.o[i],
+                h: price.h[i],
+                l: price.l[i],
+                c: price.c[i],
+                v: price.v[i],
+                ki: price.ki[i].clone(),
+            };
+            self.step(&bar, Some((&ta_values, i)));
+        }
+        price.t.len().saturating_sub(start)
+    }
+
+    fn step(&mut self, bar: &KlineData, ta: Option<(&[Option<av32>], usize)>) -> Vec<f32> {
+        let (is_new_day, is_new_contract) = match self.last {
+            Some((t, contract)) => (is_new_trading_day(&t, &bar.t), contract != bar.ki.contract),
+            None => (false, false),
+        };
+        self.last = Some((bar.t, bar.ki.contract));
+        let mut node_values: v32 = Vec::with_capacity(self.plan.nodes.len());
+        for (j, (node, state)) in self.plan.nodes.iter().zip(self.states.iter_mut()).enumerate() {
+            let is_reset = node.groups.iter().any(|x| match x {
+                Group::Day => is_new_day,
+                Group::Contract => is_new_contract,
+            });
+            if is_reset {
+                *state = Self::init_state(&node.expr);
+            }
+            let input = |k: usize| node_values[node.inputs[k]];
+            let value = match (&node.expr, state) {
+                (Expr::Col(k), _) => match k {
+                    KlineType::Open => bar.o,
+                    KlineType::High => bar.h,
+                    KlineType::Low => bar.l,
+                    KlineType::Volume => bar.v,
+                    _ => bar.c,
+                },
+                (Expr::Ta(..), _) => {
+                    let (ta_values, i) = ta.expect("a plan with Ta nodes is streamed with update(di)");
+                    ta_values[j].as_ref().unwrap()[i]
+                }
+                (Expr::Lit(x), _) => *x,
+                (Expr::Unary(_, op), _) => op.apply(input(0)),
+                (Expr::Binary(_, op, _), _) => op.apply(input(0), input(1)),
+                (Expr::Over(..), _) => input(0),
+                (Expr::Rolling(_, f, n), NodeState::Window(window)) => {
+                    window.push_back(input(0));
+                    if window.len() > *n {
+                        window.pop_front();
+                    }
+                    window_value(window.make_contiguous(), *f)
+                }
+                (Expr::Expanding(_, f), NodeState::Expanding(state)) => state.push(input(0), *f),
+                (Expr::Shift(_, n), NodeState::Window(window)) => {
+                    window.push_back(input(0));
+                    if window.len() > *n as usize {
+                        window.pop_front().unwrap()
+                    } else {
+                        f32::NAN
+                    }
+                }
+                (Expr::Shift(..), _) => input(0),
+                _ => unreachable!(),
+            };
+            node_values.push(value);
+        }
+        let res = self.plan.outputs.map(|(_, i)| node_values[*i]);
+        izip!(self.values.iter_mut(), res.iter()).for_each(|(x, y)| x.push(*y));
+        res
+    }
+}
+
+impl LiveSig for ExprPlan {
+    type R = ExprStream;
+    fn get_data(&self, _di: &Di) -> RwLock<Self::R> {
+        RwLock::new(self.stream())
+    }
+    fn update(&self, di: &Di, data: &RwLock<Self::R>) {
+        data.write().unwrap().update(di);
+    }
+}
+/* #endregion */
+
+/* #region Ta */
+#[typetag::serde]
+impl Ta for Expr {
+    fn calc_di(&self, di: &Di) -> avv32 {
+        ExprPlan::new([self.clone()]).eval(di)
+    }
+    fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32 {
+        da.map(|x| x.to_vec())
+    }
+}
+/* #endregion */
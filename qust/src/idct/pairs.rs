@@ -0,0 +1,696 @@
+use crate::live::prelude::{CondCrossTarget, GetTickerVec, OrderTarget, RetFnCrossTarget};
+use crate::sig::cond::{Cond, LoopSig};
+use crate::trade::di::Di;
+use crate::trade::ticker::Ticker;
+use super::panel::{Panel, PanelFill};
+use qust_derive::*;
+use qust_ds::prelude::*;
+use std::collections::VecDeque;
+
+type Mat = Vec<Vec<f64>>;
+
+/* #region linear algebra */
+fn solve(mut a: Mat, mut b: Vec<f64>) -> Option<Vec<f64>> {
+    if a.iter().flatten().chain(b.iter()).any(|x| !x.is_finite()) {
+        return None;
+    }
+    let n = b.len();
+    for i in 0..n {
+        let p = (i..n).max_by(|x, y| a[*x][i].abs().total_cmp(&a[*y][i].abs()))?;
+        if a[p][i].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(i, p);
+        b.swap(i, p);
+        let (top, bottom) = a.split_at_mut(i + 1);
+        let pivot = &top[i];
+        for (r, row) in bottom.iter_mut().enumerate() {
+            let f = row[i] / pivot[i];
+            row[i..].iter_mut().zip(pivot[i..].iter()).for_each(|(x, p)| *x -= f * p);
+            b[i + 1 + r] -= f * b[i];
+        }
+    }
+    let mut res = vec![0.; n];
+    for i in (0..n).rev() {
+        let s = (i + 1..n).map(|c| a[i][c] * res[c]).sum::<f64>();
+        res[i] = (b[i] - s) / a[i][i];
+    }
+    Some(res)
+}
+
+/// `x' y / n` of column major data.
+fn cross(x: &[Vec<f64>], y: &[Vec<f64>]) -> Mat {
+    let n = x.first().map(|c| c.len()).unwrap_or(1) as f64;
+    x.iter()
+        .map(|a| y.iter().map(|b| a.iter().zip(b.iter()).map(|(i, j)| i * j).sum::<f64>() / n).collect_vec())
+        .collect_vec()
+}
+
+fn mat_mul(a: &Mat, b: &Mat) -> Mat {
+    a.iter()
+        .map(|row| (0..b[0].len()).map(|j| row.iter().zip(b.iter()).map(|(x, r)| x * r[j]).sum()).collect_vec())
+        .collect_vec()
+}
+
+fn transpose(a: &Mat) -> Mat {
+    (0..a[0].len()).map(|j| a.iter().map(|row| row[j]).collect_vec()).collect_vec()
+}
+
+fn inverse(a: &Mat) -> Option<Mat> {
+    let n = a.len();
+    let cols = (0..n)
+        .map(|j| solve(a.clone(), (0..n).map(|i| if i == j { 1. } else { 0. }).collect_vec()))
+        .collect::<Option<Vec<_>>>()?;
+    Some(transpose(&cols))
+}
+
+fn cholesky(a: &Mat) -> Option<Mat> {
+    let n = a.len();
+    let mut l = vec![vec![0.; n]; n];
+    for i in 0..n {
+        for j in 0..=i {
+            let s = a[i][j] - (0..j).map(|k| l[i][k] * l[j][k]).sum::<f64>();
+            if i == j {
+                if s <= 0. {
+                    return None;
+                }
+                l[i][i] = s.sqrt();
+            } else {
+                l[i][j] = s / l[j][j];
+            }
+        }
+    }
+    Some(l)
+}
+
+/// Eigenvalues and eigenvectors (as columns) of a symmetric matrix, by Jacobi rotations.
+fn jacobi_eigen(mut a: Mat) -> (Vec<f64>, Mat) {
+    let n = a.len();
+    let mut v = (0..n).map(|i| (0..n).map(|j| if i == j { 1. } else { 0. }).collect_vec()).collect_vec();
+    for _ in 0..100 {
+        let off = (0..n).flat_map(|p| (p + 1..n).map(move |q| (p, q))).map(|(p, q)| a[p][q].powi(2)).sum::<f64>();
+        if off < 1e-24 {
+            break;
+        }
+        for p in 0..n {
+            for q in p + 1..n {
+                if a[p][q].abs() < 1e-300 {
+                    continue;
+                }
+                let theta = (a[q][q] - a[p][p]) / (2. * a[p][q]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.).sqrt());
+                let c = 1. / (t * t + 1.).sqrt();
+                let s = t * c;
+                for row in a.iter_mut() {
+                    let (x, y) = (row[p], row[q]);
+                    row[p] = c * x - s * y;
+                    row[q] = s * x + c * y;
+                }
+                let (top, bottom) = a.split_at_mut(q);
+                for (x, y) in top[p].iter_mut().zip(bottom[0].iter_mut()) {
+                    (*x, *y) = (c * *x - s * *y, s * *x + c * *y);
+                }
+                for row in v.iter_mut() {
+                    let (x, y) = (row[p], row[q]);
+                    row[p] = c * x - s * y;
+                    row[q] = s * x + c * y;
+                }
+            }
+        }
+    }
+    ((0..n).map(|i| a[i][i]).collect_vec(), v)
+}
+
+/// Least squares of `y` on the columns of `x`, coefficients and residuals.
+fn lstsq(x: &[Vec<f64>], y: &[f64]) -> Option<(Vec<f64>, Vec<f64>)> {
+    let y = vec![y.to_vec()];
+    let xtx = cross(x, x);
+    let xty = cross(x, &y).into_iter().map(|r| r[0]).collect_vec();
+    let coef = solve(xtx, xty)?;
+    let resid = (0..y[0].len())
+        .map(|t| y[0][t] - x.iter().zip(coef.iter()).map(|(c, b)| c[t] * b).sum::<f64>())
+        .collect_vec();
+    Some((coef, resid))
+}
+
+fn demean(x: &[f64]) -> Vec<f64> {
+    let m = x.iter().sum::<f64>() / x.len() as f64;
+    x.iter().map(|v| v - m).collect_vec()
+}
+/* #endregion */
+
+/* #region cointegration */
+/// MacKinnon (2010) response surface of the Dickey-Fuller tau with a constant, by the
+/// number of series in the regression, rows are 1%, 5%, 10%.
+const MACKINNON_C: [[[f64; 4]; 3]; 4] = [
+    [[-3.43035, -6.5393, -16.786, -79.433], [-2.86154, -2.8903, -4.234, -40.04], [-2.56677, -1.5384, -2.809, 0.]],
+    [[-3.89644, -10.9519, -22.527, 0.], [-3.33613, -6.1101, -6.823, 0.], [-3.04445, -4.2412, -2.72, 0.]],
+    [[-4.29374, -14.4354, -33.195, 47.433], [-3.74066, -8.5631, -10.852, 27.982], [-3.45218, -6.2143, -3.718, 0.]],
+    [[-4.64332, -18.1031, -37.972, 0.], [-4.096, -11.2349, -11.175, 0.], [-3.8102, -8.3931, -4.137, 0.]],
+];
+
+/// Johansen critical values with an unrestricted constant by `n - r`, columns are 10%, 5%, 1%.
+const JOHANSEN_TRACE: [[f32; 3]; 6] = [
+    [2.7055, 3.8415, 6.6349],
+    [13.4294, 15.4943, 19.9349],
+    [27.0669, 29.7961, 35.4628],
+    [44.4929, 47.8545, 54.6815],
+    [65.8202, 69.8189, 77.8202],
+    [91.109, 95.7542, 104.9637],
+];
+const JOHANSEN_MAX_EIG: [[f32; 3]; 6] = [
+    [2.7055, 3.8415, 6.6349],
+    [12.2971, 14.2639, 18.52],
+    [18.8928, 21.1314, 25.865],
+    [25.1236, 27.5858, 32.7172],
+    [31.2379, 33.8777, 39.3693],
+    [37.2786, 40.0763, 45.8662],
+];
+
+#[ta_derive]
+pub struct AdfRes {
+    pub stat: f32,
+    pub lags: usize,
+    pub nobs: usize,
+    /// critical values at 10%, 5%, 1%
+    pub crit: [f32; 3],
+}
+
+impl AdfRes {
+    /// Unit root rejected at 5%.
+    pub fn is_stationary(&self) -> bool {
+        self.stat < self.crit[1]
+    }
+}
+
+fn adf_crit(n_series: usize, nobs: usize) -> [f32; 3] {
+    let Some(table) = MACKINNON_C.get(n_series - 1) else {
+        return [f32::NAN; 3];
+    };
+    let t = nobs as f64;
+    let at = |row: &[f64; 4]| (row[0] + row[1] / t + row[2] / t.powi(2) + row[3] / t.powi(3)) as f32;
+    [at(&table[2]), at(&table[1]), at(&table[0])]
+}
+
+fn adf_stat(y: &[f64], lags: usize) -> Option<(f32, usize)> {
+    let dy = y.windows(2).map(|w| w[1] - w[0]).collect_vec();
+    let nobs = dy.len().checked_sub(lags)?;
+    if nobs < lags + 4 {
+        return None;
+    }
+    let mut x = vec![vec![1.; nobs], (lags..dy.len()).map(|t| y[t]).collect_vec()];
+    for l in 1..=lags {
+        x.push((lags..dy.len()).map(|t| dy[t - l]).collect_vec());
+    }
+    let target = dy[lags..].to_vec();
+    let (coef, resid) = lstsq(&x, &target)?;
+    let dof = (nobs - x.len()) as f64;
+    let s2 = resid.iter().map(|e| e * e).sum::<f64>() / dof;
+    let xtx_inv = inverse(&cross(&x, &x))?;
+    let se = (s2 * xtx_inv[1][1] / nobs as f64).sqrt();
+    Some(((coef[1] / se) as f32, nobs))
+}
+
+/// Augmented Dickey-Fuller test with a constant and `lags` lagged differences.
+pub fn adf(y: &[f32], lags: usize) -> AdfRes {
+    adf_n(&y.map(|x| *x as f64), lags, 1)
+}
+
+fn adf_n(y: &[f64], lags: usize, n_series: usize) -> AdfRes {
+    let (stat, nobs) = adf_stat(y, lags).unwrap_or((f32::NAN, 0));
+    AdfRes { stat, lags, nobs, crit: adf_crit(n_series, nobs) }
+}
+
+/// Bars for a deviation of the spread to halve, from the AR(1) fit of its changes.
+pub fn half_life(spread: &[f32]) -> f32 {
+    let data = spread.iter().filter(|x| x.is_finite()).map(|x| *x as f64).collect_vec();
+    if data.len() < 3 {
+        return f32::NAN;
+    }
+    let x = vec![vec![1.; data.len() - 1], data[..data.len() - 1].to_vec()];
+    let dy = data.windows(2).map(|w| w[1] - w[0]).collect_vec();
+    match lstsq(&x, &dy) {
+        Some((coef, _)) if coef[1] < 0. && coef[1] > -1. => (-(2f64.ln()) / (1. + coef[1]).ln()) as f32,
+        _ => f32::NAN,
+    }
+}
+
+#[ta_derive]
+pub struct EngleGrangerRes {
+    pub alpha: f32,
+    pub beta: v32,
+    pub adf: AdfRes,
+    pub half_life: f32,
+}
+
+/// Engle-Granger: OLS of `y` on `xs` and an ADF test of the residuals with the critical
+/// values for the number of series.
+pub fn engle_granger(y: &[f32], xs: &[&[f32]], lags: usize) -> EngleGrangerRes {
+    let hedge = hedge_ratio(y, xs, Hedge::Ols);
+    let resid = hedge.spread.map(|x| *x as f64);
+    EngleGrangerRes {
+        alpha: hedge.coef[0][0],
+        beta: hedge.coef[1..].map(|x| x[0]),
+        adf: adf_n(&resid, lags, xs.len() + 1),
+        half_life: half_life(&hedge.spread),
+    }
+}
+
+#[ta_derive]
+pub struct JohansenRes {
+    /// eigenvalues, largest first
+    pub eig: v32,
+    /// cointegrating vectors in the order of `eig`, normalized on the first series
+    pub vectors: Vec<v32>,
+    pub trace: v32,
+    pub max_eig: v32,
+    /// critical values at 10%, 5%, 1% of `trace[r]` and `max_eig[r]`
+    pub trace_crit: Vec<[f32; 3]>,
+    pub max_eig_crit: Vec<[f32; 3]>,
+}
+
+impl JohansenRes {
+    /// Cointegration rank by the trace test at 5%.
+    pub fn rank(&self) -> usize {
+        self.trace
+            .iter()
+            .zip(self.trace_crit.iter())
+            .take_while(|(x, c)| **x > c[1])
+            .count()
+    }
+}
+
+/// Johansen test with a constant and `k_ar_diff` lagged differences, None on a non finite price.
+pub fn johansen(series: &[&[f32]], k_ar_diff: usize) -> Option<JohansenRes> {
+    let n = series.len();
+    let len = series.iter().map(|x| x.len()).min()?;
+    if series.iter().any(|s| s[..len].iter().any(|x| !x.is_finite())) {
+        return None;
+    }
+    let x = series.map(|s| demean(&s[..len].map(|v| *v as f64)));
+    let dx = x.map(|s| s.windows(2).map(|w| w[1] - w[0]).collect_vec());
+    let rows = (k_ar_diff..dx[0].len()).collect_vec();
+    if rows.len() < n * (k_ar_diff + 1) + 4 {
+        return None;
+    }
+    let z = (1..=k_ar_diff)
+        .flat_map(|l| dx.iter().map(move |s| (l, s)))
+        .map(|(l, s)| demean(&rows.map(|t| s[t - l])))
+        .collect_vec();
+    let resid = |data: &[f64]| -> Option<Vec<f64>> {
+        let data = demean(data);
+        if z.is_empty() {
+            return Some(data);
+        }
+        Some(lstsq(&z, &data)?.1)
+    };
+    let r0 = dx.iter().map(|s| resid(&rows.map(|t| s[*t]))).collect::<Option<Vec<_>>>()?;
+    let r1 = x.iter().map(|s| resid(&rows.map(|t| s[*t]))).collect::<Option<Vec<_>>>()?;
+    let s00 = cross(&r0, &r0);
+    let s01 = cross(&r0, &r1);
+    let s11 = cross(&r1, &r1);
+    let c = inverse(&cholesky(&s11)?)?;
+    let m = mat_mul(&mat_mul(&c, &mat_mul(&transpose(&s01), &mat_mul(&inverse(&s00)?, &s01))), &transpose(&c));
+    let (eig, w) = jacobi_eigen(m);
+    if eig.iter().any(|x| !x.is_finite()) {
+        return None;
+    }
+    let vectors = mat_mul(&transpose(&c), &w);
+    let mut order = (0..n).collect_vec();
+    order.sort_by(|a, b| eig[*b].total_cmp(&eig[*a]));
+    let t = rows.len() as f64;
+    let eig = order.map(|i| eig[*i].clamp(0., 1. - 1e-12));
+    let log_1m = eig.map(|l| (1. - l).ln());
+    let crit = |table: &[[f32; 3]; 6], r: usize| table.get(n - r - 1).cloned().unwrap_or([f32::NAN; 3]);
+    Some(JohansenRes {
+        vectors: order.map(|i| {
+            let v = vectors.iter().map(|row| row[*i]).collect_vec();
+            v.map(|x| (x / v[0]) as f32)
+        }),
+        trace: (0..n).map(|r| (-t * log_1m[r..].iter().sum::<f64>()) as f32).collect_vec(),
+        max_eig: log_1m.map(|x| (-t * x) as f32),
+        trace_crit: (0..n).map(|r| crit(&JOHANSEN_TRACE, r)).collect_vec(),
+        max_eig_crit: (0..n).map(|r| crit(&JOHANSEN_MAX_EIG, r)).collect_vec(),
+        eig: eig.map(|x| *x as f32),
+    })
+}
+/* #endregion */
+
+/* #region hedge ratio */
+#[ta_derive]
+#[derive(Copy, PartialEq)]
+pub enum Hedge {
+    /// one fit on the whole sample, expanding when streamed
+    Ols,
+    RollingOls(usize),
+    /// random walk coefficients, `delta` sets the state noise, `ve` the observation noise
+    Kalman { delta: f32, ve: f32 },
+}
+
+/// Streaming estimate of `y = alpha + beta x`, the same object runs in research and live.
+#[derive(Debug, Clone)]
+pub struct HedgeFilter {
+    pub hedge: Hedge,
+    xtx: Mat,
+    xty: Vec<f64>,
+    window: VecDeque<(Vec<f64>, f64)>,
+    state: Vec<f64>,
+    cov: Mat,
+}
+
+impl HedgeFilter {
+    /// `k` is the number of `x` series.
+    pub fn new(hedge: Hedge, k: usize) -> Self {
+        Self {
+            hedge,
+            xtx: vec![vec![0.; k + 1]; k + 1],
+            xty: vec![0.; k + 1],
+            window: VecDeque::new(),
+            state: vec![0.; k + 1],
+            cov: vec![vec![0.; k + 1]; k + 1],
+        }
+    }
+
+    fn add(&mut self, h: &[f64], y: f64, sign: f64) {
+        for (i, hi) in h.iter().enumerate() {
+            for (j, hj) in h.iter().enumerate() {
+                self.xtx[i][j] += sign * hi * hj;
+            }
+            self.xty[i] += sign * hi * y;
+        }
+    }
+
+    /// Coefficients `[alpha, beta..]` after the observation, nan until there are enough of them.
+    pub fn update(&mut self, y: f64, x: &[f64]) -> Vec<f64> {
+        let k = self.state.len();
+        let h = std::iter::once(1.).chain(x.iter().cloned()).collect_vec();
+        match self.hedge {
+            Hedge::Ols | Hedge::RollingOls(_) => {
+                self.add(&h, y, 1.);
+                self.window.push_back((h, y));
+                if let Hedge::RollingOls(n) = self.hedge {
+                    if self.window.len() > n {
+                        let (h, y) = self.window.pop_front().unwrap();
+                        self.add(&h, y, -1.);
+                    } else if self.window.len() < n {
+                        return vec![f64::NAN; k];
+                    }
+                } else {
+                    self.window.clear();
+                }
+                self.state = solve(self.xtx.clone(), self.xty.clone()).unwrap_or_else(|| vec![f64::NAN; k]);
+            }
+            Hedge::Kalman { delta, ve } => {
+                let vw = delta as f64 / (1. - delta as f64);
+                let r = (0..k)
+                    .map(|i| (0..k).map(|j| self.cov[i][j] + if i == j { vw } else { 0. }).collect_vec())
+                    .collect_vec();
+                let rh = r.iter().map(|row| row.iter().zip(h.iter()).map(|(a, b)| a * b).sum::<f64>()).collect_vec();
+                let q = h.iter().zip(rh.iter()).map(|(a, b)| a * b).sum::<f64>() + ve as f64;
+                let e = y - h.iter().zip(self.state.iter()).map(|(a, b)| a * b).sum::<f64>();
+                let gain = rh.map(|x| x / q);
+                self.state.iter_mut().zip(gain.iter()).for_each(|(s, g)| *s += g * e);
+                self.cov = (0..k).map(|i| (0..k).map(|j| r[i][j] - gain[i] * rh[j]).collect_vec()).collect_vec();
+            }
+        }
+        self.state.clone()
+    }
+}
+
+/// Rolling z-score with running sums.
+#[derive(Debug, Clone)]
+pub struct ZScore {
+    pub window: usize,
+    data: VecDeque<f64>,
+    sum: f64,
+    sum2: f64,
+}
+
+impl ZScore {
+    pub fn new(window: usize) -> Self {
+        Self { window, data: VecDeque::with_capacity(window + 1), sum: 0., sum2: 0. }
+    }
+
+    pub fn update(&mut self, x: f64) -> f32 {
+        if !x.is_finite() {
+            return f32::NAN;
+        }
+        self.data.push_back(x);
+        self.sum += x;
+        self.sum2 += x * x;
+        if self.data.len() > self.window {
+            let old = self.data.pop_front().unwrap();
+            self.sum -= old;
+            self.sum2 -= old * old;
+        }
+        if self.data.len() < self.window.max(2) {
+            return f32::NAN;
+        }
+        let n = self.data.len() as f64;
+        let mean = self.sum / n;
+        let var = (self.sum2 - n * mean * mean) / (n - 1.);
+        if var <= 0. {
+            return 0.;
+        }
+        ((x - mean) / var.sqrt()) as f32
+    }
+}
+
+#[ta_derive]
+pub struct HedgeRes {
+    /// `coef[0]` is alpha, `coef[i]` the beta of the i-th x, one value per bar
+    pub coef: Vec<v32>,
+    pub spread: v32,
+}
+
+/// Hedge ratios of `y` on `xs` and the spread `y - alpha - beta x`. `Ols` fits the whole
+/// sample, the others only use the past and match the live `HedgeFilter`.
+pub fn hedge_ratio(y: &[f32], xs: &[&[f32]], hedge: Hedge) -> HedgeRes {
+    let len = y.len();
+    let x_at = |t: usize| xs.iter().map(|x| x[t] as f64).collect_vec();
+    let coef_vec = match hedge {
+        Hedge::Ols => {
+            let mut columns = vec![vec![1.; len]];
+            columns.extend(xs.iter().map(|x| x.map(|v| *v as f64)));
+            let coef = lstsq(&columns, &y.map(|v| *v as f64))
+                .map(|x| x.0)
+                .unwrap_or_else(|| vec![f64::NAN; xs.len() + 1]);
+            vec![coef; len]
+        }
+        _ => {
+            let mut filter = HedgeFilter::new(hedge, xs.len());
+            (0..len).map(|t| filter.update(y[t] as f64, &x_at(t))).collect_vec()
+        }
+    };
+    let spread = coef_vec
+        .iter()
+        .enumerate()
+        .map(|(t, coef)| {
+            let fit = coef[0] + coef[1..].iter().zip(x_at(t).iter()).map(|(b, x)| b * x).sum::<f64>();
+            (y[t] as f64 - fit) as f32
+        })
+        .collect_vec();
+    HedgeRes {
+        coef: (0..=xs.len()).map(|i| coef_vec.map(|c| c[i] as f32)).collect_vec(),
+        spread,
+    }
+}
+/* #endregion */
+
+/* #region PairData */
+/// Close of two or more `Di` on the union of their bar times, each forward filled the way
+/// `MillisAlignment` fills a missing leg, rows before every leg has a bar are dropped.
+#[derive(Debug, Clone)]
+pub struct PairData {
+    pub time: vdt,
+    pub tickers: Vec<Ticker>,
+    /// `prices[0]` is y, the rest are the hedge legs
+    pub prices: Vec<v32>,
+}
+
+impl PairData {
+    pub fn new(dis: &[&Di], log: bool) -> Self {
+        let data = dis.map(|di| (di.pcon.ticker, di.t(), di.c()));
+        let series = data
+            .iter()
+            .map(|(ticker, t, c)| (*ticker, &t[..], &c[..]))
+            .collect_vec();
+        let panel = Panel::align(&series, PanelFill::Ffill);
+        let start = (0..panel.len())
+            .find(|i| panel.values.iter().all(|x| x[*i].is_finite()))
+            .unwrap_or(panel.len());
+        let prices = panel.values.map(|x| {
+            x[start..].map(|v| if log { v.ln() } else { *v })
+        });
+        Self { time: panel.time[start..].to_vec(), tickers: panel.tickers, prices }
+    }
+
+    fn xs(&self) -> Vec<&[f32]> {
+        self.prices[1..].iter().map(|x| &x[..]).collect_vec()
+    }
+
+    pub fn hedge(&self, hedge: Hedge) -> HedgeRes {
+        hedge_ratio(&self.prices[0], &self.xs(), hedge)
+    }
+
+    pub fn zscore(&self, hedge: Hedge, window: usize) -> v32 {
+        let mut z = ZScore::new(window);
+        self.hedge(hedge).spread.iter().map(|x| z.update(*x as f64)).collect_vec()
+    }
+
+    pub fn engle_granger(&self, lags: usize) -> EngleGrangerRes {
+        engle_granger(&self.prices[0], &self.xs(), lags)
+    }
+
+    pub fn johansen(&self, k_ar_diff: usize) -> Option<JohansenRes> {
+        johansen(&self.prices.iter().map(|x| &x[..]).collect_vec(), k_ar_diff)
+    }
+}
+/* #endregion */
+
+/* #region signals */
+/// Z-score of the spread of a `Di` against a partner close series. The partner is kept
+/// in the struct, so the condition runs on the `Di` of the first leg alone. The series is
+/// frozen at `new` and never sees a live bar, so this is for research only, trade a pair
+/// live with `PairTrade`, which gets the ticks of every leg.
+#[ta_derive]
+pub struct PairZ {
+    pub partner: Ticker,
+    pub partner_t: vdt,
+    pub partner_c: v32,
+    pub hedge: Hedge,
+    pub window: usize,
+    pub log: bool,
+}
+
+impl PairZ {
+    pub fn new(partner: &Di, hedge: Hedge, window: usize, log: bool) -> Self {
+        Self {
+            partner: partner.pcon.ticker,
+            partner_t: partner.t().to_vec(),
+            partner_c: partner.c().to_vec(),
+            hedge,
+            window,
+            log,
+        }
+    }
+
+    pub fn zscore(&self, di: &Di) -> v32 {
+        let t = di.t();
+        if t.is_empty() || self.partner_t.is_empty() {
+            return vec![f32::NAN; t.len()];
+        }
+        let f = |x: f32| if self.log { x.ln() } else { x };
+        let x = Reindex::new(&self.partner_t[..], &t[..]).reindex(&self.partner_c).ffill(f32::NAN).map(|v| f(*v));
+        let y = di.c().map(|v| f(*v));
+        let mut filter = HedgeFilter::new(self.hedge, 1);
+        let mut z = ZScore::new(self.window);
+        y.iter()
+            .zip(x.iter())
+            .map(|(y, x)| {
+                if !x.is_finite() {
+                    return f32::NAN;
+                }
+                let coef = filter.update(*y as f64, &[*x as f64]);
+                z.update(*y as f64 - coef[0] - coef[1] * *x as f64)
+            })
+            .collect_vec()
+    }
+}
+
+/// True when the pair z-score is in `range`, e.g. `f32::NEG_INFINITY..-2.` to buy the spread.
+#[ta_derive]
+pub struct PairZCond {
+    pub pair: PairZ,
+    pub range: std::ops::Range<f32>,
+}
+
+#[typetag::serde]
+impl Cond for PairZCond {
+    fn cond<'a>(&self, di: &'a Di) -> LoopSig<'a> {
+        let data = self.pair.zscore(di);
+        let range = self.range.clone();
+        Box::new(move |e, _o| range.contains(&data[e]))
+    }
+}
+
+/// Mean reversion on the spread of `tickers[0]` against the others: enter at `entry` std,
+/// exit inside `exit` std, stop out beyond `stop` std. Hedge lots are fixed at entry.
+#[ta_derive]
+pub struct PairTrade {
+    pub tickers: Vec<Ticker>,
+    pub hedge: Hedge,
+    pub window: usize,
+    pub log: bool,
+    pub entry: f32,
+    pub exit: f32,
+    pub stop: f32,
+    /// lots of the first leg
+    pub lots: f32,
+}
+
+impl GetTickerVec for PairTrade {
+    fn get_ticker_vec(&self) -> Vec<Ticker> {
+        self.tickers.clone()
+    }
+}
+
+fn target_of(num: f32) -> OrderTarget {
+    if num > 0. {
+        OrderTarget::Lo(num)
+    } else if num < 0. {
+        OrderTarget::Sh(-num)
+    } else {
+        OrderTarget::No
+    }
+}
+
+impl CondCrossTarget for PairTrade {
+    fn cond_cross_target(&self) -> RetFnCrossTarget {
+        let mut filter = HedgeFilter::new(self.hedge, self.tickers.len() - 1);
+        let mut z = ZScore::new(self.window);
+        let pv = self.tickers.map(|x| x.info().pv);
+        let mut lots = vec![0f32; self.tickers.len()];
+        let mut state = 0f32;
+        Box::new(move |tick_data_vec| {
+            let price = tick_data_vec.map(|x| (x.bid1 + x.ask1) / 2.);
+            let level = price.map(|x| if self.log { (*x as f64).ln() } else { *x as f64 });
+            let coef = filter.update(level[0], &level[1..]);
+            let fit = coef[0] + coef[1..].iter().zip(level[1..].iter()).map(|(b, x)| b * x).sum::<f64>();
+            let zscore = z.update(level[0] - fit);
+            if zscore.is_nan() {
+                return lots.map(|x| target_of(*x));
+            }
+            let state_new = if state == 0. {
+                if zscore > self.entry && zscore < self.stop {
+                    -1.
+                } else if zscore < -self.entry && zscore > -self.stop {
+                    1.
+                } else {
+                    0.
+                }
+            } else if (state > 0. && (zscore > -self.exit || zscore < -self.stop))
+                || (state < 0. && (zscore < self.exit || zscore > self.stop))
+            {
+                0.
+            } else {
+                state
+            };
+            if state_new != state {
+                state = state_new;
+                lots = (0..price.len())
+                    .map(|i| {
+                        if i == 0 {
+                            return state * self.lots;
+                        }
+                        // value of one unit of x per unit of y, prices for a log spread
+                        let ratio = if self.log { price[0] * pv[0] / (price[i] * pv[i]) } else { pv[0] / pv[i] };
+                        (-state * self.lots * coef[i] as f32 * ratio).round()
+                    })
+                    .collect_vec();
+            }
+            lots.map(|x| target_of(*x))
+        })
+    }
+}
+/* #endregion */
@@ -0,0 +1,296 @@
+use crate::idct::expr::{Expr, ExprPlan};
+use crate::std_prelude::*;
+use crate::trade::di::{Di, Dil};
+use crate::trade::ticker::{Comdty, Ticker, ToSection};
+use qust_derive::*;
+use qust_ds::prelude::*;
+
+/* #region Panel */
+/// How a ticker without a bar at a timestamp of the common index is filled.
+#[ta_derive]
+#[derive(Copy)]
+pub enum PanelFill {
+    Nan,
+    /// the last value of the ticker, nan before its first bar
+    Ffill,
+    Value(f32),
+}
+
+#[ta_derive]
+#[derive(Copy)]
+pub enum Winsor {
+    /// clip to the cross-sectional quantiles `(low, high)`
+    Quantile(f32, f32),
+    /// clip to mean ± k std
+    Sigma(f32),
+}
+
+/// One value per (timestamp, ticker) on the union of the bar times of a `Dil`.
+/// `values[j]` is the column of `tickers[j]`, nan is a missing value and is ignored by
+/// every cross-sectional operation.
+#[derive(Debug, Clone)]
+pub struct Panel {
+    pub time: vdt,
+    pub tickers: Vec<Ticker>,
+    pub values: Vec<v32>,
+}
+
+impl Panel {
+    /// Align `(time, value)` series on the union of their times.
+    pub fn align(series: &[(Ticker, &[dt], &[f32])], fill: PanelFill) -> Self {
+        let time = series.map(|x| x.1.to_vec()).union_vecs();
+        let values = series.map(|(_, t, v)| {
+            if t.is_empty() || time.is_empty() {
+                return vec![f32::NAN; time.len()];
+            }
+            let data = Reindex::new(t, &time).reindex(v);
+            match fill {
+                PanelFill::Nan => data.fillna(f32::NAN),
+                PanelFill::Value(x) => data.fillna(x),
+                PanelFill::Ffill => data.ffill(f32::NAN),
+            }
+        });
+        Panel { time, tickers: series.map(|x| x.0), values }
+    }
+
+    pub fn len(&self) -> usize {
+        self.time.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.time.is_empty()
+    }
+
+    pub fn column(&self, ticker: Ticker) -> Option<&v32> {
+        self.tickers.iter().position(|x| *x == ticker).map(|i| &self.values[i])
+    }
+
+    pub fn row(&self, i: usize) -> v32 {
+        self.values.map(|x| x[i])
+    }
+
+    /// Run `f` on every row with the non nan values only, `f` writes its result in place.
+    pub fn map_rows<F: Fn(&mut [f32])>(&self, f: F) -> Panel {
+        let mut values = self.values.clone();
+        let mut row_values = Vec::with_capacity(self.tickers.len());
+        let mut row_index = Vec::with_capacity(self.tickers.len());
+        for i in 0..self.len() {
+            row_values.clear();
+            row_index.clear();
+            for (j, column) in self.values.iter().enumerate() {
+                if !column[i].is_nan() {
+                    row_values.push(column[i]);
+                    row_index.push(j);
+                }
+            }
+            f(&mut row_values);
+            for (x, j) in row_values.iter().zip(row_index.iter()) {
+                values[*j][i] = *x;
+            }
+        }
+        Panel { time: self.time.clone(), tickers: self.tickers.clone(), values }
+    }
+
+    /// Like `map_rows`, each row is split by `group` first.
+    pub fn map_rows_by<K, G, F>(&self, group: G, f: F) -> Panel
+    where
+        K: Eq + std::hash::Hash,
+        G: Fn(Ticker) -> K,
+        F: Fn(&mut [f32]),
+    {
+        let keys = self.tickers.map(|x| group(*x));
+        let mut grp: hm<&K, Vec<usize>> = hm::new();
+        keys.iter().enumerate().for_each(|(j, k)| grp.entry(k).or_default().push(j));
+        let mut res = self.clone();
+        for columns in grp.values() {
+            let part = Panel {
+                time: self.time.clone(),
+                tickers: columns.map(|j| self.tickers[*j]),
+                values: columns.map(|j| self.values[*j].clone()),
+            }
+            .map_rows(&f);
+            columns.iter().zip(part.values).for_each(|(j, x)| res.values[*j] = x);
+        }
+        res
+    }
+
+    /// Share of the row at or below the value, ties get their average rank, in `(0, 1]`.
+    pub fn rank(&self) -> Panel {
+        self.map_rows(rank_row)
+    }
+
+    pub fn zscore(&self) -> Panel {
+        self.map_rows(zscore_row)
+    }
+
+    pub fn demean(&self) -> Panel {
+        self.map_rows(|row| {
+            let m = mean(row);
+            row.iter_mut().for_each(|x| *x -= m);
+        })
+    }
+
+    pub fn winsorize(&self, winsor: Winsor) -> Panel {
+        self.map_rows(|row| {
+            if row.is_empty() {
+                return;
+            }
+            let (low, high) = match winsor {
+                Winsor::Quantile(low, high) => (quantile(row, low), quantile(row, high)),
+                Winsor::Sigma(k) => {
+                    let (m, s) = (mean(row), std(row));
+                    (m - k * s, m + k * s)
+                }
+            };
+            if low.is_nan() || high.is_nan() {
+                return;
+            }
+            row.iter_mut().for_each(|x| *x = x.clamp(low, high));
+        })
+    }
+
+    /// Remove the sector mean, tickers without a `ToSection` sector form a group of their own.
+    pub fn neutralize(&self) -> Panel {
+        self.map_rows_by(sector_of, |row| {
+            let m = mean(row);
+            row.iter_mut().for_each(|x| *x -= m);
+        })
+    }
+
+    /// Z-score inside each sector.
+    pub fn zscore_by_sector(&self) -> Panel {
+        self.map_rows_by(sector_of, zscore_row)
+    }
+
+    /// Bucket of each value from 0 (lowest) to `n - 1` (highest), by cross-sectional rank.
+    /// With `n == 0` there is no bucket and every value is nan.
+    pub fn quantile_bucket(&self, n: usize) -> Panel {
+        if n == 0 {
+            return self.map_rows(|row| row.fill(f32::NAN));
+        }
+        self.map_rows(|row| {
+            let len = row.len();
+            rank_row(row);
+            row.iter_mut().for_each(|x| {
+                let rank = *x * len as f32;
+                *x = (((rank - 1.) / len as f32) * n as f32).floor().clamp(0., n as f32 - 1.);
+            });
+        })
+    }
+
+    /// Values of this panel on the index and tickers of another one, like pandas `reindex_like`.
+    pub fn reindex_like(&self, other: &Panel) -> Panel {
+        let values = other.tickers.map(|ticker| match self.column(*ticker) {
+            Some(column) if !self.time.is_empty() && !other.time.is_empty() => {
+                Reindex::new(&self.time, &other.time).reindex(column).fillna(f32::NAN)
+            }
+            _ => vec![f32::NAN; other.len()],
+        });
+        Panel { time: other.time.clone(), tickers: other.tickers.clone(), values }
+    }
+}
+
+fn sector_of(ticker: Ticker) -> Result<Comdty, Ticker> {
+    ticker.try_section().ok_or(ticker)
+}
+
+fn mean(row: &[f32]) -> f32 {
+    row.iter().sum::<f32>() / row.len() as f32
+}
+
+fn std(row: &[f32]) -> f32 {
+    if row.len() < 2 {
+        return f32::NAN;
+    }
+    let m = mean(row);
+    (row.iter().map(|x| (x - m).powi(2)).sum::<f32>() / (row.len() as f32 - 1.)).sqrt()
+}
+
+fn quantile(row: &[f32], q: f32) -> f32 {
+    let mut sorted = row.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let pos = q.clamp(0., 1.) * (sorted.len() - 1) as f32;
+    let (i, w) = (pos.floor() as usize, pos.fract());
+    if i + 1 < sorted.len() {
+        sorted[i] * (1. - w) + sorted[i + 1] * w
+    } else {
+        sorted[i]
+    }
+}
+
+/// Ranks of a row in place, ties get their average rank, divided by the row length.
+pub fn rank_row(row: &mut [f32]) {
+    let len = row.len();
+    let mut order = (0..len).collect_vec();
+    order.sort_by(|a, b| row[*a].partial_cmp(&row[*b]).unwrap());
+    let mut ranks = vec![0f32; len];
+    let mut i = 0;
+    while i < len {
+        let mut k = i;
+        while k + 1 < len && row[order[k + 1]] == row[order[i]] {
+            k += 1;
+        }
+        let rank = (i + k) as f32 / 2. + 1.;
+        order[i..=k].iter().for_each(|j| ranks[*j] = rank / len as f32);
+        i = k + 1;
+    }
+    row.copy_from_slice(&ranks);
+}
+
+fn zscore_row(row: &mut [f32]) {
+    let (m, s) = (mean(row), std(row));
+    row.iter_mut().for_each(|x| *x = if s > 0. { (*x - m) / s } else { 0. });
+}
+/* #endregion */
+
+/* #region Dil */
+impl Dil {
+    /// One series per `Di` aligned on the union of the bar times.
+    pub fn panel<F: Fn(&Di) -> av32>(&self, f: F, fill: PanelFill) -> Panel {
+        let data = self.dil.map(|di| (di.pcon.ticker, di.t(), f(di)));
+        let series = data
+            .iter()
+            .map(|(ticker, t, v)| (*ticker, &t[..], &v[..]))
+            .collect_vec();
+        Panel::align(&series, fill)
+    }
+
+    pub fn panel_expr(&self, expr: &Expr, fill: PanelFill) -> Panel {
+        let plan = ExprPlan::new([expr.clone()]);
+        self.panel(|di| plan.eval(di).remove(0), fill)
+    }
+}
+/* #endregion */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn times(s: &[&str]) -> vdt {
+        s.map(|x| dt::parse_from_str(x, "%Y-%m-%d %H:%M:%S").unwrap())
+    }
+
+    #[test]
+    fn reindex_like_puts_self_on_the_other_index() {
+        let t_long = times(&["2024-01-02 09:00:00", "2024-01-02 09:01:00", "2024-01-02 09:02:00"]);
+        let t_short = times(&["2024-01-02 09:01:00"]);
+        let long = Panel::align(&[(Ticker::rb, &t_long, &[1., 2., 3.])], PanelFill::Nan);
+        let short = Panel::align(
+            &[(Ticker::rb, &t_short, &[0.]), (Ticker::au, &t_short, &[0.])],
+            PanelFill::Nan,
+        );
+        let res = long.reindex_like(&short);
+        assert_eq!(res.time, short.time);
+        assert_eq!(res.tickers, short.tickers);
+        assert_eq!(res.values[0], vec![2.]);
+        assert!(res.values[1][0].is_nan());
+    }
+
+    #[test]
+    fn quantile_bucket_of_zero_is_nan() {
+        let t = times(&["2024-01-02 09:00:00"]);
+        let panel = Panel::align(&[(Ticker::rb, &t, &[1.]), (Ticker::au, &t, &[2.])], PanelFill::Nan);
+        assert_eq!(panel.quantile_bucket(2).row(0), vec![0., 1.]);
+        assert!(panel.quantile_bucket(0).row(0).iter().all(|x| x.is_nan()));
+    }
+}
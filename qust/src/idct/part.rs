@@ -1,7 +1,7 @@
 use crate::idct::ta::Ta;
 use crate::prelude::TradingPeriod;
 use crate::prelude::{Calc, CalcSave, CalcSaveWrapper, Pre};
-use crate::trade::di::*;
+use crate::trade::{di::*, mmap::Col};
 use chrono::Timelike;
 use qust_derive::*;
 use qust_ds::prelude::*;
@@ -21,7 +21,7 @@ fn _find_day_index(time_vec: &[dt]) -> vuz {
     res.push(time_vec.len());
     res
 }
-pub fn find_day_index_night_flat(time_vec: avdt) -> vuz {
+pub fn find_day_index_night_flat(time_vec: &[dt]) -> vuz {
     let mut res = vec![0usize; time_vec.len()];
     let hour_vec = time_vec.iter().map(|x| x.hour()).collect_vec();
     let end_range = 13..16;
@@ -34,7 +34,7 @@ pub fn find_day_index_night_flat(time_vec: avdt) -> vuz {
     }
     res
 }
-pub fn find_day_index_night(time_vec: avdt) -> vuz {
+pub fn find_day_index_night(time_vec: &[dt]) -> vuz {
     let mut res = vec![0usize];
     let hour_vec = time_vec.iter().map(|x| x.hour()).collect_vec();
     let end_range = 13..16;
@@ -57,32 +57,34 @@ impl CalcSave for FindDayIndex {
     }
 }
 
-pub(crate) fn find_day_index_night_pre(time_vec: &[dt]) -> vuz {
-    let mut res = vec![0usize];
-    let hour_vec = time_vec.map(|x| x.hour());
-    let tt_vec = time_vec.map(|x| x.time());
+/// Whether the bar at `now` opens a new trading day after the bar at `pre`, the night
+/// session belongs to the next day.
+pub fn is_new_trading_day(pre: &dt, now: &dt) -> bool {
+    let (hour_pre, hour_now) = (pre.hour(), now.hour());
     let end_range_light = 8..20;
     let end_range_night = 20..23;
-    izip!(hour_vec.windows(2), tt_vec.windows(2))
+    let is_in_light_pre = end_range_light.contains(&hour_pre);
+    let is_in_night_now = end_range_night.contains(&hour_now);
+    if is_in_light_pre && is_in_night_now {
+        true
+    } else {
+        let is_time_growing = pre.time() > now.time();
+        if is_in_light_pre && is_time_growing {
+            true
+        } else {
+            let is_in_night_pre = end_range_night.contains(&hour_pre);
+            is_in_night_pre && is_in_night_now && is_time_growing
+        }
+    }
+}
+
+pub(crate) fn find_day_index_night_pre(time_vec: &[dt]) -> vuz {
+    let mut res = vec![0usize];
+    time_vec
+        .windows(2)
         .enumerate()
-        .for_each(|(i, (h, t))| {
-            let (hour_pre, hour_now) = (h.first().unwrap(), h.last().unwrap());
-            let is_in_light_pre = end_range_light.contains(hour_pre);
-            let is_in_night_now = end_range_night.contains(hour_now);
-            let is_in_cut = if is_in_light_pre && is_in_night_now {
-                true
-            } else {
-                let time_pre = t.first().unwrap();
-                let time_now = t.last().unwrap();
-                let is_time_growing = time_pre > time_now;
-                if is_in_light_pre && is_time_growing {
-                    true
-                } else {
-                    let is_in_night_pre = end_range_night.contains(hour_pre);
-                    is_in_night_pre && is_in_night_now && is_time_growing
-                }
-            };
-            if is_in_cut {
+        .for_each(|(i, t)| {
+            if is_new_trading_day(&t[0], &t[1]) {
                 res.push(i + 1);
             }
         });
@@ -111,7 +113,7 @@ impl Part {
             .pip(CalcSaveWrapper)
             .calc(di);
         let index_part = index_part.downcast_ref().unwrap();
-        let data = ta.calc_di(di);
+        let data = ta.calc_di_col(di);
         let mut data_iter = self.cut_part(index_part, &data);
         let mut res_part = ta.calc_da(data_iter.next().unwrap(), di);
         let mut accu = init_a_matrix(data[0].len(), res_part.len());
@@ -136,7 +138,7 @@ impl Part {
     fn cut_part<'a>(
         &self,
         index_part: &'a vuz,
-        data: &'a avv32,
+        data: &'a [Col<f32>],
     ) -> impl Iterator<Item = Vec<&'a [f32]>> {
         index_part.windows(2).map(|x| {
             data.iter()
@@ -5,7 +5,7 @@ use super::prelude::Convert;
 use crate::idct::fore::ForeTaCalc;
 use crate::idct::part::Part::*;
 use crate::prelude::{find_day_index_night_flat, KlineState, PriBox};
-use crate::trade::di::Di;
+use crate::trade::{di::Di, mmap::Col};
 use crate::trade::ticker::Comm;
 use qust_ds::roll::RollFunc;
 use qust_ds::prelude::*;
@@ -26,7 +26,12 @@ pub enum KlineType {
 pub trait Ta {
     fn start(&self, _di: &Di) {}
     fn calc_di(&self, di: &Di) -> avv32 {
-        vec![di.c()]
+        vec![di.c().to_arc()]
+    }
+    /// `calc_di` as columns, this is what `calc_da` is fed with. Override it to hand the bars
+    /// of a mapped `Di` to `calc_da` without copying them.
+    fn calc_di_col(&self, di: &Di) -> Vec<Col<f32>> {
+        self.calc_di(di).into_map(Col::from)
     }
     fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32;
     fn end(&self, _di: &Di) {}
@@ -86,7 +91,7 @@ impl Di {
     pub fn profit(&self) -> Vec<f32> {
         let c = self.c();
         let c_lag = c.lag(1f32);
-        let mut res = izip!(c.iter(), c_lag.iter(), self.pcon.price.ki.rolling(2))
+        let mut res = izip!(c.iter(), c_lag.iter(), self.base_ki().rolling(2))
             .map(|(x, y, z)| {
                 if z.first().unwrap().contract == z.last().unwrap().contract {
                     x / y - 1.
@@ -125,6 +130,10 @@ impl Ta for CommSlip {
 #[typetag::serde]
 impl Ta for KlineType {
     fn calc_di(&self, di: &Di) -> avv32 {
+        self.calc_di_col(di).into_map(|x| x.to_arc())
+    }
+
+    fn calc_di_col(&self, di: &Di) -> Vec<Col<f32>> {
         use KlineType::*;
         match self {
             Open => di.o(),
@@ -149,7 +158,7 @@ pub struct Rsi(pub usize);
 #[typetag::serde]
 impl Ta for Rsi {
     fn calc_di(&self, di: &Di) -> avv32 {
-        vec![di.c()]
+        vec![di.c().to_arc()]
     }
     fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32 {
         let da = da[0];
@@ -182,7 +191,7 @@ pub struct Tr;
 #[typetag::serde]
 impl Ta for Tr {
     fn calc_di(&self, di: &Di) -> avv32 {
-        vec![di.h(), di.l(), di.c()]
+        vec![di.h().to_arc(), di.l().to_arc(), di.c().to_arc()]
     }
     fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32 {
         let h = da[0];
@@ -221,7 +230,7 @@ pub struct RollTa<T>(pub T, pub RollFunc, pub RollOps);
 #[typetag::serde(name = "rollta_klinetype")]
 impl Ta for RollTa<KlineType> {
     fn calc_di(&self, di: &Di) -> avv32 {
-        vec![di.c()]
+        vec![di.c().to_arc()]
     }
     fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32 {
         da.roll(self.1, self.2.clone())
@@ -250,7 +259,7 @@ pub struct Max(pub KlineType, pub usize);
 #[typetag::serde]
 impl Ta for Max {
     fn calc_di(&self, di: &Di) -> avv32 {
-        vec![di.get_kline(&self.0)]
+        vec![di.get_kline(&self.0).to_arc()]
     }
     fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32 {
         da.roll_max(self.1)
@@ -263,7 +272,7 @@ pub struct Min(pub KlineType, pub usize);
 #[typetag::serde]
 impl Ta for Min {
     fn calc_di(&self, di: &Di) -> avv32 {
-        vec![di.get_kline(&self.0)]
+        vec![di.get_kline(&self.0).to_arc()]
     }
     fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32 {
         da.roll_min(self.1)
@@ -279,7 +288,7 @@ pub struct KDayRatio(pub usize);
 #[typetag::serde]
 impl Ta for KDayRatio {
     fn calc_di(&self, di: &Di) -> avv32 {
-        vec![di.o(), di.c()]
+        vec![di.o().to_arc(), di.c().to_arc()]
     }
     fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32 {
         let gap = izip!(da[0].iter(), da[1].iter())
@@ -320,12 +329,12 @@ pub struct ShiftDays(pub usize, pub KlineType, pub IndexSpec);
 #[typetag::serde]
 impl Ta for ShiftDays {
     fn calc_di(&self, di: &Di) -> avv32 {
-        vec![di.get_kline(&self.1)]
+        vec![di.get_kline(&self.1).to_arc()]
     }
 
     fn calc_da(&self, da: Vec<&[f32]>, di: &Di) -> vv32 {
         // let da_vec = di.t().iter().map(|x| x.date()).collect();
-        let da_vec = find_day_index_night_flat(di.t());
+        let da_vec = find_day_index_night_flat(&di.t());
         let grp = Grp(da_vec);
         let (vec_index, vec_value) = grp.apply(da[0], |x| self.2.get(x));
         let vec_value = vec_value.lag(self.0 as f32);
@@ -358,7 +367,7 @@ impl Ta for ShiftInter {
                     index += 1.;
                 }
             });
-         vec![di.get_kline(&self.kline), Arc::new(da_vec)]
+         vec![di.get_kline(&self.kline).to_arc(), Arc::new(da_vec)]
     }
 
     fn calc_da(&self,da:Vec<&[f32]>, _di: &Di) -> vv32 {
@@ -379,7 +388,7 @@ pub struct DayKlineWrapper(pub KlineType);
 #[typetag::serde(name = "DayKlineWrapper")]
 impl Ta for DayKlineWrapper {
     fn calc_da(&self, _da: Vec<&[f32]>, di: &Di) -> vv32 {
-        let da_vec = find_day_index_night_flat(di.t());
+        let da_vec = find_day_index_night_flat(&di.t());
         let grp = Grp(da_vec);
         let (_, vec_value) = match self.0 {
             KlineType::Open => grp.apply(&di.o(), |x| vec![x[0]; x.len()]),
@@ -437,7 +446,7 @@ pub struct Jta(pub usize, pub usize, pub usize);
 impl Ta for Kta {
     fn calc_di(&self, di: &Di) -> avv32 {
         // vec![di.h(), di.l(), di.c()]
-        vec![di.c(), di.c(), di.c()]
+        vec![di.c().to_arc(), di.c().to_arc(), di.c().to_arc()]
     }
     fn calc_da(&self, da: Vec<&[f32]>, _di: &Di) -> vv32 {
         let rsvnum =
@@ -1,21 +1,27 @@
 #![allow(non_upper_case_globals, non_camel_case_types, clippy::collapsible_else_if)]
 pub mod trade {
+    pub mod calendar;
+    pub mod clean;
     pub mod di;
     pub mod idx;
     pub mod inter;
+    pub mod mmap;
     pub mod ticker;
     pub(crate) mod version;
 
     pub mod prelude {
-        pub use super::{di::*, idx::*, inter::*, ticker::*};
+        pub use super::{calendar::*, clean::*, di::*, idx::*, inter::*, mmap::*, ticker::*};
     }
 }
 
 pub mod idct {
     pub mod calc;
     pub mod dcon;
+    pub mod expr;
     pub mod fore;
     pub mod macros;
+    pub mod pairs;
+    pub mod panel;
     pub mod part;
     pub mod pms;
     pub mod ta;
@@ -25,6 +31,8 @@ pub mod idct {
             calc::*,
             dcon::{Convert::*, *},
             fore::*,
+            pairs::*,
+            panel::*,
             part::*,
             pms::*,
             ta::{Max as maxta, Min as minta, *},
@@ -39,6 +47,7 @@ pub mod sig {
     pub mod livesig;
     pub mod pnl;
     pub mod posi;
+    pub mod trade_list;
 
     pub mod prelude {
         pub use super::{
@@ -48,6 +57,7 @@ pub mod sig {
             livesig::*,
             pnl::*,
             posi::{Dire::*, *},
+            trade_list::*,
         };
         pub const and: LogicOps = LogicOps::And;
         pub const or: LogicOps = LogicOps::Or;
@@ -57,6 +67,13 @@ pub mod sig {
 pub mod live {
     pub mod order_types;
     pub mod live_ops;
+    pub mod pipe;
+    pub mod latency;
+    pub mod control;
+    pub mod alert;
+    pub mod stra_manager;
+    pub mod scheduler;
+    pub mod time_rules;
     pub mod match_ops;
     pub mod algo;
     pub mod thread_manger;
@@ -64,12 +81,21 @@ pub mod live {
     pub mod cross;
     pub mod trend;
     pub mod bt;
+    pub mod divergence;
 
     pub mod prelude {
         pub use super::{
             bt::*,
+            divergence::*,
             order_types::*,
             live_ops::*,
+            pipe::*,
+            latency::*,
+            control::*,
+            alert::*,
+            stra_manager::*,
+            scheduler::*,
+            time_rules::*,
             match_ops::*,
             algo::*,
             live_run::*,
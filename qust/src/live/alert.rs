@@ -0,0 +1,451 @@
+use std::collections::VecDeque;
+use std::io::Write;
+use std::process::{ Command, Stdio };
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use chrono::{ Duration, Local };
+use once_cell::sync::Lazy;
+use serde::{ Deserialize, Serialize };
+use tokio::sync::broadcast::error::RecvError;
+use anyhow::{ anyhow, Result };
+use qust_ds::prelude::*;
+use qust_derive::*;
+use dyn_clone::{clone_trait_object, DynClone};
+use crate::{ loge, std_prelude::* };
+use crate::prelude::{ ExtractTicker, TradingCalendar };
+use super::control::{ emit, LiveEvent, StraControl, EVENTS };
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum AlertLevel {
+    Info,
+    Warn,
+    Critical,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AlertKind {
+    LoginFailed,
+    Disconnected,
+    Reconnected,
+    OrderRejected,
+    RiskGate,
+    PositionMismatch,
+    StaleData,
+    Other,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Alert {
+    pub level: AlertLevel,
+    pub kind: AlertKind,
+    /// the account, contract or strategy it is about, alerts are deduplicated by it
+    pub source: String,
+    pub msg: String,
+    pub time: dt,
+    /// alerts of the same kind and source held back since the last one sent
+    #[serde(default)]
+    pub suppressed: usize,
+    /// alerts of any kind dropped by the rate limit since the last one sent
+    #[serde(default)]
+    pub dropped: usize,
+}
+
+impl Alert {
+    pub fn new(level: AlertLevel, kind: AlertKind, source: impl Into<String>, msg: impl Into<String>) -> Self {
+        Self {
+            level,
+            kind,
+            source: source.into(),
+            msg: msg.into(),
+            time: Local::now().naive_local(),
+            suppressed: 0,
+            dropped: 0,
+        }
+    }
+}
+
+impl std::fmt::Display for Alert {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}] {:?} {:?} {}: {}", self.time.format("%Y-%m-%d %H:%M:%S"), self.level, self.kind, self.source, self.msg)?;
+        if self.suppressed > 0 {
+            write!(f, " ({} alike held back)", self.suppressed)?;
+        }
+        if self.dropped > 0 {
+            write!(f, " ({} dropped by the rate limit)", self.dropped)?;
+        }
+        Ok(())
+    }
+}
+
+/// Alerts raised so far, `AlertBus` counts the ones it is done with against it.
+static RAISED: AtomicUsize = AtomicUsize::new(0);
+
+type BusProgress = Arc<(Mutex<usize>, Condvar)>;
+
+static BUS_PROGRESS: Lazy<Mutex<Vec<BusProgress>>> = Lazy::new(Default::default);
+
+/// Logs `alert` and hands it to the `AlertBus`es running.
+pub fn raise(alert: Alert) {
+    loge!(level: Warn, "ctp", "alert {}", alert);
+    RAISED.fetch_add(1, Ordering::SeqCst);
+    emit(LiveEvent::Alert { alert });
+}
+
+/// Waits until every `AlertBus` running has sent the alerts raised so far, for at most
+/// `timeout`. False if one of them is still busy, e.g. before the process exits.
+pub fn flush_alerts(timeout: dura) -> bool {
+    let raised = RAISED.load(Ordering::SeqCst);
+    let deadline = Instant::now() + timeout;
+    for progress in BUS_PROGRESS.lock().unwrap().iter() {
+        let (done, cvar) = &**progress;
+        let mut done = done.lock().unwrap();
+        while *done < raised {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return false;
+            }
+            done = cvar.wait_timeout(done, left).unwrap().0;
+        }
+    }
+    true
+}
+
+/// Where alerts go, set in the `sinks` of `AlertConfig` by the typetag name,
+/// e.g. `{"AlertSink":"FileSink","path":"./logs/alerts.log"}`.
+#[clone_trait]
+pub trait AlertSink {
+    fn send(&self, alert: &Alert) -> Result<()>;
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StdoutSink;
+
+#[typetag::serde]
+impl AlertSink for StdoutSink {
+    fn send(&self, alert: &Alert) -> Result<()> {
+        println!("{}", alert);
+        Ok(())
+    }
+}
+
+/// One json line per alert.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileSink {
+    pub path: String,
+}
+
+#[typetag::serde]
+impl AlertSink for FileSink {
+    fn send(&self, alert: &Alert) -> Result<()> {
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(alert)?)?;
+        Ok(())
+    }
+}
+
+/// Mails through the local `sendmail`, or another command reading a message with headers
+/// from stdin.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmailSink {
+    pub from: String,
+    pub to: Vec<String>,
+    #[serde(default = "default_sendmail")]
+    pub command: Vec<String>,
+}
+
+fn default_sendmail() -> Vec<String> {
+    vec!["sendmail".into(), "-t".into()]
+}
+
+#[typetag::serde]
+impl AlertSink for EmailSink {
+    fn send(&self, alert: &Alert) -> Result<()> {
+        let (program, args) = self.command
+            .split_first()
+            .ok_or_else(|| anyhow!("email sink has no command"))?;
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()?;
+        let message = format!(
+            "From: {}\r\nTo: {}\r\nSubject: [{:?}] {:?} {}\r\n\r\n{}\r\n",
+            self.from,
+            self.to.join(", "),
+            alert.level,
+            alert.kind,
+            alert.source,
+            alert,
+        );
+        child
+            .stdin
+            .take()
+            .ok_or_else(|| anyhow!("no stdin of {}", program))?
+            .write_all(message.as_bytes())?;
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(anyhow!("{} exited with {}", program, status));
+        }
+        Ok(())
+    }
+}
+
+/// Keeps what it is sent, for tests.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StubSink {
+    #[serde(skip)]
+    sent: Arc<Mutex<Vec<Alert>>>,
+}
+
+impl StubSink {
+    pub fn sent(&self) -> Vec<Alert> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+#[typetag::serde]
+impl AlertSink for StubSink {
+    fn send(&self, alert: &Alert) -> Result<()> {
+        self.sent.lock().unwrap().push(alert.clone());
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AlertConfig {
+    pub min_level: AlertLevel,
+    /// an alert of the kind and source of one sent within this many seconds is held back
+    pub dedup_secs: i64,
+    /// alerts sent in a minute at most, the rest are dropped
+    pub max_per_minute: usize,
+    pub sinks: Vec<AlertSinkBox>,
+}
+
+impl Default for AlertConfig {
+    fn default() -> Self {
+        Self {
+            min_level: AlertLevel::Warn,
+            dedup_secs: 300,
+            max_per_minute: 20,
+            sinks: vec![Box::new(StdoutSink)],
+        }
+    }
+}
+
+/// Sends the alerts raised to the sinks, a flapping connection is held back by the
+/// deduplication and a burst by the rate limit. Both go by the time of the alerts.
+pub struct AlertBus {
+    pub config: AlertConfig,
+    last_sent: hm<(AlertKind, String), (dt, usize)>,
+    sent: VecDeque<dt>,
+    dropped: usize,
+}
+
+impl AlertBus {
+    pub fn new(config: AlertConfig) -> Self {
+        Self { config, last_sent: hm::new(), sent: Default::default(), dropped: 0 }
+    }
+
+    /// The alert to send with the counts of those held back, None if it is held back.
+    pub fn filter(&mut self, mut alert: Alert) -> Option<Alert> {
+        if alert.level < self.config.min_level {
+            return None;
+        }
+        let key = (alert.kind, alert.source.clone());
+        if let Some((time, suppressed)) = self.last_sent.get_mut(&key) {
+            if alert.time - *time < Duration::seconds(self.config.dedup_secs) {
+                *suppressed += 1;
+                return None;
+            }
+        }
+        while self.sent.front().is_some_and(|x| alert.time - *x >= Duration::minutes(1)) {
+            self.sent.pop_front();
+        }
+        if self.sent.len() >= self.config.max_per_minute {
+            self.dropped += 1;
+            return None;
+        }
+        self.sent.push_back(alert.time);
+        alert.suppressed = self.last_sent.insert(key, (alert.time, 0)).map(|x| x.1).unwrap_or(0);
+        alert.dropped = std::mem::take(&mut self.dropped);
+        Some(alert)
+    }
+
+    pub fn dispatch(&mut self, alert: Alert) {
+        let Some(alert) = self.filter(alert) else {
+            return;
+        };
+        for sink in self.config.sinks.iter() {
+            if let Err(e) = sink.send(&alert) {
+                loge!(level: Error, "ctp", "alert sink {:?} failed: {}", sink, e);
+            }
+        }
+    }
+
+    /// Dispatches the alerts of `EVENTS` on a thread of its own.
+    pub fn spawn(mut self) -> thread::JoinHandle<()> {
+        let mut rx = EVENTS.subscribe();
+        // alerts raised before the bus subscribed never reach it
+        let progress: BusProgress = Arc::new((Mutex::new(RAISED.load(Ordering::SeqCst)), Condvar::new()));
+        BUS_PROGRESS.lock().unwrap().push(progress.clone());
+        thread::spawn(move || loop {
+            let done = match rx.blocking_recv() {
+                Ok(LiveEvent::Alert { alert }) => {
+                    self.dispatch(alert);
+                    *progress.0.lock().unwrap() + 1
+                }
+                Ok(_) => continue,
+                Err(RecvError::Lagged(n)) => {
+                    loge!(level: Warn, "ctp", "alert bus skipped {} events", n);
+                    RAISED.load(Ordering::SeqCst)
+                }
+                Err(RecvError::Closed) => break,
+            };
+            *progress.0.lock().unwrap() = done;
+            progress.1.notify_all();
+        })
+    }
+}
+
+/// `PositionMismatch` alerts for the contracts `account` holds other than the strategies
+/// trading them do together.
+pub fn position_mismatches(account: &hm<String, f32>, controls: &[Arc<StraControl>]) -> Vec<Alert> {
+    let mut expected: hm<String, f32> = hm::new();
+    for status in controls.iter().flat_map(|x| x.status().contracts) {
+        *expected.entry(status.contract).or_default() += status.hold;
+    }
+    expected
+        .into_iter()
+        .sorted_by(|a, b| a.0.cmp(&b.0))
+        .filter_map(|(contract, hold)| {
+            let held = account.get(&contract).copied().unwrap_or_default();
+            (held != hold).then(|| Alert::new(
+                AlertLevel::Critical,
+                AlertKind::PositionMismatch,
+                contract,
+                format!("account holds {} and the strategies {}", held, hold),
+            ))
+        })
+        .collect_vec()
+}
+
+/// When each contract watched got its last tick, for `StaleData` alerts.
+#[derive(Default)]
+pub struct TickWatch {
+    last: RwLock<hm<String, dt>>,
+    calendar: RwLock<TradingCalendar>,
+}
+
+pub static TICK_WATCH: Lazy<TickWatch> = Lazy::new(Default::default);
+
+impl TickWatch {
+    /// Starts watching `contract` as if it ticked now.
+    pub fn watch(&self, contract: &str) {
+        let now = Local::now().naive_local();
+        self.last.write().unwrap().entry(contract.into()).or_insert(now);
+    }
+
+    pub fn unwatch(&self, contract: &str) {
+        self.last.write().unwrap().remove(contract);
+    }
+
+    /// Holidays without sessions, weekends have none with any calendar.
+    pub fn set_calendar(&self, calendar: TradingCalendar) {
+        *self.calendar.write().unwrap() = calendar;
+    }
+
+    /// Takes every contract as ticked now, on `Start` of a session so the time since the
+    /// last session is not taken for stale data.
+    pub fn reset(&self) {
+        let now = Local::now().naive_local();
+        self.last.write().unwrap().values_mut().for_each(|x| *x = now);
+    }
+
+    pub fn on_tick(&self, contract: &str) {
+        let now = Local::now().naive_local();
+        if let Some(last) = self.last.write().unwrap().get_mut(contract) {
+            *last = now;
+        }
+    }
+
+    /// Contracts without a tick in the last `max_secs`, only while their session has run
+    /// all that time so breaks are not taken for stale data. Nothing is stale on weekends
+    /// and holidays, the session times of the tickers run on those days too.
+    pub fn check(&self, now: dt, max_secs: i64) -> Vec<Alert> {
+        if !has_session(&self.calendar.read().unwrap(), now) {
+            return vec![];
+        }
+        let from = now - Duration::seconds(max_secs);
+        self.last
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, last)| **last < from)
+            .filter(|(contract, _)| {
+                contract
+                    .as_str()
+                    .extract_ticker()
+                    .is_some_and(|(ticker, _)| ticker.in_session(now.time(), 0) && ticker.in_session(from.time(), 0))
+            })
+            .map(|(contract, last)| Alert::new(
+                AlertLevel::Warn,
+                AlertKind::StaleData,
+                contract.clone(),
+                format!("no tick since {}", last.format("%H:%M:%S")),
+            ))
+            .collect_vec()
+    }
+}
+
+/// False on days without the day session at `now`, or evenings without the night session.
+fn has_session(calendar: &TradingCalendar, now: dt) -> bool {
+    let time = now.time();
+    if time >= 180000.to_tt() {
+        calendar.has_night_after(now.date())
+    } else if time < 60000.to_tt() {
+        calendar.has_night_after(now.date() - Duration::days(1))
+    } else {
+        calendar.is_trading_day(&now.date())
+    }
+}
+
+/// Raises the `StaleData` alerts of `TICK_WATCH` every `every`.
+pub fn spawn_stale_check(max_secs: i64, every: dura) -> thread::JoinHandle<()> {
+    thread::spawn(move || loop {
+        sleep(every);
+        TICK_WATCH
+            .check(Local::now().naive_local(), max_secs)
+            .into_iter()
+            .for_each(raise);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flush_waits_until_the_bus_sent_the_alert() {
+        AlertBus::new(AlertConfig { sinks: vec![], ..Default::default() }).spawn();
+        raise(Alert::new(AlertLevel::Critical, AlertKind::RiskGate, "test", "flush"));
+        assert!(flush_alerts(dura::from_secs(5)));
+    }
+
+    #[test]
+    fn no_stale_data_on_weekends_and_holidays() {
+        let t = |s: &str| dt::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap();
+        let watch = TickWatch::default();
+        // friday afternoon
+        watch.last.write().unwrap().insert("rb2405".into(), t("2024-01-05 14:00:00"));
+        assert_eq!(watch.check(t("2024-01-05 21:30:00"), 60).len(), 1);
+        assert!(watch.check(t("2024-01-06 10:00:00"), 60).is_empty());
+        assert!(watch.check(t("2024-01-07 21:30:00"), 60).is_empty());
+        assert_eq!(watch.check(t("2024-01-08 10:00:00"), 60).len(), 1);
+        watch.set_calendar(TradingCalendar::new([t("2024-01-08 00:00:00").date()]));
+        assert!(watch.check(t("2024-01-05 21:30:00"), 60).is_empty());
+        assert!(watch.check(t("2024-01-08 10:00:00"), 60).is_empty());
+    }
+}
@@ -2,6 +2,7 @@ use qust_ds::prelude::*;
 use crate::trade::prelude::*;
 use crate::sig::posi::Dire;
 use super::order_types::*;
+use super::algo::Algo;
 
 pub type WithDi<'a, T> = WithInfo<T, &'a Di>; 
 pub type WithTicker<T> = WithInfo<T, Ticker>;
@@ -16,17 +17,7 @@ pub struct DiKline<'a> {
 
 impl std::fmt::Debug for DiKline<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let price = &self.di.pcon.price;
-        let kline = KlineData {
-            t: price.t[self.i],
-            o: price.o[self.i],
-            h: price.h[self.i],
-            l: price.l[self.i],
-            c: price.c[self.i],
-            v: price.v[self.i],
-            ki: price.ki[self.i].clone(),
-        };
-        write!(f, "{:?}", kline)
+        write!(f, "{:?}", self.di.kline_data(self.i))
     }
 }
 
@@ -54,6 +45,11 @@ pub trait BtKline<Input> {
 
 pub trait ApiType: Send + Sync {
     fn api_type(&self) -> RetFnApi;
+
+    /// The strategy with its `Algo` replaced, None if it has none to replace.
+    fn api_type_algo<'a>(&'a self, _algo: &dyn Algo) -> Option<RetFnApi<'a>> {
+        None
+    }
     fn api_type_box(&self) -> Box<dyn ApiType>
     where
         Self: Clone + 'static,
@@ -0,0 +1,306 @@
+use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
+use once_cell::sync::Lazy;
+use serde::{ Deserialize, Serialize };
+use tokio::sync::broadcast;
+use anyhow::{ anyhow, Result };
+use qust_ds::prelude::*;
+use crate::{ loge, std_prelude::* };
+use crate::prelude::{ Ticker, TickData };
+use super::algo::{ AlgoBox, OrderActionNum };
+use super::order_types::{ Hold, OrderAction };
+use super::scheduler::SessionEvent;
+use super::alert::{ raise, Alert, AlertKind, AlertLevel };
+
+/// Every strategy closes all its positions and opens nothing while it is on.
+pub static KILL_SWITCH: AtomicBool = AtomicBool::new(false);
+
+pub fn kill_switch(on: bool) {
+    KILL_SWITCH.store(on, Ordering::SeqCst);
+    loge!(level: Warn, "ctp", "kill switch {}", if on { "on" } else { "off" });
+    emit(LiveEvent::Kill { on });
+    if on {
+        raise(Alert::new(AlertLevel::Critical, AlertKind::RiskGate, "kill switch", "every strategy is flattening"));
+    }
+}
+
+pub fn is_killed() -> bool {
+    KILL_SWITCH.load(Ordering::SeqCst)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum LiveEvent {
+    Order { stra: String, contract: String, action: OrderAction },
+    Fill { stra: String, contract: String, action: OrderAction, time: dt },
+    Control { stra: String, msg: String },
+    Kill { on: bool },
+    Session(SessionEvent),
+    Alert { alert: Alert },
+}
+
+/// Events of the live engine, for dashboards and alerts.
+pub static EVENTS: Lazy<broadcast::Sender<LiveEvent>> = Lazy::new(|| broadcast::channel(4096).0);
+
+pub fn emit(event: LiveEvent) {
+    // no subscriber is fine
+    let _ = EVENTS.send(event);
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContractStatus {
+    pub contract: String,
+    pub ticker: Ticker,
+    pub hold: f32,
+    /// hold the last order of the strategy goes for
+    pub target: f32,
+    pub last: f32,
+    /// money paid for the fills, negative when bought. Fills come from the trade returns at
+    /// the traded price, a hold taken before the start has no cash, so its pnl is off by
+    /// its cost until it is closed
+    pub cash: f32,
+    /// `cash` plus the hold valued at `last`
+    pub pnl: f32,
+    pub flatten: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StraStatus {
+    pub id: String,
+    pub name: String,
+    pub paused: bool,
+    pub multiplier: f32,
+    pub algo: Option<String>,
+    pub contracts: Vec<ContractStatus>,
+}
+
+/// Runtime switches of a live strategy, set from outside and read by its `ApiBridge` on
+/// every update. The strategy keeps updating while paused or flattening, only its orders
+/// are held back.
+pub struct StraControl {
+    pub id: String,
+    pub name: String,
+    paused: AtomicBool,
+    multiplier: RwLock<f32>,
+    algo: RwLock<Option<AlgoBox>>,
+    algo_version: AtomicUsize,
+    supports_algo: bool,
+    contracts: RwLock<Vec<ContractStatus>>,
+}
+
+impl std::fmt::Debug for StraControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StraControl")
+            .field("id", &self.id)
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+impl StraControl {
+    pub fn new(id: String, name: String, contracts: Vec<(Ticker, sstr)>, supports_algo: bool) -> Self {
+        let contracts = contracts
+            .into_iter()
+            .map(|(ticker, contract)| ContractStatus {
+                contract: contract.to_string(),
+                ticker,
+                hold: 0.,
+                target: 0.,
+                last: 0.,
+                cash: 0.,
+                pnl: 0.,
+                flatten: false,
+            })
+            .collect_vec();
+        Self {
+            id,
+            name,
+            paused: AtomicBool::new(false),
+            multiplier: RwLock::new(1.),
+            algo: RwLock::new(None),
+            algo_version: AtomicUsize::new(0),
+            supports_algo,
+            contracts: RwLock::new(contracts),
+        }
+    }
+
+    pub fn status(&self) -> StraStatus {
+        let mut contracts = self.contracts.read().unwrap().clone();
+        for status in contracts.iter_mut() {
+            status.pnl = status.cash + status.hold * status.last * status.ticker.info().pv;
+        }
+        StraStatus {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            paused: self.is_paused(),
+            multiplier: self.multiplier(),
+            algo: self.algo.read().unwrap().as_ref().map(|x| format!("{:?}", x)),
+            contracts,
+        }
+    }
+
+    fn control_event(&self, msg: String) {
+        loge!("ctp", "{} {}", self.id, msg);
+        emit(LiveEvent::Control { stra: self.id.clone(), msg });
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Working orders are cancelled, positions are kept.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+        self.control_event("paused".into());
+    }
+
+    /// Also ends flattening.
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+        self.contracts.write().unwrap().iter_mut().for_each(|x| x.flatten = false);
+        self.control_event("resumed".into());
+    }
+
+    /// Closes `contract`, or every contract, and keeps it flat until `resume`. False if the
+    /// strategy does not trade it.
+    pub fn flatten(&self, contract: Option<&str>) -> bool {
+        let mut found = false;
+        for status in self.contracts.write().unwrap().iter_mut() {
+            if contract.is_none_or(|x| x == status.contract) {
+                status.flatten = true;
+                found = true;
+            }
+        }
+        if found {
+            self.control_event(format!("flatten {}", contract.unwrap_or("all")));
+        }
+        found
+    }
+
+    pub fn multiplier(&self) -> f32 {
+        *self.multiplier.read().unwrap()
+    }
+
+    /// Scales the targets of the strategy, the hold it sees is divided by the multiplier.
+    pub fn set_multiplier(&self, multiplier: f32) -> Result<()> {
+        if !multiplier.is_finite() || multiplier <= 0. {
+            return Err(anyhow!("multiplier must be positive: {}", multiplier));
+        }
+        *self.multiplier.write().unwrap() = multiplier;
+        self.control_event(format!("multiplier {}", multiplier));
+        Ok(())
+    }
+
+    pub fn set_algo(&self, algo: AlgoBox) -> Result<()> {
+        if !self.supports_algo {
+            return Err(anyhow!("{} has no algo to replace", self.id));
+        }
+        let msg = format!("algo {:?}", algo);
+        *self.algo.write().unwrap() = Some(algo);
+        self.algo_version.fetch_add(1, Ordering::SeqCst);
+        self.control_event(msg);
+        Ok(())
+    }
+
+    /// The algo set since `version` was seen, `version` is moved to the current one.
+    pub fn algo_changed(&self, version: &mut usize) -> Option<AlgoBox> {
+        let now = self.algo_version.load(Ordering::SeqCst);
+        if now == *version {
+            return None;
+        }
+        *version = now;
+        self.algo.read().unwrap().clone()
+    }
+
+    /// The hold handed to the strategy, in its own lots, fractional when the multiplier is
+    /// not a whole number.
+    pub fn hold_to_stra(&self, hold: &Hold) -> Hold {
+        let multiplier = self.multiplier();
+        Hold { lo: hold.lo / multiplier, sh: hold.sh / multiplier }
+    }
+
+    /// The order of the strategy on contract `i` after the switches.
+    pub fn gate(&self, i: usize, hold: &Hold, tick_data: &TickData, order_action: OrderAction) -> OrderAction {
+        let hold = hold.sum();
+        let mut contracts = self.contracts.write().unwrap();
+        let status = &mut contracts[i];
+        let res = if is_killed() || status.flatten {
+            OrderActionNum::from_hold_target(hold, 0.).into_order_action(tick_data.ask1, tick_data.bid1)
+        } else if self.is_paused() {
+            OrderAction::No
+        } else {
+            scale_order_action(order_action, hold, self.multiplier())
+        };
+        status.target = hold + signed_num(&res);
+        res
+    }
+
+    pub fn on_tick(&self, i: usize, tick_data: &TickData) {
+        self.contracts.write().unwrap()[i].last = tick_data.c;
+    }
+
+    pub fn on_hold(&self, i: usize, hold: &Hold) {
+        self.contracts.write().unwrap()[i].hold = hold.sum();
+    }
+
+    pub fn on_order(&self, i: usize, order_action: &OrderAction) {
+        let contract = self.contracts.read().unwrap()[i].contract.clone();
+        emit(LiveEvent::Order { stra: self.id.clone(), contract, action: order_action.clone() });
+    }
+
+    pub fn on_fill(&self, i: usize, order_action: &OrderAction, time: dt) {
+        let mut contracts = self.contracts.write().unwrap();
+        let status = &mut contracts[i];
+        let price = match *order_action {
+            OrderAction::LoOpen(_, p) | OrderAction::LoClose(_, p) | OrderAction::ShOpen(_, p) | OrderAction::ShClose(_, p) => p,
+            OrderAction::No => return,
+        };
+        status.cash -= signed_num(order_action) * price * status.ticker.info().pv;
+        let contract = status.contract.clone();
+        drop(contracts);
+        emit(LiveEvent::Fill { stra: self.id.clone(), contract, action: order_action.clone(), time });
+    }
+}
+
+fn signed_num(order_action: &OrderAction) -> f32 {
+    match *order_action {
+        OrderAction::LoOpen(i, _) | OrderAction::LoClose(i, _) => i,
+        OrderAction::ShOpen(i, _) | OrderAction::ShClose(i, _) => -i,
+        OrderAction::No => 0.,
+    }
+}
+
+/// The order of the strategy goes for a target in its lots, `hold / multiplier` plus the
+/// order. The target is scaled to whole lots and the order is made from the real `hold`,
+/// so a rounded order never overshoots the target and flips the position back and forth.
+fn scale_order_action(order_action: OrderAction, hold: f32, multiplier: f32) -> OrderAction {
+    if multiplier == 1. {
+        return order_action;
+    }
+    let price = match order_action {
+        OrderAction::LoOpen(_, p) | OrderAction::LoClose(_, p) | OrderAction::ShOpen(_, p) | OrderAction::ShClose(_, p) => p,
+        OrderAction::No => return OrderAction::No,
+    };
+    let target = ((hold / multiplier + signed_num(&order_action)) * multiplier).round();
+    OrderActionNum::from_hold_target(hold, target).into_order_action(price, price)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fractional_multiplier_settles_on_the_scaled_target() {
+        let control = StraControl::new("s".into(), "s".into(), vec![(Ticker::rb, "rb2405")], false);
+        control.set_multiplier(1.5).unwrap();
+        let tick_data = TickData { bid1: 3800., ask1: 3801., ..Default::default() };
+        let mut hold = Hold::default();
+        for _ in 0..4 {
+            let target = 1.;
+            let num = target - control.hold_to_stra(&hold).sum();
+            let action = OrderActionNum::from_hold_target(0., num).into_order_action(3801., 3800.);
+            let order = control.gate(0, &hold, &tick_data, action);
+            hold.lo += signed_num(&order);
+        }
+        assert_eq!(hold.sum(), 2.);
+    }
+}
@@ -7,6 +7,8 @@ use crate::trade::inter::*;
 use super::super::order_types::*;
 use super::super::live_ops::*;
 use super::super::live_run::*;
+use super::super::pipe::*;
+use super::super::control::StraControl;
 use std::sync::{ Arc, Mutex };
 use crate::trade::ticker::*;
 use std::collections::VecDeque;
@@ -16,30 +18,58 @@ use super::update_sync::*;
 
 
 
+lazy_static! {
+    static ref COMBO_CONTRACTS: Mutex<std::collections::HashSet<sstr>> = Default::default();
+}
+
+/// The combination contract as `sstr`, leaked once per contract and not per strategy.
+fn intern_contract(contract: String) -> sstr {
+    let mut contracts = COMBO_CONTRACTS.lock().unwrap();
+    match contracts.get(contract.as_str()) {
+        Some(x) => x,
+        None => {
+            let x: sstr = Box::leak(contract.into_boxed_str());
+            contracts.insert(x);
+            x
+        }
+    }
+}
+
 pub struct TradeCross<T> {
     pub stra: T,
     data_recv: NotifyDataRecv,
     pub trade_api: Vec<TradeApi>,
     pub trade_manager: Vec<TradeManager>,
+    pub control: Arc<StraControl>,
 }
 
 impl<T: GetTickerVec + std::fmt::Debug> TradeCross<T> {
-    pub fn new(stra: T, tickers: &hm<Ticker, sstr>) -> Self {
+    /// Errors if the strategy asks for a combination contract the exchange does not list.
+    pub fn new(stra: T, tickers: &hm<Ticker, sstr>) -> anyhow::Result<Self> {
         let data_recv = NotifyDataRecv::default();
         let mut trade_api = vec![];
         let mut trade_manager = vec![];
-        stra
-            .get_ticker_vec()
+        let ticker_vec = stra.get_ticker_vec();
+        let contract_vec = ticker_vec.iter().map(|x| tickers[x]).collect_vec();
+        let stra_id = (&stra, &contract_vec).gen_unique_id(ORDER_RET_ID_LEN);
+        let combo = stra.combo_contract(&contract_vec)?.map(|x| {
+            let contract = intern_contract(x);
+            (ticker_vec[0], contract, (&stra, contract).gen_unique_id(ORDER_RET_ID_LEN))
+        });
+        ticker_vec
             .iter()
-            .for_each(|&ticker| {
-                let contract = tickers[&ticker];
+            .zip(contract_vec.iter())
+            .map(|(&ticker, &contract)| (ticker, contract, (&stra, ticker).gen_unique_id(ORDER_RET_ID_LEN)))
+            .chain(combo)
+            .for_each(|(ticker, contract, order_return_id)| {
                 let data_recv_id = DataRecvId {
                     tick_data_id: contract.to_string(),
-                    order_return_id: (&stra, ticker).gen_unique_id(ORDER_RET_ID_LEN),
+                    order_return_id,
                 };
                 let trade_api_part = TradeApi {
                     contract,
                     ticker,
+                    stra_id: stra_id.clone(),
                     data_recv_id,
                     data_send: NotifyDataSend::default(),
                     data_recv: data_recv.clone(),
@@ -59,13 +89,28 @@ impl<T: GetTickerVec + std::fmt::Debug> TradeCross<T> {
                 trade_api.push(trade_api_part);
                 trade_manager.push(trade_manager_part);
             });
-        Self {
+        let control = StraControl::new(
+            stra_id,
+            format!("{:?}", stra).chars().take(64).collect(),
+            trade_api.iter().map(|x| (x.ticker, x.contract)).collect_vec(),
+            false,
+        );
+        Ok(Self {
             stra,
             data_recv,
             trade_api,
             trade_manager,
-        }
+            control: Arc::new(control),
+        })
+    }
 
+    /// Sets how data gets to the strategy, ticks of slow strategies can be coalesced.
+    pub fn with_pipe(mut self, config: PipeConfig) -> Self {
+        self.data_recv = DataRecvPipe::with_config(config).pip(Arc::new);
+        for trade_api in self.trade_api.iter_mut() {
+            trade_api.data_recv = self.data_recv.clone();
+        }
+        self
     }
 }
 
@@ -75,12 +120,18 @@ impl<T: CondCrossUpdatedDataIndex + Send + Sync> ApiBridge for TradeCross<T> {
         self.trade_api.clone()
     }
 
-    fn data_recv_get(&self) -> Arc<NotifyDataQue<DataRecv>> {
+    fn data_recv_get(&self) -> NotifyDataRecv {
         self.data_recv.clone()
     }
 
-    fn handle_notify<'a>(&'a self) -> Box<dyn FnMut(VecDeque<DataRecv>) + 'a> {
+    fn control(&self) -> Arc<StraControl> {
+        self.control.clone()
+    }
+
+    fn handle_notify<'a>(&'a self) -> Box<dyn FnMut(VecDeque<Stamped<DataRecv>>) + 'a> {
         let pool_len = self.trade_api.len();
+        let mut last_tick = vec![TickData::default(); pool_len];
+        let control = &self.control;
         let mut stra_ops = self.stra.cond_cross_updated_data_index();
         let mut order_pool_vec = self.trade_manager
             .iter()
@@ -92,7 +143,7 @@ impl<T: CondCrossUpdatedDataIndex + Send + Sync> ApiBridge for TradeCross<T> {
             .collect_vec();
         Box::new(move |data_recv_que| {
             let mut data_recv_que = data_recv_que;
-            while let Some(data_recv) = data_recv_que.pop_front() {
+            while let Some(Stamped { data: data_recv, mut stamp }) = data_recv_que.pop_front() {
                 let mut i;
                 let updated_data_index = match data_recv {
                     DataRecv::TickData(contract, tick_data) => {
@@ -100,12 +151,15 @@ impl<T: CondCrossUpdatedDataIndex + Send + Sync> ApiBridge for TradeCross<T> {
                             continue;
                         }
                         i = contract_vec.position(&contract);
+                        control.on_tick(i, &tick_data);
+                        last_tick[i] = tick_data.clone();
                         UpdatedDataIndex { index: i, data: UpdatedData::TickData(tick_data) }
                     }
                     DataRecv::OrderRecv(order_recv) => {
                         i = contract_vec.position(&order_recv.contract.as_str());
-                        order_pool_vec[i].update_order(order_recv);
-                        UpdatedDataIndex { index: i, data: UpdatedData::Hold(order_pool_vec[i].hold.clone())}
+                        let _ = order_pool_vec[i].update_order(order_recv);
+                        control.on_hold(i, &order_pool_vec[i].hold);
+                        UpdatedDataIndex { index: i, data: UpdatedData::Hold(control.hold_to_stra(&order_pool_vec[i].hold))}
                     }
                     DataRecv::OrderRecvHis(order_recv_vec) => {
                         for order_pool in order_pool_vec.iter_mut() {
@@ -113,18 +167,28 @@ impl<T: CondCrossUpdatedDataIndex + Send + Sync> ApiBridge for TradeCross<T> {
                         }
                         continue;
                     }
+                    DataRecv::Fill(fill) => {
+                        if let Some(i) = contract_vec.iter().position(|x| *x == fill.contract) {
+                            control.on_fill(i, &fill.action, fill.time);
+                        }
+                        continue;
+                    }
                 };
                 let Some(order_action_vec) = stra_ops(updated_data_index) else {
-                    return;
+                    continue;
                 };
+                stamp.mark(Hop::Stra);
                 order_action_vec.into_iter().zip(order_pool_vec.iter_mut()).zip(self.trade_api.iter())
-                    .for_each(|((order_action, order_pool), trade_api_part)| {
+                    .enumerate()
+                    .for_each(|(i, ((order_action, order_pool), trade_api_part))| {
                         let data_send = &trade_api_part.data_send;
+                        let order_action = control.gate(i, &order_pool.hold, &last_tick[i], order_action);
                         match order_pool.process_order_action(order_action) {
                             Ok(Some(order_send)) => {
-                                data_send.push(order_send);
-                                data_send.keep_last();
-                                data_send.notify_all();;
+                                control.on_order(i, &order_send.order_action);
+                                let mut stamp = stamp.clone();
+                                stamp.mark(Hop::Pool);
+                                data_send.blocking_send(order_send, stamp);
                             }
                             Ok(None) => {
                                 // println!("update order None");
@@ -145,7 +209,9 @@ where
 {
     type Output = Vec<Vec<TradeInfo>>;
     fn bt_tick(&self, input: Vec<&'a [TickData]>) -> Self::Output {
-        let pool_size = self.data.get_ticker_vec().len();
+        // a combination contract without its own series is matched on ticks made of the legs
+        let pool_size = self.data.pool_size();
+        let leg_len = input.len();
         let mut res = repeat_to_vec(Vec::new, pool_size);
         let mut stra_ops = self.data.cond_cross_updated_data_index();
         let mut tick_data_merged = input.into_iter().enumerate()
@@ -159,10 +225,7 @@ where
         let match_pool = repeat_to_vec(|| self.info.clone(), pool_size);
         let mut match_ops_vec = match_pool.iter().map(|x| x.bt_match()).collect_vec();
         tick_data_merged.sort_by(|x, y| x.1.t.cmp(&y.1.t));
-        for (i, tick_data) in tick_data_merged.into_iter() {
-            if tick_data.ask1 == 0. || tick_data.bid1 == 0. {
-                continue;
-            }
+        let mut step = |i: usize, tick_data: &TickData| {
             let stream_bt_match = StreamBtMatch {
                 tick_data,
                 hold: &mut hold_vec[i],
@@ -188,6 +251,21 @@ where
             if let Some(order_action_vec) = stra_ops(updated_data_index) {
                 order_action_pre = order_action_vec;
             }
+        };
+        let mut legs_last: Vec<Option<&TickData>> = vec![None; leg_len];
+        for (i, tick_data) in tick_data_merged.into_iter() {
+            if tick_data.ask1 == 0. || tick_data.bid1 == 0. {
+                continue;
+            }
+            step(i, tick_data);
+            if pool_size > leg_len {
+                legs_last[i] = Some(tick_data);
+                if let Some(legs) = legs_last.iter().cloned().collect::<Option<Vec<_>>>() {
+                    if let Some(combo) = self.data.combo_tick(&legs) {
+                        step(leg_len, &combo);
+                    }
+                }
+            }
         }
         res
     }
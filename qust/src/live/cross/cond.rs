@@ -49,6 +49,16 @@ pub trait GetTickerVec {
     fn pool_size(&self) -> usize {
         self.get_ticker_vec().len()
     }
+    /// An exchange combination contract traded after the tickers, given their contracts.
+    /// Errors when the strategy needs one the exchange does not list.
+    fn combo_contract(&self, _contracts: &[&str]) -> anyhow::Result<Option<String>> {
+        Ok(None)
+    }
+    /// A tick of the combination contract made of the ticks of the tickers, for backtests
+    /// that have no series of it.
+    fn combo_tick(&self, _legs: &[&TickData]) -> Option<TickData> {
+        None
+    }
 }
 
 pub trait CrossUpdateData {
@@ -143,4 +153,16 @@ where
     fn get_ticker_vec(&self) -> Vec<Ticker> {
         self.data.get_ticker_vec()
     }
+
+    fn pool_size(&self) -> usize {
+        self.data.pool_size()
+    }
+
+    fn combo_contract(&self, contracts: &[&str]) -> anyhow::Result<Option<String>> {
+        self.data.combo_contract(contracts)
+    }
+
+    fn combo_tick(&self, legs: &[&TickData]) -> Option<TickData> {
+        self.data.combo_tick(legs)
+    }
 }
@@ -1,10 +1,12 @@
 #![allow(dead_code, unused)]
 pub mod api;
 pub mod cond;
+pub mod spread;
 pub mod update_sync;
 
 pub mod prelude {
     pub use super::api::*;
     pub use super::cond::*;
+    pub use super::spread::*;
     pub use super::update_sync::*;
 }
\ No newline at end of file
@@ -0,0 +1,401 @@
+use qust_ds::prelude::*;
+use qust_derive::*;
+use serde::{ Serialize, Deserialize };
+use crate::loge;
+use crate::prelude::{ Hold, OrderAction, OrderTarget, TickData, Ticker, ToNum };
+use super::super::algo::OrderActionNum;
+use super::cond::*;
+use super::update_sync::*;
+
+/// How the spread price is made of the leg prices.
+#[ta_derive]
+#[derive(Copy, PartialEq)]
+pub enum SpreadPrice {
+    /// sum of ratio * price
+    Linear,
+    /// price of the first leg over the price of the second
+    Ratio,
+}
+
+#[ta_derive]
+#[derive(Copy)]
+pub struct SpreadLeg {
+    pub ticker: Ticker,
+    /// signed lots of this leg in one spread unit, a long spread buys the positive legs
+    pub ratio: f32,
+}
+
+/// What to do when the hedge legs stay behind the lead leg for longer than `imbalance_ms`.
+#[ta_derive]
+#[derive(Copy, PartialEq)]
+pub enum LegImbalance {
+    /// stop working the lead leg and hedge with `2 * hedge_ticks + 1` ticks until balanced
+    Repair,
+    /// close every leg aggressively and stay flat until the strategy target goes back to no
+    Flatten,
+}
+
+#[ta_derive]
+pub struct SpreadDef {
+    pub legs: Vec<SpreadLeg>,
+    pub price: SpreadPrice,
+    /// leg worked passively, the other legs hedge its fills aggressively
+    pub lead: usize,
+    /// ticks a hedge order goes beyond the opposite best price
+    pub hedge_ticks: f32,
+    pub imbalance_ms: i64,
+    pub on_imbalance: LegImbalance,
+    /// trade the exchange combination contract instead of legging, see `native_contract`
+    pub native: bool,
+}
+
+impl SpreadDef {
+    pub fn new(legs: Vec<SpreadLeg>) -> Self {
+        Self {
+            legs,
+            price: SpreadPrice::Linear,
+            lead: 0,
+            hedge_ticks: 1.,
+            imbalance_ms: 3000,
+            on_imbalance: LegImbalance::Repair,
+            native: false,
+        }
+    }
+
+    /// Spread price of the given leg prices.
+    pub fn price_of(&self, prices: &[f32]) -> f32 {
+        match self.price {
+            SpreadPrice::Linear => self.legs
+                .iter()
+                .zip(prices.iter())
+                .map(|(leg, p)| leg.ratio * p)
+                .sum(),
+            SpreadPrice::Ratio => prices[0] / prices[1],
+        }
+    }
+
+    /// Best bid and ask of the spread when every leg is crossed at once.
+    pub fn quote<'a>(&self, legs: Vec<&'a TickData>) -> SpreadQuote<'a> {
+        // selling the spread sells the positive legs at their bid and buys the others at their ask
+        let side = |is_bid: bool| {
+            self.legs
+                .iter()
+                .zip(legs.iter())
+                .map(|(leg, tick_data)| if (leg.ratio > 0.) == is_bid { tick_data.bid1 } else { tick_data.ask1 })
+                .collect_vec()
+        };
+        let (bid, ask) = match self.price {
+            SpreadPrice::Linear => (self.price_of(&side(true)), self.price_of(&side(false))),
+            SpreadPrice::Ratio => (legs[0].bid1 / legs[1].ask1, legs[0].ask1 / legs[1].bid1),
+        };
+        SpreadQuote {
+            mid: self.price_of(&legs.iter().map(|x| (x.bid1 + x.ask1) / 2.).collect_vec()),
+            legs,
+            bid,
+            ask,
+        }
+    }
+
+    /// The exchange combination contract of a one to one two leg spread, `SP`/`SPC` on DCE
+    /// and `SPD`/`IPS` on ZCE, an error where CTP has no combination for it.
+    pub fn native_contract(&self, contracts: &[&str]) -> anyhow::Result<String> {
+        let [a, b] = &self.legs[..] else {
+            anyhow::bail!("a combination contract has two legs, got {}", self.legs.len());
+        };
+        if a.ratio != 1. || b.ratio != -1. {
+            anyhow::bail!("a combination contract is one to one, got ratios {} and {}", a.ratio, b.ratio);
+        }
+        let same = a.ticker == b.ticker;
+        let prefix = match (combo_exchange(a.ticker), combo_exchange(b.ticker)) {
+            (Some(ComboExchange::Dce), Some(ComboExchange::Dce)) => if same { "SP" } else { "SPC" },
+            (Some(ComboExchange::Zce), Some(ComboExchange::Zce)) => if same { "SPD" } else { "IPS" },
+            _ => anyhow::bail!("no combination contract of {:?} and {:?}, leg it with native false", a.ticker, b.ticker),
+        };
+        Ok(format!("{} {}&{}", prefix, contracts[0], contracts[1]))
+    }
+}
+
+enum ComboExchange {
+    Dce,
+    Zce,
+}
+
+fn combo_exchange(ticker: Ticker) -> Option<ComboExchange> {
+    use Ticker::*;
+    match ticker {
+        eg | l | pp | v | eb | jm | i | j | p | y | pg | m | a | jd | c | cs => Some(ComboExchange::Dce),
+        MA | TA | PF | SA | FG | SM | SF | ZC | OI | RM | AP | SR | CF | SH | UR => Some(ComboExchange::Zce),
+        _ => None,
+    }
+}
+
+#[derive(Debug)]
+pub struct SpreadQuote<'a> {
+    pub legs: Vec<&'a TickData>,
+    pub bid: f32,
+    pub ask: f32,
+    pub mid: f32,
+}
+
+pub type RetFnSpreadTarget<'a> = Box<dyn FnMut(&SpreadQuote) -> OrderTarget + 'a>;
+
+/// A strategy on the spread as one instrument, the target is in spread units.
+pub trait CondSpreadTarget {
+    fn cond_spread_target(&self) -> RetFnSpreadTarget;
+}
+
+/// Works the target of `stra` on the legs of `spread`, as `TradeCross` strategy or in `bt_tick`.
+#[derive(Debug, Clone)]
+pub struct SpreadStra<T> {
+    pub stra: T,
+    pub spread: SpreadDef,
+}
+
+impl<T> GetTickerVec for SpreadStra<T> {
+    fn get_ticker_vec(&self) -> Vec<Ticker> {
+        self.spread.legs.iter().map(|x| x.ticker).collect_vec()
+    }
+
+    fn pool_size(&self) -> usize {
+        self.spread.legs.len() + self.spread.native as usize
+    }
+
+    fn combo_contract(&self, contracts: &[&str]) -> anyhow::Result<Option<String>> {
+        if !self.spread.native {
+            return Ok(None);
+        }
+        self.spread.native_contract(contracts).map(Some)
+    }
+
+    fn combo_tick(&self, legs: &[&TickData]) -> Option<TickData> {
+        if !self.spread.native || self.spread.price != SpreadPrice::Linear {
+            return None;
+        }
+        let quote = self.spread.quote(legs.to_vec());
+        let depth = legs.iter().map(|x| x.bid1_v.min(x.ask1_v)).fold(f32::MAX, f32::min);
+        TickData {
+            t: legs.iter().map(|x| x.t).max()?,
+            c: quote.mid,
+            bid1: quote.bid,
+            ask1: quote.ask,
+            bid1_v: depth,
+            ask1_v: depth,
+            ..Default::default()
+        }
+        .pip(Some)
+    }
+}
+
+fn aggressive(num: OrderActionNum, tick_data: &TickData, ticks: f32, tz: f32) -> OrderAction {
+    num.into_order_action(tick_data.ask1 + ticks * tz, tick_data.bid1 - ticks * tz)
+}
+
+impl<T: CondSpreadTarget> CondCrossUpdatedDataIndex for SpreadStra<T> {
+    /// The combination contract, when traded, is the pool after the legs. Hedge legs follow
+    /// the filled lots of the lead leg, not its target, so a passive lead order that never
+    /// fills never leaves a naked hedge. Targets are whole lots, a hedge is the lead lots
+    /// times its ratio rounded to the nearest lot, and the legs are balanced when every hedge
+    /// holds exactly that.
+    fn cond_cross_updated_data_index(&self) -> RetFnCrossUpdatedDataIndex {
+        let spread = &self.spread;
+        let leg_len = spread.legs.len();
+        let mut cond_ops = self.stra.cond_spread_target();
+        let mut tick_vec: Vec<Option<TickData>> = vec![None; leg_len + 1];
+        let mut hold_vec = vec![Hold::default(); leg_len + 1];
+        let mut imbalance_since: Option<dt> = None;
+        let mut is_repairing = false;
+        let mut is_flattening = false;
+        let tz_vec = spread.legs.iter().map(|x| x.ticker.info().tz).collect_vec();
+        Box::new(move |updated_data_index| {
+            let index = updated_data_index.index;
+            match updated_data_index.data {
+                UpdatedData::TickData(tick_data) => tick_vec[index] = Some(tick_data),
+                UpdatedData::Hold(hold) => hold_vec[index] = hold,
+            }
+            if tick_vec[..leg_len].iter().any(|x| x.is_none()) {
+                return None;
+            }
+            let legs = tick_vec[..leg_len].iter().map(|x| x.as_ref().unwrap()).collect_vec();
+            let time_now = legs.iter().map(|x| x.t).max().unwrap();
+            let quote = spread.quote(legs.clone());
+            let target_spread = cond_ops(&quote).to_num();
+            let mut res = vec![OrderAction::No; leg_len + spread.native as usize];
+            if spread.native {
+                let num = OrderActionNum::from_hold_target(hold_vec[leg_len].sum(), target_spread);
+                res[leg_len] = match &tick_vec[leg_len] {
+                    Some(combo) => num.into_order_action(combo.bid1, combo.ask1),
+                    None if spread.price == SpreadPrice::Linear => num.into_order_action(quote.bid, quote.ask),
+                    None => OrderAction::No,
+                };
+                return Some(res);
+            }
+            if is_flattening && target_spread == 0. && hold_vec[..leg_len].iter().all(|x| x.sum() == 0.) {
+                is_flattening = false;
+            }
+            let lead = spread.lead;
+            let lead_lots = hold_vec[lead].sum();
+            let hedge_lots = |i: usize| (lead_lots / spread.legs[lead].ratio * spread.legs[i].ratio).round();
+            let is_balanced = (0..leg_len).all(|i| i == lead || hold_vec[i].sum() == hedge_lots(i));
+            if is_balanced || is_flattening {
+                imbalance_since = None;
+                is_repairing = false;
+            } else {
+                let since = *imbalance_since.get_or_insert(time_now);
+                if !is_repairing && (time_now - since).num_milliseconds() > spread.imbalance_ms {
+                    loge!("ctp", "spread legs unbalanced since {}: {:?} {:?}", since, hold_vec, spread.on_imbalance);
+                    match spread.on_imbalance {
+                        LegImbalance::Repair => is_repairing = true,
+                        LegImbalance::Flatten => is_flattening = true,
+                    }
+                }
+            }
+            if is_flattening {
+                for (i, action) in res.iter_mut().enumerate() {
+                    let num = OrderActionNum::from_hold_target(hold_vec[i].sum(), 0.);
+                    *action = aggressive(num, legs[i], spread.hedge_ticks, tz_vec[i]);
+                }
+                return Some(res);
+            }
+            let hedge_ticks = if is_repairing { 2. * spread.hedge_ticks + 1. } else { spread.hedge_ticks };
+            for (i, leg) in spread.legs.iter().enumerate() {
+                if i == lead {
+                    if !is_repairing {
+                        let target = (target_spread * leg.ratio).round();
+                        let num = OrderActionNum::from_hold_target(hold_vec[i].sum(), target);
+                        res[i] = num.into_order_action(legs[i].bid1, legs[i].ask1);
+                    }
+                } else {
+                    let num = OrderActionNum::from_hold_target(hold_vec[i].sum(), hedge_lots(i));
+                    res[i] = aggressive(num, legs[i], hedge_ticks, tz_vec[i]);
+                }
+            }
+            Some(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    struct FixedTarget(Rc<Cell<f32>>);
+
+    impl CondSpreadTarget for FixedTarget {
+        fn cond_spread_target(&self) -> RetFnSpreadTarget {
+            let target = self.0.clone();
+            Box::new(move |_| match target.get() {
+                x if x > 0. => OrderTarget::Lo(x),
+                x if x < 0. => OrderTarget::Sh(-x),
+                _ => OrderTarget::No,
+            })
+        }
+    }
+
+    fn tick(ms: i64, bid1: f32, ask1: f32) -> TickData {
+        let t = dt::parse_from_str("2024-01-02 09:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        TickData { t: t + chrono::Duration::milliseconds(ms), bid1, ask1, ..Default::default() }
+    }
+
+    fn hold(lots: f32) -> UpdatedData {
+        UpdatedData::Hold(if lots >= 0. { Hold { lo: lots, sh: 0. } } else { Hold { lo: 0., sh: -lots } })
+    }
+
+    fn stra(ratio: [f32; 2], on_imbalance: LegImbalance) -> (SpreadStra<FixedTarget>, Rc<Cell<f32>>) {
+        let target = Rc::new(Cell::new(0.));
+        let legs = vec![
+            SpreadLeg { ticker: Ticker::rb, ratio: ratio[0] },
+            SpreadLeg { ticker: Ticker::hc, ratio: ratio[1] },
+        ];
+        let spread = SpreadDef { on_imbalance, ..SpreadDef::new(legs) };
+        (SpreadStra { stra: FixedTarget(target.clone()), spread }, target)
+    }
+
+    #[test]
+    fn hedge_follows_lead_fills_in_whole_lots() {
+        let (stra, target) = stra([2., -3.], LegImbalance::Repair);
+        let tz = Ticker::hc.info().tz;
+        let mut ops = stra.cond_cross_updated_data_index();
+        let mut send = |index, data| ops(UpdatedDataIndex { index, data }).unwrap_or_default();
+        target.set(1.);
+        assert!(send(0, UpdatedData::TickData(tick(0, 3800., 3801.))).is_empty());
+        let res = send(1, UpdatedData::TickData(tick(0, 3900., 3901.)));
+        assert_eq!(res, vec![OrderAction::LoOpen(2., 3800.), OrderAction::No]);
+        let res = send(0, hold(1.));
+        assert_eq!(res[1], OrderAction::ShOpen(2., 3900. - tz));
+        let res = send(0, hold(2.));
+        assert_eq!(res[1], OrderAction::ShOpen(3., 3900. - tz));
+        let res = send(1, hold(-3.));
+        assert_eq!(res, vec![OrderAction::No, OrderAction::No]);
+    }
+
+    #[test]
+    fn repair_stops_the_lead_and_hedges_wider_until_balanced() {
+        let (stra, target) = stra([1., -1.], LegImbalance::Repair);
+        let tz = Ticker::hc.info().tz;
+        let imbalance_ms = stra.spread.imbalance_ms;
+        let mut ops = stra.cond_cross_updated_data_index();
+        let mut send = |index, data| ops(UpdatedDataIndex { index, data }).unwrap_or_default();
+        target.set(2.);
+        send(0, UpdatedData::TickData(tick(0, 3800., 3801.)));
+        send(1, UpdatedData::TickData(tick(0, 3900., 3901.)));
+        let res = send(0, hold(1.));
+        assert_eq!(res, vec![OrderAction::LoOpen(1., 3800.), OrderAction::ShOpen(1., 3900. - tz)]);
+        let res = send(1, UpdatedData::TickData(tick(imbalance_ms + 1, 3900., 3901.)));
+        assert_eq!(res, vec![OrderAction::No, OrderAction::ShOpen(1., 3900. - 3. * tz)]);
+        let res = send(1, hold(-1.));
+        assert_eq!(res, vec![OrderAction::LoOpen(1., 3800.), OrderAction::No]);
+    }
+
+    #[test]
+    fn flatten_stays_flat_until_the_target_is_no() {
+        let (stra, target) = stra([1., -1.], LegImbalance::Flatten);
+        let tz = Ticker::rb.info().tz;
+        let imbalance_ms = stra.spread.imbalance_ms;
+        let mut ops = stra.cond_cross_updated_data_index();
+        let mut send = |index, data| ops(UpdatedDataIndex { index, data }).unwrap_or_default();
+        target.set(1.);
+        send(0, UpdatedData::TickData(tick(0, 3800., 3801.)));
+        send(1, UpdatedData::TickData(tick(0, 3900., 3901.)));
+        send(0, hold(1.));
+        let res = send(0, UpdatedData::TickData(tick(imbalance_ms + 1, 3800., 3801.)));
+        assert_eq!(res, vec![OrderAction::ShClose(1., 3800. - tz), OrderAction::No]);
+        let res = send(0, hold(0.));
+        assert_eq!(res, vec![OrderAction::No, OrderAction::No]);
+        target.set(0.);
+        send(0, UpdatedData::TickData(tick(imbalance_ms + 2, 3800., 3801.)));
+        target.set(1.);
+        let res = send(0, UpdatedData::TickData(tick(imbalance_ms + 3, 3800., 3801.)));
+        assert_eq!(res, vec![OrderAction::LoOpen(1., 3800.), OrderAction::No]);
+    }
+
+    #[test]
+    fn native_spread_trades_the_combination_pool() {
+        let (mut stra, target) = stra([1., -1.], LegImbalance::Repair);
+        stra.spread.native = true;
+        assert_eq!(stra.pool_size(), 3);
+        let legs = [tick(0, 3800., 3801.), tick(0, 3900., 3901.)];
+        let combo = stra.combo_tick(&[&legs[0], &legs[1]]).unwrap();
+        assert_eq!((combo.bid1, combo.ask1), (-101., -99.));
+        let mut ops = stra.cond_cross_updated_data_index();
+        let mut send = |index, data| ops(UpdatedDataIndex { index, data }).unwrap_or_default();
+        target.set(1.);
+        send(0, UpdatedData::TickData(legs[0].clone()));
+        send(1, UpdatedData::TickData(legs[1].clone()));
+        let res = send(2, UpdatedData::TickData(combo));
+        assert_eq!(res, vec![OrderAction::No, OrderAction::No, OrderAction::LoOpen(1., -101.)]);
+    }
+
+    #[test]
+    fn native_spread_without_combination_is_an_error() {
+        let (mut stra, _) = stra([1., -1.], LegImbalance::Repair);
+        assert_eq!(stra.combo_contract(&["rb2405", "hc2405"]).unwrap(), None);
+        stra.spread.native = true;
+        assert!(stra.combo_contract(&["rb2405", "hc2405"]).is_err());
+        stra.spread.legs = vec![SpreadLeg { ticker: Ticker::m, ratio: 1. }, SpreadLeg { ticker: Ticker::m, ratio: -1. }];
+        assert_eq!(stra.combo_contract(&["m2405", "m2409"]).unwrap(), Some("SP m2405&m2409".to_string()));
+        stra.spread.legs[1].ratio = -2.;
+        assert!(stra.combo_contract(&["m2405", "m2409"]).is_err());
+    }
+}
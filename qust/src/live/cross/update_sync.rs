@@ -50,47 +50,151 @@ impl UpdatedPool for AllEmergedQue {
 }
 
 
+/// `TimeBucket` of 500 milliseconds, the snapshot cadence of CTP.
 #[derive(Debug)]
 pub struct MillisAlignment(pub usize);
 
 impl UpdatedPool for MillisAlignment {
     fn updated_pool(&self) -> FnMutBox<UpdatedTickDataIndex, Option<Vec<TickData>>> {
-        let mut tick_time_last = dt::default().modify();
-        let mut tick_cache = repeat_to_vec(|| Option::<TickData>::None, self.0);
-        let mut cached_set: HashSet<usize> = HashSet::with_capacity(self.0); 
-        let mut tick_vec_last = repeat_to_vec(TickData::default, self.0);
-        Box::new(move |updated_tick_data_index| {
-            // loge!("ctp", "{:?}", updated_tick_data_index);
-            let index = updated_tick_data_index.index;
-            let tick_data = updated_tick_data_index.data;
-            let tick_time_now = tick_data.t.modify();
-            let res_ffill = if tick_time_now > tick_time_last && !tick_cache.check_all(|x| x.is_none()) {
+        time_bucket_pool(self.0, 500)
+    }
+}
+
+/// Fires when every leg ticked in a bucket of `millis`, or when a later bucket starts,
+/// with the legs missing from the bucket forward filled.
+#[derive(Debug, Clone)]
+pub struct TimeBucket {
+    pub size: usize,
+    pub millis: i64,
+}
+
+impl UpdatedPool for TimeBucket {
+    fn updated_pool(&self) -> FnMutBox<UpdatedTickDataIndex, Option<Vec<TickData>>> {
+        time_bucket_pool(self.size, self.millis)
+    }
+}
+
+fn time_bucket_pool(size: usize, millis: i64) -> FnMutBox<'static, UpdatedTickDataIndex, Option<Vec<TickData>>> {
+    let bucket_of = move |t: &dt| t.and_utc().timestamp_millis().div_euclid(millis.max(1));
+    let mut tick_time_last = bucket_of(&dt::default());
+    let mut tick_cache = repeat_to_vec(|| Option::<TickData>::None, size);
+    let mut cached_set: HashSet<usize> = HashSet::with_capacity(size);
+    let mut tick_vec_last = repeat_to_vec(TickData::default, size);
+    Box::new(move |updated_tick_data_index| {
+        let index = updated_tick_data_index.index;
+        let tick_data = updated_tick_data_index.data;
+        let tick_time_now = bucket_of(&tick_data.t);
+        let res_ffill = if tick_time_now > tick_time_last && !tick_cache.check_all(|x| x.is_none()) {
+            cached_set.clear();
+            tick_cache
+                .iter_mut()
+                .zip(tick_vec_last.iter())
+                .map(|(x, y)| x.take().unwrap_or_else(|| y.clone()))
+                .collect_vec()
+                .pip(Some)
+        } else {
+            None
+        };
+        tick_vec_last[index] = tick_data.clone();
+        tick_cache[index] = Some(tick_data);
+        cached_set.insert(index);
+        tick_time_last = tick_time_now;
+        match res_ffill {
+            Some(data) => Some(data),
+            None if cached_set.len() == size => {
                 cached_set.clear();
                 tick_cache
                     .iter_mut()
-                    .zip(tick_vec_last.iter())
-                    .map(|(x, y)| x.take().unwrap_or_else(|| y.clone()))
+                    .map(|x| x.take().unwrap())
                     .collect_vec()
                     .pip(Some)
-            } else {
-                None
-            };
-            tick_vec_last[index] = tick_data.clone();
-            tick_cache[index] = Some(tick_data);
-            cached_set.insert(index);
-            tick_time_last = tick_time_now;
-            match res_ffill {
-                Some(data) => Some(data),
-                None if cached_set.len() == self.0 => {
-                    cached_set.clear();
-                    tick_cache
-                        .iter_mut()
-                        .map(|x| x.take().unwrap())
-                        .collect_vec()
-                        .pip(Some)
-                }
-                _ =>  None,
             }
+            _ =>  None,
+        }
+    })
+}
+
+/// Last tick of every leg, full once each leg ticked.
+#[derive(Debug, Clone)]
+struct FfillCache {
+    last: Vec<Option<TickData>>,
+    filled: usize,
+}
+
+impl FfillCache {
+    fn new(size: usize) -> Self {
+        Self { last: vec![None; size], filled: 0 }
+    }
+
+    fn update(&mut self, index: usize, tick_data: TickData) {
+        if self.last[index].is_none() {
+            self.filled += 1;
+        }
+        self.last[index] = Some(tick_data);
+    }
+
+    fn snapshot(&self) -> Option<Vec<TickData>> {
+        if self.filled < self.last.len() {
+            return None;
+        }
+        self.last.iter().map(|x| x.clone().unwrap()).collect_vec().pip(Some)
+    }
+}
+
+/// Fires on every tick of any leg once all legs ticked, the other legs forward filled.
+#[derive(Debug, Clone)]
+pub struct AnyFfill(pub usize);
+
+impl UpdatedPool for AnyFfill {
+    fn updated_pool(&self) -> FnMutBox<UpdatedTickDataIndex, Option<Vec<TickData>>> {
+        let mut cache = FfillCache::new(self.0);
+        Box::new(move |updated_tick_data_index| {
+            cache.update(updated_tick_data_index.index, updated_tick_data_index.data);
+            cache.snapshot()
+        })
+    }
+}
+
+/// Fires on the ticks of `primary` only, the other legs forward filled.
+#[derive(Debug, Clone)]
+pub struct LeadLag {
+    pub size: usize,
+    pub primary: usize,
+}
+
+impl UpdatedPool for LeadLag {
+    fn updated_pool(&self) -> FnMutBox<UpdatedTickDataIndex, Option<Vec<TickData>>> {
+        let mut cache = FfillCache::new(self.size);
+        let primary = self.primary;
+        Box::new(move |updated_tick_data_index| {
+            let index = updated_tick_data_index.index;
+            cache.update(index, updated_tick_data_index.data);
+            if index != primary {
+                return None;
+            }
+            cache.snapshot()
+        })
+    }
+}
+
+/// Drops the output of `pool` when a leg in it is more than `max_ms` older than the newest.
+#[derive(Debug, Clone)]
+pub struct Staleness<T> {
+    pub pool: T,
+    pub max_ms: i64,
+}
+
+impl<T: UpdatedPool> UpdatedPool for Staleness<T> {
+    fn updated_pool(&self) -> FnMutBox<UpdatedTickDataIndex, Option<Vec<TickData>>> {
+        let mut pool_ops = self.pool.updated_pool();
+        let max_ms = self.max_ms;
+        Box::new(move |updated_tick_data_index| {
+            let tick_data_vec = pool_ops(updated_tick_data_index)?;
+            let newest = tick_data_vec.iter().map(|x| x.t).max()?;
+            if tick_data_vec.iter().any(|x| (newest - x.t).num_milliseconds() > max_ms) {
+                return None;
+            }
+            Some(tick_data_vec)
         })
     }
 }
@@ -0,0 +1,244 @@
+use std::path::Path;
+use crate::{
+    idct::ta::CommSlip,
+    sig::trade_list::{ToTradeList, TradeList},
+    trade::prelude::*,
+};
+use super::bt::{TickerTradeInfo, TradeInfo};
+use super::order_types::*;
+use qust_ds::prelude::*;
+use qust_derive::*;
+
+pub const LIVE_FILL_TAG: &str = "live fill: ";
+
+/// A fill from the trade return of the api, at the traded price and time. The api logs it
+/// by `loge!` as `LIVE_FILL_TAG` followed by json and hands it to the strategy as
+/// `DataRecv::Fill`.
+#[ta_derive]
+pub struct LiveFill {
+    pub time: dt,
+    pub stra: String,
+    #[serde(default)]
+    pub contract: String,
+    pub ticker: Ticker,
+    pub action: OrderAction,
+}
+
+impl LiveFill {
+    pub fn to_log(&self) -> String {
+        format!("{}{}", LIVE_FILL_TAG, serde_json::to_string(self).unwrap())
+    }
+
+    pub fn from_log_line(line: &str) -> Option<Self> {
+        let i = line.find(LIVE_FILL_TAG)?;
+        serde_json::Deserializer::from_str(&line[i + LIVE_FILL_TAG.len()..])
+            .into_iter::<Self>()
+            .next()?
+            .ok()
+    }
+
+    pub fn read_log<P: AsRef<Path>>(path: P) -> Vec<Self> {
+        std::fs::read_to_string(path)
+            .map(|x| x.lines().filter_map(Self::from_log_line).collect_vec())
+            .unwrap_or_default()
+    }
+
+    pub fn trade_info(&self) -> TradeInfo {
+        TradeInfo { time: self.time, action: self.action.clone() }
+    }
+}
+
+///(kind, num, price), buy side is kind 0 and 1
+fn action_parts(action: &OrderAction) -> Option<(usize, f32, f32)> {
+    match *action {
+        OrderAction::LoOpen(i, p) => Some((0, i, p)),
+        OrderAction::LoClose(i, p) => Some((1, i, p)),
+        OrderAction::ShOpen(i, p) => Some((2, i, p)),
+        OrderAction::ShClose(i, p) => Some((3, i, p)),
+        OrderAction::No => None,
+    }
+}
+
+#[ta_derive]
+pub struct DivergenceConfig {
+    ///seconds, the max time gap to pair a backtest fill with a live fill
+    pub window: i64,
+    pub comm: CommSlip,
+}
+
+impl Default for DivergenceConfig {
+    fn default() -> Self {
+        Self { window: 60, comm: CommSlip(1., 0.) }
+    }
+}
+
+#[ta_derive]
+pub struct FillDiff {
+    pub stra: String,
+    pub bt_time: Option<dt>,
+    pub bt_action: OrderAction,
+    pub live_time: Option<dt>,
+    pub live_action: OrderAction,
+    ///seconds, live later than backtest is positive
+    pub delay: Option<f32>,
+    ///ticks, worse live price is positive
+    pub slippage: Option<f32>,
+}
+
+#[ta_derive]
+pub struct DailyDiff {
+    pub stra: String,
+    pub date: da,
+    pub bt_num: usize,
+    pub live_num: usize,
+    pub matched: usize,
+    pub missed: usize,
+    pub extra: usize,
+    pub delay_mean: f32,
+    pub slippage_mean: f32,
+    pub pnl_bt: f32,
+    pub pnl_live: f32,
+    pub pnl_drift: f32,
+}
+
+#[ta_derive]
+#[derive(Default)]
+pub struct DivergenceReport {
+    pub fills: Vec<FillDiff>,
+    pub daily: Vec<DailyDiff>,
+}
+
+pub trait Divergence {
+    fn divergence(&self, live: &[LiveFill], config: &DivergenceConfig) -> DivergenceReport;
+}
+
+fn match_fills(stra: &str, tz: f32, bt: &[TradeInfo], live: &[&LiveFill], window: i64) -> Vec<FillDiff> {
+    let mut live_used = vec![false; live.len()];
+    let mut res = vec![];
+    for bt_fill in bt.iter() {
+        let Some((kind, _, bt_price)) = action_parts(&bt_fill.action) else {
+            continue;
+        };
+        let matched = live
+            .iter()
+            .enumerate()
+            .filter(|(j, x)| {
+                !live_used[*j]
+                    && action_parts(&x.action).map(|y| y.0) == Some(kind)
+                    && (x.time - bt_fill.time).num_seconds().abs() <= window
+            })
+            .min_by_key(|(_, x)| (x.time - bt_fill.time).num_milliseconds().abs())
+            .map(|(j, _)| j);
+        let fill_diff = match matched {
+            Some(j) => {
+                live_used[j] = true;
+                let live_price = action_parts(&live[j].action).unwrap().2;
+                let price_diff = if kind < 2 { live_price - bt_price } else { bt_price - live_price };
+                FillDiff {
+                    stra: stra.to_string(),
+                    bt_time: Some(bt_fill.time),
+                    bt_action: bt_fill.action.clone(),
+                    live_time: Some(live[j].time),
+                    live_action: live[j].action.clone(),
+                    delay: Some((live[j].time - bt_fill.time).num_milliseconds() as f32 / 1000.),
+                    slippage: Some(price_diff / tz),
+                }
+            }
+            None => FillDiff {
+                stra: stra.to_string(),
+                bt_time: Some(bt_fill.time),
+                bt_action: bt_fill.action.clone(),
+                live_time: None,
+                live_action: OrderAction::No,
+                delay: None,
+                slippage: None,
+            },
+        };
+        res.push(fill_diff);
+    }
+    live
+        .iter()
+        .zip(live_used.iter())
+        .filter(|(_, used)| !**used)
+        .for_each(|(x, _)| {
+            res.push(FillDiff {
+                stra: stra.to_string(),
+                bt_time: None,
+                bt_action: OrderAction::No,
+                live_time: Some(x.time),
+                live_action: x.action.clone(),
+                delay: None,
+                slippage: None,
+            });
+        });
+    res
+}
+
+fn daily_pnl(trade_list: &TradeList) -> hm<da, f32> {
+    trade_list.trades.iter().fold(hm::new(), |mut accu, x| {
+        *accu.entry(x.exit_time.date()).or_insert(0.) += x.pnl;
+        accu
+    })
+}
+
+impl Divergence for (&str, &TickerTradeInfo) {
+    fn divergence(&self, live: &[LiveFill], config: &DivergenceConfig) -> DivergenceReport {
+        let (stra, bt) = *self;
+        let live = live
+            .iter()
+            .filter(|x| x.stra == stra && x.ticker == bt.info)
+            .collect_vec();
+        let fills = match_fills(stra, bt.info.info().tz, &bt.data, &live, config.window);
+        let live_trade_info = TickerTradeInfo {
+            data: live.map(|x| x.trade_info()),
+            info: bt.info,
+        };
+        let pnl_bt = daily_pnl(&bt.trade_list(config.comm.clone()));
+        let pnl_live = daily_pnl(&live_trade_info.trade_list(config.comm.clone()));
+        let date_of = |x: &FillDiff| x.bt_time.or(x.live_time).unwrap().date();
+        let dates = fills
+            .iter()
+            .map(date_of)
+            .chain(pnl_bt.keys().cloned())
+            .chain(pnl_live.keys().cloned())
+            .unique()
+            .sorted()
+            .collect_vec();
+        let daily = dates
+            .into_iter()
+            .map(|date| {
+                let fills_day = fills.iter().filter(|x| date_of(x) == date).collect_vec();
+                let matched = fills_day.iter().filter(|x| x.delay.is_some()).collect_vec();
+                let mean = |v: Vec<f32>| if v.is_empty() { 0. } else { v.iter().sum::<f32>() / v.len() as f32 };
+                let pnl_bt = pnl_bt.get(&date).cloned().unwrap_or(0.);
+                let pnl_live = pnl_live.get(&date).cloned().unwrap_or(0.);
+                DailyDiff {
+                    stra: stra.to_string(),
+                    date,
+                    bt_num: fills_day.iter().filter(|x| x.bt_time.is_some()).count(),
+                    live_num: fills_day.iter().filter(|x| x.live_time.is_some()).count(),
+                    matched: matched.len(),
+                    missed: fills_day.iter().filter(|x| x.live_time.is_none()).count(),
+                    extra: fills_day.iter().filter(|x| x.bt_time.is_none()).count(),
+                    delay_mean: mean(matched.iter().filter_map(|x| x.delay).collect_vec()),
+                    slippage_mean: mean(matched.iter().filter_map(|x| x.slippage).collect_vec()),
+                    pnl_bt,
+                    pnl_live,
+                    pnl_drift: pnl_live - pnl_bt,
+                }
+            })
+            .collect_vec();
+        DivergenceReport { fills, daily }
+    }
+}
+
+impl Divergence for [(String, TickerTradeInfo)] {
+    fn divergence(&self, live: &[LiveFill], config: &DivergenceConfig) -> DivergenceReport {
+        self.iter().fold(DivergenceReport::default(), |mut accu, (stra, bt)| {
+            let mut res = (stra.as_str(), bt).divergence(live, config);
+            accu.fills.append(&mut res.fills);
+            accu.daily.append(&mut res.daily);
+            accu
+        })
+    }
+}
@@ -0,0 +1,189 @@
+use once_cell::sync::Lazy;
+use serde::{ Deserialize, Serialize };
+use qust_ds::prelude::*;
+use crate::{ loge, std_prelude::* };
+use super::pipe::{ Hop, HopStamp };
+
+const SUB_BUCKETS: u64 = 16;
+const SUB_BITS: u32 = 4;
+
+/// Microsecond histogram with 16 log linear buckets per power of two, a quantile is off by
+/// at most 1/16 of its value.
+#[derive(Debug, Clone, Default)]
+pub struct LatencyHist {
+    buckets: Vec<u64>,
+    count: u64,
+    sum: u128,
+    max: u64,
+}
+
+fn bucket_of(us: u64) -> usize {
+    if us < SUB_BUCKETS {
+        return us as usize;
+    }
+    let exp = 63 - us.leading_zeros();
+    let sub = (us >> (exp - SUB_BITS)) & (SUB_BUCKETS - 1);
+    ((exp - SUB_BITS + 1) as u64 * SUB_BUCKETS + sub) as usize
+}
+
+fn bucket_top(i: usize) -> u64 {
+    let i = i as u64;
+    if i < SUB_BUCKETS {
+        return i;
+    }
+    let exp = i / SUB_BUCKETS + SUB_BITS as u64 - 1;
+    let sub = i % SUB_BUCKETS;
+    ((SUB_BUCKETS + sub + 1) << (exp - SUB_BITS as u64)) - 1
+}
+
+impl LatencyHist {
+    pub fn record(&mut self, d: dura) {
+        let us = d.as_micros() as u64;
+        let i = bucket_of(us);
+        if self.buckets.len() <= i {
+            self.buckets.resize(i + 1, 0);
+        }
+        self.buckets[i] += 1;
+        self.count += 1;
+        self.sum += us as u128;
+        self.max = self.max.max(us);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Upper bound of the bucket holding the `q` quantile, in microseconds.
+    pub fn quantile(&self, q: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+        let rank = ((q.clamp(0., 1.) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return bucket_top(i).min(self.max);
+            }
+        }
+        self.max
+    }
+
+    pub fn summary(&self) -> LatencySummary {
+        LatencySummary {
+            count: self.count,
+            p50_us: self.quantile(0.5),
+            p99_us: self.quantile(0.99),
+            max_us: self.max,
+            mean_us: if self.count == 0 { 0. } else { self.sum as f64 / self.count as f64 },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub p50_us: u64,
+    pub p99_us: u64,
+    pub max_us: u64,
+    pub mean_us: f64,
+}
+
+impl std::fmt::Display for LatencySummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "p50 {}us p99 {}us max {}us", self.p50_us, self.p99_us, self.max_us)
+    }
+}
+
+/// Time to reach each hop from the hop before it, and from the first hop to the last.
+#[derive(Debug, Clone, Default)]
+pub struct LatencyHists {
+    pub hops: hm<Hop, LatencyHist>,
+    pub total: LatencyHist,
+}
+
+impl LatencyHists {
+    pub fn record(&mut self, stamp: &HopStamp) {
+        for (hop, d) in stamp.hops() {
+            self.hops.entry(hop).or_default().record(d);
+        }
+        self.total.record(stamp.total());
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LatencyStats {
+    pub stra: String,
+    pub contract: String,
+    pub total: LatencySummary,
+    pub hops: Vec<(Hop, LatencySummary)>,
+}
+
+impl std::fmt::Display for LatencyStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "latency {} {} n {} total {}", self.stra, self.contract, self.total.count, self.total)?;
+        for (hop, summary) in self.hops.iter() {
+            write!(f, " | {:?} {}", hop, summary)?;
+        }
+        Ok(())
+    }
+}
+
+/// Latency of the orders sent, by strategy and contract.
+#[derive(Default)]
+pub struct LatencyBook {
+    data: Mutex<hm<(String, String), LatencyHists>>,
+}
+
+impl LatencyBook {
+    pub fn record(&self, stra: &str, contract: &str, stamp: &HopStamp) {
+        self.data
+            .lock()
+            .unwrap()
+            .entry((stra.to_string(), contract.to_string()))
+            .or_default()
+            .record(stamp);
+    }
+
+    pub fn stats(&self) -> Vec<LatencyStats> {
+        let data = self.data.lock().unwrap();
+        let mut res = data
+            .iter()
+            .map(|((stra, contract), hists)| {
+                let mut hops = hists.hops
+                    .iter()
+                    .map(|(hop, hist)| (*hop, hist.summary()))
+                    .collect_vec();
+                hops.sort_by_key(|x| x.0);
+                LatencyStats {
+                    stra: stra.clone(),
+                    contract: contract.clone(),
+                    total: hists.total.summary(),
+                    hops,
+                }
+            })
+            .collect_vec();
+        res.sort_by(|a, b| (&a.stra, &a.contract).cmp(&(&b.stra, &b.contract)));
+        res
+    }
+
+    pub fn reset(&self) {
+        self.data.lock().unwrap().clear();
+    }
+
+    pub fn log(&self) {
+        for stats in self.stats() {
+            loge!("ctp", "{}", stats);
+        }
+    }
+}
+
+pub static LATENCY: Lazy<LatencyBook> = Lazy::new(Default::default);
+
+/// Logs `LATENCY` every `every`.
+pub fn spawn_latency_log(every: dura) -> thread::JoinHandle<()> {
+    thread::spawn(move || loop {
+        sleep(every);
+        LATENCY.log();
+    })
+}
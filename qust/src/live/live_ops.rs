@@ -1,82 +1,19 @@
 use super::prelude::{ApiBridgeBox, ServiceApi};
-use crate::{ loge, std_prelude::*, trade::prelude::* };
+use crate::{ std_prelude::*, trade::prelude::* };
 use qust_ds::prelude::*;
-use std::sync::MutexGuard;
 use super::order_types::*;
-use std::collections::VecDeque;
-use anyhow::Result;
-
-#[derive(Default, Debug)]
-pub struct NotifyData<T> {
-    pub data: Mutex<T>,
-    pub started: Mutex<bool>,
-    cv: Condvar,
-}
-
-pub type NotifyDataQue<T> = NotifyData<VecDeque<T>>;
-
-impl<T> NotifyData<T> {
-    pub fn notify_all(&self) {
-        self.cv.notify_all();
-    }
-
-    pub fn wait_or_exit(&self, info: &str) -> (MutexGuard<T>, bool) {
-        let guard = self.cv.wait(self.data.lock().unwrap()).unwrap();
-        let is_started = *self.started.lock().unwrap();
-        if !is_started {
-            loge!("spy", "{info}");
-        }
-        (guard, is_started)
-    }
-
-    pub fn start(&self) {
-        *self.started.lock().unwrap() = true;
-    }
-
-    pub fn stop(&self) {
-        *self.started.lock().unwrap() = false;
-        self.notify_all();
-    }
-    
-    pub fn set(&self, data: T) {
-        *self.data.lock().unwrap() = data;
-    }
-}
-
-impl<T> NotifyData<VecDeque<T>> {
-    pub fn wait_or_exit_vec(&self, info: &str) -> (MutexGuard<VecDeque<T>>, bool) {
-        let mut guard = self.data.lock().unwrap();
-        while guard.is_empty() {
-            guard = self.cv.wait(guard).unwrap();
-        }
-        let is_started = *self.started.lock().unwrap();
-        if !is_started {
-            loge!("spy", "{info}");
-        }
-        (guard, is_started)
-    }
-    
-    pub fn push(&self, data: T) {
-        self.data.lock().unwrap().push_back(data);
-    }
-
-    pub fn keep_last(&self) {
-        let mut data = self.data.lock().unwrap();
-        if data.is_empty() {
-            return;
-        }
-        let data_last = data.pop_back().unwrap();
-        data.clear();
-        data.push_back(data_last);
-    }
-}
-
+use super::pipe::*;
+use super::control::StraControl;
+use super::divergence::LiveFill;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use anyhow::{ anyhow, Result };
 
 #[derive(Clone, Debug)]
 pub enum DataRecv {
     TickData(sstr, TickData),
     OrderRecv(OrderRecv),
     OrderRecvHis(Vec<OrderRecv>),
+    Fill(LiveFill),
 }
 
 
@@ -91,13 +28,12 @@ impl From<OrderRecv> for DataRecv {
     }
 }
 
-pub type NotifyDataSend = Arc<NotifyDataQue<OrderSend>>;
-pub type NotifyDataRecv = Arc<NotifyDataQue<DataRecv>>;
-
 #[derive(Debug, Clone)]
 pub struct TradeApiType<T, N> {
     pub contract: sstr,
     pub ticker: Ticker,
+    /// `StraControl::id` of the strategy the endpoint belongs to
+    pub stra_id: String,
     pub data_recv_id: DataRecvId,
     pub data_send: T,
     pub data_recv: N,
@@ -118,30 +54,97 @@ pub struct TradeManager {
     pub hold: Mutex<Hold>,
 }
 
+/// The strategies of a `RunningApi`, they can be attached and detached while it runs.
 pub struct StraApi {
-    pub pool: Vec<Arc<ApiBridgeBox>>,
+    pub pool: RwLock<Vec<Arc<ApiBridgeBox>>>,
+    started: AtomicBool,
 }
 
 impl StraApi {
+    pub fn new(pool: Vec<Arc<ApiBridgeBox>>) -> Self {
+        Self { pool: RwLock::new(pool), started: AtomicBool::new(false) }
+    }
+
     pub fn get_trade_api_vec(&self) -> Vec<TradeApi> {
         self
             .pool
+            .read()
+            .unwrap()
             .iter()
             .map(|x| x.gen_trade_api())
             .concat()
     }
+
+    pub fn controls(&self) -> Vec<Arc<StraControl>> {
+        self.pool.read().unwrap().iter().map(|x| x.control()).collect_vec()
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.started.load(Ordering::SeqCst)
+    }
+
+    /// Adds a strategy, it starts at once if the others are running. Returns its
+    /// endpoints for the service api.
+    pub fn attach(&self, api_bridge: ApiBridgeBox) -> Result<Vec<TradeApi>> {
+        let id = api_bridge.control().id.clone();
+        let mut pool = self.pool.write().unwrap();
+        if pool.iter().any(|x| x.control().id == id) {
+            return Err(anyhow!("strategy {} is running already", id));
+        }
+        let api_bridge = Arc::new(api_bridge);
+        let trade_api_vec = api_bridge.gen_trade_api();
+        pool.push(api_bridge.clone());
+        if self.is_started() {
+            start_trade_api(&trade_api_vec);
+            thread::spawn(move || {
+                api_bridge.start_service();
+            });
+        }
+        Ok(trade_api_vec)
+    }
+
+    /// Takes the strategy `id` out of the pool and returns its endpoints, it keeps running
+    /// until they are stopped.
+    pub fn detach(&self, id: &str) -> Result<Vec<TradeApi>> {
+        let mut pool = self.pool.write().unwrap();
+        let i = pool
+            .iter()
+            .position(|x| x.control().id == id)
+            .ok_or_else(|| anyhow!("no strategy {}", id))?;
+        Ok(pool.remove(i).gen_trade_api())
+    }
+
+    /// Stops the endpoints of a detached strategy, the others keep running.
+    pub fn stop_stra(&self, trade_api_vec: &[TradeApi]) {
+        stop_trade_api(trade_api_vec);
+    }
+}
+
+fn start_trade_api(trade_api_vec: &[TradeApi]) {
+    trade_api_vec
+        .iter()
+        .for_each(|x| {
+            x.data_send.start();
+            x.data_recv.start();
+        });
+}
+
+fn stop_trade_api(trade_api_vec: &[TradeApi]) {
+    trade_api_vec
+        .iter()
+        .for_each(|x| {
+            x.data_send.stop();
+            x.data_recv.stop();
+        });
 }
 
 
 impl ServiceApi for StraApi {
     fn start(&self, trade_api_vec: Vec<TradeApi>) -> Result<()> {
-        trade_api_vec
-            .iter()
-            .for_each(|x| {
-                x.data_send.start();
-                x.data_recv.start();
-            });
-        for stra_api in self.pool.iter() {
+        let pool = self.pool.read().unwrap();
+        start_trade_api(&trade_api_vec);
+        self.started.store(true, Ordering::SeqCst);
+        for stra_api in pool.iter() {
             let stra_api = stra_api.clone();
             thread::spawn(move || {
                 stra_api.start_service();
@@ -151,12 +154,14 @@ impl ServiceApi for StraApi {
     }
 
     fn stop(&self, trade_api_vec: Vec<TradeApi>) -> Result<()> {
-        trade_api_vec.iter().for_each(|trade_api| {
-            trade_api.data_send.stop();
-            trade_api.data_recv.stop();
-        });
+        self.started.store(false, Ordering::SeqCst);
+        stop_trade_api(&trade_api_vec);
         Ok(())
     }
+
+    fn current_trade_api(&self) -> Option<Vec<TradeApi>> {
+        Some(self.get_trade_api_vec())
+    }
 }
 
 pub trait ToStraApi {
@@ -165,9 +170,7 @@ pub trait ToStraApi {
 
 impl ToStraApi for Vec<ApiBridgeBox> {
     fn to_stra_api(self) -> StraApi {
-        StraApi {
-            pool: self.into_iter().map(Arc::new).collect_vec()
-        }
+        StraApi::new(self.into_iter().map(Arc::new).collect_vec())
     }
 }
 
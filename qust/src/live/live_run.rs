@@ -1,12 +1,54 @@
 use std::collections::VecDeque;
 use super::live_ops::*;
-use anyhow::Result;
+use super::pipe::*;
+use super::control::StraControl;
+use std::sync::Arc;
+use anyhow::{ anyhow, Result };
 use qust_ds::prelude::logging_service;
 
 pub trait ServiceApi {
     fn start(&self, trade_api: Vec<TradeApi>) -> Result<()>;
     fn stop(&self, trade_api: Vec<TradeApi>) -> Result<()>;
+
+    /// Serves endpoints of a strategy attached after the service was built, at once if
+    /// their pipes are started.
+    fn attach_trade_api(&self, _trade_api: Vec<TradeApi>) -> Result<()> {
+        Err(anyhow!("the service can not attach endpoints"))
+    }
+
+    /// Stops serving endpoints of a detached strategy.
+    fn detach_trade_api(&self, _trade_api: Vec<TradeApi>) -> Result<()> {
+        Err(anyhow!("the service can not detach endpoints"))
+    }
+
+    /// Endpoints of the strategies it runs now, None if it runs none.
+    fn current_trade_api(&self) -> Option<Vec<TradeApi>> {
+        None
+    }
 }
+
+impl<T: ServiceApi + ?Sized> ServiceApi for Arc<T> {
+    fn start(&self, trade_api: Vec<TradeApi>) -> Result<()> {
+        self.as_ref().start(trade_api)
+    }
+
+    fn stop(&self, trade_api: Vec<TradeApi>) -> Result<()> {
+        self.as_ref().stop(trade_api)
+    }
+
+    fn attach_trade_api(&self, trade_api: Vec<TradeApi>) -> Result<()> {
+        self.as_ref().attach_trade_api(trade_api)
+    }
+
+    fn detach_trade_api(&self, trade_api: Vec<TradeApi>) -> Result<()> {
+        self.as_ref().detach_trade_api(trade_api)
+    }
+
+    fn current_trade_api(&self) -> Option<Vec<TradeApi>> {
+        self.as_ref().current_trade_api()
+    }
+}
+
 pub struct RunningApi<T, N> {
     pub stra_api: T,
     pub service_api: N,
@@ -27,6 +69,18 @@ where
         Ok(())
     }
 
+    /// Takes the endpoints of strategies attached or detached since the last call, true
+    /// if they changed.
+    pub fn sync_trade_api(&mut self) -> bool {
+        let Some(trade_api) = self.stra_api.current_trade_api() else {
+            return false;
+        };
+        let ids = |x: &[TradeApi]| x.iter().map(|x| x.data_recv_id.clone()).collect::<Vec<_>>();
+        let changed = ids(&trade_api) != ids(&self.trade_api);
+        self.trade_api = trade_api;
+        changed
+    }
+
     pub fn start(&self) -> Result<()> {
         self.stra_api.start(self.trade_api.clone())?;
         self.service_api.start(self.trade_api.clone())?;
@@ -43,22 +97,17 @@ where
 pub trait ApiBridge: Send + Sync {
     fn gen_trade_api(&self) -> Vec<TradeApi>;
 
-    fn handle_notify<'a>(&'a self) -> Box<dyn FnMut(VecDeque<DataRecv>) + 'a>;
+    fn handle_notify<'a>(&'a self) -> Box<dyn FnMut(VecDeque<Stamped<DataRecv>>) + 'a>;
 
     fn data_recv_get(&self) -> NotifyDataRecv;
+
+    fn control(&self) -> Arc<StraControl>;
+
+    /// Runs the strategy on the calling thread until its data pipe stops.
     fn start_service(&self) -> Option<()> {
         let data = self.data_recv_get();
-        let mut data_ops = self.handle_notify();
-        loop {
-            let (mut guard, is_started) = data.wait_or_exit("aaa");
-            if !is_started {
-                break;
-            }
-            let mut data_receive_vec = VecDeque::default();
-            data_receive_vec.append(&mut guard);
-            drop(guard);
-            data_ops(data_receive_vec);
-        }
+        let data_ops = self.handle_notify();
+        data.serve("stra stop to receive data", data_ops);
         Some(())
     }
 
@@ -0,0 +1,375 @@
+use std::collections::VecDeque;
+use std::fmt;
+use serde::{ Deserialize, Serialize };
+use std::sync::atomic::{ AtomicBool, AtomicUsize, Ordering };
+use tokio::sync::mpsc;
+use crate::{ loge, std_prelude::* };
+use super::live_ops::DataRecv;
+use super::order_types::OrderSend;
+
+/// A point on the way from the CTP callback to the return of `req_order`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Hop {
+    /// the SPI callback fired
+    Spi,
+    /// `ApiConvert` made a `DataRecv` of it
+    Convert,
+    /// queued to the strategy
+    Dispatch,
+    /// taken by the strategy thread
+    Recv,
+    /// the strategy closure returned
+    Stra,
+    /// `OrderPool::process_order_action` made an order
+    Pool,
+    /// queued to `data_send`
+    DataSend,
+    /// taken by the order thread
+    Send,
+    /// `req_order` returned
+    Req,
+}
+
+#[derive(Debug, Clone)]
+pub struct HopStamp {
+    marks: Vec<(Hop, Instant)>,
+}
+
+impl Default for HopStamp {
+    fn default() -> Self {
+        Self::new(Hop::Spi)
+    }
+}
+
+impl HopStamp {
+    pub fn new(hop: Hop) -> Self {
+        Self { marks: vec![(hop, Instant::now())] }
+    }
+
+    pub fn mark(&mut self, hop: Hop) {
+        self.marks.push((hop, Instant::now()));
+    }
+
+    /// Time taken to reach each hop from the one before.
+    pub fn hops(&self) -> Vec<(Hop, dura)> {
+        self.marks
+            .windows(2)
+            .map(|x| (x[1].0, x[1].1 - x[0].1))
+            .collect()
+    }
+
+    pub fn total(&self) -> dura {
+        match (self.marks.first(), self.marks.last()) {
+            (Some(first), Some(last)) => last.1 - first.1,
+            _ => dura::ZERO,
+        }
+    }
+}
+
+impl fmt::Display for HopStamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "total {}us", self.total().as_micros())?;
+        for (hop, d) in self.hops() {
+            write!(f, " {:?} {}us", hop, d.as_micros())?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Stamped<T> {
+    pub data: T,
+    pub stamp: HopStamp,
+}
+
+/// How ticks get to a strategy that is slower than the market.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TickFlow {
+    /// every tick is queued, a full queue holds the market data callback of every strategy
+    Queue,
+    /// a tick replaces the pending tick of its contract, the callback never waits, the default
+    Coalesce,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PipeConfig {
+    pub capacity: usize,
+    pub tick_flow: TickFlow,
+}
+
+impl Default for PipeConfig {
+    fn default() -> Self {
+        Self { capacity: 4096, tick_flow: TickFlow::Coalesce }
+    }
+}
+
+enum Envelope<T> {
+    /// data sent in the session of the number
+    Data(usize, T),
+    Wake,
+}
+
+/// A bounded channel served by one thread at a time. `start` opens a new session, the
+/// thread serving an older one leaves once it wakes up. Data of an older session is handed
+/// to the new one when `keep_stale` says so and dropped otherwise.
+struct Chan<T> {
+    tx: mpsc::Sender<Envelope<T>>,
+    rx: Mutex<mpsc::Receiver<Envelope<T>>>,
+    /// data taken by the server of an older session and not handed to it
+    carry: Mutex<Vec<(usize, T)>>,
+    keep_stale: fn(&T) -> bool,
+    started: AtomicBool,
+    session: AtomicUsize,
+}
+
+impl<T> Chan<T> {
+    fn new(capacity: usize, keep_stale: fn(&T) -> bool) -> Self {
+        let (tx, rx) = mpsc::channel(capacity.max(1));
+        Self {
+            tx,
+            rx: Mutex::new(rx),
+            carry: Default::default(),
+            keep_stale,
+            started: AtomicBool::new(false),
+            session: AtomicUsize::new(0),
+        }
+    }
+
+    fn wake(&self) {
+        // a full channel wakes the server anyway
+        let _ = self.tx.try_send(Envelope::Wake);
+    }
+
+    fn start(&self) {
+        self.session.fetch_add(1, Ordering::SeqCst);
+        self.started.store(true, Ordering::SeqCst);
+        self.wake();
+    }
+
+    fn stop(&self) {
+        self.started.store(false, Ordering::SeqCst);
+        self.wake();
+    }
+
+    fn is_started(&self) -> bool {
+        self.started.load(Ordering::SeqCst)
+    }
+
+    fn pending(&self) -> usize {
+        self.tx.max_capacity() - self.tx.capacity()
+    }
+
+    fn envelope(&self, data: T) -> Envelope<T> {
+        Envelope::Data(self.session.load(Ordering::SeqCst), data)
+    }
+
+    /// Calls `f` with everything received since the last call until the session ends.
+    fn serve(&self, info: &str, mut f: impl FnMut(Vec<T>)) {
+        let session = self.session.load(Ordering::SeqCst);
+        let mut rx = self.rx.lock().unwrap();
+        let is_current = || self.is_started() && self.session.load(Ordering::SeqCst) == session;
+        let mut stale = 0;
+        let mut carry = vec![];
+        let mut next = self.carry
+            .lock()
+            .unwrap()
+            .drain(..)
+            .map(|(s, data)| Envelope::Data(s, data))
+            .collect::<VecDeque<_>>();
+        while is_current() {
+            if next.is_empty() {
+                let Some(envelope) = rx.blocking_recv() else {
+                    break;
+                };
+                next.push_back(envelope);
+            }
+            while let Ok(envelope) = rx.try_recv() {
+                next.push_back(envelope);
+            }
+            let mut batch = vec![];
+            for envelope in next.drain(..) {
+                match envelope {
+                    Envelope::Data(s, data) if s == session => batch.push(data),
+                    Envelope::Data(s, data) if s > session => carry.push((s, data)),
+                    Envelope::Data(_, data) if (self.keep_stale)(&data) => batch.push(data),
+                    Envelope::Data(..) => stale += 1,
+                    Envelope::Wake => {}
+                }
+            }
+            if !is_current() {
+                // the next session gets the batch before anything taken after it
+                carry.splice(0..0, batch.into_iter().map(|data| (session, data)));
+                break;
+            }
+            f(batch);
+        }
+        self.carry.lock().unwrap().extend(carry);
+        if stale > 0 {
+            loge!("spy", "{info}: dropped {stale} items of an older session");
+        }
+        loge!("spy", "{info}");
+    }
+}
+
+/// Market data and order returns for one strategy. Only ticks are dropped, when the strategy
+/// is stopped. Order returns are kept across a stop and handed to the next session, a full
+/// pipe makes the sender wait.
+pub struct DataRecvPipe {
+    chan: Chan<Stamped<DataRecv>>,
+    pub config: PipeConfig,
+    latest: Mutex<Vec<Stamped<DataRecv>>>,
+    coalesced: AtomicUsize,
+}
+
+impl Default for DataRecvPipe {
+    fn default() -> Self {
+        Self::with_config(PipeConfig::default())
+    }
+}
+
+impl fmt::Debug for DataRecvPipe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DataRecvPipe")
+            .field("config", &self.config)
+            .field("pending", &self.pending())
+            .field("coalesced", &self.coalesced())
+            .finish()
+    }
+}
+
+impl DataRecvPipe {
+    pub fn with_config(config: PipeConfig) -> Self {
+        Self {
+            chan: Chan::new(config.capacity, |x| !matches!(x.data, DataRecv::TickData(..))),
+            config,
+            latest: Default::default(),
+            coalesced: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn start(&self) {
+        self.latest.lock().unwrap().clear();
+        self.chan.start();
+    }
+
+    pub fn stop(&self) {
+        self.chan.stop();
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.chan.is_started()
+    }
+
+    pub fn pending(&self) -> usize {
+        self.chan.pending()
+    }
+
+    /// Ticks replaced before the strategy took them.
+    pub fn coalesced(&self) -> usize {
+        self.coalesced.load(Ordering::Relaxed)
+    }
+
+    /// Waits for room unless it is a tick to coalesce. Ticks sent outside a session are dropped.
+    pub async fn send(&self, data: DataRecv, stamp: HopStamp) {
+        if !self.is_started() && matches!(data, DataRecv::TickData(..)) {
+            return;
+        }
+        let mut data = Stamped { data, stamp };
+        data.stamp.mark(Hop::Dispatch);
+        match (&data.data, self.config.tick_flow) {
+            (DataRecv::TickData(contract, _), TickFlow::Coalesce) => {
+                let contract = *contract;
+                let mut latest = self.latest.lock().unwrap();
+                let same = latest
+                    .iter()
+                    .position(|x| matches!(x.data, DataRecv::TickData(c, _) if c == contract));
+                if let Some(i) = same {
+                    latest.remove(i);
+                    self.coalesced.fetch_add(1, Ordering::Relaxed);
+                }
+                latest.push(data);
+                drop(latest);
+                self.chan.wake();
+            }
+            _ => {
+                let _ = self.chan.tx.send(self.chan.envelope(data)).await;
+            }
+        }
+    }
+
+    /// Hands the strategy all data received since its last call, in order, coalesced ticks
+    /// last. Blocks the calling thread until the session stops.
+    pub fn serve(&self, info: &str, mut f: impl FnMut(VecDeque<Stamped<DataRecv>>)) {
+        self.chan.serve(info, |batch| {
+            let mut que = VecDeque::from(batch);
+            que.extend(self.latest.lock().unwrap().drain(..));
+            if que.is_empty() {
+                return;
+            }
+            que.iter_mut().for_each(|x| x.stamp.mark(Hop::Recv));
+            f(que);
+        });
+    }
+}
+
+/// Orders of a strategy to the trading API, delivered one by one and in order.
+pub struct OrderSendPipe {
+    chan: Chan<Stamped<OrderSend>>,
+}
+
+impl Default for OrderSendPipe {
+    fn default() -> Self {
+        // orders of a stopped session never go out
+        Self { chan: Chan::new(PipeConfig::default().capacity, |_| false) }
+    }
+}
+
+impl fmt::Debug for OrderSendPipe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OrderSendPipe")
+            .field("pending", &self.pending())
+            .finish()
+    }
+}
+
+impl OrderSendPipe {
+    pub fn start(&self) {
+        self.chan.start();
+    }
+
+    pub fn stop(&self) {
+        self.chan.stop();
+    }
+
+    pub fn is_started(&self) -> bool {
+        self.chan.is_started()
+    }
+
+    pub fn pending(&self) -> usize {
+        self.chan.pending()
+    }
+
+    /// Waits for room, never call it from an async task.
+    pub fn blocking_send(&self, data: OrderSend, stamp: HopStamp) {
+        if !self.is_started() {
+            loge!("spy", "order dropped outside a session: {:?}", data);
+            return;
+        }
+        let mut data = Stamped { data, stamp };
+        data.stamp.mark(Hop::DataSend);
+        let _ = self.chan.tx.blocking_send(self.chan.envelope(data));
+    }
+
+    /// Blocks the calling thread until the session stops.
+    pub fn serve(&self, info: &str, mut f: impl FnMut(Stamped<OrderSend>)) {
+        self.chan.serve(info, |batch| {
+            for mut data in batch {
+                data.stamp.mark(Hop::Send);
+                f(data);
+            }
+        });
+    }
+}
+
+pub type NotifyDataSend = Arc<OrderSendPipe>;
+pub type NotifyDataRecv = Arc<DataRecvPipe>;
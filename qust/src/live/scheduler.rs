@@ -0,0 +1,378 @@
+use std::collections::VecDeque;
+use chrono::{ Duration, Local };
+use serde::{ Deserialize, Serialize };
+use qust_ds::prelude::*;
+use crate::{ loge, std_prelude::* };
+use crate::prelude::{ Ticker, TradingCalendar };
+use super::control::{ emit, LiveEvent, StraStatus };
+use super::latency::{ LatencyStats, LATENCY };
+use super::live_ops::StraApi;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SessionPart {
+    /// from the evening before the trading day, it may run past midnight
+    Night,
+    Day,
+}
+
+/// The order of the phases of one session, events at the same time come in this order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum SessionPhase {
+    /// `pre_open_lead` before the open, the service logs in
+    Start,
+    /// `auction_lead` before the first open of a trading day
+    Auction,
+    Open,
+    /// `pre_close_lead` before the close
+    PreClose,
+    Close,
+    /// `post_close_lag` after the close, the service logs out
+    Stop,
+    /// `eod_lag` after the day session closes
+    EndOfDay,
+}
+
+/// A session of the trading day, the breaks inside it are not split out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionWindow {
+    pub part: SessionPart,
+    pub trading_day: da,
+    pub open: dt,
+    /// the latest of `closes`
+    pub close: dt,
+    /// the tickers closing at each time, in time order
+    pub closes: Vec<(dt, Vec<Ticker>)>,
+}
+
+impl SessionWindow {
+    pub fn tickers(&self) -> Vec<Ticker> {
+        self.closes.iter().flat_map(|x| x.1.iter().cloned()).collect_vec()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionEvent {
+    pub phase: SessionPhase,
+    pub part: SessionPart,
+    pub trading_day: da,
+    pub time: dt,
+    /// tickers the event is for, `PreClose` and `Close` come once for every close time
+    #[serde(default)]
+    pub tickers: Vec<Ticker>,
+}
+
+/// Seconds around the sessions, holidays come from `calendar`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SchedulerConfig {
+    pub calendar: TradingCalendar,
+    pub pre_open_lead: i64,
+    pub auction_lead: i64,
+    /// 0 sends no `PreClose`
+    pub pre_close_lead: i64,
+    pub post_close_lag: i64,
+    /// shorter than `post_close_lag`, so the `EndOfDay` tasks run before the service logs out
+    pub eod_lag: i64,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            calendar: Default::default(),
+            pre_open_lead: 600,
+            auction_lead: 300,
+            pre_close_lead: 60,
+            post_close_lag: 120,
+            eod_lag: 60,
+        }
+    }
+}
+
+impl SchedulerConfig {
+    /// Sessions of `tickers` for the trading days in `start..=end`, the earliest of them
+    /// opens each one and every ticker closes it at its own time.
+    pub fn sessions(&self, tickers: &[Ticker], start: da, end: da) -> Vec<SessionWindow> {
+        let evening = 180000.to_tt();
+        // past midnight closes are later than any evening close
+        let close_key = |x: tt| (x < evening, x);
+        let part_of = |is_night: bool| {
+            let by_ticker = tickers
+                .iter()
+                .filter_map(|ticker| {
+                    let sessions = ticker.sessions();
+                    let part = sessions.iter().filter(|x| (x.0 >= evening) == is_night).collect_vec();
+                    let open = part.iter().map(|x| x.0).min()?;
+                    let close = part.iter().map(|x| x.1).max_by_key(|&x| close_key(x))?;
+                    Some((*ticker, open, close))
+                })
+                .collect_vec();
+            let open = by_ticker.iter().map(|x| x.1).min()?;
+            let closes = by_ticker
+                .iter()
+                .sorted_by_key(|x| close_key(x.2))
+                .chunk_by(|x| x.2)
+                .into_iter()
+                .map(|(close, group)| (close, group.map(|x| x.0).collect_vec()))
+                .collect_vec();
+            Some((open, closes))
+        };
+        let window = |part, trading_day, open_day: da, (open, closes): &(tt, Vec<(tt, Vec<Ticker>)>)| {
+            let closes = closes
+                .iter()
+                .map(|(close, tickers)| {
+                    let close_day = if *close < *open { open_day + Duration::days(1) } else { open_day };
+                    (close_day.and_time(*close), tickers.clone())
+                })
+                .collect_vec();
+            SessionWindow {
+                part,
+                trading_day,
+                open: open_day.and_time(*open),
+                close: closes.last().unwrap().0,
+                closes,
+            }
+        };
+        let night = part_of(true);
+        let day = part_of(false);
+        let mut res = vec![];
+        for trading_day in self.calendar.trading_days(start, end) {
+            if let Some(night) = &night {
+                let evening_day = self.calendar.prev_trading_day(trading_day);
+                if self.calendar.has_night_after(evening_day) {
+                    res.push(window(SessionPart::Night, trading_day, evening_day, night));
+                }
+            }
+            if let Some(day) = &day {
+                res.push(window(SessionPart::Day, trading_day, trading_day, day));
+            }
+        }
+        res
+    }
+
+    pub fn events(&self, windows: &[SessionWindow]) -> Vec<SessionEvent> {
+        let secs = Duration::seconds;
+        let mut res = vec![];
+        for (i, window) in windows.iter().enumerate() {
+            let first_of_day = i == 0 || windows[i - 1].trading_day != window.trading_day;
+            let all = window.tickers();
+            let mut push = |phase, time, tickers: &Vec<Ticker>| res.push(SessionEvent {
+                phase,
+                part: window.part,
+                trading_day: window.trading_day,
+                time,
+                tickers: tickers.clone(),
+            });
+            push(SessionPhase::Start, window.open - secs(self.pre_open_lead), &all);
+            if first_of_day {
+                push(SessionPhase::Auction, window.open - secs(self.auction_lead), &all);
+            }
+            push(SessionPhase::Open, window.open, &all);
+            for (close, tickers) in window.closes.iter() {
+                if self.pre_close_lead > 0 {
+                    push(SessionPhase::PreClose, *close - secs(self.pre_close_lead), tickers);
+                }
+                push(SessionPhase::Close, *close, tickers);
+            }
+            push(SessionPhase::Stop, window.close + secs(self.post_close_lag), &all);
+            if window.part == SessionPart::Day {
+                push(SessionPhase::EndOfDay, window.close + secs(self.eod_lag), &all);
+            }
+        }
+        res.sort_by_key(|x| (x.time, x.phase));
+        res
+    }
+}
+
+pub type SessionTaskFn = Box<dyn FnMut(&SessionEvent) + Send>;
+
+struct SessionTask {
+    phase: SessionPhase,
+    name: String,
+    f: SessionTaskFn,
+}
+
+/// Walks the sessions of the tickers traded, runs the tasks of each phase and emits
+/// `LiveEvent::Session` for the strategies.
+pub struct SessionScheduler {
+    pub config: SchedulerConfig,
+    tickers: Vec<Ticker>,
+    tasks: Vec<SessionTask>,
+    queue: VecDeque<SessionEvent>,
+    /// events up to this time are taken
+    cursor: dt,
+}
+
+impl SessionScheduler {
+    pub fn new(tickers: Vec<Ticker>, config: SchedulerConfig) -> Self {
+        Self {
+            config,
+            tickers: tickers.into_iter().unique().collect_vec(),
+            tasks: vec![],
+            queue: Default::default(),
+            cursor: Local::now().naive_local(),
+        }
+    }
+
+    pub fn with_task(mut self, phase: SessionPhase, name: &str, f: impl FnMut(&SessionEvent) + Send + 'static) -> Self {
+        self.tasks.push(SessionTask { phase, name: name.into(), f: Box::new(f) });
+        self
+    }
+
+    /// Flattens the contracts of every strategy on the `PreClose` of their ticker in the `part`
+    /// sessions and resumes them on the next `Open`. Strategies paused or flattening from
+    /// outside are left alone.
+    pub fn with_flatten(self, stra_api: Arc<StraApi>, part: SessionPart) -> Self {
+        let flattened: Arc<Mutex<Vec<String>>> = Default::default();
+        let flattened_open = flattened.clone();
+        let stra_api_open = stra_api.clone();
+        self
+            .with_task(SessionPhase::PreClose, "flatten", move |event| {
+                if event.part != part {
+                    return;
+                }
+                let mut flattened = flattened.lock().unwrap();
+                for control in stra_api.controls() {
+                    let status = control.status();
+                    let is_ours = flattened.contains(&control.id);
+                    if status.paused || (!is_ours && status.contracts.iter().any(|x| x.flatten)) {
+                        continue;
+                    }
+                    let closing = status.contracts
+                        .iter()
+                        .filter(|x| event.tickers.contains(&x.ticker))
+                        .filter(|x| control.flatten(Some(&x.contract)))
+                        .count();
+                    if closing > 0 && !is_ours {
+                        flattened.push(control.id.clone());
+                    }
+                }
+            })
+            .with_task(SessionPhase::Open, "resume flattened", move |_| {
+                let ids = std::mem::take(&mut *flattened_open.lock().unwrap());
+                stra_api_open
+                    .controls()
+                    .into_iter()
+                    .filter(|x| ids.contains(&x.id))
+                    .for_each(|x| x.resume());
+            })
+    }
+
+    /// Sessions are looked up again for `tickers` from the next event on.
+    pub fn set_tickers(&mut self, tickers: Vec<Ticker>) {
+        let tickers = tickers.into_iter().unique().collect_vec();
+        if tickers != self.tickers {
+            self.tickers = tickers;
+            self.queue.clear();
+        }
+    }
+
+    fn windows_around(&self, t: dt) -> Vec<SessionWindow> {
+        let date = t.date();
+        self.config.sessions(&self.tickers, date - Duration::days(1), date + Duration::days(14))
+    }
+
+    /// True from `Start` to `Stop` of a session.
+    pub fn should_run(&self, t: dt) -> bool {
+        self.windows_around(t).iter().any(|x| {
+            t >= x.open - Duration::seconds(self.config.pre_open_lead)
+                && t < x.close + Duration::seconds(self.config.post_close_lag)
+        })
+    }
+
+    /// The events after `t`, at least two weeks of them.
+    pub fn events_after(&self, t: dt) -> Vec<SessionEvent> {
+        self.config
+            .events(&self.windows_around(t))
+            .into_iter()
+            .filter(|x| x.time > t)
+            .collect_vec()
+    }
+
+    /// The next event after the last one taken, its tasks are not run.
+    pub fn next_event(&mut self) -> Option<SessionEvent> {
+        if self.queue.is_empty() {
+            self.queue = self.events_after(self.cursor).into();
+        }
+        let event = self.queue.pop_front()?;
+        self.cursor = event.time;
+        Some(event)
+    }
+
+    pub fn run_tasks(&mut self, event: &SessionEvent) {
+        for task in self.tasks.iter_mut().filter(|x| x.phase == event.phase) {
+            loge!("ctp", "session task {} on {:?}", task.name, event);
+            (task.f)(event);
+        }
+        emit(LiveEvent::Session(event.clone()));
+    }
+
+    /// Sleeps until the next event, runs its tasks and returns it. None if the tickers
+    /// have no sessions.
+    pub fn wait_next(&mut self) -> Option<SessionEvent> {
+        let event = self.next_event()?;
+        loop {
+            let left = event.time - Local::now().naive_local();
+            if left <= Duration::zero() {
+                break;
+            }
+            // short naps follow clock changes
+            sleep(left.to_std().unwrap_or_default().min(dura::from_secs(30)));
+        }
+        loge!("ctp", "session {:?} {:?} of {}", event.part, event.phase, event.trading_day);
+        self.run_tasks(&event);
+        Some(event)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DayReport {
+    pub trading_day: da,
+    pub strategies: Vec<StraStatus>,
+    pub latency: Vec<LatencyStats>,
+}
+
+/// An `EndOfDay` task writing a `DayReport` to `dir/report_<trading day>.json`.
+pub fn eod_report(stra_api: Arc<StraApi>, dir: &str) -> impl FnMut(&SessionEvent) + Send + 'static {
+    let dir = dir.to_string();
+    move |event| {
+        let report = DayReport {
+            trading_day: event.trading_day,
+            strategies: stra_api.controls().iter().map(|x| x.status()).collect_vec(),
+            latency: LATENCY.stats(),
+        };
+        let path = format!("{}/report_{}.json", dir, event.trading_day.format("%Y%m%d"));
+        let res = serde_json::to_string_pretty(&report)
+            .map_err(anyhow::Error::from)
+            .and_then(|x| std::fs::write(&path, x).map_err(anyhow::Error::from));
+        match res {
+            Ok(()) => loge!("ctp", "day report written to {}", path),
+            Err(e) => loge!(level: Error, "ctp", "day report {} not written: {}", path, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_close_time_gets_its_own_pre_close() {
+        let config = SchedulerConfig::default();
+        let day = da::from_ymd_opt(2024, 1, 3).unwrap();
+        let windows = config.sessions(&[Ticker::au, Ticker::rb], day, day);
+        let night = &windows[0];
+        assert_eq!(night.part, SessionPart::Night);
+        assert_eq!(night.closes.len(), 2);
+        assert_eq!(night.closes[0].1, vec![Ticker::rb]);
+        assert_eq!(night.closes[1].1, vec![Ticker::au]);
+        assert_eq!(night.close, night.closes[1].0);
+        let pre_close = config
+            .events(&windows)
+            .into_iter()
+            .filter(|x| x.phase == SessionPhase::PreClose && x.part == SessionPart::Night)
+            .collect_vec();
+        assert_eq!(pre_close.len(), 2);
+        assert_eq!(pre_close[0].tickers, vec![Ticker::rb]);
+        assert!(pre_close[0].time < pre_close[1].time);
+    }
+}
@@ -0,0 +1,294 @@
+use std::path::Path as FilePath;
+use anyhow::{ anyhow, Result };
+use serde::{ Deserialize, Serialize };
+use qust_ds::prelude::*;
+use qust_derive::*;
+use dyn_clone::{clone_trait_object, DynClone};
+use crate::{ loge, std_prelude::* };
+use crate::prelude::{ Dil, Stral, Ticker };
+use super::algo::AlgoBox;
+use super::live_ops::StraApi;
+use super::live_run::{ ApiBridgeBox, ServiceApi };
+use super::trend::prelude::TradeOne;
+
+/// A strategy written down in a json or toml file, built into `ApiBridge`s when it is
+/// attached to a running `StraApi`.
+#[clone_trait]
+pub trait StraSpec {
+    /// Every ticker needs a contract before the spec is built.
+    fn tickers(&self) -> Result<Vec<Ticker>>;
+    fn build(&self, contracts: &hm<Ticker, sstr>) -> Result<Vec<ApiBridgeBox>>;
+}
+
+/// `Stral` and the `Dil` it runs on, read with `rof`, one `TradeOne` per ticker.
+#[ta_derive2]
+pub struct StralSpec {
+    pub stral_path: String,
+    pub dil_path: String,
+    pub algo: AlgoBox,
+}
+
+fn rof_path<T: serde::de::DeserializeOwned>(path: &str) -> Result<T> {
+    let path = FilePath::new(path);
+    let (Some(name), Some(dir)) = (path.file_name().and_then(|x| x.to_str()), path.parent().and_then(|x| x.to_str())) else {
+        return Err(anyhow!("not a file path: {:?}", path));
+    };
+    if !path.is_file() {
+        return Err(anyhow!("file not exist: {:?}", path));
+    }
+    Ok(T::rof(name, dir))
+}
+
+#[typetag::serde]
+impl StraSpec for StralSpec {
+    fn tickers(&self) -> Result<Vec<Ticker>> {
+        rof_path::<Stral>(&self.stral_path)?
+            .0
+            .iter()
+            .map(|x| x.ident.ticker)
+            .unique()
+            .collect_vec()
+            .pip(Ok)
+    }
+
+    fn build(&self, contracts: &hm<Ticker, sstr>) -> Result<Vec<ApiBridgeBox>> {
+        let stral: Stral = rof_path(&self.stral_path)?;
+        let dil: Dil = rof_path(&self.dil_path)?;
+        let mut res: hm<Ticker, Vec<_>> = hm::new();
+        for (ident, stral_part) in stral.0.into_iter().into_group_map_by(|x| x.ident.clone()) {
+            let di = dil.dil
+                .iter()
+                .find(|x| x.pcon.ident() == ident)
+                .ok_or_else(|| anyhow!("no di for {:?} in {}", ident, self.dil_path))?;
+            res
+                .entry(ident.ticker)
+                .or_default()
+                .push(Stral(stral_part).with_info(RwLock::new(di.clone())));
+        }
+        res
+            .into_iter()
+            .map(|(ticker, stra)| {
+                let stra = stra.with_info(ticker).with_info(self.algo.clone());
+                Box::new(TradeOne::new(stra, ticker, contracts)) as ApiBridgeBox
+            })
+            .collect_vec()
+            .pip(Ok)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NamedStraSpec {
+    pub name: String,
+    pub spec: StraSpecBox,
+}
+
+impl NamedStraSpec {
+    pub fn from_json(text: &str) -> Result<Self> {
+        Ok(serde_json::from_str(text)?)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttachedSpec {
+    pub name: String,
+    pub spec: StraSpecBox,
+    /// `StraControl::id` of the strategies built
+    pub stra: Vec<String>,
+}
+
+/// Attaches and detaches strategies of specs to a `StraApi` and the service api it runs
+/// on, the other strategies keep running.
+pub struct StraManager {
+    pub stra_api: Arc<StraApi>,
+    contracts: hm<Ticker, sstr>,
+    specs: Mutex<Vec<AttachedSpec>>,
+}
+
+impl StraManager {
+    pub fn new(stra_api: Arc<StraApi>, contracts: hm<Ticker, sstr>) -> Self {
+        Self { stra_api, contracts, specs: Default::default() }
+    }
+
+    pub fn specs(&self) -> Vec<AttachedSpec> {
+        self.specs.lock().unwrap().clone()
+    }
+
+    /// Builds the strategies without attaching them.
+    pub fn validate(&self, spec: &NamedStraSpec) -> Result<Vec<ApiBridgeBox>> {
+        if self.specs.lock().unwrap().iter().any(|x| x.name == spec.name) {
+            return Err(anyhow!("spec {} is attached already", spec.name));
+        }
+        let missing = spec.spec
+            .tickers()?
+            .into_iter()
+            .filter(|x| !self.contracts.contains_key(x))
+            .collect_vec();
+        if !missing.is_empty() {
+            return Err(anyhow!("spec {} has no contract for {:?}", spec.name, missing));
+        }
+        let api_bridges = spec.spec.build(&self.contracts)?;
+        let running = self.stra_api.controls().into_iter().map(|x| x.id.clone()).collect_vec();
+        for api_bridge in api_bridges.iter() {
+            let id = &api_bridge.control().id;
+            if running.contains(id) {
+                return Err(anyhow!("spec {} builds {} which is running already", spec.name, id));
+            }
+        }
+        Ok(api_bridges)
+    }
+
+    /// Returns the ids of the strategies attached. Nothing is left attached on error.
+    pub fn attach(&self, spec: NamedStraSpec, service: &dyn ServiceApi) -> Result<Vec<String>> {
+        let api_bridges = self.validate(&spec)?;
+        let mut stra: Vec<String> = vec![];
+        for api_bridge in api_bridges {
+            let id = api_bridge.control().id.clone();
+            let res = self.stra_api.attach(api_bridge).and_then(|trade_api| {
+                if let Err(e) = service.attach_trade_api(trade_api.clone()) {
+                    self.stra_api.detach(&id)?;
+                    self.stra_api.stop_stra(&trade_api);
+                    return Err(e);
+                }
+                Ok(())
+            });
+            if let Err(e) = res {
+                for id in stra.iter() {
+                    self.detach_stra(id, service)?;
+                }
+                return Err(e);
+            }
+            stra.push(id);
+        }
+        loge!("ctp", "spec {} attached: {:?}", spec.name, stra);
+        self.specs.lock().unwrap().push(AttachedSpec { name: spec.name, spec: spec.spec, stra: stra.clone() });
+        Ok(stra)
+    }
+
+    fn detach_stra(&self, id: &str, service: &dyn ServiceApi) -> Result<()> {
+        let trade_api = self.stra_api.detach(id)?;
+        service.detach_trade_api(trade_api.clone())?;
+        self.stra_api.stop_stra(&trade_api);
+        Ok(())
+    }
+
+    /// Refuses while the strategies hold positions unless `force`, the positions are left
+    /// to the account then.
+    pub fn detach(&self, name: &str, force: bool, service: &dyn ServiceApi) -> Result<AttachedSpec> {
+        let mut specs = self.specs.lock().unwrap();
+        let i = specs
+            .iter()
+            .position(|x| x.name == name)
+            .ok_or_else(|| anyhow!("no spec {}", name))?;
+        let holding = self.stra_api
+            .controls()
+            .into_iter()
+            .filter(|x| specs[i].stra.contains(&x.id))
+            .flat_map(|x| x.status().contracts)
+            .filter(|x| x.hold != 0.)
+            .map(|x| x.contract)
+            .collect_vec();
+        if !holding.is_empty() && !force {
+            return Err(anyhow!("spec {} still holds {:?}, flatten it first", name, holding));
+        }
+        let spec = specs.remove(i);
+        for id in spec.stra.iter() {
+            self.detach_stra(id, service)?;
+        }
+        loge!("ctp", "spec {} detached", name);
+        Ok(spec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use crate::prelude::{ DataRecv, DataRecvId, NotifyDataRecv, NotifyDataSend, Stamped, TradeApi };
+    use super::super::control::StraControl;
+    use super::super::live_run::ApiBridge;
+
+    struct EmptyBridge {
+        trade_api: Vec<TradeApi>,
+        control: Arc<StraControl>,
+    }
+
+    impl ApiBridge for EmptyBridge {
+        fn gen_trade_api(&self) -> Vec<TradeApi> {
+            self.trade_api.clone()
+        }
+
+        fn handle_notify<'a>(&'a self) -> Box<dyn FnMut(VecDeque<Stamped<DataRecv>>) + 'a> {
+            Box::new(|_| {})
+        }
+
+        fn data_recv_get(&self) -> NotifyDataRecv {
+            self.trade_api[0].data_recv.clone()
+        }
+
+        fn control(&self) -> Arc<StraControl> {
+            self.control.clone()
+        }
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct EmptySpec;
+
+    #[typetag::serde]
+    impl StraSpec for EmptySpec {
+        fn tickers(&self) -> Result<Vec<Ticker>> {
+            Ok(vec![Ticker::rb])
+        }
+
+        fn build(&self, contracts: &hm<Ticker, sstr>) -> Result<Vec<ApiBridgeBox>> {
+            let contract = contracts[&Ticker::rb];
+            let trade_api = TradeApi {
+                contract,
+                ticker: Ticker::rb,
+                stra_id: "empty".into(),
+                data_recv_id: DataRecvId { tick_data_id: contract.into(), order_return_id: "empty".into() },
+                data_send: NotifyDataSend::default(),
+                data_recv: NotifyDataRecv::default(),
+            };
+            let control = StraControl::new("empty".into(), "empty".into(), vec![(Ticker::rb, contract)], false);
+            Ok(vec![Box::new(EmptyBridge { trade_api: vec![trade_api], control: Arc::new(control) })])
+        }
+    }
+
+    struct EmptyService;
+
+    impl ServiceApi for EmptyService {
+        fn start(&self, _trade_api: Vec<TradeApi>) -> Result<()> {
+            Ok(())
+        }
+
+        fn stop(&self, _trade_api: Vec<TradeApi>) -> Result<()> {
+            Ok(())
+        }
+
+        fn attach_trade_api(&self, _trade_api: Vec<TradeApi>) -> Result<()> {
+            Ok(())
+        }
+
+        fn detach_trade_api(&self, _trade_api: Vec<TradeApi>) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn detach_then_attach_starts_the_strategy_again() {
+        let stra_api = Arc::new(StraApi::new(vec![]));
+        stra_api.start(vec![]).unwrap();
+        let manager = StraManager::new(stra_api.clone(), hm::from([(Ticker::rb, "rb2405")]));
+        let spec = NamedStraSpec { name: "a".into(), spec: Box::new(EmptySpec) };
+        manager.attach(spec.clone(), &EmptyService).unwrap();
+        let first = stra_api.get_trade_api_vec();
+        manager.detach("a", false, &EmptyService).unwrap();
+        assert!(stra_api.is_started());
+        assert!(!first[0].data_recv.is_started());
+        manager.attach(spec, &EmptyService).unwrap();
+        let second = stra_api.get_trade_api_vec();
+        assert!(second[0].data_recv.is_started() && second[0].data_send.is_started());
+        manager.detach("a", false, &EmptyService).unwrap();
+        assert!(stra_api.is_started());
+    }
+}
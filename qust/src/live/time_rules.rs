@@ -0,0 +1,264 @@
+use chrono::Duration;
+use serde::{ Deserialize, Serialize };
+use qust_ds::prelude::*;
+use qust_derive::*;
+use crate::prelude::{ TickData, Ticker, TradingCalendar };
+use super::algo::{ Algo, AlgoBox, OrderActionNum, RetFnAlgo };
+use super::bt::{ ApiType, RetFnApi, StreamApiType };
+use super::order_types::OrderAction;
+
+/// A range of the time of day, it runs past midnight when `end < start`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeWindow {
+    pub start: tt,
+    pub end: tt,
+}
+
+impl TimeWindow {
+    pub fn new(start: tt, end: tt) -> Self {
+        Self { start, end }
+    }
+
+    pub fn contains(&self, t: tt) -> bool {
+        if self.start <= self.end {
+            t >= self.start && t < self.end
+        } else {
+            t >= self.start || t < self.end
+        }
+    }
+
+    /// Seconds from `start` to `t`, `t` is in the window.
+    fn elapsed(&self, t: tt) -> i64 {
+        let secs = (t - self.start).num_seconds();
+        if secs < 0 { secs + 86400 } else { secs }
+    }
+}
+
+/// The price a flatten order goes at.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum FlattenPrice {
+    /// at the own side of the book, bid1 for a buy
+    Passive,
+    /// at the other side of the book, ask1 for a buy
+    Aggressive,
+    /// this much through the other side, the limit order fills as a market one
+    Market(f32),
+}
+
+impl FlattenPrice {
+    /// The order closing `hold` at this price.
+    pub fn close(&self, hold: f32, tick_data: &TickData) -> OrderAction {
+        let (lo_price, sh_price) = match self {
+            FlattenPrice::Passive => (tick_data.bid1, tick_data.ask1),
+            FlattenPrice::Aggressive => (tick_data.ask1, tick_data.bid1),
+            FlattenPrice::Market(x) => (tick_data.ask1 + x, tick_data.bid1 - x),
+        };
+        OrderActionNum::from_hold_target(hold, 0.).into_order_action(lo_price, sh_price)
+    }
+}
+
+/// Goes for `price` from `after` seconds into the flatten on.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct EscalationStep {
+    pub after: i64,
+    pub price: FlattenPrice,
+}
+
+/// The step of `ladder` at `elapsed` seconds, `Passive` before the first one.
+fn ladder_price(ladder: &[EscalationStep], elapsed: i64) -> FlattenPrice {
+    ladder
+        .iter()
+        .filter(|x| x.after <= elapsed)
+        .max_by_key(|x| x.after)
+        .map(|x| x.price)
+        .unwrap_or(FlattenPrice::Passive)
+}
+
+/// Flat inside `window`, e.g. `14:56:50..15:00:00` with passive, aggressive and market steps.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForcedFlatten {
+    pub window: TimeWindow,
+    #[serde(default)]
+    pub ladder: Vec<EscalationStep>,
+}
+
+/// Flat by the close of the last session before a weekend or holiday, from `lead` seconds
+/// before it. A night session ends the trading day before a break when `ticker` has one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NoHoldOverBreak {
+    pub ticker: Ticker,
+    #[serde(default)]
+    pub calendar: TradingCalendar,
+    pub lead: i64,
+    #[serde(default)]
+    pub ladder: Vec<EscalationStep>,
+}
+
+impl NoHoldOverBreak {
+    /// The close of the session part `t` is in, if a break follows it. `t` is the wall clock
+    /// time, as live ticks are dated by `feed_time`, not the trading day.
+    pub fn close_before_break(&self, t: dt) -> Option<dt> {
+        let evening = 180000.to_tt();
+        let sessions = self.ticker.sessions();
+        let night = sessions.iter().find(|x| x.0 >= evening);
+        let in_night = night.is_some_and(|x| TimeWindow::new(x.0, x.1).contains(t.time()));
+        let (last_day, close) = if in_night {
+            let (open, close) = *night?;
+            let evening_day = if t.time() < open { t.date() - Duration::days(1) } else { t.date() };
+            let close_day = if close < open { evening_day + Duration::days(1) } else { evening_day };
+            (evening_day, close_day.and_time(close))
+        } else {
+            let close = sessions.iter().filter(|x| x.0 < evening).map(|x| x.1).max()?;
+            if t.time() >= close || (night.is_some() && self.calendar.has_night_after(t.date())) {
+                return None;
+            }
+            (t.date(), t.date().and_time(close))
+        };
+        let next = self.calendar.next_trading_day(last_day);
+        (next - last_day > Duration::days(1)).then_some(close)
+    }
+}
+
+/// What the rules allow at a time.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TimeRuleState {
+    Free,
+    NoOpen,
+    Flatten(FlattenPrice),
+}
+
+/// Time rules of a strategy, they act on its orders from the tick time alone, so the tick
+/// backtest and live see the same orders.
+#[ta_derive]
+#[derive(Default)]
+pub struct TimeRules {
+    /// opens are held back, closes go through
+    #[serde(default)]
+    pub no_open: Vec<TimeWindow>,
+    #[serde(default)]
+    pub flatten: Vec<ForcedFlatten>,
+    #[serde(default)]
+    pub no_hold_over_break: Option<NoHoldOverBreak>,
+}
+
+impl TimeRules {
+    pub fn with_no_open(mut self, window: TimeWindow) -> Self {
+        self.no_open.push(window);
+        self
+    }
+
+    pub fn with_flatten(mut self, window: TimeWindow, ladder: Vec<EscalationStep>) -> Self {
+        self.flatten.push(ForcedFlatten { window, ladder });
+        self
+    }
+
+    pub fn with_no_hold_over_break(mut self, rule: NoHoldOverBreak) -> Self {
+        self.no_hold_over_break = Some(rule);
+        self
+    }
+
+    /// The most aggressive flatten of the rules in force at `t`.
+    pub fn state(&self, t: dt) -> TimeRuleState {
+        let mut flatten = self.flatten
+            .iter()
+            .filter(|x| x.window.contains(t.time()))
+            .map(|x| ladder_price(&x.ladder, x.window.elapsed(t.time())))
+            .collect_vec();
+        if let Some(rule) = &self.no_hold_over_break {
+            if let Some(close) = rule.close_before_break(t) {
+                let elapsed = (t - (close - Duration::seconds(rule.lead))).num_seconds();
+                if elapsed >= 0 {
+                    flatten.push(ladder_price(&rule.ladder, elapsed));
+                }
+            }
+        }
+        let rank = |x: &FlattenPrice| match x {
+            FlattenPrice::Passive => 0.,
+            FlattenPrice::Aggressive => 1.,
+            FlattenPrice::Market(x) => 2. + x,
+        };
+        if let Some(price) = flatten.into_iter().max_by(|a, b| rank(a).total_cmp(&rank(b))) {
+            TimeRuleState::Flatten(price)
+        } else if self.no_open.iter().any(|x| x.contains(t.time())) {
+            TimeRuleState::NoOpen
+        } else {
+            TimeRuleState::Free
+        }
+    }
+
+    /// `order_action` of the strategy after the rules.
+    pub fn apply(&self, stream_api: &StreamApiType, order_action: OrderAction) -> OrderAction {
+        match self.state(stream_api.tick_data.t) {
+            TimeRuleState::Free => order_action,
+            TimeRuleState::NoOpen => match order_action {
+                OrderAction::LoOpen(..) | OrderAction::ShOpen(..) => OrderAction::No,
+                other => other,
+            },
+            TimeRuleState::Flatten(price) => price.close(stream_api.hold.sum(), stream_api.tick_data),
+        }
+    }
+}
+
+impl<T: ApiType> ApiType for WithInfo<T, TimeRules> {
+    fn api_type(&self) -> RetFnApi {
+        let mut ops_fn = self.data.api_type();
+        Box::new(move |stream_api| {
+            let order_action = ops_fn(stream_api.clone());
+            self.info.apply(&stream_api, order_action)
+        })
+    }
+
+    fn api_type_algo<'a>(&'a self, algo: &dyn Algo) -> Option<RetFnApi<'a>> {
+        let mut ops_fn = self.data.api_type_algo(algo)?;
+        Box::new(move |stream_api: StreamApiType| {
+            let order_action = ops_fn(stream_api.clone());
+            self.info.apply(&stream_api, order_action)
+        })
+        .pip(|x| Some(x as RetFnApi))
+    }
+}
+
+/// `algo` under `rules`, it can be set on a running strategy like any `Algo`.
+#[ta_derive2]
+pub struct AlgoTimeRules {
+    pub algo: AlgoBox,
+    pub rules: TimeRules,
+}
+
+#[typetag::serde]
+impl Algo for AlgoTimeRules {
+    fn algo(&self) -> RetFnAlgo {
+        let mut algo_fn = self.algo.algo();
+        let rules = self.rules.clone();
+        Box::new(move |stream| {
+            let order_action = algo_fn(stream);
+            rules.apply(&stream.stream_api, order_action)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::prelude::feed_time;
+
+    #[test]
+    fn friday_night_holds_over_the_weekend() {
+        let rule = NoHoldOverBreak {
+            ticker: Ticker::au,
+            calendar: TradingCalendar::default(),
+            lead: 60,
+            ladder: vec![],
+        };
+        let t = |x: &str| dt::parse_from_str(x, "%Y-%m-%d %H:%M:%S").unwrap();
+        // a tick of the night of Friday 2024-01-05, its TradingDay is Monday 2024-01-08
+        let friday_night = feed_time(230000.to_tt(), t("2024-01-05 23:00:01"));
+        assert_eq!(rule.close_before_break(friday_night), Some(t("2024-01-06 02:30:00")));
+        let after_midnight = feed_time(22900.to_tt(), t("2024-01-06 02:29:00"));
+        assert_eq!(rule.close_before_break(after_midnight), Some(t("2024-01-06 02:30:00")));
+        let rules = TimeRules::default().with_no_hold_over_break(rule.clone());
+        assert_eq!(rules.state(after_midnight), TimeRuleState::Flatten(FlattenPrice::Passive));
+        let tuesday_night = feed_time(230000.to_tt(), t("2024-01-09 23:00:01"));
+        assert_eq!(rule.close_before_break(tuesday_night), None);
+    }
+}
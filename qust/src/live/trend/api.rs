@@ -155,6 +155,7 @@ where
 {
     fn cond_type3<'a>(&'a self, di: &'a RwLock<Di>) -> RetFnCondType3<'a> {
         let mut di = di.write().unwrap();
+        di.own_price();
         let pcon_ident = di.pcon.ident();
         let mut ptm_fn = self.cond_type1(&di);
         let mut update_tick_fn = pcon_ident.inter.update_tick_func(pcon_ident.ticker);
@@ -268,6 +269,6 @@ impl<T: Algo + Clone> ToStraApi for WithInfo<WithInfo<DiStral<'_>, T>, &hm<Ticke
                 Arc::new(trade_one_box)
             })
             .collect_vec()
-            .pip(|x| StraApi { pool: x })
+            .pip(StraApi::new)
     }
 }
@@ -1,5 +1,5 @@
 #![allow(clippy::collapsible_if)]
-use std::sync::{Arc, RwLock};
+use std::sync::RwLock;
 use std::thread;
 use itertools::{izip, Itertools};
 use crate::loge;
@@ -98,9 +98,13 @@ struct KlineRange {
 
 impl<T: CondType7, N: Algo> ApiType for WithInfo<T, N> {
     fn api_type(&self) -> RetFnApi {
+        self.api_type_algo(&self.info).unwrap()
+    }
+
+    fn api_type_algo<'a>(&'a self, algo: &dyn Algo) -> Option<RetFnApi<'a>> {
         let mut ops_fn = self.data.cond_type7();
-        let mut algo_fn = self.info.algo();
-        Box::new(move |stream_api| {
+        let mut algo_fn = algo.algo();
+        Box::new(move |stream_api: StreamApiType| {
             let order_target = ops_fn(stream_api.tick_data);
             // loge!("ctp", "stra calc a res: {:?}", order_target);
             let stream_algo = StreamAlgo {
@@ -109,6 +113,7 @@ impl<T: CondType7, N: Algo> ApiType for WithInfo<T, N> {
             };
             algo_fn(&stream_algo)
         })
+        .pip(|x| Some(x as RetFnApi))
     }
 }
 
@@ -201,6 +206,7 @@ where
 impl<T: CondType1> CondTypeA for WithInfo<T, RwLock<Di>> {
     fn cond_type_a(&self) -> RetFnCondType3 {
         let mut di = self.info.write().unwrap();
+        di.own_price();
         let pcon_ident = di.pcon.ident();
         let mut ptm_fn = self.data.cond_type1(&di);
         let mut update_tick_fn = pcon_ident.inter.update_tick_func(pcon_ident.ticker);
@@ -256,7 +262,7 @@ where
     fn cond_type_a(&self) -> RetFnCondType3 {
         let di = self.info;
         let mut ops_fn = self.data.cond_type1(di);
-        let mut kline_range_vec = izip!(di.pcon.price.ki.iter(), di.pcon.price.t.iter(), 0..)
+        let mut kline_range_vec = izip!(di.base_ki().iter(), di.base_t().iter(), 0..)
             .map(|(x, y, z)| {
                 KlineRange { 
                     time_open: x.open_time,
@@ -313,19 +319,25 @@ where
     T: CondTypeA + Send + Sync,
 {
     fn api_type(&self) -> RetFnApi {
+        self.api_type_algo(self.info.as_ref()).unwrap()
+    }
+
+    fn api_type_algo<'a>(&'a self, algo: &dyn Algo) -> Option<RetFnApi<'a>> {
         let mut ops_fn = self.data.cond_type_a();
-        let mut algo_fn = self.info.algo();
-        Box::new(move |stream_api| {
+        let mut algo_fn = algo.algo();
+        Box::new(move |stream_api: StreamApiType| {
             let order_target = ops_fn(&stream_api);
             let stream_algo = StreamAlgo { stream_api, order_target };
             algo_fn(&stream_algo)
         })
+        .pip(|x| Some(x as RetFnApi))
     }
 }
 
 impl ApiType for WithInfo<Box<dyn CondType4>, &Di> {
     fn api_type(&self) -> RetFnApi {
         let mut di = self.info.clone();
+        di.own_price();
         let mut ops_fn = self.data.cond_type4(&di);
         let mut update_tick_fn = di.pcon.inter.update_tick_func(di.pcon.ticker);
         let mut last_update_tick_time = Default::default();
@@ -558,7 +570,7 @@ impl IntoPnlRes for TickerTradeInfo {
         PnlResPreInfo {
             ticker: self.info,
             t,
-            c: Arc::new(c),
+            c: c.into(),
             profit, 
             comm: cs2,
             pass_num,
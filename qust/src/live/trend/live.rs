@@ -5,7 +5,9 @@ use crate::trade::inter::*;
 use super::super::order_types::*;
 use super::super::live_ops::*;
 use super::super::live_run::*;
-use std::sync::Mutex;
+use super::super::pipe::*;
+use super::super::control::StraControl;
+use std::sync::{ Arc, Mutex };
 use crate::trade::ticker::*;
 use std::collections::VecDeque;
 
@@ -16,6 +18,7 @@ pub struct TradeOne<T> {
     pub stra: T,
     pub trade_api: TradeApi,
     pub trade_manager: TradeManager,
+    pub control: Arc<StraControl>,
 }
 
 impl<T: std::fmt::Debug> TradeOne<T> {
@@ -28,6 +31,7 @@ impl<T: std::fmt::Debug> TradeOne<T> {
         let trade_api = TradeApi {
             contract,
             ticker,
+            stra_id: data_recv_id.order_return_id.clone(),
             data_recv_id,
             data_send: Default::default(),
             data_recv: Default::default(),
@@ -43,7 +47,19 @@ impl<T: std::fmt::Debug> TradeOne<T> {
             order_pool,
             hold: Default::default(),
         };
-        Self { stra, trade_api, trade_manager }
+        let control = StraControl::new(
+            trade_api.data_recv_id.order_return_id.clone(),
+            format!("{:?}", stra).chars().take(64).collect(),
+            vec![(ticker, contract)],
+            true,
+        );
+        Self { stra, trade_api, trade_manager, control: Arc::new(control) }
+    }
+
+    /// Sets how data gets to the strategy, ticks of slow strategies can be coalesced.
+    pub fn with_pipe(mut self, config: PipeConfig) -> Self {
+        self.trade_api.data_recv = DataRecvPipe::with_config(config).pip(Arc::new);
+        self
     }
 }
 
@@ -58,26 +74,46 @@ impl<T: ApiType> ApiBridge for TradeOne<T> {
         self.trade_api.data_recv.clone()
     }
 
-    fn handle_notify<'a>(&'a self) -> Box<dyn FnMut(VecDeque<DataRecv>) + 'a> {
+    fn control(&self) -> Arc<StraControl> {
+        self.control.clone()
+    }
+
+    fn handle_notify<'a>(&'a self) -> Box<dyn FnMut(VecDeque<Stamped<DataRecv>>) + 'a> {
         let mut order_pool = self.trade_manager.order_pool.lock().unwrap();
         let mut live_api_ops = self.stra.api_type();
         let mut last_tick_data = TickData::default();
         let ticker = self.trade_api.ticker;
+        let control = &self.control;
+        let mut algo_version = 0;
         Box::new(move |data_recv_que| {
             let mut data_recv_que = data_recv_que;
-            while let Some(data_receive) = data_recv_que.pop_front() {
+            if let Some(algo) = control.algo_changed(&mut algo_version) {
+                // the old closure goes first, it may hold the data it reads
+                live_api_ops = Box::new(|_| OrderAction::No);
+                live_api_ops = self.stra
+                    .api_type_algo(&*algo)
+                    .unwrap_or_else(|| self.stra.api_type());
+            }
+            while let Some(Stamped { data: data_receive, mut stamp }) = data_recv_que.pop_front() {
                 match data_receive {
                     DataRecv::TickData(_, tick_data) => {
                         loge!(ticker, "data recive ---------- tick data --------------");
                         last_tick_data = tick_data;
-                        let stream_api = StreamApiType { tick_data: &last_tick_data, hold: &order_pool.hold };
+                        control.on_tick(0, &last_tick_data);
+                        let hold = control.hold_to_stra(&order_pool.hold);
+                        let stream_api = StreamApiType { tick_data: &last_tick_data, hold: &hold };
                         live_api_ops(stream_api);
                         loge!(ticker, "data recive ++++++++++ tick data ++++++++++++++");
                     }
                     DataRecv::OrderRecv(data_receive) => {
                         loge!(ticker, "data recive ---------- data receive --------------");
-                        if let Err(e) = order_pool.update_order(data_receive) {
-                            loge!(ticker, "update err {:?}", e);
+                        match order_pool.update_order(data_receive) {
+                            Ok(_) => {
+                                control.on_hold(0, &order_pool.hold);
+                            }
+                            Err(e) => {
+                                loge!(ticker, "update err {:?}", e);
+                            }
                         }
                         loge!(ticker, "data recive ++++++++++ data receive ++++++++++++++");
                     } 
@@ -85,19 +121,25 @@ impl<T: ApiType> ApiBridge for TradeOne<T> {
                         order_pool.update_order_his(order_recv_vec);
                         continue;
                     }
+                    DataRecv::Fill(fill) => {
+                        control.on_fill(0, &fill.action, fill.time);
+                        continue;
+                    }
                 }
                 if data_recv_que.is_empty() {
                     loge!(ticker, "data receive ----------: {:?}", &order_pool.hold);
-                    let stream_api = StreamApiType { tick_data: &last_tick_data, hold: &order_pool.hold };
+                    let hold = control.hold_to_stra(&order_pool.hold);
+                    let stream_api = StreamApiType { tick_data: &last_tick_data, hold: &hold };
                     let order_action = live_api_ops(stream_api);
+                    stamp.mark(Hop::Stra);
+                    let order_action = control.gate(0, &order_pool.hold, &last_tick_data, order_action);
                     loge!(ticker, "stra calced a order_action: {:?}", order_action);
                     match order_pool.process_order_action(order_action) {
                         Ok(Some(order_input)) => {
+                            stamp.mark(Hop::Pool);
                             loge!(ticker, "data receive +++++++ stra send a order to ctp: {:?}", order_input);
-                            let mut order_input_que = VecDeque::new();
-                            order_input_que.push_back(order_input);
-                            self.trade_api.data_send.set(order_input_que);
-                            self.trade_api.data_send.notify_all();
+                            control.on_order(0, &order_input.order_action);
+                            self.trade_api.data_send.blocking_send(order_input, stamp);
                         }
                         Ok(None) => {
                             loge!(ticker, "data receive +++++++ stra order pool calc a none order send");
@@ -15,7 +15,7 @@ pub struct StraCond(pub Stra, pub CondWeight, pub CondWeight);
 
 impl StraCond {
     pub fn get_cond_m(&self, di: &mut Di) -> vv32 {
-        let grp = Grp(di.pcon.price.t.iter().map(|x| x.date()).collect_vec());
+        let grp = Grp(di.base_t().iter().map(|x| x.date()).collect_vec());
         izip!(self.1.0.iter(), self.2.0.iter())
             .fold(vec![], |mut acc, (cond1, cond2)| {
                 let w = cond1.1;
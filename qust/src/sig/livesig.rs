@@ -28,6 +28,11 @@ pub trait LiveSig: Calc<Arc<BoxAny>> {
     fn update2<'a>(&'a self, _di: &'a Di, _data: &'a RwLock<Self::R>) -> LiveSigUpDate<'a> {
         todo!()
     }
+    /// Estimated heap size of the state, counted against the budget of `DataSave`. The
+    /// default takes 16 bytes per bar.
+    fn state_bytes(&self, di: &Di, _data: &RwLock<Self::R>) -> usize {
+        di.len() * 16
+    }
 }
 
 clone_trait_object!(<R> LiveSig<R = R>);
@@ -54,6 +59,12 @@ impl Tsig {
 
 impl LiveSig for Tsig {
     type R = (HashSet<OpenIng>, TsigRes);
+    fn state_bytes(&self, _di: &Di, data: &RwLock<Self::R>) -> usize {
+        let data = data.read().unwrap();
+        data.0.len() * std::mem::size_of::<OpenIng>()
+            + std::mem::size_of_val(&data.1 .0[..])
+            + std::mem::size_of_val(&data.1 .1[..])
+    }
     fn get_data(&self, di: &Di) -> RwLock<(HashSet<OpenIng>, TsigRes)> {
         let len = di.len() + 500;
         let o_vec = Vec::with_capacity(len);
@@ -175,6 +186,13 @@ pub enum Stp {
 impl LiveSig for Stp {
     type R = StpRes;
 
+    fn state_bytes(&self, _di: &Di, data: &RwLock<Self::R>) -> usize {
+        let data = data.read().unwrap();
+        std::mem::size_of_val(&data.hold[..])
+            + std::mem::size_of_val(&data.open[..])
+            + std::mem::size_of_val(&data.exit[..])
+    }
+
     fn get_data(&self, di: &Di) -> RwLock<Self::R> {
         let len = di.len();
         let h_res = Vec::with_capacity(len);
@@ -232,6 +250,10 @@ pub struct CondWeight(pub Vec<(Box<dyn Cond>, f32)>);
 impl LiveSig for CondWeight {
     type R = v32;
 
+    fn state_bytes(&self, _di: &Di, data: &RwLock<Self::R>) -> usize {
+        data.read().unwrap().len() * 4
+    }
+
     fn get_data(&self, di: &Di) -> RwLock<Self::R> {
         let init_len = di.len() + 500;
         RwLock::new(Vec::with_capacity(init_len))
@@ -266,6 +288,11 @@ pub enum Ptm {
 impl LiveSig for Ptm {
     type R = PtmResState;
 
+    fn state_bytes(&self, _di: &Di, data: &RwLock<Self::R>) -> usize {
+        let (hold, open, exit) = &data.read().unwrap().ptm_res;
+        std::mem::size_of_val(&hold[..]) + std::mem::size_of_val(&open[..]) + std::mem::size_of_val(&exit[..])
+    }
+
     fn get_data(&self, di: &Di) -> RwLock<Self::R> {
         let res = Self::R::new(di.len());
         RwLock::new(res)
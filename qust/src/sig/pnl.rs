@@ -30,7 +30,7 @@ impl Di {
             t: self.t().to_vec(),
             profit: self.profit(),
             comm,
-            pass_num: self.pcon.price.ki.iter().skip(1).map(|ki| ((ki.pass_last + ki.pass_this) as f32 / 120.)).collect_vec(),
+            pass_num: self.base_ki().iter().skip(1).map(|ki| ((ki.pass_last + ki.pass_this) as f32 / 120.)).collect_vec(),
             ptm_res,
         };
         pnl_res_pre_info.into_pnl_res()
@@ -45,7 +45,7 @@ pub trait IntoPnlRes {
 pub struct PnlResPreInfo<'a> {
     pub ticker: Ticker,
     pub t: vdt,
-    pub c: av32,
+    pub c: Col<f32>,
     pub profit: v32,
     pub comm: CommSlip,
     pub pass_num: v32,
@@ -0,0 +1,185 @@
+use crate::{
+    idct::ta::CommSlip,
+    live::{bt::TickerTradeInfo, order_types::OrderAction},
+    sig::{livesig::LiveSig, posi::{Dire, PtmResState, ToNum}},
+    std_prelude::*,
+    trade::prelude::*,
+};
+use qust_ds::prelude::*;
+use qust_derive::*;
+
+#[ta_derive]
+pub struct Trade {
+    pub entry_time: dt,
+    pub exit_time: dt,
+    pub entry_price: f32,
+    pub exit_price: f32,
+    pub dire: Dire,
+    pub size: f32,
+    ///money, net of comm and slip
+    pub pnl: f32,
+    pub cost: f32,
+    pub bars: usize,
+    ///money, worst unrealized pnl while holding
+    pub mae: f32,
+    ///money, best unrealized pnl while holding
+    pub mfe: f32,
+}
+
+#[ta_derive]
+pub struct TradeList {
+    pub ticker: Ticker,
+    pub trades: Vec<Trade>,
+}
+
+/// One point of a position path, `hold` is the signed position after `price` is traded.
+struct PathPoint {
+    t: dt,
+    price: f32,
+    high: f32,
+    low: f32,
+    hold: f32,
+}
+
+struct OpenTrade {
+    i: usize,
+    dire: Dire,
+    size: f32,
+    pnl: f32,
+    cost: f32,
+    mae: f32,
+    mfe: f32,
+}
+
+fn trade_list_from_path(ticker: Ticker, comm: &CommSlip, path: &[PathPoint]) -> TradeList {
+    let info = ticker.info();
+    let cost_fn = |num: f32, price: f32| -> f32 {
+        let comm_money = match info.comm {
+            Comm::F(i) => comm.0 * i * num,
+            Comm::P(i) => comm.0 * i * num * price * info.pv,
+        };
+        comm_money + comm.1 * info.slip * info.tz * info.pv * num
+    };
+    let mut trades = vec![];
+    let mut open: Option<OpenTrade> = None;
+    let mut hold_last = 0f32;
+    let mut price_last = f32::NAN;
+    for (i, point) in path.iter().enumerate() {
+        if let Some(trade) = open.as_mut() {
+            trade.pnl += hold_last * (point.price - price_last) * info.pv;
+            let (best, worst) = match trade.dire {
+                Dire::Lo => (point.high, point.low),
+                Dire::Sh => (point.low, point.high),
+            };
+            let pnl_now = trade.pnl;
+            let unrealized = |p: f32| pnl_now - hold_last * (point.price - p) * info.pv;
+            trade.mfe = trade.mfe.max(unrealized(best));
+            trade.mae = trade.mae.min(unrealized(worst));
+        }
+        let is_closed = hold_last != 0. && (point.hold == 0. || point.hold.signum() != hold_last.signum());
+        if is_closed {
+            let mut trade = open.take().unwrap();
+            let cost = cost_fn(hold_last.abs(), point.price);
+            trade.cost += cost;
+            trade.pnl -= cost;
+            let entry = &path[trade.i];
+            trades.push(Trade {
+                entry_time: entry.t,
+                exit_time: point.t,
+                entry_price: entry.price,
+                exit_price: point.price,
+                dire: trade.dire,
+                size: trade.size,
+                pnl: trade.pnl,
+                cost: trade.cost,
+                bars: i - trade.i,
+                mae: trade.mae,
+                mfe: trade.mfe,
+            });
+        }
+        match open.as_mut() {
+            Some(trade) => {
+                let cost = cost_fn((point.hold - hold_last).abs(), point.price);
+                trade.cost += cost;
+                trade.pnl -= cost;
+                trade.size = trade.size.max(point.hold.abs());
+            }
+            None if point.hold != 0. => {
+                let cost = cost_fn(point.hold.abs(), point.price);
+                open = Some(OpenTrade {
+                    i,
+                    dire: if point.hold > 0. { Dire::Lo } else { Dire::Sh },
+                    size: point.hold.abs(),
+                    pnl: -cost,
+                    cost,
+                    mae: -cost,
+                    mfe: -cost,
+                });
+            }
+            None => {}
+        }
+        hold_last = point.hold;
+        price_last = point.price;
+    }
+    TradeList { ticker, trades }
+}
+
+pub trait ToTradeList {
+    fn trade_list(&self, comm: CommSlip) -> TradeList;
+}
+
+impl Di {
+    pub fn trade_list<T: LiveSig<R = PtmResState> + AsRef<T>>(
+        &self,
+        sig: &T,
+        comm: CommSlip,
+    ) -> TradeList {
+        let b = self.calc(sig);
+        let ptm_res = &b
+            .downcast_ref::<RwLock<PtmResState>>()
+            .unwrap()
+            .read()
+            .unwrap()
+            .ptm_res;
+        let path = izip!(
+            self.t().iter(),
+            self.c().iter(),
+            self.h().iter(),
+            self.l().iter(),
+            ptm_res.0.iter(),
+        )
+            .map(|(t, c, h, l, hold)| PathPoint { t: *t, price: *c, high: *h, low: *l, hold: hold.to_num() })
+            .collect_vec();
+        trade_list_from_path(self.pcon.ticker, &comm, &path)
+    }
+}
+
+impl ToTradeList for TickerTradeInfo {
+    fn trade_list(&self, comm: CommSlip) -> TradeList {
+        let path = self.data
+            .iter()
+            .scan(0f32, |hold, trade_info| {
+                let (delta, price) = match trade_info.action {
+                    OrderAction::LoOpen(i, price) | OrderAction::LoClose(i, price) => (i, price),
+                    OrderAction::ShOpen(i, price) | OrderAction::ShClose(i, price) => (-i, price),
+                    OrderAction::No => (0., f32::NAN),
+                };
+                *hold += delta;
+                Some(PathPoint { t: trade_info.time, price, high: price, low: price, hold: *hold })
+            })
+            .filter(|x| !x.price.is_nan())
+            .collect_vec();
+        trade_list_from_path(self.info, &comm, &path)
+    }
+}
+
+impl TradeList {
+    pub fn pnl_sum(&self) -> f32 {
+        self.trades.iter().map(|x| x.pnl).sum()
+    }
+
+    pub fn win_rate(&self) -> f32 {
+        let n = self.trades.len().max(1) as f32;
+        self.trades.iter().filter(|x| x.pnl > 0.).count() as f32 / n
+    }
+}
@@ -0,0 +1,101 @@
+use chrono::{Datelike, Duration, Weekday};
+use qust_ds::prelude::*;
+use qust_derive::*;
+use std::collections::BTreeSet;
+
+/// Trading days are weekdays that are not in `holidays`.
+#[ta_derive]
+#[derive(Default)]
+pub struct TradingCalendar {
+    pub holidays: BTreeSet<da>,
+}
+
+impl TradingCalendar {
+    pub fn new<T: IntoIterator<Item = da>>(holidays: T) -> Self {
+        Self { holidays: holidays.into_iter().collect() }
+    }
+
+    pub fn is_trading_day(&self, date: &da) -> bool {
+        !matches!(date.weekday(), Weekday::Sat | Weekday::Sun) && !self.holidays.contains(date)
+    }
+
+    /// Trading days in `start..=end`.
+    pub fn trading_days(&self, start: da, end: da) -> Vec<da> {
+        let mut res = vec![];
+        let mut date = start;
+        while date <= end {
+            if self.is_trading_day(&date) {
+                res.push(date);
+            }
+            date += Duration::days(1);
+        }
+        res
+    }
+
+    pub fn next_trading_day(&self, date: da) -> da {
+        let mut res = date + Duration::days(1);
+        while !self.is_trading_day(&res) {
+            res += Duration::days(1);
+        }
+        res
+    }
+
+    pub fn prev_trading_day(&self, date: da) -> da {
+        let mut res = date - Duration::days(1);
+        while !self.is_trading_day(&res) {
+            res -= Duration::days(1);
+        }
+        res
+    }
+
+    /// The trading day `t` belongs to, a night session counts for the next trading day,
+    /// also after midnight.
+    pub fn trading_day(&self, t: dt) -> da {
+        let time = t.time();
+        if time >= 180000.to_tt() {
+            self.next_trading_day(t.date())
+        } else if time < 60000.to_tt() {
+            self.next_trading_day(t.date() - Duration::days(1))
+        } else {
+            t.date()
+        }
+    }
+
+    /// The evening of trading day `date` has a night session unless a holiday comes before
+    /// the next trading day.
+    pub fn has_night_after(&self, date: da) -> bool {
+        if !self.is_trading_day(&date) {
+            return false;
+        }
+        let next = self.next_trading_day(date);
+        let mut day = date + Duration::days(1);
+        while day < next {
+            if !matches!(day.weekday(), Weekday::Sat | Weekday::Sun) {
+                return false;
+            }
+            day += Duration::days(1);
+        }
+        true
+    }
+
+    /// Trading days in `start..=end` that are not in `dates`.
+    pub fn missing_days(&self, start: da, end: da, dates: &[da]) -> Vec<da> {
+        let dates = dates.iter().collect::<BTreeSet<_>>();
+        self.trading_days(start, end)
+            .into_iter()
+            .filter(|x| !dates.contains(x))
+            .collect_vec()
+    }
+}
+
+/// The time of a live tick from its time of day and the local time `now` it came in at.
+/// CTP's `TradingDay` is the next trading day for a night tick and `ActionDay` is not the
+/// calendar day on every exchange, so the date is the one of `now`, a day off when `time`
+/// and `now` are on the two sides of midnight.
+pub fn feed_time(time: tt, now: dt) -> dt {
+    [now.date() - Duration::days(1), now.date(), now.date() + Duration::days(1)]
+        .map(|x| x.and_time(time))
+        .into_iter()
+        .min_by_key(|x| (*x - now).num_milliseconds().abs())
+        .unwrap()
+}
@@ -0,0 +1,373 @@
+use super::calendar::TradingCalendar;
+use super::di::PriceTick;
+use super::inter::TickData;
+use super::ticker::{ExtractTicker, Ticker};
+use qust_ds::prelude::*;
+use qust_derive::*;
+use std::sync::{Arc, Mutex, RwLock};
+
+#[ta_derive]
+pub enum VolumeMode {
+    /// volume is already per tick
+    PerTick,
+    /// volume is the cumulative day volume as sent by CTP
+    Cumulative,
+}
+
+#[ta_derive]
+pub struct TickCleanConfig {
+    pub session: bool,
+    /// seconds before a session start still accepted, keeps the call auction print
+    pub session_lead: i64,
+    pub dedup: bool,
+    /// a tick at most this many milliseconds behind the last one gets the last time,
+    /// older ones are dropped
+    pub max_back_ms: i64,
+    pub volume: VolumeMode,
+    /// a price jump of more than this many ticks from the last price is rejected, 0 disables
+    pub max_jump_ticks: f32,
+    /// reject prices that are not a multiple of the tick size
+    pub on_grid: bool,
+}
+
+impl Default for TickCleanConfig {
+    fn default() -> Self {
+        Self {
+            session: true,
+            session_lead: 60,
+            dedup: true,
+            max_back_ms: 1000,
+            volume: VolumeMode::PerTick,
+            max_jump_ticks: 0.,
+            on_grid: false,
+        }
+    }
+}
+
+#[ta_derive]
+#[derive(Default)]
+pub struct TickQuality {
+    pub ticker: Option<Ticker>,
+    /// the trading day, a night session is in the report of the next trading day
+    pub date: da,
+    pub total: usize,
+    pub kept: usize,
+    pub out_session: usize,
+    pub duplicated: usize,
+    pub time_repaired: usize,
+    pub time_dropped: usize,
+    pub bad_price: usize,
+    /// outside of the limit prices of the day
+    #[serde(default)]
+    pub out_limit: usize,
+    pub outlier: usize,
+    pub volume_reset: usize,
+    /// seconds, the longest in session gap between two kept ticks
+    pub max_gap: f32,
+    pub first: Option<dt>,
+    pub last: Option<dt>,
+}
+
+impl TickQuality {
+    pub fn dropped(&self) -> usize {
+        self.total - self.kept
+    }
+}
+
+/// Cleans the ticks of one ticker in time order, the same object is used for a saved
+/// `PriceTick` and for ticks coming from the market data feed.
+#[derive(Debug, Clone)]
+pub struct TickCleaner {
+    pub ticker: Ticker,
+    pub config: TickCleanConfig,
+    /// maps a tick to its trading day, the day rolls there and not at midnight
+    pub calendar: TradingCalendar,
+    /// (lower, upper) limit price of the trading day, prices outside are rejected, the feed
+    /// sends them with every tick
+    pub limits: Option<(f32, f32)>,
+    /// last kept tick of the session in progress, with the volume as received
+    last: Option<TickData>,
+    last_cum_v: Option<f32>,
+    quality: TickQuality,
+    pub reports: Vec<TickQuality>,
+}
+
+impl TickCleaner {
+    pub fn new(ticker: Ticker, config: TickCleanConfig) -> Self {
+        Self {
+            ticker,
+            config,
+            calendar: TradingCalendar::default(),
+            limits: None,
+            last: None,
+            last_cum_v: None,
+            quality: TickQuality::default(),
+            reports: vec![],
+        }
+    }
+
+    fn roll_day(&mut self, date: da) {
+        if self.quality.total > 0 && self.quality.date != date {
+            let quality = std::mem::take(&mut self.quality);
+            self.reports.push(quality);
+            self.last = None;
+            self.last_cum_v = None;
+        }
+        if self.quality.total == 0 {
+            self.quality.ticker = Some(self.ticker);
+            self.quality.date = date;
+        }
+    }
+
+    pub fn clean(&mut self, mut tick: TickData) -> Option<TickData> {
+        self.roll_day(self.calendar.trading_day(tick.t));
+        let q = &mut self.quality;
+        q.total += 1;
+        let info = self.ticker.info();
+        if !tick.c.is_finite() || tick.c <= 0. || tick.c >= 1e10 {
+            q.bad_price += 1;
+            return None;
+        }
+        if self.config.session && !self.ticker.in_session(tick.t.time(), self.config.session_lead) {
+            q.out_session += 1;
+            return None;
+        }
+        if let Some((lower, upper)) = self.limits {
+            if tick.c < lower - info.tz * 1e-3 || tick.c > upper + info.tz * 1e-3 {
+                q.out_limit += 1;
+                return None;
+            }
+        }
+        if self.config.on_grid {
+            let n = tick.c / info.tz;
+            if (n - n.round()).abs() > 1e-3 {
+                q.bad_price += 1;
+                return None;
+            }
+        }
+        // time order, dedup and jumps are checked within a session, the open of a session is
+        // not held against the close of the one before
+        let session_now = self.ticker.session_index(tick.t.time(), 0);
+        if self.last.as_ref().is_some_and(|last| self.ticker.session_index(last.t.time(), 0) != session_now) {
+            self.last = None;
+        }
+        if let Some(last) = &self.last {
+            if tick.t < last.t {
+                if (last.t - tick.t).num_milliseconds() > self.config.max_back_ms {
+                    q.time_dropped += 1;
+                    return None;
+                }
+                tick.t = last.t;
+                q.time_repaired += 1;
+            }
+            let is_same = tick.t == last.t
+                && tick.c == last.c
+                && tick.v == last.v
+                && tick.bid1 == last.bid1
+                && tick.ask1 == last.ask1
+                && tick.bid1_v == last.bid1_v
+                && tick.ask1_v == last.ask1_v;
+            if self.config.dedup && is_same {
+                q.duplicated += 1;
+                return None;
+            }
+            if self.config.max_jump_ticks > 0. && (tick.c - last.c).abs() / info.tz > self.config.max_jump_ticks {
+                q.outlier += 1;
+                return None;
+            }
+            if session_now.is_some() {
+                let gap = (tick.t - last.t).num_milliseconds() as f32 / 1000.;
+                q.max_gap = q.max_gap.max(gap);
+            }
+        }
+        q.kept += 1;
+        q.first.get_or_insert(tick.t);
+        q.last = Some(tick.t);
+        self.last = Some(tick.clone());
+        if let VolumeMode::Cumulative = self.config.volume {
+            let cum_v = tick.v;
+            tick.v = match self.last_cum_v {
+                Some(last) if cum_v >= last => cum_v - last,
+                Some(_) => {
+                    q.volume_reset += 1;
+                    cum_v
+                }
+                None => cum_v,
+            };
+            self.last_cum_v = Some(cum_v);
+        }
+        Some(tick)
+    }
+
+    pub fn clean_price_tick(&mut self, price: &PriceTick) -> PriceTick {
+        let mut res = PriceTick::with_capacity(price.t.len());
+        price
+            .to_tick_data()
+            .into_iter()
+            .filter_map(|x| self.clean(x))
+            .for_each(|x| res.update(&x));
+        res
+    }
+
+    /// The report of the day in progress, `reports` only holds finished days.
+    pub fn current(&self) -> &TickQuality {
+        &self.quality
+    }
+
+    /// Close the day in progress and return every report collected so far.
+    pub fn finish(&mut self) -> Vec<TickQuality> {
+        if self.quality.total > 0 {
+            let quality = std::mem::take(&mut self.quality);
+            self.reports.push(quality);
+        }
+        std::mem::take(&mut self.reports)
+    }
+}
+
+/// One cleaner per contract for the live feed, cleaners are made on the first tick of a
+/// contract. Each cleaner has a lock of its own, so md threads of different contracts do
+/// not wait for each other.
+#[derive(Debug, Default)]
+pub struct TickCleanerSet {
+    pub config: TickCleanConfig,
+    pub calendar: TradingCalendar,
+    cleaners: RwLock<hm<String, Arc<Mutex<TickCleaner>>>>,
+}
+
+impl TickCleanerSet {
+    pub fn new(config: TickCleanConfig) -> Self {
+        Self { config, calendar: TradingCalendar::default(), cleaners: Default::default() }
+    }
+
+    fn cleaner(&self, contract: &str) -> Option<Arc<Mutex<TickCleaner>>> {
+        if let Some(cleaner) = self.cleaners.read().unwrap().get(contract) {
+            return Some(cleaner.clone());
+        }
+        let (ticker, _) = contract.extract_ticker()?;
+        self.cleaners
+            .write()
+            .unwrap()
+            .entry(contract.to_string())
+            .or_insert_with(|| {
+                let mut cleaner = TickCleaner::new(ticker, self.config.clone());
+                cleaner.calendar = self.calendar.clone();
+                Arc::new(Mutex::new(cleaner))
+            })
+            .clone()
+            .pip(Some)
+    }
+
+    /// Cleans `tick` with the `limits` of the day when known, and returns the reports of the
+    /// trading days the tick closed. Ticks of a contract that can not be mapped to a ticker
+    /// are passed through.
+    pub fn clean(
+        &self,
+        contract: &str,
+        tick: TickData,
+        limits: Option<(f32, f32)>,
+    ) -> (Option<TickData>, Vec<TickQuality>) {
+        let Some(cleaner) = self.cleaner(contract) else {
+            return (Some(tick), vec![]);
+        };
+        let mut cleaner = cleaner.lock().unwrap();
+        if limits.is_some() {
+            cleaner.limits = limits;
+        }
+        let res = cleaner.clean(tick);
+        (res, std::mem::take(&mut cleaner.reports))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trade::calendar::feed_time;
+
+    fn tick(t: &str, c: f32, v: f32) -> TickData {
+        TickData {
+            t: dt::parse_from_str(t, "%Y-%m-%d %H:%M:%S%.f").unwrap(),
+            c,
+            v,
+            ..Default::default()
+        }
+    }
+
+    fn cumulative() -> TickCleanConfig {
+        TickCleanConfig { volume: VolumeMode::Cumulative, ..Default::default() }
+    }
+
+    #[test]
+    fn night_session_rolls_on_the_trading_day() {
+        let mut cleaner = TickCleaner::new(Ticker::au, cumulative());
+        let v = [
+            ("2024-01-02 21:00:00.500", 10.),
+            ("2024-01-02 23:59:59.500", 30.),
+            ("2024-01-03 00:00:00.500", 35.),
+            ("2024-01-03 02:29:59.500", 50.),
+            ("2024-01-03 09:00:00.500", 60.),
+            ("2024-01-03 21:00:00.500", 5.),
+        ]
+        .map(|(t, v)| cleaner.clean(tick(t, 500., v)).unwrap().v);
+        assert_eq!(v, [10., 20., 5., 15., 10., 5.]);
+        let reports = cleaner.finish();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].date, da::from_ymd_opt(2024, 1, 3).unwrap());
+        assert_eq!(reports[0].kept, 5);
+        assert_eq!(reports[0].volume_reset, 0);
+        assert_eq!(reports[1].date, da::from_ymd_opt(2024, 1, 4).unwrap());
+    }
+
+    #[test]
+    fn live_night_then_day_session_is_kept() {
+        let t = |x: &str| dt::parse_from_str(x, "%Y-%m-%d %H:%M:%S%.f").unwrap();
+        // the feed sends the time of day only, with the TradingDay 2024-01-03 for all of them
+        let feed = [
+            ("21:00:00.500", "2024-01-02 21:00:00.600"),
+            ("23:59:59.900", "2024-01-03 00:00:00.100"),
+            ("00:00:01.000", "2024-01-03 00:00:01.100"),
+            ("09:00:00.500", "2024-01-03 09:00:00.600"),
+            ("10:30:00.500", "2024-01-03 10:30:00.600"),
+        ]
+        .map(|(time, now)| feed_time(tt::parse_from_str(time, "%H:%M:%S%.f").unwrap(), t(now)));
+        assert_eq!(feed[1], t("2024-01-02 23:59:59.900"));
+        assert_eq!(feed[2], t("2024-01-03 00:00:01.000"));
+        // the day session opens with a gap larger than `max_jump_ticks`
+        let config = TickCleanConfig { max_jump_ticks: 10., ..cumulative() };
+        let mut cleaner = TickCleaner::new(Ticker::au, config);
+        let kept = izip!(feed.iter(), [500., 500.02, 500.04, 501., 501.02], [10., 20., 25., 30., 40.])
+            .filter_map(|(t, c, v)| cleaner.clean(TickData { t: *t, c, v, ..Default::default() }))
+            .count();
+        assert_eq!(kept, 5);
+        assert_eq!(cleaner.current().date, da::from_ymd_opt(2024, 1, 3).unwrap());
+        assert_eq!(cleaner.current().time_dropped, 0);
+    }
+
+    #[test]
+    fn friday_night_belongs_to_monday() {
+        let calendar = TradingCalendar::default();
+        let t = |x: &str| dt::parse_from_str(x, "%Y-%m-%d %H:%M:%S").unwrap();
+        let monday = da::from_ymd_opt(2024, 1, 8).unwrap();
+        assert_eq!(calendar.trading_day(t("2024-01-05 21:00:00")), monday);
+        assert_eq!(calendar.trading_day(t("2024-01-06 02:00:00")), monday);
+        assert_eq!(calendar.trading_day(t("2024-01-08 10:00:00")), monday);
+    }
+
+    #[test]
+    fn closing_print_is_kept() {
+        let mut cleaner = TickCleaner::new(Ticker::rb, TickCleanConfig::default());
+        assert!(cleaner.clean(tick("2024-01-02 14:59:59.500", 3800., 1.)).is_some());
+        assert!(cleaner.clean(tick("2024-01-02 15:00:00.500", 3801., 1.)).is_some());
+        assert!(cleaner.clean(tick("2024-01-02 15:00:01.000", 3801., 1.)).is_none());
+        assert_eq!(cleaner.current().out_session, 1);
+    }
+
+    #[test]
+    fn prices_outside_the_limits_are_rejected() {
+        let set = TickCleanerSet::new(TickCleanConfig::default());
+        let limits = Some((3600., 4000.));
+        let (res, _) = set.clean("rb2405", tick("2024-01-02 09:00:01", 4100., 1.), limits);
+        assert!(res.is_none());
+        let (res, _) = set.clean("rb2405", tick("2024-01-02 09:00:02", 3900., 1.), limits);
+        assert!(res.is_some());
+    }
+}
@@ -165,14 +165,15 @@ impl PriceOri {
     }
 }
 
+/// Columns are `Col`, so a `PriceArc` can read a mapped column file in place.
 #[derive(Clone)]
 pub struct PriceArc {
-    pub t: avdt,
-    pub o: av32,
-    pub h: av32,
-    pub l: av32,
-    pub c: av32,
-    pub v: av32,
+    pub t: Col<dt>,
+    pub o: Col<f32>,
+    pub h: Col<f32>,
+    pub l: Col<f32>,
+    pub c: Col<f32>,
+    pub v: Col<f32>,
     pub ki: Arc<Vec<KlineInfo>>,
     pub immut_info: Vec<Arc<vv32>>,
     pub finished: Option<Vec<KlineState>>,
@@ -262,6 +263,7 @@ impl Pcon {
     pub fn to_di(self) -> Di {
         Di {
             pcon: self,
+            mapped: None,
             data_save: DataSave::default(),
             dcon: RwLock::new(vec![Tf(0, 1)]),
             part: RwLock::new(vec![Part::ono]),
@@ -272,9 +274,12 @@ impl Pcon {
 /* #endregion */
 
 /* #region Di */
-#[derive(Serialize, Deserialize, AsRef)]
+#[derive(Deserialize, AsRef)]
 pub struct DiType<T> {
     pub pcon: T,
+    /// The bars read in place from a column file, `pcon.price` is left empty then.
+    #[serde(skip)]
+    pub mapped: Option<PriceArc>,
     #[serde(skip)]
     pub data_save: DataSave,
     pub dcon: RwLock<Vec<Convert>>,
@@ -282,16 +287,89 @@ pub struct DiType<T> {
 }
 pub type Di = DiType<Pcon>;
 
+/// A mapped `Di` is saved with its bars, as if it was never mapped.
+impl Serialize for Di {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut state = serializer.serialize_struct("DiType", 3)?;
+        state.serialize_field("pcon", &*self.owned_pcon())?;
+        state.serialize_field("dcon", &self.dcon)?;
+        state.serialize_field("part", &self.part)?;
+        state.end()
+    }
+}
+
 impl Clone for Di {
     fn clone(&self) -> Self {
-        self.pcon.clone().to_di()
+        let mut res = self.pcon.clone().to_di();
+        res.mapped.clone_from(&self.mapped);
+        res
     }
 }
 
 impl Di {
     pub fn size(&self) -> usize {
-        self.pcon.price.t.len()
+        self.len()
     }
+
+    /// The bars before any convert, shared with the mapping when the di is mapped.
+    pub fn price_arc(&self) -> PriceArc {
+        match &self.mapped {
+            Some(price) => price.clone(),
+            None => self.pcon.price.clone().to_arc(),
+        }
+    }
+
+    /// `pcon` with the mapped bars copied in when the di is mapped.
+    pub fn owned_pcon(&self) -> std::borrow::Cow<'_, Pcon> {
+        match &self.mapped {
+            Some(price) => std::borrow::Cow::Owned(
+                price.clone().to_price_ori().to_pcon(self.pcon.inter.clone(), self.pcon.ticker),
+            ),
+            None => std::borrow::Cow::Borrowed(&self.pcon),
+        }
+    }
+
+    /// Copy the mapped bars into `pcon.price`, call it before changing the bars.
+    pub fn own_price(&mut self) {
+        if let Some(price) = self.mapped.take() {
+            self.pcon.price = price.to_price_ori();
+        }
+    }
+
+    pub fn base_t(&self) -> &[dt] {
+        match &self.mapped {
+            Some(price) => &price.t,
+            None => &self.pcon.price.t,
+        }
+    }
+
+    pub fn base_c(&self) -> &[f32] {
+        match &self.mapped {
+            Some(price) => &price.c,
+            None => &self.pcon.price.c,
+        }
+    }
+
+    pub fn base_ki(&self) -> &[KlineInfo] {
+        match &self.mapped {
+            Some(price) => &price.ki,
+            None => &self.pcon.price.ki,
+        }
+    }
+
+    /// The `i`th bar before any convert.
+    pub fn kline_data(&self, i: usize) -> KlineData {
+        let ki = self.base_ki()[i].clone();
+        match &self.mapped {
+            Some(p) => KlineData { t: p.t[i], o: p.o[i], h: p.h[i], l: p.l[i], c: p.c[i], v: p.v[i], ki },
+            None => {
+                let p = &self.pcon.price;
+                KlineData { t: p.t[i], o: p.o[i], h: p.h[i], l: p.l[i], c: p.c[i], v: p.v[i], ki }
+            }
+        }
+    }
+
     pub fn last_dcon(&self) -> Convert {
         let dcon_vec = self.dcon.read().unwrap();
         dcon_vec[dcon_vec.len() - 1].clone()
@@ -302,7 +380,7 @@ impl Di {
         part_vec[part_vec.len() - 1].clone()
     }
 
-    pub fn get_kline(&self, p: &KlineType) -> av32 {
+    pub fn get_kline(&self, p: &KlineType) -> Col<f32> {
         match p {
             KlineType::Open => self.o(),
             KlineType::High => self.h(),
@@ -329,22 +407,22 @@ impl Di {
 
     }
 
-    pub fn t(&self) -> avdt {
+    pub fn t(&self) -> Col<dt> {
         self.calc(self.last_dcon()).t
     }
-    pub fn o(&self) -> av32 {
+    pub fn o(&self) -> Col<f32> {
         self.calc(self.last_dcon()).o
     }
-    pub fn h(&self) -> av32 {
+    pub fn h(&self) -> Col<f32> {
         self.calc(self.last_dcon()).h
     }
-    pub fn l(&self) -> av32 {
+    pub fn l(&self) -> Col<f32> {
         self.calc(self.last_dcon()).l
     }
-    pub fn c(&self) -> av32 {
+    pub fn c(&self) -> Col<f32> {
         self.calc(self.last_dcon()).c
     }
-    pub fn v(&self) -> av32 {
+    pub fn v(&self) -> Col<f32> {
         self.calc(self.last_dcon()).v
     }
     pub fn immut_info(&self) -> Vec<Arc<vv32>> {
@@ -352,10 +430,10 @@ impl Di {
     }
 
     pub fn len(&self) -> usize {
-        self.pcon.price.t.len()
+        self.base_t().len()
     }
     pub fn is_empty(&self) -> bool {
-        self.pcon.price.t.is_empty()
+        self.base_t().is_empty()
     }
 
     pub fn clear(&self) {
@@ -363,18 +441,43 @@ impl Di {
     }
 
     pub fn clear2(&self) {
-        self.data_save.save_pms2d.write().unwrap().clear();
-        self.data_save.save_dcon.write().unwrap().clear();
-        self.data_save.save_others.write().unwrap().clear();
+        self.data_save.clear_kind(&[CacheKind::Pms, CacheKind::Dcon, CacheKind::Others]);
     }
 
     pub fn calc<T: AsRef<N>, N: Calc<R> + ?Sized, R>(&self, x: T) -> R {
         x.as_ref().calc(self)
     }
 
+    /// Append the bars built from `price_tick`, ticks at or before the last finished bar are
+    /// skipped, so a bar that was still open at the last update is rebuilt from its first tick.
+    /// Results cached on the old bars are dropped when bars were added, returns the number of new bars.
+    pub fn update_from_tick(&mut self, price_tick: &PriceTick) -> usize {
+        self.own_price();
+        let start = match self.pcon.price.t.last() {
+            Some(t) => price_tick.t.partition_point(|x| x <= t),
+            None => 0,
+        };
+        if start >= price_tick.t.len() {
+            return 0;
+        }
+        let mut f = self.pcon.inter.update_tick_func(self.pcon.ticker);
+        let mut price_ori = PriceOri::with_capacity(0);
+        price_tick.to_tick_data()[start..]
+            .iter()
+            .for_each(|x| {
+                f(x, &mut price_ori);
+            });
+        let n = price_ori.t.len();
+        if n > 0 {
+            self.pcon.price.cat(&mut price_ori);
+            self.data_save.on_append();
+        }
+        n
+    }
+
     pub fn tz_profit(&self) -> f32 {
         let tz = self.pcon.ticker.info().tz;
-        10000. * tz / self.pcon.price.c.last().unwrap()
+        10000. * tz / self.base_c().last().unwrap()
     }
 }
 
@@ -384,10 +487,10 @@ impl Debug for Di {
             f,
             "{:<15} ---  {:<24} .. {:<24}  ---  {:<10} --- {}",
             self.pcon.ident().to_string(),
-            self.pcon.price.ki.first().unwrap().open_time.to_string(),
-            self.pcon.price.t.last().unwrap().to_string(),
-            self.pcon.price.t.len().to_string(),
-            (self.pcon.price.ki.map(|x| x.pass_this as f32).mean() / 120.) as usize,
+            self.base_ki().first().unwrap().open_time.to_string(),
+            self.base_t().last().unwrap().to_string(),
+            self.len().to_string(),
+            (self.base_ki().map(|x| x.pass_this as f32).mean() / 120.) as usize,
         )
     }
 }
@@ -406,12 +509,18 @@ impl Dil {
     pub fn clear1(&self) {
         self.dil
             .iter()
-            .for_each(|x| x.data_save.save_pms2d.write().unwrap().clear());
+            .for_each(|x| x.data_save.clear_kind(&[CacheKind::Pms]));
     }
     pub fn clear2(&mut self) {
         self.dil
             .iter()
-            .for_each(|x| x.data_save.save_dcon.write().unwrap().clear());
+            .for_each(|x| x.data_save.clear_kind(&[CacheKind::Dcon]));
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.dil
+            .iter()
+            .fold(CacheStats::default(), |accu, x| accu.merge(&x.data_save.stats()))
     }
 
     pub fn total_kline_nums(&self) -> usize {
@@ -452,12 +561,12 @@ impl ToArc for PriceOri {
     type Output = PriceArc;
     fn to_arc(self) -> Self::Output {
         PriceArc {
-            t: self.t.to_arc(),
-            o: self.o.to_arc(),
-            h: self.h.to_arc(),
-            l: self.l.to_arc(),
-            c: self.c.to_arc(),
-            v: self.v.to_arc(),
+            t: self.t.into(),
+            o: self.o.into(),
+            h: self.h.into(),
+            l: self.l.into(),
+            c: self.c.into(),
+            v: self.v.into(),
             ki: self.ki.to_arc(),
             immut_info: self.immut_info.map(|x| x.clone().to_arc()),
             finished: None,
@@ -231,14 +231,19 @@ impl IdxOut for PriceOri {
 
 impl IdxOut for Di {
     fn idx_out(&self, idx: Idx) -> Self {
-        self.pcon
+        let mut di = self.clone();
+        di.own_price();
+        di.pcon
             .price
             .idx_out(idx)
             .to_pcon(self.pcon.inter.clone(), self.pcon.ticker)
             .to_di()
     }
     fn get_time_vec(&self) -> Cow<'_, Vec<dt>> {
-        self.pcon.price.get_time_vec()
+        match &self.mapped {
+            Some(price) => Cow::Owned(price.t.to_vec()),
+            None => self.pcon.price.get_time_vec(),
+        }
     }
 }
 
@@ -478,7 +483,7 @@ impl HasLen for PriceArc {
 }
 impl HasLen for Di {
     fn size(&self) -> usize {
-        self.pcon.price.size()
+        self.len()
     }
 }
 impl HasLen for Dil {
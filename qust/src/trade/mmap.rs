@@ -0,0 +1,619 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::Arc;
+use memmap2::Mmap;
+use qust_ds::prelude::*;
+use super::di::{PriceArc, PriceOri, PriceTick, Di, KlineInfo};
+use super::inter::{TickData, TriBox};
+use super::ticker::Ticker;
+
+/* #region Column file */
+/// Layout of a column file, all little endian:
+/// `MAGIC | n_col: u32 | n_row: u64 | n_col * (name: [u8; 16], kind: u64, offset: u64) | columns`,
+/// every column starts on an 8 byte boundary so it can be viewed in place. Times are kept as
+/// milliseconds since epoch and turned into `dt` once, when the file is opened.
+const MAGIC: &[u8; 8] = b"QUSTCOL3";
+const NAME_LEN: usize = 16;
+const HEAD_LEN: usize = 8 + 4 + 8;
+const ENTRY_LEN: usize = NAME_LEN + 8 + 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColKind {
+    ///milliseconds since epoch, stored as i64
+    Time,
+    F32,
+    I32,
+}
+
+impl ColKind {
+    fn size(&self) -> usize {
+        match self {
+            ColKind::Time => 8,
+            ColKind::F32 | ColKind::I32 => 4,
+        }
+    }
+
+    fn from_u64(x: u64) -> Option<Self> {
+        match x {
+            0 => Some(ColKind::Time),
+            1 => Some(ColKind::F32),
+            2 => Some(ColKind::I32),
+            _ => None,
+        }
+    }
+
+    fn to_u64(self) -> u64 {
+        match self {
+            ColKind::Time => 0,
+            ColKind::F32 => 1,
+            ColKind::I32 => 2,
+        }
+    }
+}
+
+pub enum ColRef<'a> {
+    Time(&'a [dt]),
+    F32(&'a [f32]),
+    I32(&'a [i32]),
+}
+
+impl ColRef<'_> {
+    fn kind(&self) -> ColKind {
+        match self {
+            ColRef::Time(_) => ColKind::Time,
+            ColRef::F32(_) => ColKind::F32,
+            ColRef::I32(_) => ColKind::I32,
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            ColRef::Time(x) => x.len(),
+            ColRef::F32(x) => x.len(),
+            ColRef::I32(x) => x.len(),
+        }
+    }
+}
+
+fn align8(x: usize) -> usize {
+    (x + 7) & !7
+}
+
+fn invalid(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_string())
+}
+
+pub fn write_columns<P: AsRef<Path>>(path: P, cols: &[(&str, ColRef)]) -> std::io::Result<()> {
+    let n_row = cols.first().map(|x| x.1.len()).unwrap_or(0);
+    if cols.iter().any(|x| x.1.len() != n_row || x.0.len() > NAME_LEN) {
+        return Err(invalid("column length or name does not fit"));
+    }
+    let path = path.as_ref();
+    let path_tmp = path.with_extension("tmp");
+    let mut w = BufWriter::new(File::create(&path_tmp)?);
+    w.write_all(MAGIC)?;
+    w.write_all(&(cols.len() as u32).to_le_bytes())?;
+    w.write_all(&(n_row as u64).to_le_bytes())?;
+    let mut offset = align8(HEAD_LEN + cols.len() * ENTRY_LEN);
+    for (name, col) in cols.iter() {
+        let mut name_bytes = [0u8; NAME_LEN];
+        name_bytes[..name.len()].copy_from_slice(name.as_bytes());
+        w.write_all(&name_bytes)?;
+        w.write_all(&col.kind().to_u64().to_le_bytes())?;
+        w.write_all(&(offset as u64).to_le_bytes())?;
+        offset = align8(offset + n_row * col.kind().size());
+    }
+    let mut pos = HEAD_LEN + cols.len() * ENTRY_LEN;
+    for (_, col) in cols.iter() {
+        w.write_all(&vec![0u8; align8(pos) - pos])?;
+        pos = align8(pos);
+        match col {
+            ColRef::Time(x) => x
+                .iter()
+                .try_for_each(|t| w.write_all(&t.and_utc().timestamp_millis().to_le_bytes()))?,
+            ColRef::F32(x) => x.iter().try_for_each(|v| w.write_all(&v.to_le_bytes()))?,
+            ColRef::I32(x) => x.iter().try_for_each(|v| w.write_all(&v.to_le_bytes()))?,
+        }
+        pos += n_row * col.kind().size();
+    }
+    w.flush()?;
+    drop(w);
+    std::fs::rename(path_tmp, path)
+}
+
+/// A read-only mapping of a column file. Clones share the same mapping, and every process
+/// that opens the same file shares its pages through the page cache. Time columns are
+/// read into `dt` when the file is opened, the other columns stay in the mapping.
+#[derive(Clone)]
+pub struct MmapColumns {
+    mmap: Arc<Mmap>,
+    pub n_row: usize,
+    pub cols: Vec<(String, ColKind, usize)>,
+    times: Vec<(String, Arc<Vec<dt>>)>,
+}
+
+impl MmapColumns {
+    pub fn open<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        if cfg!(target_endian = "big") {
+            return Err(invalid("column files are little endian"));
+        }
+        let file = File::open(path)?;
+        // Safety: the file is only replaced by rename, never written in place.
+        let mmap = unsafe { Mmap::map(&file)? };
+        if mmap.len() < HEAD_LEN || &mmap[..8] != MAGIC {
+            return Err(invalid("not a column file"));
+        }
+        let n_col = u32::from_le_bytes(mmap[8..12].try_into().unwrap()) as usize;
+        let n_row = usize::try_from(u64::from_le_bytes(mmap[12..20].try_into().unwrap()))
+            .map_err(|_| invalid("too many rows"))?;
+        let entries_end = n_col
+            .checked_mul(ENTRY_LEN)
+            .and_then(|x| x.checked_add(HEAD_LEN))
+            .filter(|x| *x <= mmap.len())
+            .ok_or_else(|| invalid("column entries out of file"))?;
+        let mut cols = Vec::with_capacity(n_col);
+        for entry in mmap[HEAD_LEN..entries_end].chunks_exact(ENTRY_LEN) {
+            let name = String::from_utf8_lossy(&entry[..NAME_LEN]).trim_end_matches('\0').to_string();
+            let kind = ColKind::from_u64(u64::from_le_bytes(entry[NAME_LEN..NAME_LEN + 8].try_into().unwrap()))
+                .ok_or_else(|| invalid("unknown column kind"))?;
+            let offset = usize::try_from(u64::from_le_bytes(entry[NAME_LEN + 8..].try_into().unwrap()))
+                .ok()
+                .filter(|x| x.is_multiple_of(8))
+                .filter(|x| {
+                    n_row
+                        .checked_mul(kind.size())
+                        .and_then(|len| len.checked_add(*x))
+                        .is_some_and(|end| end <= mmap.len())
+                })
+                .ok_or_else(|| invalid("column out of file"))?;
+            cols.push((name, kind, offset));
+        }
+        let mut res = Self { mmap: Arc::new(mmap), n_row, cols, times: vec![] };
+        for (name, kind, _) in res.cols.iter() {
+            if *kind != ColKind::Time {
+                continue;
+            }
+            let time = res
+                .slice::<i64>(name, ColKind::Time)
+                .unwrap()
+                .iter()
+                .map(|x| chrono::DateTime::from_timestamp_millis(*x).map(|x| x.naive_utc()))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| invalid("time out of range"))?;
+            res.times.push((name.clone(), Arc::new(time)));
+        }
+        Ok(res)
+    }
+
+    fn slice<T>(&self, name: &str, kind: ColKind) -> Option<&[T]> {
+        let (_, _, offset) = self.cols.iter().find(|x| x.0 == name && x.1 == kind)?;
+        let ptr = self.mmap[*offset..].as_ptr();
+        // Safety: the offset is 8 byte aligned inside a page aligned mapping and the
+        // length was checked against the file size in `open`.
+        Some(unsafe { std::slice::from_raw_parts(ptr as *const T, self.n_row) })
+    }
+
+    ///milliseconds since epoch as stored
+    pub fn time(&self, name: &str) -> Option<&[i64]> {
+        self.slice(name, ColKind::Time)
+    }
+
+    pub fn f32(&self, name: &str) -> Option<&[f32]> {
+        self.slice(name, ColKind::F32)
+    }
+
+    pub fn i32(&self, name: &str) -> Option<&[i32]> {
+        self.slice(name, ColKind::I32)
+    }
+
+    pub fn dt(&self, name: &str) -> Option<&[dt]> {
+        self.times.iter().find(|x| x.0 == name).map(|x| x.1.as_slice())
+    }
+
+    /// A time column, shared with the clones of the mapping.
+    fn col_dt(&self, name: &str) -> Option<Col<dt>> {
+        self.times.iter().find(|x| x.0 == name).map(|x| Col::Owned(x.1.clone()))
+    }
+
+    /// A column sharing the mapping, it keeps the mapping alive.
+    fn col<T>(&self, name: &str, kind: ColKind) -> Option<Col<T>> {
+        let (_, _, offset) = self.cols.iter().find(|x| x.0 == name && x.1 == kind)?;
+        Some(Col::Mapped(MappedCol {
+            mmap: self.mmap.clone(),
+            offset: *offset,
+            len: self.n_row,
+            _t: PhantomData,
+        }))
+    }
+}
+
+/// A column of a `PriceArc`, owned or borrowed from a mapped column file. Clones share the
+/// values either way.
+pub enum Col<T> {
+    Owned(Arc<Vec<T>>),
+    Mapped(MappedCol<T>),
+}
+
+/// `len` values at `offset` of a mapping, checked by `MmapColumns::open`.
+pub struct MappedCol<T> {
+    mmap: Arc<Mmap>,
+    offset: usize,
+    len: usize,
+    _t: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for MappedCol<T> {
+    fn clone(&self) -> Self {
+        Self { mmap: self.mmap.clone(), offset: self.offset, len: self.len, _t: PhantomData }
+    }
+}
+
+impl<T> Clone for Col<T> {
+    fn clone(&self) -> Self {
+        match self {
+            Col::Owned(x) => Col::Owned(x.clone()),
+            Col::Mapped(x) => Col::Mapped(x.clone()),
+        }
+    }
+}
+
+impl<T> Deref for Col<T> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        match self {
+            Col::Owned(x) => x,
+            // Safety: as `MmapColumns::slice`.
+            Col::Mapped(x) => unsafe {
+                std::slice::from_raw_parts(x.mmap[x.offset..].as_ptr() as *const T, x.len)
+            },
+        }
+    }
+}
+
+impl<T> AsRef<[T]> for Col<T> {
+    fn as_ref(&self) -> &[T] {
+        self
+    }
+}
+
+impl<T> From<Vec<T>> for Col<T> {
+    fn from(value: Vec<T>) -> Self {
+        Col::Owned(Arc::new(value))
+    }
+}
+
+impl<T> From<Arc<Vec<T>>> for Col<T> {
+    fn from(value: Arc<Vec<T>>) -> Self {
+        Col::Owned(value)
+    }
+}
+
+impl<T> Default for Col<T> {
+    fn default() -> Self {
+        Col::Owned(Default::default())
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for Col<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T> Col<T> {
+    pub fn is_mapped(&self) -> bool {
+        matches!(self, Col::Mapped(_))
+    }
+}
+
+impl<T: Clone> Col<T> {
+    /// The values in an `Arc<Vec>`, copied out of the mapping when mapped.
+    pub fn to_arc(&self) -> Arc<Vec<T>> {
+        match self {
+            Col::Owned(x) => x.clone(),
+            Col::Mapped(_) => Arc::new(self.to_vec()),
+        }
+    }
+}
+/* #endregion */
+
+/* #region Price views */
+#[derive(Clone, Copy)]
+pub struct PriceTickView<'a> {
+    pub t: &'a [dt],
+    pub c: &'a [f32],
+    pub v: &'a [f32],
+    pub ct: &'a [i32],
+    pub bid1: &'a [f32],
+    pub ask1: &'a [f32],
+    pub bid1_v: &'a [f32],
+    pub ask1_v: &'a [f32],
+}
+
+#[derive(Clone, Copy)]
+pub struct PriceOriView<'a> {
+    pub t: &'a [dt],
+    pub o: &'a [f32],
+    pub h: &'a [f32],
+    pub l: &'a [f32],
+    pub c: &'a [f32],
+    pub v: &'a [f32],
+    pub ki: &'a [KlineInfo],
+}
+
+impl PriceTickView<'_> {
+    pub fn len(&self) -> usize {
+        self.t.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.t.is_empty()
+    }
+
+    /// Ticks with `range.start <= t < range.end`, found by binary search, no copy.
+    pub fn between(&self, range: std::ops::Range<dt>) -> Self {
+        let start = self.t.partition_point(|x| *x < range.start);
+        let end = self.t.partition_point(|x| *x < range.end);
+        Self {
+            t: &self.t[start..end],
+            c: &self.c[start..end],
+            v: &self.v[start..end],
+            ct: &self.ct[start..end],
+            bid1: &self.bid1[start..end],
+            ask1: &self.ask1[start..end],
+            bid1_v: &self.bid1_v[start..end],
+            ask1_v: &self.ask1_v[start..end],
+        }
+    }
+
+    pub fn tick_data(&self, i: usize) -> TickData {
+        TickData {
+            t: self.t[i],
+            c: self.c[i],
+            v: self.v[i],
+            bid1: self.bid1[i],
+            ask1: self.ask1[i],
+            bid1_v: self.bid1_v[i],
+            ask1_v: self.ask1_v[i],
+            ct: self.ct[i],
+        }
+    }
+
+    /// Copies the ticks out of the mapping.
+    pub fn to_owned(&self) -> PriceTick {
+        PriceTick {
+            t: self.t.to_vec(),
+            c: self.c.to_vec(),
+            v: self.v.to_vec(),
+            ct: self.ct.to_vec(),
+            bid1: self.bid1.to_vec(),
+            ask1: self.ask1.to_vec(),
+            bid1_v: self.bid1_v.to_vec(),
+            ask1_v: self.ask1_v.to_vec(),
+        }
+    }
+
+    /// Builds the klines from the mapped ticks, the ticks are never collected into a
+    /// `PriceTick`. Save the result with `MmapPriceOri::save` to map the klines next time.
+    pub fn to_price_ori(&self, r: TriBox, ticker: Ticker) -> PriceOri {
+        if self.is_empty() {
+            return PriceOri::with_capacity(0);
+        }
+        let bounds = PriceTick {
+            t: vec![self.t[0], self.t[self.len() - 1]],
+            ..Default::default()
+        };
+        let mut price_ori = r.gen_price_ori(&bounds);
+        let mut f = r.update_tick_func(ticker);
+        for i in 0..self.len() {
+            f(&self.tick_data(i), &mut price_ori);
+        }
+        price_ori.shrink_to_fit();
+        price_ori
+    }
+}
+
+impl PriceOriView<'_> {
+    pub fn len(&self) -> usize {
+        self.t.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.t.is_empty()
+    }
+
+    /// Copies the klines out of the mapping, `immut_info` is not kept in the column file.
+    pub fn to_owned(&self) -> PriceOri {
+        PriceOri {
+            t: self.t.to_vec(),
+            o: self.o.to_vec(),
+            h: self.h.to_vec(),
+            l: self.l.to_vec(),
+            c: self.c.to_vec(),
+            v: self.v.to_vec(),
+            ki: self.ki.to_vec(),
+            immut_info: vec![],
+        }
+    }
+}
+/* #endregion */
+
+/* #region Mapped prices */
+#[derive(Clone)]
+pub struct MmapPriceTick(pub MmapColumns);
+
+impl MmapPriceTick {
+    pub fn save<P: AsRef<Path>>(price: &PriceTick, path: P) -> std::io::Result<()> {
+        write_columns(path, &[
+            ("t", ColRef::Time(&price.t)),
+            ("c", ColRef::F32(&price.c)),
+            ("v", ColRef::F32(&price.v)),
+            ("ct", ColRef::I32(&price.ct)),
+            ("bid1", ColRef::F32(&price.bid1)),
+            ("ask1", ColRef::F32(&price.ask1)),
+            ("bid1_v", ColRef::F32(&price.bid1_v)),
+            ("ask1_v", ColRef::F32(&price.ask1_v)),
+        ])
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let res = Self(MmapColumns::open(path)?);
+        res.try_view().ok_or_else(|| invalid("missing tick columns"))?;
+        Ok(res)
+    }
+
+    fn try_view(&self) -> Option<PriceTickView<'_>> {
+        let m = &self.0;
+        Some(PriceTickView {
+            t: m.dt("t")?,
+            c: m.f32("c")?,
+            v: m.f32("v")?,
+            ct: m.i32("ct")?,
+            bid1: m.f32("bid1")?,
+            ask1: m.f32("ask1")?,
+            bid1_v: m.f32("bid1_v")?,
+            ask1_v: m.f32("ask1_v")?,
+        })
+    }
+
+    pub fn view(&self) -> PriceTickView<'_> {
+        self.try_view().unwrap()
+    }
+}
+
+/// The klines of a column file, `ki` is read out of the file when it is opened.
+#[derive(Clone)]
+pub struct MmapPriceOri(pub MmapColumns, pub Arc<Vec<KlineInfo>>);
+
+impl MmapPriceOri {
+    pub fn save<P: AsRef<Path>>(price: &PriceOri, path: P) -> std::io::Result<()> {
+        let open_time = price.ki.map(|x| x.open_time);
+        let pass_last = price.ki.map(|x| x.pass_last as i32);
+        let pass_this = price.ki.map(|x| x.pass_this as i32);
+        let contract = price.ki.map(|x| x.contract);
+        write_columns(path, &[
+            ("t", ColRef::Time(&price.t)),
+            ("o", ColRef::F32(&price.o)),
+            ("h", ColRef::F32(&price.h)),
+            ("l", ColRef::F32(&price.l)),
+            ("c", ColRef::F32(&price.c)),
+            ("v", ColRef::F32(&price.v)),
+            ("ki_open_time", ColRef::Time(&open_time)),
+            ("ki_pass_last", ColRef::I32(&pass_last)),
+            ("ki_pass_this", ColRef::I32(&pass_this)),
+            ("ki_contract", ColRef::I32(&contract)),
+        ])
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let m = MmapColumns::open(path)?;
+        let ki = izip!(
+            m.dt("ki_open_time").ok_or_else(|| invalid("missing kline columns"))?,
+            m.i32("ki_pass_last").ok_or_else(|| invalid("missing kline columns"))?,
+            m.i32("ki_pass_this").ok_or_else(|| invalid("missing kline columns"))?,
+            m.i32("ki_contract").ok_or_else(|| invalid("missing kline columns"))?,
+        )
+        .map(|(open_time, pass_last, pass_this, contract)| KlineInfo {
+            open_time: *open_time,
+            pass_last: *pass_last as u16,
+            pass_this: *pass_this as u16,
+            contract: *contract,
+        })
+        .collect_vec();
+        let res = Self(m, Arc::new(ki));
+        res.try_view().ok_or_else(|| invalid("missing kline columns"))?;
+        Ok(res)
+    }
+
+    fn try_view(&self) -> Option<PriceOriView<'_>> {
+        let m = &self.0;
+        Some(PriceOriView {
+            t: m.dt("t")?,
+            o: m.f32("o")?,
+            h: m.f32("h")?,
+            l: m.f32("l")?,
+            c: m.f32("c")?,
+            v: m.f32("v")?,
+            ki: &self.1,
+        })
+    }
+
+    pub fn view(&self) -> PriceOriView<'_> {
+        self.try_view().unwrap()
+    }
+
+    /// Columns that read the mapping in place, the times and `ki` are shared with the
+    /// clones of the mapping.
+    pub fn price_arc(&self) -> PriceArc {
+        let m = &self.0;
+        PriceArc {
+            t: m.col_dt("t").unwrap(),
+            o: m.col("o", ColKind::F32).unwrap(),
+            h: m.col("h", ColKind::F32).unwrap(),
+            l: m.col("l", ColKind::F32).unwrap(),
+            c: m.col("c", ColKind::F32).unwrap(),
+            v: m.col("v", ColKind::F32).unwrap(),
+            ki: self.1.clone(),
+            immut_info: vec![],
+            finished: None,
+        }
+    }
+
+    /// A `Di` reading the bars from the mapping, `Di::own_price` copies them when the bars
+    /// are changed.
+    pub fn to_di(&self, inter: TriBox, ticker: Ticker) -> Di {
+        let mut res = PriceOri::with_capacity(0).to_pcon(inter, ticker).to_di();
+        res.mapped = Some(self.price_arc());
+        res
+    }
+}
+/* #endregion */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kline_file_keeps_times_and_ki() {
+        let t0 = da::from_ymd_opt(2024, 3, 1).unwrap().and_hms_milli_opt(21, 0, 0, 500).unwrap();
+        let t = vec![t0, t0 + chrono::Duration::minutes(1)];
+        let price = PriceOri {
+            t: t.clone(),
+            o: vec![1., 2.],
+            h: vec![1., 2.],
+            l: vec![1., 2.],
+            c: vec![1., 2.],
+            v: vec![10., 20.],
+            ki: vec![
+                KlineInfo { open_time: t[0], pass_last: 1, pass_this: 2, contract: 0 },
+                KlineInfo { open_time: t[1], pass_last: 3, pass_this: 4, contract: 1 },
+            ],
+            immut_info: vec![],
+        };
+        let path = std::env::temp_dir().join(format!("qust_mmap_{}.col", std::process::id()));
+        MmapPriceOri::save(&price, &path).unwrap();
+        let price_arc = MmapPriceOri::open(&path).unwrap().price_arc();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(&price_arc.t[..], &t[..]);
+        assert_eq!(price_arc.c.to_vec(), vec![1., 2.]);
+        assert_eq!(price_arc.ki.map(|x| (x.open_time, x.pass_last, x.pass_this, x.contract)), vec![
+            (t[0], 1, 2, 0),
+            (t[1], 3, 4, 1),
+        ]);
+    }
+
+    #[test]
+    fn open_refuses_a_column_past_the_end() {
+        let path = std::env::temp_dir().join(format!("qust_mmap_bad_{}.col", std::process::id()));
+        write_columns(&path, &[("c", ColRef::F32(&[1., 2.]))]).unwrap();
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[12..20].copy_from_slice(&u64::MAX.to_le_bytes());
+        std::fs::write(&path, bytes).unwrap();
+        let res = MmapColumns::open(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(res.is_err());
+    }
+}
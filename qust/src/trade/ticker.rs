@@ -1,6 +1,7 @@
 #![allow(dead_code)]
 use serde::{Deserialize, Serialize};
 use regex::Regex;
+use qust_ds::prelude::{tt, ToTt};
 
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Deserialize, Serialize, Ord)]
 pub enum Ticker {
@@ -335,8 +336,8 @@ lazy_static! {
     ];
 }
 
-#[derive(Debug)]
-enum Comdty {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Comdty {
     Soft,
     NonferrousMetals,
     Ceral,
@@ -348,14 +349,20 @@ enum Comdty {
     Oil,
 }
 
-trait ToSection {
-    fn to_section(self) -> Comdty;
+pub trait ToSection {
+    fn try_section(self) -> Option<Comdty>;
+    fn to_section(self) -> Comdty
+    where
+        Self: Sized,
+    {
+        self.try_section().expect("this ticker not implement section")
+    }
 }
 impl ToSection for Ticker {
-    fn to_section(self) -> Comdty {
+    fn try_section(self) -> Option<Comdty> {
         use Comdty::*;
         use Ticker::*;
-        match self {
+        let res = match self {
             AP | SR | sp | CF => Soft,
             al | cu | ni | sn | zn => NonferrousMetals,
             c | cs => Ceral,
@@ -365,8 +372,9 @@ impl ToSection for Ticker {
             jm | FG | hc | i | j | SM | rb | SF | ZC | ss => BlackMaterial,
             fu | sc | pg => Energy,
             p | y | OI => Oil,
-            _ => panic!("this ticker not implement section"),
-        }
+            _ => return None,
+        };
+        Some(res)
     }
 }
 
@@ -389,6 +397,51 @@ impl From<Ticker> for TradingPeriod {
     }
 }
 
+impl Ticker {
+    /// Continuous trading sessions as (start, end), a night session running past midnight
+    /// has `end < start`.
+    pub fn sessions(&self) -> Vec<(tt, tt)> {
+        use Ticker::*;
+        let mut res = vec![
+            (90000.to_tt(), 101500.to_tt()),
+            (103000.to_tt(), 113000.to_tt()),
+            (133000.to_tt(), 150000.to_tt()),
+        ];
+        let night_end: usize = match TradingPeriod::from(*self) {
+            TradingPeriod::Light => return res,
+            TradingPeriod::LightNightMorn => match self {
+                au | ag | sc => 23000,
+                _ => 10000,
+            },
+            TradingPeriod::LightNight => match self {
+                ss => 10000,
+                _ => 230000,
+            },
+        };
+        res.push((210000.to_tt(), night_end.to_tt()));
+        res
+    }
+
+    /// `lead` seconds before a session start are accepted for the call auction print, the
+    /// whole closing second is in the session, so the 15:00:00.500 print is kept.
+    pub fn in_session(&self, t: tt, lead: i64) -> bool {
+        self.session_index(t, lead).is_some()
+    }
+
+    /// Position in `sessions` of the session `t` is in, see `in_session`.
+    pub fn session_index(&self, t: tt, lead: i64) -> Option<usize> {
+        self.sessions().iter().position(|(start, end)| {
+            let start = *start - chrono::Duration::seconds(lead);
+            let end = *end + chrono::Duration::seconds(1);
+            if start < end {
+                t >= start && t < end
+            } else {
+                t >= start || t < end
+            }
+        })
+    }
+}
+
 
 pub trait ExtractTicker {
     type Output;